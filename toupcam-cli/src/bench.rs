@@ -0,0 +1,137 @@
+//! Measure streaming performance in every mode and bit depth.
+//!
+//! Each combination is streamed for a fixed time, and the achieved frame
+//! rate, USB throughput and error counts are reported, along with a
+//! histogram of how long each bulk read took. Running this on different
+//! hosts, ports and hubs shows where a setup is losing frames.
+
+use crate::util::Error;
+use std::time::{ Duration, Instant };
+use toupcam::stats::{ LatencyHistogram, StreamStats };
+use toupcam::{ BitDepth, Camera, CameraMode };
+
+/// Options for a benchmark run.
+pub struct BenchArgs {
+    /// How long to stream at each mode/depth
+    pub duration: Duration,
+    /// Only benchmark this mode
+    pub mode: Option<CameraMode>,
+    /// Only benchmark this bit depth
+    pub depth: Option<BitDepth>,
+    pub exposure: Option<Duration>,
+    /// Number of bulk transfers in flight
+    pub queue_depth: Option<usize>,
+}
+
+/// Width of the longest bar in a histogram.
+const BAR_WIDTH: u64 = 40;
+
+/// Format a duration from microseconds up to seconds, compactly.
+fn short(d: Duration) -> String {
+    let us = d.as_micros();
+    if us < 1000 {
+        format!("{}us", us)
+    } else if us < 1_000_000 {
+        format!("{}ms", us / 1000)
+    } else {
+        format!("{}s", us / 1_000_000)
+    }
+}
+
+fn print_histogram(hist: &LatencyHistogram) {
+    let counts = hist.counts();
+    let peak = counts.iter().copied().max().unwrap_or(0).max(1);
+    let (Some(first), Some(last)) = (counts.iter().position(|n| *n > 0),
+        counts.iter().rposition(|n| *n > 0)) else { return; };
+    for (idx, n) in counts.iter().enumerate().take(last + 1).skip(first) {
+        let (lo, hi) = LatencyHistogram::bucket_range(idx);
+        let hi = if hi == Duration::MAX { "".to_string() } else { short(hi) };
+        let bar = "#".repeat((n * BAR_WIDTH).div_ceil(peak) as usize);
+        println!("    {:>6} .. {:<6} {:>8} {}", short(lo), hi, n, bar);
+    }
+}
+
+fn print_stats(stats: &StreamStats, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64();
+    let lat = &stats.chunk_latency;
+    println!("  fps:            {:.2}", stats.delivered as f64 / secs);
+    println!("  throughput:     {:.2} MB/s", stats.bytes as f64 / secs / 1e6);
+    println!("  frames:         {} delivered, {} truncated, {} desynchronized",
+        stats.delivered, stats.truncated, stats.desynchronized);
+    println!("  frame transfer: {:?} average", stats.avg_transfer_time());
+    println!("  chunk latency:  {} reads, mean {:?}, p50 <{}, p99 <{}, max {:?}",
+        lat.count(), lat.mean(), short(lat.percentile(0.5)), short(lat.percentile(0.99)),
+        lat.max());
+    print_histogram(lat);
+}
+
+/// Stream for `duration` and return the statistics, along with how long the
+/// stream actually ran.
+fn bench_one(cam: &mut Camera, duration: Duration)
+    -> Result<(StreamStats, Duration), Error>
+{
+    cam.start_stream()?;
+    // The first frame after starting is usually truncated; don't count it
+    loop {
+        match cam.read_frame() {
+            Err(toupcam::Error::FirstFrame) => continue,
+            res => { res?; break; },
+        }
+    }
+    cam.reset_stream_stats();
+    let start = Instant::now();
+    let mut errors = 0;
+    while start.elapsed() < duration {
+        match cam.read_frame() {
+            Ok(_) => {},
+            // Counted in the statistics
+            Err(toupcam::Error::FirstFrame) | Err(toupcam::Error::Desynchronized) => {},
+            Err(e) => {
+                errors += 1;
+                println!("  read failed: {:?}", e);
+                if errors >= 10 { break; }
+            },
+        }
+    }
+    let elapsed = start.elapsed();
+    let stats = cam.stream_stats();
+    cam.stop_stream()?;
+    Ok((stats, elapsed))
+}
+
+pub fn run(args: BenchArgs) -> Result<(), Error> {
+    let mut cam = Camera::open()?;
+    let caps = cam.capabilities();
+    if let Some(exposure) = args.exposure { cam.set_exposure_time(exposure)?; }
+    if let Some(depth) = args.queue_depth { cam.set_queue_depth(depth); }
+    println!("{}, exposure {:?}, queue depth {}, {:?} per setting", caps.model,
+        cam.get_exposure_time(), cam.get_queue_depth(), args.duration);
+
+    let mut summary = Vec::new();
+    for (mode, (w, h)) in caps.resolutions.iter() {
+        if args.mode.is_some_and(|m| m != *mode) { continue; }
+        for depth in caps.depths.iter() {
+            if args.depth.is_some_and(|d| d != *depth) { continue; }
+            let bits = match depth { BitDepth::BitDepth8 => 8, BitDepth::BitDepth12 => 12 };
+            println!();
+            println!("{:?} ({}x{}), {}-bit", mode, w, h, bits);
+            cam.set_mode(*mode)?;
+            cam.set_depth(*depth)?;
+            let (stats, elapsed) = bench_one(&mut cam, args.duration)?;
+            print_stats(&stats, elapsed);
+            summary.push((*mode, bits, stats, elapsed));
+        }
+    }
+
+    println!();
+    println!("{:<8} {:>4} {:>8} {:>10} {:>9} {:>7} {:>9}",
+        "mode", "bits", "fps", "MB/s", "truncated", "desync", "p99 chunk");
+    for (mode, bits, stats, elapsed) in summary.iter() {
+        let secs = elapsed.as_secs_f64();
+        println!("{:<8} {:>4} {:>8.2} {:>10.2} {:>9} {:>7} {:>9}",
+            format!("{:?}", mode), bits, stats.delivered as f64 / secs,
+            stats.bytes as f64 / secs / 1e6, stats.truncated, stats.desynchronized,
+            short(stats.chunk_latency.percentile(0.99)));
+    }
+    Ok(())
+}
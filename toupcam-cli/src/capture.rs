@@ -0,0 +1,108 @@
+//! Capture a number of frames with given settings, one file per frame.
+//!
+//! Frames are written as `<prefix>_NNNN.<ext>` in the output directory, in
+//! one of the formats the library can write. Every format except `raw`
+//! (the bytes as they come off the wire) and `pgm` carries the acquisition
+//! parameters; with `--sidecar`, a JSON file describing the capture is
+//! written next to each frame as well.
+
+use crate::util::Error;
+use std::path::Path;
+use std::time::{ Duration, SystemTime };
+use toupcam::metadata::CaptureMetadata;
+use toupcam::sidecar::Sidecar;
+use toupcam::source::FrameSource;
+
+/// File format for captured frames.
+#[derive(Copy, Clone, Debug)]
+pub enum Format { Raw, Pgm, Png, Tiff, Dng, Fits }
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Raw => "raw", Self::Pgm => "pgm", Self::Png => "png",
+            Self::Tiff => "tiff", Self::Dng => "dng", Self::Fits => "fits",
+        }
+    }
+}
+
+/// Parse a capture format (`raw`, `pgm`, `png`, `tiff`, `dng` or `fits`).
+pub fn parse_format(s: &str) -> Result<Format, String> {
+    match s {
+        "raw" => Ok(Format::Raw),
+        "pgm" => Ok(Format::Pgm),
+        "png" => Ok(Format::Png),
+        "tiff" | "tif" => Ok(Format::Tiff),
+        "dng" => Ok(Format::Dng),
+        "fits" | "fit" => Ok(Format::Fits),
+        _ => Err(format!("unknown format '{}' (expected raw, pgm, png, tiff, dng or fits)", s)),
+    }
+}
+
+/// Options for a capture run.
+pub struct CaptureArgs<'a> {
+    pub out: &'a Path,
+    pub format: Format,
+    pub mode: toupcam::CameraMode,
+    pub depth: toupcam::BitDepth,
+    pub exposure: Option<Duration>,
+    pub gain: Option<u16>,
+    pub count: u64,
+    /// File names start with this
+    pub prefix: &'a str,
+    /// Write a JSON sidecar next to each frame
+    pub sidecar: bool,
+}
+
+/// Write a frame in the chosen format.
+fn save(frame: &toupcam::Frame, path: &Path, format: Format, meta: &CaptureMetadata)
+    -> Result<(), toupcam::Error>
+{
+    match format {
+        Format::Raw => std::fs::write(path, &frame.data[..frame.info().len()])?,
+        Format::Pgm => frame.save_pgm(path)?,
+        Format::Png => frame.save_png(path, &Default::default())?,
+        Format::Tiff => frame.save_tiff(path, Some(meta))?,
+        Format::Dng => frame.save_dng(path, &Default::default(), Some(meta))?,
+        Format::Fits => frame.save_fits(path, Some(meta))?,
+    }
+    Ok(())
+}
+
+pub fn run(args: CaptureArgs) -> Result<(), Error> {
+    std::fs::create_dir_all(args.out)?;
+
+    let mut cam = toupcam::Camera::open()?;
+    cam.set_mode(args.mode)?;
+    cam.set_depth(args.depth)?;
+    if let Some(exposure) = args.exposure { cam.set_exposure_time(exposure)?; }
+    if let Some(gain) = args.gain { cam.set_gain(gain)?; }
+    // Reads the serial number once, rather than for every frame
+    let mut meta = cam.metadata();
+    println!("capturing {} frame(s): {:?}, {} bits, exposure {:?}, gain 0x{:04x}",
+        args.count, meta.mode, meta.bits, meta.exposure, meta.gain);
+
+    let mut idx = 0;
+    let res = loop {
+        if idx >= args.count { break Ok(()); }
+        let frame = match cam.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        };
+        if !frame.complete {
+            println!("skipping truncated frame {}", frame.seq);
+            continue;
+        }
+        meta.timestamp = SystemTime::now();
+        let path = args.out.join(format!("{}_{:04}.{}", args.prefix, idx,
+            args.format.extension()));
+        if let Err(e) = save(&frame, &path, args.format, &meta) { break Err(e); }
+        if args.sidecar {
+            Sidecar::new(&meta, &frame.info()).save_next_to(&path)?;
+        }
+        println!("[{:04}] {}", idx, path.display());
+        idx += 1;
+    };
+    cam.stop_stream()?;
+    Ok(res?)
+}
@@ -0,0 +1,84 @@
+//! Print what's known about the connected cameras.
+//!
+//! This is meant as a quick health check before starting capture software:
+//! if a camera is listed here with sensible settings, it can be opened and
+//! configured. The EEPROM layout hasn't been worked out, so it's only shown
+//! as a size and any readable strings found in it (which may include a
+//! serial number or model name).
+
+use crate::util::Error;
+use toupcam::{ BitDepth, Camera };
+
+/// Runs of at least this many printable characters count as strings.
+const MIN_STRING_LEN: usize = 4;
+
+/// Printable ASCII strings in a blob of data.
+fn strings(data: &[u8]) -> Vec<String> {
+    data.split(|b| !(b.is_ascii_graphic() || *b == b' '))
+        .filter(|s| s.len() >= MIN_STRING_LEN)
+        .map(|s| String::from_utf8_lossy(s).trim().to_string())
+        .filter(|s| s.len() >= MIN_STRING_LEN)
+        .collect()
+}
+
+fn depth_bits(depth: BitDepth) -> u32 {
+    match depth { BitDepth::BitDepth8 => 8, BitDepth::BitDepth12 => 12 }
+}
+
+pub fn run(eeprom: bool) -> Result<(), Error> {
+    let devices = Camera::list()?;
+    println!("{} camera(s) found", devices.len());
+    for (idx, loc) in devices.iter().enumerate() {
+        let ports: Vec<String> = loc.ports.iter().map(|p| p.to_string()).collect();
+        println!();
+        println!("[{}] bus {} port {}", idx, loc.bus, ports.join("."));
+        let mut cam = match Camera::open_at(loc) {
+            Ok(cam) => cam,
+            Err(e) => {
+                println!("  couldn't open: {:?}", e);
+                continue;
+            },
+        };
+        let caps = cam.capabilities();
+        let (major, minor, sub) = cam.firmware_version();
+        println!("  model:       {}", caps.model);
+        match cam.serial_number() {
+            Ok(Some(serial)) => println!("  serial:      {}", serial),
+            Ok(None) => println!("  serial:      (none)"),
+            Err(e) => println!("  serial:      (couldn't read: {:?})", e),
+        }
+        println!("  firmware:    {}.{}.{} (bcdDevice)", major, minor, sub);
+
+        println!("  modes:");
+        for (mode, (w, h)) in caps.resolutions.iter() {
+            println!("    {:?}: {}x{}", mode, w, h);
+        }
+        let depths: Vec<String> = caps.depths.iter()
+            .map(|d| format!("{}-bit", depth_bits(*d))).collect();
+        println!("  depths:      {}", depths.join(", "));
+        println!("  exposure:    {:?} to {:?}", caps.exposure_min, caps.exposure_max);
+        println!("  gain:        0x{:04x} to 0x{:04x}", caps.gain_min, caps.gain_max);
+
+        let (h, v) = cam.get_flip();
+        println!("  current settings:");
+        println!("    mode:      {:?}", cam.get_mode());
+        println!("    depth:     {}-bit", depth_bits(cam.get_depth()));
+        println!("    exposure:  {:?}", cam.get_exposure_time());
+        println!("    gain:      0x{:04x}", cam.get_gain());
+        println!("    flip:      horizontal={} vertical={}", h, v);
+        println!("    cfa:       {}", cam.cfa().name());
+
+        if eeprom {
+            match cam.read_eeprom() {
+                Ok(data) => {
+                    println!("  eeprom:      {} bytes", data.len());
+                    for s in strings(&data) {
+                        println!("    {:?}", s);
+                    }
+                },
+                Err(e) => println!("  eeprom:      (couldn't read: {:?})", e),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,275 @@
+
+mod util;
+mod capture;
+mod info;
+mod sweep;
+mod simulate;
+mod stream;
+mod bench;
+mod regs;
+mod selftest;
+mod record;
+
+use clap::{ Parser, Subcommand };
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(about = "Command-line tools for the MU1603 camera")]
+struct Cli {
+    #[command(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List connected cameras, with their capabilities and settings.
+    Info {
+        /// Also read the EEPROM (and show any strings in it)
+        #[arg(long)]
+        eeprom: bool,
+    },
+    /// Capture frames with the given settings, one file per frame.
+    Capture {
+        /// Sensor mode
+        #[arg(long, default_value = "1")]
+        mode: toupcam::CameraMode,
+        /// Bit depth
+        #[arg(long, default_value = "12")]
+        depth: toupcam::BitDepth,
+        /// Exposure time (i.e. `50ms`)
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        exposure: Option<Duration>,
+        /// Raw analog gain, within the range from `info` (i.e. `0x610c`)
+        #[arg(long, value_parser = toupcam::parse::gain)]
+        gain: Option<u16>,
+        /// Number of frames
+        #[arg(long, default_value_t = 1)]
+        count: u64,
+        /// File format (`raw`, `pgm`, `png`, `tiff`, `dng` or `fits`)
+        #[arg(long, default_value = "tiff", value_parser = capture::parse_format)]
+        format: capture::Format,
+        /// Output directory
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+        /// Start of the file names
+        #[arg(long, default_value = "frame")]
+        prefix: String,
+        /// Write a JSON sidecar next to each frame
+        #[arg(long)]
+        sidecar: bool,
+    },
+    /// Record a stream of frames to SER or `.tpraw` files (Ctrl-C to stop).
+    Record {
+        /// Container format (`ser` or `tpraw`)
+        #[arg(long, default_value = "ser", value_parser = record::parse_container)]
+        format: record::Container,
+        /// Output directory
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+        /// Start of the file names
+        #[arg(long, default_value = "rec")]
+        prefix: String,
+        /// Sensor mode
+        #[arg(long, default_value = "1")]
+        mode: toupcam::CameraMode,
+        /// Bit depth
+        #[arg(long, default_value = "12")]
+        depth: toupcam::BitDepth,
+        /// Exposure time (i.e. `20ms`)
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        exposure: Option<Duration>,
+        /// Raw analog gain, within the range from `info` (i.e. `0x610c`)
+        #[arg(long, value_parser = toupcam::parse::gain)]
+        gain: Option<u16>,
+        /// Stop after this long (i.e. `10s`); otherwise, record until Ctrl-C
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        duration: Option<Duration>,
+        /// Start a new file when the current one reaches this size (i.e. `2G`)
+        #[arg(long, value_parser = util::parse_size)]
+        split_size: Option<u64>,
+        /// Start a new file when the current one covers this long (i.e. `60s`)
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        split_duration: Option<Duration>,
+        /// Write a JSON sidecar next to each file
+        #[arg(long)]
+        sidecar: bool,
+        /// Compress frames with zstd at this level (`tpraw` only; i.e. `3`)
+        #[arg(long)]
+        compress: Option<i32>,
+    },
+    /// Capture one frame per exposure setting and report linearity.
+    Sweep {
+        /// Range of exposure times (i.e. `1ms..1s`)
+        #[arg(long, default_value = "1ms..1s", value_parser = util::parse_range)]
+        exposures: util::DurationRange,
+        /// Number of exposure settings (log-spaced)
+        #[arg(long, default_value_t = 20)]
+        steps: usize,
+        /// Output directory
+        #[arg(long, default_value = ".")]
+        out: PathBuf,
+    },
+    /// Simulate a recorded raw frame at a different exposure/gain.
+    Simulate {
+        /// Raw frame to read
+        input: PathBuf,
+        /// Where to write the simulated raw frame
+        #[arg(long)]
+        out: PathBuf,
+        /// Sensor mode the frame was captured in
+        #[arg(long, default_value = "1")]
+        mode: toupcam::CameraMode,
+        /// Bit depth the frame was captured with
+        #[arg(long, default_value = "12")]
+        depth: toupcam::BitDepth,
+        /// Exposure time the frame was captured with
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        exposure: Duration,
+        /// Exposure time to simulate
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        to_exposure: Duration,
+        /// Relative gain to simulate
+        #[arg(long, default_value_t = 1.0)]
+        gain: f64,
+        /// Response measured with `sweep` (i.e. `sweep.csv`)
+        #[arg(long)]
+        response: Option<PathBuf>,
+        /// Black level (overrides the one derived from `--response`)
+        #[arg(long)]
+        black: Option<f64>,
+    },
+    /// Measure frame rate, throughput and USB latency in each mode/depth.
+    Bench {
+        /// How long to stream at each setting (i.e. `10s`)
+        #[arg(long, default_value = "5s", value_parser = toupcam::parse::duration)]
+        duration: Duration,
+        /// Only benchmark this mode
+        #[arg(long)]
+        mode: Option<toupcam::CameraMode>,
+        /// Only benchmark this bit depth
+        #[arg(long)]
+        depth: Option<toupcam::BitDepth>,
+        /// Exposure time (short exposures measure the USB link, not the sensor)
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        exposure: Option<Duration>,
+        /// Number of bulk transfers kept in flight
+        #[arg(long)]
+        queue_depth: Option<usize>,
+    },
+    /// Check that the camera can be opened, initialized and streamed from.
+    Selftest {
+        /// Number of frames to capture and check
+        #[arg(long, default_value_t = 5)]
+        frames: usize,
+    },
+    /// Inspect or write device registers (for reverse-engineering).
+    Regs {
+        #[command(subcommand)]
+        cmd: RegsCommand,
+        /// File that every register access is appended to
+        #[arg(long, default_value = "regs.log", global = true)]
+        log: PathBuf,
+        /// Start the stream first (runs the initialization scripts)
+        #[arg(long, global = true)]
+        start: bool,
+    },
+    /// Stream frames to stdout (or a named pipe) for other programs.
+    Stream {
+        /// Output format (`y4m` for players/encoders, or `raw`)
+        #[arg(long, default_value = "y4m", value_parser = util::parse_pipe_format)]
+        format: toupcam::pipe::PipeFormat,
+        /// Named pipe to write to, instead of stdout
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Sensor mode
+        #[arg(long, default_value = "1")]
+        mode: toupcam::CameraMode,
+        /// Bit depth
+        #[arg(long, default_value = "12")]
+        depth: toupcam::BitDepth,
+        /// Exposure time (i.e. `20ms`)
+        #[arg(long, value_parser = toupcam::parse::duration)]
+        exposure: Option<Duration>,
+        /// Raw analog gain, within the range from `info` (i.e. `0x610c`)
+        #[arg(long, value_parser = toupcam::parse::gain)]
+        gain: Option<u16>,
+        /// Stop after this many frames
+        #[arg(long)]
+        count: Option<u64>,
+        /// Frame rate given to players (y4m only)
+        #[arg(long, default_value_t = 30.0)]
+        fps: f64,
+    },
+}
+
+#[derive(Subcommand)]
+enum RegsCommand {
+    /// List the known registers with the last value written to each.
+    Dump,
+    /// Write a value to a register.
+    Write {
+        /// Register name (from the protocol descriptor) or hex address
+        reg: String,
+        /// Value to write (hex)
+        #[arg(value_parser = regs::parse_hex)]
+        val: u16,
+        /// Treat a bare address as a system register instead of a sensor one
+        #[arg(long)]
+        sys: bool,
+        /// Confirm that writing arbitrary values may wedge or damage the camera
+        #[arg(long = "i-know-what-im-doing", required = true)]
+        confirmed: bool,
+    },
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let res = match cli.cmd {
+        Command::Info { eeprom } => info::run(eeprom),
+        Command::Capture { mode, depth, exposure, gain, count, format, out, prefix,
+            sidecar } =>
+        {
+            capture::run(capture::CaptureArgs {
+                out: &out, format, mode, depth, exposure, gain, count, prefix: &prefix,
+                sidecar,
+            })
+        },
+        Command::Record { format, out, prefix, mode, depth, exposure, gain, duration,
+            split_size, split_duration, sidecar, compress } =>
+        {
+            record::run(record::RecordArgs {
+                out: &out, container: format, mode, depth, exposure, gain, duration,
+                split_size, split_duration, prefix: &prefix, sidecar, compress,
+            })
+        },
+        Command::Sweep { exposures, steps, out } => {
+            sweep::run(exposures, steps, &out)
+        },
+        Command::Simulate { input, out, mode, depth, exposure, to_exposure,
+            gain, response, black } =>
+        {
+            simulate::run(simulate::SimArgs {
+                input: &input, output: &out, mode, depth, exposure,
+                to_exposure, gain, response: response.as_deref(), black,
+            })
+        },
+        Command::Bench { duration, mode, depth, exposure, queue_depth } => {
+            bench::run(bench::BenchArgs { duration, mode, depth, exposure, queue_depth })
+        },
+        Command::Selftest { frames } => selftest::run(frames),
+        Command::Regs { cmd: RegsCommand::Dump, log, start } => regs::dump(&log, start),
+        Command::Regs { cmd: RegsCommand::Write { reg, val, sys, .. }, log, start } => {
+            regs::write(&log, regs::RegisterArg { reg: &reg, sys }, val, start)
+        },
+        Command::Stream { format, out, mode, depth, exposure, gain, count, fps } => {
+            stream::run(stream::StreamArgs {
+                output: out.as_deref(), format, mode, depth, exposure, gain, count, fps,
+            })
+        },
+    };
+    if let Err(e) = res {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,197 @@
+//! Record a stream of frames to SER or `.tpraw` files, without the UI.
+//!
+//! Frames go into `<prefix>_NNN.<ext>` in the output directory. Recording
+//! stops after `--duration` (or on Ctrl-C, after which the current file is
+//! finished properly), and a new file is started whenever the current one
+//! reaches `--split-size` or `--split-duration`, which keeps files small
+//! enough for stacking software and limits what's lost if something fails.
+//! `.tpraw` frames can also be compressed with zstd (`--compress`).
+
+use crate::util::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, Instant };
+use toupcam::{ Camera, Frame };
+use toupcam::ser::{ self, SerWriter };
+use toupcam::sidecar::Sidecar;
+use toupcam::tpraw::{ TprawHeader, TprawWriter };
+
+/// File format for recordings.
+#[derive(Copy, Clone, Debug)]
+pub enum Container { Ser, Tpraw }
+impl Container {
+    fn extension(self) -> &'static str {
+        match self { Self::Ser => "ser", Self::Tpraw => "tpraw" }
+    }
+}
+
+/// Parse a container format (`ser` or `tpraw`).
+pub fn parse_container(s: &str) -> Result<Container, String> {
+    match s {
+        "ser" => Ok(Container::Ser),
+        "tpraw" | "raw" => Ok(Container::Tpraw),
+        _ => Err(format!("unknown container '{}' (expected ser or tpraw)", s)),
+    }
+}
+
+/// Options for a recording.
+pub struct RecordArgs<'a> {
+    pub out: &'a Path,
+    pub container: Container,
+    pub mode: toupcam::CameraMode,
+    pub depth: toupcam::BitDepth,
+    pub exposure: Option<Duration>,
+    pub gain: Option<u16>,
+    /// Stop recording after this long
+    pub duration: Option<Duration>,
+    /// Start a new file once the current one is this big (in bytes)
+    pub split_size: Option<u64>,
+    /// Start a new file once the current one covers this long
+    pub split_duration: Option<Duration>,
+    /// File names start with this
+    pub prefix: &'a str,
+    /// Write a JSON sidecar next to each file
+    pub sidecar: bool,
+    /// zstd compression level for `.tpraw` frames
+    pub compress: Option<i32>,
+}
+
+/// The file currently being written.
+enum Writer {
+    Ser(SerWriter<BufWriter<File>>),
+    Tpraw(TprawWriter<BufWriter<File>>),
+}
+
+/// A file being recorded, and what's needed to finish it.
+struct Segment {
+    writer: Writer,
+    path: PathBuf,
+    started: Instant,
+    /// Bytes written so far (for `--split-size`)
+    len: u64,
+    /// First frame in the file (for the sidecar)
+    first: Option<Sidecar>,
+}
+impl Segment {
+    fn create(cam: &Camera, path: PathBuf, container: Container, compress: Option<i32>)
+        -> Result<Self, Error>
+    {
+        let header = TprawHeader::for_camera(cam);
+        let (writer, len) = match container {
+            Container::Ser => {
+                let w = SerWriter::create(&path, header.width, header.height, header.bpp,
+                    header.cfa)?;
+                (Writer::Ser(w), ser::HEADER_LEN)
+            },
+            Container::Tpraw => {
+                let mut w = TprawWriter::create_with_header(&path, &header)?;
+                w.set_compression(compress);
+                let len = w.len();
+                (Writer::Tpraw(w), len)
+            },
+        };
+        Ok(Self { writer, path, started: Instant::now(), len, first: None })
+    }
+
+    fn write(&mut self, cam: &Camera, frame: &Frame, sidecar: bool) -> Result<(), Error> {
+        match &mut self.writer {
+            Writer::Ser(w) => {
+                w.write_frame(frame)?;
+                // Frame data plus its timestamp in the trailer
+                self.len += frame.info().len() as u64 + 8;
+            },
+            Writer::Tpraw(w) => {
+                w.write_frame(frame)?;
+                self.len = w.len();
+            },
+        }
+        if sidecar && self.first.is_none() {
+            self.first = Some(cam.sidecar(&frame.info()));
+        }
+        Ok(())
+    }
+
+    fn frames(&self) -> u64 {
+        match &self.writer {
+            Writer::Ser(w) => w.frames(),
+            Writer::Tpraw(w) => w.frames(),
+        }
+    }
+
+    /// Finish the file, returning the number of frames in it.
+    fn finish(self) -> Result<u64, Error> {
+        let frames = self.frames();
+        match self.writer {
+            Writer::Ser(w) => { w.finish()?; },
+            Writer::Tpraw(w) => { w.finish()?; },
+        }
+        if let Some(mut sidecar) = self.first {
+            sidecar.frames = frames;
+            sidecar.save_next_to(&self.path)?;
+        }
+        println!("{}: {} frame(s)", self.path.display(), frames);
+        Ok(frames)
+    }
+}
+
+pub fn run(args: RecordArgs) -> Result<(), Error> {
+    if args.compress.is_some() && matches!(args.container, Container::Ser) {
+        return Err(std::io::Error::other("SER files can't be compressed").into());
+    }
+    std::fs::create_dir_all(args.out)?;
+
+    let mut cam = Camera::open()?;
+    cam.set_mode(args.mode)?;
+    cam.set_depth(args.depth)?;
+    if let Some(exposure) = args.exposure { cam.set_exposure_time(exposure)?; }
+    if let Some(gain) = args.gain { cam.set_gain(gain)?; }
+
+    // Ctrl-C interrupts the read in progress; the loop below then finishes
+    // the current file before exiting.
+    let cancel = cam.cancel_token();
+    ctrlc::set_handler(move || cancel.cancel())
+        .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+
+    let path = |idx: usize| args.out.join(format!("{}_{:03}.{}", args.prefix, idx,
+        args.container.extension()));
+    let mut idx = 0;
+    let mut segment = Segment::create(&cam, path(idx), args.container, args.compress)?;
+    let mut total = 0;
+    cam.start_stream()?;
+    let start = Instant::now();
+    println!("recording to {} (Ctrl-C to stop)", args.out.display());
+
+    let res = loop {
+        if args.duration.is_some_and(|d| start.elapsed() >= d) { break Ok(()); }
+        let frame = match cam.read_frame() {
+            Ok(frame) => frame,
+            Err(toupcam::Error::FirstFrame) | Err(toupcam::Error::Desynchronized) => continue,
+            Err(toupcam::Error::Cancelled) => {
+                println!("interrupted");
+                break Ok(());
+            },
+            Err(e) => break Err(e.into()),
+        };
+        if !frame.complete { continue; }
+
+        let full = args.split_size.is_some_and(|max| segment.len + frame.info().len() as u64 > max)
+            || args.split_duration.is_some_and(|d| segment.started.elapsed() >= d);
+        if full && segment.frames() > 0 {
+            total += segment.finish()?;
+            idx += 1;
+            segment = Segment::create(&cam, path(idx), args.container, args.compress)?;
+        }
+        if let Err(e) = segment.write(&cam, &frame, args.sidecar) { break Err(e); }
+    };
+    // Finish the file first, so it's complete even if stopping fails
+    let finished = segment.finish().map(|frames| total += frames);
+    let stopped = cam.stop_stream().map_err(Error::from);
+    println!("{} frame(s) in {} file(s), {:.1}s", total, idx + 1,
+        start.elapsed().as_secs_f64());
+    // Report every failure, and return the first one
+    let mut errors = [res, finished, stopped].into_iter().filter_map(Result::err);
+    let first = errors.next();
+    for e in errors { eprintln!("also: {}", e); }
+    first.map_or(Ok(()), Err)
+}
@@ -0,0 +1,102 @@
+//! Inspect and poke device registers, for reverse-engineering sessions.
+//!
+//! `regs dump` lists the registers named in the protocol descriptor with the
+//! last value the driver wrote to them (no way of reading registers back
+//! from the device is known; see `toupcam::raw`), and `regs write` writes a
+//! single register. Every access is appended to a log file, so a session can
+//! be pieced together afterwards.
+
+use crate::util::Error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use toupcam::Camera;
+use toupcam::protocol::RegisterKind;
+
+/// A register given on the command line, by name or address.
+pub struct RegisterArg<'a> {
+    /// Register name from the protocol descriptor, or a hex address
+    pub reg: &'a str,
+    /// Treat a bare address as a system register (rather than a sensor one)
+    pub sys: bool,
+}
+
+fn kind_name(kind: RegisterKind) -> &'static str {
+    match kind { RegisterKind::Sensor => "sensor", RegisterKind::Sys => "sys" }
+}
+
+/// Parse a hex value, with or without a `0x` prefix.
+pub fn parse_hex(s: &str) -> Result<u16, String> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("bad hex value '{}'", s))
+}
+
+/// Open the camera with register accesses appended to `log`.
+fn open(log: &Path, start: bool) -> Result<Camera, Error> {
+    let mut f = OpenOptions::new().create(true).append(true).open(log)?;
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default().as_secs();
+    writeln!(f, "# toupcam-cli regs session, unix time {}", now)?;
+    let mut cam = Camera::open()?;
+    cam.set_register_log(Some(Box::new(f)));
+    if start {
+        // Runs the start/init scripts, which populates the shadow registers
+        cam.start_stream()?;
+    }
+    Ok(cam)
+}
+
+pub fn dump(log: &Path, start: bool) -> Result<(), Error> {
+    let mut cam = open(log, start)?;
+    let registers = cam.protocol().registers.clone();
+    println!("{:<16} {:<6} {:>6} {:>6}", "name", "kind", "addr", "value");
+    for reg in registers.iter() {
+        let val = cam.raw().shadow(reg.kind, reg.addr);
+        let val = val.map(|v| format!("{:04x}", v)).unwrap_or_else(|| "----".to_string());
+        println!("{:<16} {:<6} {:>6} {:>6}", reg.name, kind_name(reg.kind),
+            format!("{:04x}", reg.addr), val);
+    }
+    // Anything else the scripts wrote to
+    let others: Vec<_> = cam.raw().shadow_all().into_iter()
+        .filter(|(kind, addr, _)| {
+            !registers.iter().any(|r| r.kind == *kind && r.addr == *addr)
+        }).collect();
+    if !others.is_empty() {
+        println!();
+        println!("other registers written since opening:");
+        for (kind, addr, val) in others {
+            println!("{:<16} {:<6} {:>6} {:>6}", "", kind_name(kind),
+                format!("{:04x}", addr), format!("{:04x}", val));
+        }
+    }
+    if !start {
+        println!();
+        println!("(values are only known after writes; use --start to run the init scripts)");
+    }
+    println!("accesses logged to {}", log.display());
+    Ok(())
+}
+
+pub fn write(log: &Path, reg: RegisterArg, val: u16, start: bool) -> Result<(), Error> {
+    let mut cam = open(log, start)?;
+    let (kind, addr) = match cam.protocol().register(reg.reg) {
+        Some(r) => (r.kind, r.addr),
+        None => {
+            let addr = parse_hex(reg.reg).map_err(|e| Error::Io(std::io::Error::other(
+                format!("{} (and no register with that name)", e))))?;
+            (if reg.sys { RegisterKind::Sys } else { RegisterKind::Sensor }, addr)
+        },
+    };
+    let prev = cam.raw().shadow(kind, addr);
+    match kind {
+        RegisterKind::Sensor => cam.raw().sensor_write(addr, val)?,
+        RegisterKind::Sys => cam.raw().sys_write(addr, val)?,
+    }
+    match prev {
+        Some(prev) => println!("{} {:04x}: {:04x} -> {:04x}", kind_name(kind), addr, prev, val),
+        None => println!("{} {:04x}: -> {:04x}", kind_name(kind), addr, val),
+    }
+    println!("accesses logged to {}", log.display());
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! Check that the camera can be found, configured and streamed from.
+//!
+//! This runs through what any capture program does (open the device, run
+//! the initialization scripts, read frames, stop and restart the stream)
+//! and reports each step as passed or failed. It's meant for validating a
+//! new setup: cabling, hubs, and permissions on the device node.
+
+use crate::util::Error;
+use toupcam::{ Camera, Frame };
+
+/// Times to stop and restart the stream.
+const RESTARTS: usize = 2;
+/// Read attempts before giving up on getting a complete frame.
+const MAX_ATTEMPTS: usize = 10;
+
+/// Outcome of each step.
+struct Report { passed: usize, failed: usize }
+impl Report {
+    fn check(&mut self, name: &str, res: Result<String, String>) -> bool {
+        match res {
+            Ok(detail) => {
+                self.passed += 1;
+                println!("PASS  {:<24} {}", name, detail);
+                true
+            },
+            Err(detail) => {
+                self.failed += 1;
+                println!("FAIL  {:<24} {}", name, detail);
+                false
+            },
+        }
+    }
+}
+
+/// Describe an error, with a hint for the usual causes.
+fn describe(e: &toupcam::Error) -> String {
+    match e {
+        toupcam::Error::Rusb(rusb::Error::Access) => {
+            format!("{:?} (no permission to open the device; check udev rules)", e)
+        },
+        toupcam::Error::Rusb(rusb::Error::Busy) => {
+            format!("{:?} (another program has the device open)", e)
+        },
+        toupcam::Error::Rusb(rusb::Error::Timeout) => {
+            format!("{:?} (no data; check the cable and USB port)", e)
+        },
+        e => format!("{:?}", e),
+    }
+}
+
+/// Read the next complete frame.
+fn grab(cam: &mut Camera) -> Result<Frame, String> {
+    let mut last = String::new();
+    for _ in 0..MAX_ATTEMPTS {
+        match cam.read_frame() {
+            Ok(frame) if frame.complete => return Ok(frame),
+            Ok(_) => last = "truncated frame".to_string(),
+            Err(toupcam::Error::FirstFrame) => last = "truncated frame".to_string(),
+            Err(toupcam::Error::Desynchronized) => last = "lost frame boundaries".to_string(),
+            Err(e) => return Err(describe(&e)),
+        }
+    }
+    Err(format!("no complete frame in {} reads (last: {})", MAX_ATTEMPTS, last))
+}
+
+/// Check the size and contents of a frame.
+fn check_frame(cam: &Camera, frame: &Frame) -> Result<String, String> {
+    let (w, h) = cam.get_mode().dimensions();
+    if (frame.width, frame.height) != (w, h) {
+        return Err(format!("got {}x{}, expected {}x{}", frame.width, frame.height, w, h));
+    }
+    if frame.data.len() < cam.frame_len() {
+        return Err(format!("got {} bytes, expected {}", frame.data.len(), cam.frame_len()));
+    }
+    let (min, max) = (0..w * h).map(|idx| frame.sample(idx))
+        .fold((u16::MAX, 0), |(lo, hi), v| (lo.min(v), hi.max(v)));
+    let mean = crate::util::frame_mean(frame);
+    let full = frame.max_value();
+    let detail = format!("{}x{}, min {} max {} mean {:.1}", w, h, min, max, mean);
+    if max == 0 {
+        Err(format!("{} (all zero)", detail))
+    } else if min == full {
+        Err(format!("{} (all saturated)", detail))
+    } else {
+        Ok(detail)
+    }
+}
+
+pub fn run(frames: usize) -> Result<(), Error> {
+    let mut report = Report { passed: 0, failed: 0 };
+    let mut cam = match open(&mut report) {
+        Some(cam) => cam,
+        None => return finish(report),
+    };
+
+    let res = cam.start_stream().map(|_| "initialization scripts ran".to_string());
+    if report.check("start stream", res.map_err(|e| describe(&e))) {
+        for idx in 0..frames {
+            let res = grab(&mut cam).and_then(|f| check_frame(&cam, &f));
+            report.check(&format!("frame {}", idx), res);
+        }
+        let stats = cam.stream_stats();
+        report.check("stream statistics", Ok(format!("{:.1} fps, {:.1} MB/s, {} truncated",
+            stats.fps(), stats.throughput() / 1e6, stats.truncated)));
+
+        for cycle in 0..RESTARTS {
+            let res = cam.stop_stream().map_err(|e| describe(&e))
+                .and_then(|_| cam.start_stream().map_err(|e| describe(&e)))
+                .and_then(|_| grab(&mut cam))
+                .and_then(|f| check_frame(&cam, &f));
+            report.check(&format!("stop/start {}", cycle + 1), res);
+        }
+    }
+    let res = cam.stop_stream().map(|_| String::new());
+    report.check("stop stream", res.map_err(|e| describe(&e)));
+    finish(report)
+}
+
+/// Find and open the camera.
+fn open(report: &mut Report) -> Option<Camera> {
+    let res = match Camera::list() {
+        Ok(devices) if devices.is_empty() => Err("no camera found".to_string()),
+        Ok(devices) => Ok(format!("{} camera(s)", devices.len())),
+        Err(e) => Err(describe(&e)),
+    };
+    if !report.check("find device", res) { return None; }
+    match Camera::open() {
+        Ok(cam) => {
+            let caps = cam.capabilities();
+            report.check("open device", Ok(caps.model.to_string()));
+            Some(cam)
+        },
+        Err(e) => {
+            report.check("open device", Err(describe(&e)));
+            None
+        },
+    }
+}
+
+fn finish(report: Report) -> Result<(), Error> {
+    println!();
+    println!("{} passed, {} failed", report.passed, report.failed);
+    if report.failed > 0 {
+        return Err(Error::Io(std::io::Error::other("self-test failed")));
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! Simulate how a recorded raw frame would look at a different exposure.
+//!
+//! The sensor response is modelled as `value = black + k * exposure * gain`,
+//! where the black level comes from the fit over a `sweep` run (or is given
+//! explicitly). Each pixel is scaled above the black level and clipped at
+//! full-scale.
+//!
+//! This only simulates the *signal*. Noise in the source frame is scaled
+//! along with it, so brighter simulations look noisier than reality, and
+//! darker simulations look cleaner than reality (read noise isn't modelled).
+
+use crate::util::{ self, Error };
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Options for a simulation run.
+pub struct SimArgs<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    pub mode: toupcam::CameraMode,
+    pub depth: toupcam::BitDepth,
+    pub exposure: Duration,
+    pub to_exposure: Duration,
+    pub gain: f64,
+    pub response: Option<&'a Path>,
+    pub black: Option<f64>,
+}
+
+pub fn run(args: SimArgs) -> Result<(), Error> {
+    let (width, height) = args.mode.dimensions();
+    let bpp = match args.depth {
+        toupcam::BitDepth::BitDepth12 => 2,
+        toupcam::BitDepth::BitDepth8  => 1,
+    };
+    let full_scale = toupcam::Frame::max_value_for(bpp);
+
+    let data = std::fs::read(args.input)?;
+    if data.len() != width * height * bpp {
+        return Err(Error::Io(std::io::Error::other(format!(
+            "{} is {} bytes, expected {} for {:?}/{:?}", args.input.display(),
+            data.len(), width * height * bpp, args.mode, args.depth))));
+    }
+
+    // Black level from an explicit value, or the offset of the sweep fit
+    let black = match (args.black, args.response) {
+        (Some(b), _) => b,
+        (None, Some(path)) => {
+            let points: Vec<_> = util::read_sweep_csv(path)?.into_iter()
+                .filter(|(_, m)| *m < full_scale as f64 * 0.9).collect();
+            if points.len() < 2 {
+                return Err(Error::Io(std::io::Error::other(
+                    "not enough unsaturated points in the response file")));
+            }
+            let (_, offset, r2) = util::fit_linear(&points);
+            println!("response fit: black={:.2} r^2={:.6}", offset, r2);
+            offset.max(0.0)
+        },
+        (None, None) => 0.0,
+    };
+
+    let scale = (args.to_exposure.as_secs_f64() / args.exposure.as_secs_f64())
+        * args.gain;
+
+    let mut out = Vec::with_capacity(data.len());
+    let (mut src_clipped, mut dst_clipped) = (0usize, 0usize);
+    for idx in 0..width * height {
+        let v = toupcam::Frame::sample_at(&data, bpp, idx);
+        if v >= full_scale { src_clipped += 1; }
+        let sim = black + (v as f64 - black).max(0.0) * scale;
+        let sim = if sim >= full_scale as f64 {
+            dst_clipped += 1;
+            full_scale
+        } else {
+            sim.round() as u16
+        };
+        match bpp {
+            2 => out.extend_from_slice(&sim.to_le_bytes()),
+            _ => out.push(sim as u8),
+        }
+    }
+    File::create(args.output)?.write_all(&out)?;
+
+    let pixels = (width * height) as f64;
+    println!("scale={:.3} -> {}", scale, args.output.display());
+    println!("clipped: source {:.3}%, simulated {:.3}%",
+        100.0 * src_clipped as f64 / pixels, 100.0 * dst_clipped as f64 / pixels);
+    if src_clipped > 0 && scale < 1.0 {
+        println!("warning: source has clipped pixels; their true values are \
+            unknown, so they're underestimated in the simulation");
+    }
+    if scale > 1.0 {
+        println!("note: noise is overestimated by ~{:.2}x (shot noise grows \
+            with sqrt(signal))", scale.sqrt());
+    } else if scale < 1.0 {
+        println!("note: read noise isn't modelled; the real frame will be \
+            noisier than the simulation");
+    }
+    Ok(())
+}
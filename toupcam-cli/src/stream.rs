@@ -0,0 +1,71 @@
+//! Stream frames to stdout (or a named pipe) for other programs.
+//!
+//! With `--format y4m`, the output can go straight into a player or encoder
+//! (i.e. `toupcam-cli stream | mpv -`, or `| ffmpeg -i - out.mp4`). With
+//! `--format raw`, each frame is the raw mosaic behind a small header (see
+//! `toupcam::pipe`). Progress goes to stderr so it stays out of the stream.
+
+use crate::util::Error;
+use std::io::{ ErrorKind, Write };
+use std::path::Path;
+use std::time::Duration;
+use toupcam::pipe::{ PipeFormat, PipeWriter };
+use toupcam::source::FrameSource;
+
+/// Options for a streaming run.
+pub struct StreamArgs<'a> {
+    /// Named pipe (or file) to write to, instead of stdout
+    pub output: Option<&'a Path>,
+    pub format: PipeFormat,
+    pub mode: toupcam::CameraMode,
+    pub depth: toupcam::BitDepth,
+    pub exposure: Option<Duration>,
+    pub gain: Option<u16>,
+    /// Stop after this many frames
+    pub count: Option<u64>,
+    /// Frame rate given to players (y4m only)
+    pub fps: f64,
+}
+
+pub fn run(args: StreamArgs) -> Result<(), Error> {
+    let mut cam = toupcam::Camera::open()?;
+    cam.set_mode(args.mode)?;
+    cam.set_depth(args.depth)?;
+    if let Some(exposure) = args.exposure { cam.set_exposure_time(exposure)?; }
+    if let Some(gain) = args.gain { cam.set_gain(gain)?; }
+
+    let res = match args.output {
+        Some(path) => {
+            eprintln!("waiting for a reader on {}", path.display());
+            let w = PipeWriter::create(path, args.format)?.with_frame_rate(args.fps);
+            stream(&mut cam, w, args.count)
+        },
+        None => {
+            let w = PipeWriter::stdout(args.format).with_frame_rate(args.fps);
+            stream(&mut cam, w, args.count)
+        },
+    };
+    cam.stop_stream()?;
+    res
+}
+
+/// Write frames until `count` is reached or the reader goes away.
+fn stream<W: Write>(cam: &mut toupcam::Camera, mut w: PipeWriter<W>, count: Option<u64>)
+    -> Result<(), Error>
+{
+    while count.is_none_or(|count| w.frames() < count) {
+        let Some(frame) = cam.next_frame()? else { break; };
+        if !frame.complete { continue; }
+        match w.write_frame(&frame) {
+            Ok(()) => {},
+            // The reader closing the pipe is the usual way to stop
+            Err(toupcam::Error::Io(e)) if e.kind() == ErrorKind::BrokenPipe => break,
+            Err(e) => return Err(e.into()),
+        }
+        if w.frames().is_multiple_of(30) {
+            eprintln!("{} frames", w.frames());
+        }
+    }
+    eprintln!("{} frames written", w.frames());
+    Ok(())
+}
@@ -0,0 +1,88 @@
+//! Capture a single frame over a range of exposure times.
+//!
+//! This is mostly useful for checking the exposure model in the library:
+//! the mean brightness should scale linearly with exposure time until the
+//! sensor starts to saturate.
+
+use crate::util::{ self, Error, DurationRange };
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Ignore points brighter than this fraction of full-scale when fitting.
+const SATURATION_CUTOFF: f64 = 0.9;
+
+/// Log-spaced exposure times across the range.
+fn exposure_steps(range: DurationRange, steps: usize) -> Vec<Duration> {
+    let (a, b) = (range.start.as_secs_f64(), range.end.as_secs_f64());
+    if steps < 2 || a == b { return vec![range.start]; }
+    let ratio = (b / a).powf(1.0 / (steps - 1) as f64);
+    (0..steps).map(|i| Duration::from_secs_f64(a * ratio.powi(i as i32)))
+        .collect()
+}
+
+pub fn run(range: DurationRange, steps: usize, out: &Path) -> Result<(), Error> {
+    std::fs::create_dir_all(out)?;
+
+    let mut cam = toupcam::Camera::open()?;
+    let full_scale = match cam.get_depth() {
+        toupcam::BitDepth::BitDepth12 => 4095.0,
+        toupcam::BitDepth::BitDepth8  => 255.0,
+    };
+    cam.start_stream()?;
+
+    let mut points = Vec::new();
+    for (idx, exposure) in exposure_steps(range, steps).into_iter().enumerate() {
+        // One at a time, so that we aren't holding on to every frame
+        let us = exposure.as_micros();
+        let frame = cam.capture_bracket(&[us as u64])?.remove(0);
+
+        let fname = out.join(format!("sweep_{:03}_{}us.raw", idx, us));
+        File::create(&fname)?.write_all(&frame.data)?;
+
+        let mean = util::frame_mean(&frame);
+        println!("[{:03}] {:>9}us mean={:8.2} -> {}", idx, us, mean,
+            fname.display());
+        points.push((exposure.as_secs_f64(), mean));
+    }
+    cam.stop_stream()?;
+
+    report(&points, full_scale, &mut std::io::stdout())?;
+    let mut csv = File::create(out.join("sweep.csv"))?;
+    writeln!(csv, "exposure_us,mean")?;
+    for (t, mean) in points.iter() {
+        writeln!(csv, "{:.0},{:.3}", t * 1e6, mean)?;
+    }
+    Ok(())
+}
+
+/// Fit `mean = slope * t + offset` over unsaturated points and print the
+/// residuals for each step.
+fn report(points: &[(f64, f64)], full_scale: f64, w: &mut impl Write)
+    -> std::io::Result<()>
+{
+    let fit: Vec<(f64, f64)> = points.iter().copied()
+        .filter(|(_, m)| *m < full_scale * SATURATION_CUTOFF)
+        .collect();
+    writeln!(w, "linearity report ({} of {} points unsaturated)",
+        fit.len(), points.len())?;
+    if fit.len() < 2 {
+        writeln!(w, "not enough unsaturated points to fit")?;
+        return Ok(());
+    }
+
+    let (slope, offset, r2) = util::fit_linear(&fit);
+
+    writeln!(w, "slope={:.3}/ms offset={:.3} r^2={:.6}",
+        slope / 1000.0, offset, r2)?;
+    for (t, m) in points.iter() {
+        let predicted = slope * t + offset;
+        let err = if predicted != 0.0 { (m - predicted) / predicted } else { 0.0 };
+        let flag = if *m >= full_scale * SATURATION_CUTOFF { " (saturated)" }
+            else { "" };
+        writeln!(w, "{:>9.0}us mean={:8.2} fit={:8.2} err={:+6.2}%{}",
+            t * 1e6, m, predicted, err * 100.0, flag)?;
+    }
+    Ok(())
+}
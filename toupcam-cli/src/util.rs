@@ -0,0 +1,98 @@
+//! Helpers shared between subcommands.
+
+use std::time::Duration;
+
+/// Errors surfaced by subcommands.
+#[derive(Debug)]
+pub enum Error {
+    Camera(toupcam::Error),
+    Io(std::io::Error),
+}
+impl From<toupcam::Error> for Error {
+    fn from(e: toupcam::Error) -> Self { Self::Camera(e) }
+}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Self::Io(e) }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Camera(e) => write!(f, "camera: {:?}", e),
+            Self::Io(e) => write!(f, "i/o: {}", e),
+        }
+    }
+}
+
+/// An inclusive range of durations.
+#[derive(Copy, Clone, Debug)]
+pub struct DurationRange { pub start: Duration, pub end: Duration }
+
+/// Parse a range of durations (i.e. `1ms..1s`).
+pub fn parse_range(s: &str) -> Result<DurationRange, String> {
+    let (a, b) = s.split_once("..")
+        .ok_or_else(|| format!("expected '<start>..<end>', got '{}'", s))?;
+    let (start, end) = (toupcam::parse::duration(a)?, toupcam::parse::duration(b)?);
+    if start > end {
+        return Err(format!("empty range '{}'", s));
+    }
+    Ok(DurationRange { start, end })
+}
+
+/// Mean pixel value of a frame.
+pub fn frame_mean(frame: &toupcam::Frame) -> f64 {
+    let len = frame.data.len() / frame.bpp.max(1);
+    let sum: u64 = (0..len).map(|idx| frame.sample(idx) as u64).sum();
+    sum as f64 / (frame.width * frame.height) as f64
+}
+
+/// Least-squares fit of `y = slope * x + offset`.
+///
+/// Returns `(slope, offset, r^2)`.
+pub fn fit_linear(points: &[(f64, f64)]) -> (f64, f64, f64) {
+    let n = points.len() as f64;
+    let mx = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let my = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx: f64 = points.iter().map(|(x, _)| (x - mx).powi(2)).sum();
+    let sxy: f64 = points.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+    let syy: f64 = points.iter().map(|(_, y)| (y - my).powi(2)).sum();
+    let slope  = if sxx > 0.0 { sxy / sxx } else { 0.0 };
+    let offset = my - slope * mx;
+    let r2 = if sxx > 0.0 && syy > 0.0 { (sxy * sxy) / (sxx * syy) } else { 1.0 };
+    (slope, offset, r2)
+}
+
+/// Parse a size in bytes, with an optional `K`, `M` or `G` suffix (powers
+/// of 1024, i.e. `500M`).
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (num, scale) = match s.char_indices().last() {
+        Some((idx, 'K' | 'k')) => (&s[..idx], 1 << 10),
+        Some((idx, 'M' | 'm')) => (&s[..idx], 1 << 20),
+        Some((idx, 'G' | 'g')) => (&s[..idx], 1 << 30),
+        _ => (s, 1),
+    };
+    let num: f64 = num.parse().map_err(|_| format!("bad size '{}'", s))?;
+    Ok((num * scale as f64) as u64)
+}
+
+/// Parse a streaming format (`y4m` or `raw`).
+pub fn parse_pipe_format(s: &str) -> Result<toupcam::pipe::PipeFormat, String> {
+    match s {
+        "y4m" => Ok(toupcam::pipe::PipeFormat::Y4m),
+        "raw" => Ok(toupcam::pipe::PipeFormat::Raw),
+        _ => Err(format!("unknown format '{}' (expected y4m or raw)", s)),
+    }
+}
+
+/// Read the `exposure_us,mean` points written by the `sweep` command.
+pub fn read_sweep_csv(path: &std::path::Path) -> Result<Vec<(f64, f64)>, Error> {
+    let text = std::fs::read_to_string(path)?;
+    let mut points = Vec::new();
+    for line in text.lines().skip(1) {
+        let mut it = line.split(',').map(|x| x.trim().parse::<f64>());
+        if let (Some(Ok(us)), Some(Ok(mean))) = (it.next(), it.next()) {
+            points.push((us * 1e-6, mean));
+        }
+    }
+    Ok(points)
+}
@@ -0,0 +1,54 @@
+//! Command-line options.
+//!
+//! Camera settings and the output directory given here override the ones
+//! in the profile (and are saved with it on exit).
+
+use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
+use toupcam::{ BitDepth, CameraMode };
+
+#[derive(Parser)]
+#[command(about = "Live preview for the MU1603 camera")]
+pub struct Args {
+    /// Settings profile, loaded at startup and saved on exit
+    #[arg(long, default_value = "default")]
+    pub profile: String,
+    /// USB serial number of a camera to open (the first one found
+    /// otherwise). Given more than once, the cameras are shown together
+    #[arg(long, conflicts_with = "all")]
+    pub serial: Vec<String>,
+    /// Open every connected camera
+    #[arg(long)]
+    pub all: bool,
+    /// Sensor mode (0, 1 or 2)
+    #[arg(long)]
+    pub mode: Option<CameraMode>,
+    /// Bit depth (8 or 12)
+    #[arg(long)]
+    pub depth: Option<BitDepth>,
+    /// Exposure time (i.e. `50ms`)
+    #[arg(long, value_parser = toupcam::parse::duration)]
+    pub exposure: Option<Duration>,
+    /// Raw analog gain, within the range from `info` (i.e. `0x610c`)
+    #[arg(long, value_parser = toupcam::parse::gain)]
+    pub gain: Option<u16>,
+    /// Directory for snapshots
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+    /// Initial window size (i.e. `1280x960`)
+    #[arg(long, value_parser = parse_size)]
+    pub window: Option<(u32, u32)>,
+    /// Start in fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+}
+
+/// Parse a window size (`<width>x<height>`).
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let bad = || format!("expected '<width>x<height>', got '{}'", s);
+    let (w, h) = s.split_once('x').ok_or_else(bad)?;
+    let (w, h): (u32, u32) = (w.parse().map_err(|_| bad())?, h.parse().map_err(|_| bad())?);
+    if w == 0 || h == 0 { return Err(bad()); }
+    Ok((w, h))
+}
@@ -0,0 +1,183 @@
+//! Capturing master darks and flats from the preview (the `C` key), and
+//! applying them to it (the `K` key).
+//!
+//! The wizard asks for the lens to be capped, averages a few frames into a
+//! master dark, then asks for an evenly lit field and does the same for a
+//! master flat (after subtracting the new dark). Either step can be
+//! skipped with `Esc`. Masters are saved with [toupcam::calibration] as
+//! `master_dark.cal` and `master_flat.cal` in the output directory, and
+//! loaded from there at startup.
+//!
+//! A master dark only fits frames taken with the same exposure time and
+//! gain, so it needs to be captured again after changing them.
+
+use crate::controls::Settings;
+use egui::{ Align2, Color32, Painter, Pos2, Rect };
+use std::path::{ Path, PathBuf };
+use toupcam::Frame;
+use toupcam::calibration::{ MasterDark, MasterFlat };
+
+/// Number of frames averaged into each master.
+const FRAMES: usize = 10;
+/// Frames ignored after confirming a step, which may have been read
+/// before the lens was capped (or uncapped).
+const SETTLE: usize = 2;
+
+const DARK_FILE: &str = "master_dark.cal";
+const FLAT_FILE: &str = "master_flat.cal";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind { Dark, Flat }
+impl Kind {
+    fn name(self) -> &'static str {
+        match self { Self::Dark => "dark", Self::Flat => "flat" }
+    }
+}
+
+enum Step {
+    Idle,
+    /// Waiting for `Enter`
+    Prompt(Kind),
+    Capturing { kind: Kind, skip: usize, frames: Vec<Frame> },
+}
+
+pub struct Wizard {
+    step: Step,
+    dir: PathBuf,
+    dark: Option<MasterDark>,
+    flat: Option<MasterFlat>,
+    /// Apply the masters to the preview
+    pub enabled: bool,
+}
+impl Wizard {
+    /// Load any masters saved in `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let dark = load(&dir.join(DARK_FILE), |p| MasterDark::load(p));
+        let flat = load(&dir.join(FLAT_FILE), |p| MasterFlat::load(p));
+        Self { step: Step::Idle, dir, dark, flat, enabled: false }
+    }
+
+    pub fn dark(&self) -> Option<&MasterDark> { self.dark.as_ref() }
+    pub fn flat(&self) -> Option<&MasterFlat> { self.flat.as_ref() }
+
+    /// Returns 'true' while the wizard is waiting for a key or capturing.
+    pub fn is_active(&self) -> bool { !matches!(self.step, Step::Idle) }
+
+    /// Start with the dark.
+    pub fn start(&mut self) {
+        self.step = Step::Prompt(Kind::Dark);
+    }
+
+    /// `Enter`: start capturing.
+    pub fn confirm(&mut self) {
+        if let Step::Prompt(kind) = self.step {
+            self.step = Step::Capturing { kind, skip: SETTLE, frames: Vec::new() };
+        }
+    }
+
+    /// `Esc`: skip the current step.
+    pub fn skip(&mut self) {
+        self.step = match self.step {
+            Step::Prompt(Kind::Dark) | Step::Capturing { kind: Kind::Dark, .. } => {
+                Step::Prompt(Kind::Flat)
+            },
+            _ => Step::Idle,
+        };
+    }
+
+    /// Add a frame while capturing. Once a master is done, it's saved and a
+    /// message is returned: `Ok` if the master was replaced, `Err` if not.
+    pub fn push(&mut self, frame: &Frame, settings: &Settings) -> Option<Result<String, String>> {
+        let Step::Capturing { kind, skip, frames } = &mut self.step else { return None; };
+        let kind = *kind;
+        if !frame.complete { return None; }
+        if *skip > 0 {
+            *skip -= 1;
+            return None;
+        }
+        frames.push(Frame::from_info(frame.data.to_vec(), &frame.info()));
+        if frames.len() < FRAMES { return None; }
+
+        let mut frames = std::mem::take(frames);
+        let res = match kind {
+            Kind::Dark => self.finish_dark(&frames, settings),
+            Kind::Flat => self.finish_flat(&mut frames, settings),
+        };
+        self.step = match kind {
+            Kind::Dark => Step::Prompt(Kind::Flat),
+            Kind::Flat => Step::Idle,
+        };
+        if res.is_ok() { self.enabled = true; }
+        Some(res.map_err(|e| format!("Couldn't make the master {}: {}", kind.name(), e)))
+    }
+
+    fn finish_dark(&mut self, frames: &[Frame], settings: &Settings) -> Result<String, String> {
+        let dark = MasterDark::from_frames(frames, settings.exposure, settings.gain)
+            .map_err(|e| format!("{:?}", e))?;
+        let path = self.dir.join(DARK_FILE);
+        save(&self.dir, |dir| dark.save(dir.join(DARK_FILE)))?;
+        self.dark = Some(dark);
+        Ok(format!("Saved {}", path.display()))
+    }
+
+    fn finish_flat(&mut self, frames: &mut [Frame], settings: &Settings)
+        -> Result<String, String>
+    {
+        if let Some(dark) = self.dark.as_ref() {
+            for frame in frames.iter_mut() {
+                // A dark for another mode or bit depth doesn't apply
+                if dark.subtract(frame).is_err() { break; }
+            }
+        }
+        let flat = MasterFlat::from_frames(frames, settings.exposure, settings.gain)
+            .map_err(|e| format!("{:?}", e))?;
+        let path = self.dir.join(FLAT_FILE);
+        save(&self.dir, |dir| flat.save(dir.join(FLAT_FILE)))?;
+        self.flat = Some(flat);
+        Ok(format!("Saved {}", path.display()))
+    }
+
+    /// Draw the current step in the middle of `area` (the preview).
+    pub fn draw(&self, painter: &Painter, area: Rect) {
+        let lines: Vec<String> = match &self.step {
+            Step::Idle => return,
+            Step::Prompt(Kind::Dark) => vec![
+                "MASTER DARK".into(),
+                "Cap the lens, then press Enter".into(),
+                "Esc skips to the flat".into(),
+            ],
+            Step::Prompt(Kind::Flat) => vec![
+                "MASTER FLAT".into(),
+                "Point at an evenly lit field,".into(),
+                "about half of full scale,".into(),
+                "then press Enter".into(),
+                "Esc cancels".into(),
+            ],
+            Step::Capturing { kind, frames, .. } => vec![
+                format!("Capturing {} {}/{}", kind.name(), frames.len(), FRAMES),
+            ],
+        };
+        let pos = Pos2::new(area.center().x, area.center().y - 40.0);
+        crate::gui::text_box(painter, pos, Align2::CENTER_TOP, Color32::from_rgb(255, 255, 128),
+            &lines);
+    }
+}
+
+/// Load a master if there's one at `path`.
+fn load<T>(path: &Path, f: impl FnOnce(&Path) -> Result<T, toupcam::Error>) -> Option<T> {
+    if !path.exists() { return None; }
+    match f(path) {
+        Ok(master) => Some(master),
+        Err(e) => {
+            println!("couldn't load {}: {:?}", path.display(), e);
+            None
+        },
+    }
+}
+
+/// Save into `dir`, creating it if needed.
+fn save(dir: &Path, f: impl FnOnce(&Path) -> Result<(), toupcam::Error>) -> Result<(), String> {
+    std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    f(dir).map_err(|e| format!("{:?}", e))
+}
@@ -0,0 +1,193 @@
+//! Getting the preview back after the camera goes away (the `R` key).
+//!
+//! When the camera thread stops (the camera was unplugged, or a transfer
+//! failed), the [Connection] takes the camera back from it, shows a banner,
+//! and watches for the camera to be plugged in again (see
+//! [toupcam::hotplug]). Reconnecting reopens the camera, puts back the
+//! settings from the UI, and starts a fresh camera thread and worker.
+//! It happens by itself when the camera shows up again, or with `R`
+//! (i.e. where hotplug isn't available).
+
+use crate::controls::Settings;
+use crate::worker::{ Options, Processed, Worker };
+use egui::{ Align2, Color32, Painter, Pos2, Rect };
+use toupcam::{ Camera, Error };
+use toupcam::calibration::{ MasterDark, MasterFlat };
+use toupcam::demosaic::RgbImage;
+use toupcam::hotplug::{ HotplugEvent, HotplugMonitor };
+use toupcam::stream::{ Backpressure, Control, StreamConfig, StreamHandle };
+
+enum State {
+    Streaming { stream: StreamHandle, worker: Worker },
+    /// The camera thread has stopped. The camera is kept to be reopened
+    /// where it was, unless that already failed.
+    Stopped(Option<Box<Camera>>),
+}
+
+pub struct Connection {
+    state: State,
+    /// Serial number to look for when the camera has to be opened again
+    serial: Option<String>,
+    /// What the worker does with frames (kept for the next worker)
+    options: Options,
+    /// Why the camera thread stopped
+    error: Option<String>,
+    /// Watches for the camera while it's disconnected
+    hotplug: Option<HotplugMonitor>,
+}
+impl Connection {
+    /// Start streaming from `cam`.
+    pub fn start(cam: Camera, serial: Option<String>, options: Options) -> Self {
+        Self { state: spawn(cam, options), serial, options, error: None, hotplug: None }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, State::Streaming { .. })
+    }
+
+    /// The next finished frame, if there is one. On an error, the camera
+    /// thread has stopped and the connection is now disconnected.
+    pub fn try_recv(&mut self) -> Option<Result<Processed, Error>> {
+        let State::Streaming { worker, .. } = &self.state else { return None; };
+        let res = worker.try_recv();
+        if let Some(Err(e)) = &res { self.stopped(e); }
+        res
+    }
+
+    /// Take the camera back from the (stopped) camera thread.
+    fn stopped(&mut self, e: &Error) {
+        let state = std::mem::replace(&mut self.state, State::Stopped(None));
+        if let State::Streaming { stream, worker } = state {
+            let cam = stream.stop();
+            worker.join();
+            self.state = State::Stopped(Some(Box::new(cam)));
+        }
+        self.error = Some(format!("{:?}", e));
+        self.hotplug = match HotplugMonitor::new(false) {
+            Ok(monitor) => Some(monitor),
+            Err(e) => {
+                println!("can't watch for the camera ({:?}), press R to reconnect", e);
+                None
+            },
+        };
+    }
+
+    /// Returns 'true' if a camera was plugged in since the last call.
+    pub fn arrived(&self) -> bool {
+        let Some(hotplug) = self.hotplug.as_ref() else { return false; };
+        let mut arrived = false;
+        while let Some(event) = hotplug.try_next() {
+            arrived |= matches!(event, HotplugEvent::Arrived { .. });
+        }
+        arrived
+    }
+
+    /// Reopen the camera with the current `settings`, and start streaming
+    /// again. The masters have to be sent to the new worker afterwards.
+    pub fn reconnect(&mut self, settings: &Settings) -> Result<(), String> {
+        let State::Stopped(cam) = &mut self.state else { return Ok(()); };
+        let mut cam = match cam.take() {
+            Some(mut cam) => match cam.reconnect() {
+                Ok(()) => *cam,
+                // Moved to another port, or still wedged: close it, and
+                // look for the camera again
+                Err(_) => { drop(cam); self.open()? },
+            },
+            None => self.open()?,
+        };
+        if let Err(e) = restore(&mut cam, settings) {
+            println!("couldn't restore the settings: {:?}", e);
+        }
+        self.state = spawn(cam, self.options);
+        self.error = None;
+        self.hotplug = None;
+        Ok(())
+    }
+
+    fn open(&self) -> Result<Camera, String> {
+        match self.serial.as_deref() {
+            Some(serial) => Camera::open_serial(serial),
+            None => Camera::open(),
+        }.map_err(|e| format!("{:?}", e))
+    }
+
+    /// Send a change to the camera thread. While disconnected, changes are
+    /// only kept in the [Settings], and applied when reconnecting.
+    pub fn control(&self, control: Control) {
+        if let State::Streaming { stream, .. } = &self.state { stream.control(control); }
+    }
+
+    /// Frames dropped by the camera thread (since it was started).
+    pub fn dropped(&self) -> u64 {
+        match &self.state {
+            State::Streaming { stream, .. } => stream.dropped(),
+            State::Stopped(_) => 0,
+        }
+    }
+
+    pub fn set_options(&mut self, options: Options) {
+        self.options = options;
+        if let State::Streaming { worker, .. } = &self.state { worker.set_options(options); }
+    }
+
+    pub fn set_calibration(&self, dark: Option<MasterDark>, flat: Option<MasterFlat>) {
+        if let State::Streaming { worker, .. } = &self.state {
+            worker.set_calibration(dark, flat);
+        }
+    }
+
+    pub fn recycle(&self, image: RgbImage) {
+        if let State::Streaming { worker, .. } = &self.state { worker.recycle(image); }
+    }
+
+    /// Stop the camera thread and the worker.
+    pub fn stop(self) {
+        if let State::Streaming { stream, worker } = self.state {
+            drop(stream.stop());
+            worker.join();
+        }
+    }
+
+    /// Draw a banner over `area` (the preview) while disconnected.
+    pub fn draw(&self, painter: &Painter, area: Rect) {
+        let Some(error) = self.error.as_ref() else { return; };
+        let lines = [
+            "CAMERA DISCONNECTED".to_string(),
+            error.clone(),
+            if self.hotplug.is_some() {
+                "Plug it back in, or press R to reconnect".to_string()
+            } else {
+                "Press R to reconnect".to_string()
+            },
+        ];
+        let pos = Pos2::new(area.center().x, area.min.y + area.height() / 3.0);
+        crate::gui::text_box(painter, pos, Align2::CENTER_TOP, Color32::from_rgb(255, 96, 96),
+            &lines);
+    }
+}
+
+/// Start a camera thread and a worker for its frames.
+fn spawn(cam: Camera, options: Options) -> State {
+    // Only the latest frame matters for the preview.
+    let (frame_rx, stream) = cam.start_streaming_thread(StreamConfig {
+        queue: 2,
+        backpressure: Backpressure::DropOldest,
+        ..Default::default()
+    });
+    // Frames are processed on the worker thread; the event loop only shows them
+    State::Streaming { stream, worker: Worker::spawn(frame_rx, options) }
+}
+
+/// Put the settings from the UI back on a reopened camera.
+fn restore(cam: &mut Camera, settings: &Settings) -> Result<(), Error> {
+    // The mode and bit depth can only change while stopped (the camera
+    // thread starts the stream again)
+    if cam.get_mode() != settings.mode || cam.get_depth() != settings.depth {
+        cam.stop_stream()?;
+        cam.set_mode(settings.mode)?;
+        cam.set_depth(settings.depth)?;
+    }
+    cam.set_exposure_time(settings.exposure)?;
+    cam.set_gain(settings.gain)?;
+    Ok(())
+}
@@ -0,0 +1,130 @@
+//! Keyboard controls for the camera settings.
+//!
+//! | Key                | Action                          |
+//! |--------------------|---------------------------------|
+//! | Up / Down          | Exposure up/down by a quarter stop |
+//! | Page Up / Down     | Exposure up/down by a stop      |
+//! | Right / Left       | Gain up/down by `0x100`         |
+//! | Shift+Right / Left | Gain up/down by `0x1000`        |
+//! | M                  | Next sensor mode                |
+//! | B                  | Switch between 8 and 12 bits    |
+//!
+//! The gain stays within the range the camera reports (see
+//! [toupcam::Camera::capabilities]), which for now is only `0x610c`.
+//!
+//! Changes are sent to the camera thread, which applies them between frames
+//! (restarting the stream for a new mode or bit depth).
+
+use sdl2::keyboard::{ Keycode, Mod };
+use std::time::Duration;
+use toupcam::{ BitDepth, CameraMode };
+use toupcam::stream::Control;
+
+/// Camera settings as last requested from the UI.
+pub struct Settings {
+    pub exposure: Duration,
+    pub gain: u16,
+    pub mode: CameraMode,
+    pub depth: BitDepth,
+    /// Gains for the preview (see [crate::white_balance])
+    pub white_balance: Option<[f32; 3]>,
+    exposure_range: (Duration, Duration),
+    gain_range: (u16, u16),
+    modes: Vec<CameraMode>,
+    depths: Vec<BitDepth>,
+}
+impl Settings {
+    /// The camera's current settings.
+    pub fn new(cam: &toupcam::Camera) -> Self {
+        let caps = cam.capabilities();
+        Self {
+            exposure: cam.get_exposure_time(),
+            gain: cam.get_gain(),
+            mode: cam.get_mode(),
+            depth: cam.get_depth(),
+            white_balance: None,
+            exposure_range: (caps.exposure_min, caps.exposure_max),
+            gain_range: (caps.gain_min, caps.gain_max),
+            modes: caps.resolutions.iter().map(|(mode, _)| *mode).collect(),
+            depths: caps.depths,
+        }
+    }
+
+    /// Update the settings for a key press, returning the change to send to
+    /// the camera thread (if any).
+    pub fn handle_key(&mut self, key: Keycode, keymod: Mod) -> Option<Control> {
+        let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+        let gain_step = if shift { 0x1000 } else { 0x0100 };
+        match key {
+            Keycode::Up => self.scale_exposure(2f64.powf(0.25)),
+            Keycode::Down => self.scale_exposure(2f64.powf(-0.25)),
+            Keycode::PageUp => self.scale_exposure(2.0),
+            Keycode::PageDown => self.scale_exposure(0.5),
+            Keycode::Right => self.set_gain(self.gain.saturating_add(gain_step)),
+            Keycode::Left => self.set_gain(self.gain.saturating_sub(gain_step)),
+            Keycode::M => self.next_mode(),
+            Keycode::B => self.next_depth(),
+            _ => None,
+        }
+    }
+
+    /// Shortest and longest exposure time.
+    pub fn exposure_range(&self) -> (Duration, Duration) { self.exposure_range }
+
+    /// Lowest and highest gain.
+    pub fn gain_range(&self) -> (u16, u16) { self.gain_range }
+
+    fn scale_exposure(&mut self, factor: f64) -> Option<Control> {
+        self.set_exposure(self.exposure.mul_f64(factor))
+    }
+
+    /// Change the exposure time (clamped to the supported range).
+    pub fn set_exposure(&mut self, exposure: Duration) -> Option<Control> {
+        let (min, max) = self.exposure_range;
+        let exposure = exposure.clamp(min, max);
+        if exposure == self.exposure { return None; }
+        self.exposure = exposure;
+        Some(Control::Exposure(exposure))
+    }
+
+    /// Change the gain (clamped to the supported range).
+    pub fn set_gain(&mut self, gain: u16) -> Option<Control> {
+        let (min, max) = self.gain_range;
+        let gain = gain.clamp(min, max);
+        if gain == self.gain { return None; }
+        self.gain = gain;
+        Some(Control::Gain(gain))
+    }
+
+    /// Switch to the next sensor mode.
+    pub fn next_mode(&mut self) -> Option<Control> {
+        self.mode = next(&self.modes, self.mode)?;
+        Some(Control::Mode(self.mode))
+    }
+
+    /// Switch to the next bit depth.
+    pub fn next_depth(&mut self) -> Option<Control> {
+        self.depth = next(&self.depths, self.depth)?;
+        Some(Control::Depth(self.depth))
+    }
+
+    /// Significant bits per sample.
+    pub fn bits(&self) -> u32 {
+        match self.depth { BitDepth::BitDepth8 => 8, BitDepth::BitDepth12 => 12 }
+    }
+
+    /// Short description for the window title.
+    pub fn describe(&self) -> String {
+        let (w, h) = self.mode.dimensions();
+        format!("{:?} {}x{} {}-bit, exposure {:.1}ms, gain 0x{:04x}", self.mode, w, h,
+            self.bits(), self.exposure.as_secs_f64() * 1e3, self.gain)
+    }
+}
+
+/// The item after `cur` in `items` (wrapping around), or `None` if there's
+/// nothing else to switch to.
+fn next<T: Copy + PartialEq>(items: &[T], cur: T) -> Option<T> {
+    let idx = items.iter().position(|x| *x == cur).map_or(0, |i| i + 1);
+    let item = *items.get(idx % items.len().max(1))?;
+    (item != cur).then_some(item)
+}
@@ -0,0 +1,132 @@
+//! Focus assist (the `A` key).
+//!
+//! Shows a magnified patch from the center of the image with a readout of
+//! the focus metric (see [toupcam::focus::focus_metric]) and a short history
+//! of it, so the peak can be found by watching the number rise and fall.
+//! The second press also turns on focus peaking, which paints strong edges
+//! in the preview red.
+
+use egui::{ Align2, Color32, Painter, Pos2, Rect, Stroke, TextureId, Vec2 };
+use std::collections::VecDeque;
+use toupcam::Frame;
+use toupcam::demosaic::RgbImage;
+use toupcam::focus::focus_metric;
+
+/// Side of the patch taken from the center of the image, in image pixels.
+const PATCH: f32 = 200.0;
+/// Magnification of the patch.
+const ZOOM: f32 = 3.0;
+/// Number of frames kept in the history.
+const HISTORY: usize = 120;
+/// Only every Nth pair of rows is used for the metric.
+const METRIC_STEP: usize = 2;
+/// Luma gradient (0 to 255) above which an edge is painted.
+const PEAKING_THRESHOLD: i32 = 48;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode { Off, Patch, Peaking }
+
+pub struct FocusAssist {
+    pub mode: Mode,
+    history: VecDeque<f64>,
+    /// Best value seen since focus assist was turned on
+    best: f64,
+}
+impl FocusAssist {
+    pub fn new() -> Self {
+        Self { mode: Mode::Off, history: VecDeque::new(), best: 0.0 }
+    }
+
+    /// Off, then the patch, then the patch and peaking.
+    pub fn cycle(&mut self) {
+        self.mode = match self.mode {
+            Mode::Off => Mode::Patch,
+            Mode::Patch => Mode::Peaking,
+            Mode::Peaking => Mode::Off,
+        };
+        if self.mode == Mode::Off {
+            self.history.clear();
+            self.best = 0.0;
+        }
+    }
+
+    /// Add the focus metric for a new frame (see [metric]).
+    pub fn push(&mut self, metric: f64) {
+        if self.mode == Mode::Off { return; }
+        if self.history.len() == HISTORY { self.history.pop_front(); }
+        self.history.push_back(metric);
+        self.best = self.best.max(metric);
+    }
+
+    /// Draw the magnified patch from `texture` (the preview, `tex_size` in
+    /// size) and the readout in the top-right corner of `area`.
+    pub fn draw(&self, painter: &Painter, texture: TextureId, tex_size: (u32, u32), area: Rect) {
+        if self.mode == Mode::Off { return; }
+        let tex_size = Vec2::new(tex_size.0 as f32, tex_size.1 as f32);
+        let side = PATCH.min(tex_size.x).min(tex_size.y);
+        let src = Rect::from_center_size((tex_size / 2.0).to_pos2(), Vec2::splat(side));
+        let uv = Rect::from_min_max((src.min.to_vec2() / tex_size).to_pos2(),
+            (src.max.to_vec2() / tex_size).to_pos2());
+        let size = side * ZOOM;
+        let dst = Rect::from_min_size(Pos2::new(area.max.x - size - 16.0, area.min.y + 16.0),
+            Vec2::splat(size));
+        painter.image(texture, dst, uv, Color32::WHITE);
+        painter.rect_stroke(dst, 0.0, Stroke::new(1.0, Color32::WHITE));
+
+        let history = Rect::from_min_size(dst.left_bottom() + Vec2::new(0.0, 8.0),
+            Vec2::new(size, 48.0));
+        self.draw_history(painter, history);
+        let metric = self.history.back().copied().unwrap_or(0.0);
+        let lines = [
+            format!("FOCUS {:.1}", metric),
+            format!("BEST  {:.1}", self.best),
+        ];
+        crate::gui::text_box(painter, history.left_bottom() + Vec2::new(0.0, 4.0),
+            Align2::LEFT_TOP, Color32::WHITE, &lines);
+    }
+
+    /// Plot the history of the metric, relative to the best value.
+    fn draw_history(&self, painter: &Painter, area: Rect) {
+        painter.rect_filled(area, 0.0, Color32::from_black_alpha(160));
+        if self.best <= 0.0 { return; }
+        let bar_w = (area.width() / HISTORY as f32).floor().max(1.0);
+        for (idx, v) in self.history.iter().enumerate() {
+            let h = ((v / self.best) as f32 * area.height()).round().max(1.0);
+            let x = area.min.x + idx as f32 * bar_w;
+            let bar = Rect::from_min_max(Pos2::new(x, area.max.y - h),
+                Pos2::new(x + bar_w, area.max.y));
+            painter.rect_filled(bar, 0.0, Color32::from_rgb(255, 200, 64));
+        }
+    }
+}
+
+/// Compute the focus metric for a frame (on the worker thread).
+pub fn metric(frame: &Frame) -> Option<f64> {
+    frame.complete.then(|| focus_metric(frame, METRIC_STEP))
+}
+
+/// Paint strong edges in an image red.
+pub fn peak(img: &mut RgbImage) {
+    let (width, height) = (img.width, img.height);
+    if width < 3 || height < 3 { return; }
+    let pitch = width * 3;
+    let luma = |buf: &[u8], x: usize, y: usize| {
+        let p = y * pitch + x * 3;
+        (buf[p] as i32 * 77 + buf[p + 1] as i32 * 150 + buf[p + 2] as i32 * 29) >> 8
+    };
+    // Work out the edges first, so painting doesn't affect the gradients
+    let buffer = &mut img.data;
+    let mut edges = Vec::new();
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let gx = luma(buffer, x + 1, y) - luma(buffer, x - 1, y);
+            let gy = luma(buffer, x, y + 1) - luma(buffer, x, y - 1);
+            if gx.abs().max(gy.abs()) > PEAKING_THRESHOLD { edges.push((x, y)); }
+        }
+    }
+    for (x, y) in edges {
+        let p = y * pitch + x * 3;
+        buffer[p..p + 3].copy_from_slice(&[255, 0, 0]);
+    }
+}
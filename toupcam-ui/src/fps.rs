@@ -0,0 +1,103 @@
+//! Frame rate and latency readout (the `I` key).
+//!
+//! Capture FPS counts every frame read from the camera (using sequence
+//! numbers, so frames that were never shown still count), and display FPS
+//! counts the frames actually uploaded. Latency is the time from the end of
+//! readout to the upload, which includes queueing and processing. Dropped
+//! frames were thrown away by the streaming thread because the preview
+//! fell behind; skipped frames are gaps in the sequence numbers for any
+//! other reason.
+
+use egui::{ Align2, Color32, Painter, Rect, Vec2 };
+use std::collections::VecDeque;
+use std::time::{ Duration, Instant };
+use toupcam::Frame;
+
+/// Number of frames averaged over.
+const WINDOW: usize = 30;
+/// Distance from the top-left corner of the preview.
+const MARGIN: f32 = 16.0;
+
+/// Keeps the last few values of something, up to [WINDOW].
+fn push<T>(queue: &mut VecDeque<T>, value: T) {
+    if queue.len() == WINDOW { queue.pop_front(); }
+    queue.push_back(value);
+}
+
+pub struct FpsOverlay {
+    pub visible: bool,
+    /// Sequence number and readout time of the frames shown
+    captured: VecDeque<(u64, Instant)>,
+    /// Upload times
+    shown: VecDeque<Instant>,
+    latency: VecDeque<Duration>,
+    processing: VecDeque<Duration>,
+    /// Frames dropped by the streaming thread
+    dropped: u64,
+    /// Frames missing from the sequence (including the dropped ones)
+    gaps: u64,
+}
+impl FpsOverlay {
+    pub fn new() -> Self {
+        Self { visible: false, captured: VecDeque::new(), shown: VecDeque::new(),
+            latency: VecDeque::new(), processing: VecDeque::new(), dropped: 0, gaps: 0,
+        }
+    }
+
+    /// Start over (for a new camera thread, with new sequence numbers).
+    pub fn reset(&mut self) {
+        *self = Self { visible: self.visible, ..Self::new() };
+    }
+
+    /// Count a frame that has just been uploaded, which took `processing`
+    /// on the worker thread. `dropped` is the total from the streaming
+    /// thread (see [toupcam::stream::StreamHandle::dropped]).
+    pub fn shown(&mut self, frame: &Frame, processing: Duration, dropped: u64) {
+        let now = Instant::now();
+        if let Some((seq, _)) = self.captured.back() {
+            self.gaps += frame.seq.saturating_sub(*seq + 1);
+        }
+        self.dropped = dropped;
+        push(&mut self.captured, (frame.seq, frame.timestamp));
+        push(&mut self.shown, now);
+        push(&mut self.latency, now.saturating_duration_since(frame.timestamp));
+        push(&mut self.processing, processing);
+    }
+
+    /// Frames per second of the readout.
+    fn capture_fps(&self) -> f64 {
+        let (Some(first), Some(last)) = (self.captured.front(), self.captured.back())
+            else { return 0.0; };
+        let secs = last.1.saturating_duration_since(first.1).as_secs_f64();
+        if secs <= 0.0 { return 0.0; }
+        last.0.saturating_sub(first.0) as f64 / secs
+    }
+
+    /// Frames per second shown in the window.
+    fn display_fps(&self) -> f64 {
+        let (Some(first), Some(last)) = (self.shown.front(), self.shown.back())
+            else { return 0.0; };
+        let secs = last.saturating_duration_since(*first).as_secs_f64();
+        if secs <= 0.0 { return 0.0; }
+        (self.shown.len() - 1) as f64 / secs
+    }
+
+    /// Draw the readout in the top-left corner of `area` (the preview).
+    pub fn draw(&self, painter: &Painter, area: Rect) {
+        if !self.visible { return; }
+        let mean_ms = |q: &VecDeque<Duration>| {
+            if q.is_empty() { return 0.0; }
+            q.iter().sum::<Duration>().as_secs_f64() * 1000.0 / q.len() as f64
+        };
+        let lines = [
+            format!("CAPTURE {:5.1} FPS", self.capture_fps()),
+            format!("DISPLAY {:5.1} FPS", self.display_fps()),
+            format!("LATENCY {:5.0} MS", mean_ms(&self.latency)),
+            format!("PROCESS {:5.0} MS", mean_ms(&self.processing)),
+            format!("DROPPED {}", self.dropped),
+            format!("SKIPPED {}", self.gaps.saturating_sub(self.dropped)),
+        ];
+        crate::gui::text_box(painter, area.min + Vec2::splat(MARGIN), Align2::LEFT_TOP,
+            Color32::WHITE, &lines);
+    }
+}
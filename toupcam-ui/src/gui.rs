@@ -0,0 +1,165 @@
+//! Drawing the window with egui.
+//!
+//! SDL2 only provides the window, an OpenGL context and the events. egui
+//! paints everything with OpenGL (see [egui_glow]): the previews (as
+//! textures), the overlays, and the control panel (see [crate::panel]).
+//! [Gui] passes the mouse events on to egui and paints a frame whenever
+//! the window is redrawn.
+
+use egui::{ Align2, Color32, FontId, Id, LayerId, Order, Painter, Pos2, Rect, Vec2 };
+use sdl2::event::{ Event, WindowEvent };
+use sdl2::keyboard::Mod;
+use sdl2::mouse::MouseButton;
+use sdl2::video::Window;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+/// Size of the text in overlays, in points.
+const TEXT_SIZE: f32 = 14.0;
+/// Space around the text in a [text_box].
+const PAD: f32 = 6.0;
+
+pub struct Gui {
+    pub ctx: egui::Context,
+    painter: egui_glow::Painter,
+    /// Events since the last frame
+    events: Vec<egui::Event>,
+    modifiers: egui::Modifiers,
+    start: Instant,
+}
+impl Gui {
+    /// Paint with `gl`, the (current) OpenGL context of the window.
+    pub fn new(gl: egui_glow::glow::Context) -> Result<Self, String> {
+        let painter = egui_glow::Painter::new(Arc::new(gl), "", None, false)
+            .map_err(|e| e.to_string())?;
+        Ok(Self { ctx: egui::Context::default(), painter, events: Vec::new(),
+            modifiers: egui::Modifiers::default(), start: Instant::now(),
+        })
+    }
+
+    /// Pass an event on to egui (only the mouse and modifier keys matter).
+    pub fn handle_event(&mut self, event: &Event) {
+        let pos = |x: i32, y: i32| Pos2::new(x as f32, y as f32);
+        let button = |b: MouseButton| match b {
+            MouseButton::Right => Some(egui::PointerButton::Secondary),
+            MouseButton::Middle => Some(egui::PointerButton::Middle),
+            MouseButton::Left => Some(egui::PointerButton::Primary),
+            _ => None,
+        };
+        let event = match *event {
+            Event::MouseMotion { x, y, .. } => egui::Event::PointerMoved(pos(x, y)),
+            Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                let Some(button) = button(mouse_btn) else { return; };
+                egui::Event::PointerButton { pos: pos(x, y), button, pressed: true,
+                    modifiers: self.modifiers }
+            },
+            Event::MouseButtonUp { mouse_btn, x, y, .. } => {
+                let Some(button) = button(mouse_btn) else { return; };
+                egui::Event::PointerButton { pos: pos(x, y), button, pressed: false,
+                    modifiers: self.modifiers }
+            },
+            Event::MouseWheel { x, y, .. } => egui::Event::MouseWheel {
+                unit: egui::MouseWheelUnit::Line, delta: Vec2::new(x as f32, y as f32),
+                modifiers: self.modifiers,
+            },
+            Event::Window { win_event: WindowEvent::Leave, .. } => egui::Event::PointerGone,
+            Event::KeyDown { keymod, .. } | Event::KeyUp { keymod, .. } => {
+                self.modifiers = egui::Modifiers {
+                    alt: keymod.intersects(Mod::LALTMOD | Mod::RALTMOD),
+                    ctrl: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+                    shift: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD),
+                    mac_cmd: false,
+                    command: keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD),
+                };
+                return;
+            },
+            _ => return,
+        };
+        self.events.push(event);
+    }
+
+    /// Returns 'true' while egui has the mouse (i.e. a slider is dragged).
+    pub fn is_using_pointer(&self) -> bool {
+        self.ctx.is_using_pointer()
+    }
+
+    /// Lay out a frame with `ui`, paint it and show it. Returns 'true' if
+    /// egui wants another frame right away (i.e. for an animation).
+    pub fn frame(&mut self, window: &Window, ui: impl FnMut(&egui::Context)) -> bool {
+        let (w, h) = window.size();
+        let (pixels_w, pixels_h) = window.drawable_size();
+        let mut input = egui::RawInput {
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(w as f32, h as f32))),
+            time: Some(self.start.elapsed().as_secs_f64()),
+            modifiers: self.modifiers,
+            events: std::mem::take(&mut self.events),
+            ..Default::default()
+        };
+        if let Some(viewport) = input.viewports.get_mut(&egui::ViewportId::ROOT) {
+            viewport.native_pixels_per_point = Some(pixels_w as f32 / w.max(1) as f32);
+        }
+        let output = self.ctx.run(input, ui);
+        let primitives = self.ctx.tessellate(output.shapes, output.pixels_per_point);
+        self.painter.clear([pixels_w, pixels_h], [0.0, 0.0, 0.0, 1.0]);
+        self.painter.paint_and_update_textures([pixels_w, pixels_h], output.pixels_per_point,
+            &primitives, &output.textures_delta);
+        window.gl_swap_window();
+        output.viewport_output.get(&egui::ViewportId::ROOT)
+            .is_some_and(|viewport| viewport.repaint_delay.is_zero())
+    }
+}
+impl Drop for Gui {
+    fn drop(&mut self) {
+        self.painter.destroy();
+    }
+}
+
+/// Convert a rectangle from SDL2 (i.e. a pane in the window).
+pub fn rect(r: sdl2::rect::Rect) -> Rect {
+    Rect::from_min_size(Pos2::new(r.x() as f32, r.y() as f32),
+        Vec2::new(r.width() as f32, r.height() as f32))
+}
+
+/// Draw lines of text on a translucent box, placed at `pos` as `align`
+/// says (i.e. [Align2::LEFT_TOP] puts its top-left corner there). Returns
+/// the box.
+pub fn text_box(painter: &Painter, pos: Pos2, align: Align2, color: Color32, lines: &[String])
+    -> Rect
+{
+    let galley = painter.layout_no_wrap(lines.join("\n"), FontId::monospace(TEXT_SIZE), color);
+    let rect = align.anchor_size(pos, galley.size() + Vec2::splat(2.0 * PAD));
+    painter.rect_filled(rect, 2.0, Color32::from_black_alpha(160));
+    painter.galley(rect.min + Vec2::splat(PAD), galley, color);
+    rect
+}
+
+/// A message shown for a few seconds (i.e. to confirm a snapshot).
+#[derive(Default)]
+pub struct Notice {
+    message: Option<(String, Color32, Instant)>,
+}
+impl Notice {
+    /// How long a message stays up.
+    const DURATION: Duration = Duration::from_secs(3);
+
+    pub fn show(&mut self, message: impl Into<String>, color: Color32) {
+        self.message = Some((message.into(), color, Instant::now() + Self::DURATION));
+    }
+
+    /// Forget the message once it has timed out. Returns 'true' if it did
+    /// (and the window needs to be redrawn).
+    pub fn expire(&mut self) -> bool {
+        let expired = self.message.as_ref().is_some_and(|(_, _, until)| Instant::now() >= *until);
+        if expired { self.message = None; }
+        expired
+    }
+
+    /// Draw the message centered at the top of the window, over everything
+    /// else.
+    pub fn draw(&self, ctx: &egui::Context) {
+        let Some((message, color, _)) = self.message.as_ref() else { return; };
+        let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("notice")));
+        let pos = Pos2::new(ctx.screen_rect().center().x, 16.0);
+        text_box(&painter, pos, Align2::CENTER_TOP, *color, std::slice::from_ref(message));
+    }
+}
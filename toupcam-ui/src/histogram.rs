@@ -0,0 +1,77 @@
+//! Per-channel histogram drawn over the preview.
+//!
+//! Computed from a sample of the raw frame (see [Frame::histogram_sampled]),
+//! so it shows what the sensor recorded rather than the stretched preview.
+//! Counts are drawn on a log scale, which keeps small clipped peaks visible.
+
+use egui::{ Color32, Painter, Pos2, Rect, Shape, Stroke, Vec2 };
+use toupcam::Frame;
+use toupcam::histogram::Channel;
+
+/// Number of columns drawn (sample values are grouped to fit).
+const COLUMNS: usize = 256;
+/// Height of the plot, in pixels.
+const HEIGHT: f32 = 160.0;
+/// Distance from the bottom-left corner of the preview.
+const MARGIN: f32 = 16.0;
+/// Only every Nth 2x2 cell in each direction is counted.
+const SAMPLE_STEP: usize = 4;
+
+/// Red, green (both), and blue counts per column.
+pub type Columns = [[u64; COLUMNS]; 3];
+
+/// Compute the histogram for a frame (on the worker thread).
+pub fn columns(frame: &Frame) -> Columns {
+    let hist = frame.histogram_sampled(SAMPLE_STEP);
+    let group = hist.bins().div_ceil(COLUMNS);
+    let channels: [&[Channel]; 3] = [
+        &[Channel::Red], &[Channel::Green1, Channel::Green2], &[Channel::Blue],
+    ];
+    let mut columns = [[0; COLUMNS]; 3];
+    for (out, chans) in columns.iter_mut().zip(channels.iter()) {
+        for ch in chans.iter() {
+            for (v, n) in hist.get(*ch).iter().enumerate() {
+                out[(v / group).min(COLUMNS - 1)] += *n as u64;
+            }
+        }
+    }
+    columns
+}
+
+pub struct HistogramOverlay {
+    columns: Columns,
+    pub visible: bool,
+}
+impl HistogramOverlay {
+    pub fn new() -> Self {
+        Self { columns: [[0; COLUMNS]; 3], visible: true }
+    }
+
+    /// Show the histogram for a new frame.
+    pub fn set(&mut self, columns: Columns) {
+        self.columns = columns;
+    }
+
+    /// Draw the histogram in the bottom-left corner of `area` (the preview).
+    pub fn draw(&self, painter: &Painter, area: Rect) {
+        if !self.visible { return; }
+        let (x0, y0) = (area.min.x + MARGIN, area.max.y - MARGIN - HEIGHT);
+        let plot = Rect::from_min_size(Pos2::new(x0, y0), Vec2::new(COLUMNS as f32, HEIGHT));
+        painter.rect_filled(plot, 0.0, Color32::from_black_alpha(160));
+
+        let peak = self.columns.iter().flatten().copied().max().unwrap_or(0);
+        let scale = ((peak + 1) as f64).ln().max(1.0);
+        let colors = [
+            Color32::from_rgba_unmultiplied(255, 64, 64, 220),
+            Color32::from_rgba_unmultiplied(64, 255, 64, 220),
+            Color32::from_rgba_unmultiplied(64, 128, 255, 220),
+        ];
+        for (counts, color) in self.columns.iter().zip(colors.iter()) {
+            let points: Vec<Pos2> = counts.iter().enumerate().map(|(x, n)| {
+                let h = ((*n + 1) as f64).ln() / scale * (HEIGHT - 1.0) as f64;
+                Pos2::new(x0 + x as f32, y0 + HEIGHT - 1.0 - h as f32)
+            }).collect();
+            painter.add(Shape::line(points, Stroke::new(1.0, *color)));
+        }
+    }
+}
@@ -1,157 +1,402 @@
 
-use sdl2::pixels::PixelFormatEnum;
-use bayer::{ RasterMut, RasterDepth };
+mod args;
+mod calibrate;
+mod connection;
+mod controls;
+mod focus;
+mod fps;
+mod gui;
+mod histogram;
+mod panel;
+mod preview;
+mod profile;
+mod raw_view;
+mod view;
+mod white_balance;
+mod worker;
+mod snapshot;
+mod stretch;
+mod zebra;
+
+use clap::Parser;
+use sdl2::event::{ Event, WindowEvent };
+use sdl2::keyboard::Keycode;
+use egui::Color32;
+use sdl2::mouse::MouseButton;
+use sdl2::rect::Rect;
+use sdl2::video::{ GLProfile, Window };
+use toupcam::stream::Control;
 
-use std::sync::mpsc::*;
 use std::fs::File;
 use std::io::Read;
-use std::time::{ Instant, Duration };
-
-enum CameraCtrl {
-    Stop
-}
-
-struct DataPacket { frame: toupcam::Frame, ts: Instant }
-
+use std::time::Duration;
 
 fn main() {
-
-    // Channel for moving data from the camera thread to the main thread
-    let (frame_tx, frame_rx) = channel();
-    let (ctrl_tx, ctrl_rx) = channel();
+    let args = args::Args::parse();
+    let profile_path = profile::Profile::path(&args.profile);
+    // A profile that can't be read is left alone, rather than overwritten
+    let (mut profile, keep_profile) = match profile::Profile::load(&profile_path) {
+        Ok(profile) => (profile.unwrap_or_default(), true),
+        Err(e) => {
+            println!("couldn't load profile ({}), using defaults", e);
+            (profile::Profile::default(), false)
+        },
+    };
+    if args.mode.is_some() { profile.mode = args.mode; }
+    if args.depth.is_some() { profile.depth = args.depth; }
+    if args.exposure.is_some() { profile.exposure = args.exposure; }
+    if args.gain.is_some() { profile.gain = args.gain; }
+    if let Some(out) = args.out { profile.output_dir = out; }
 
     // Brief SDL2 setup.
-    // All we need is a way to draw RGB24 textures.
+    // All we need is a window with an OpenGL context for egui to draw in.
     let sdl    = sdl2::init().unwrap();
     let video  = sdl.video().unwrap();
-    let window = video.window("Preview", 2320, 1740)
-        .position_centered().opengl().build().unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
+    let gl_attr = video.gl_attr();
+    gl_attr.set_context_profile(GLProfile::Core);
+    gl_attr.set_context_version(3, 2);
+    gl_attr.set_context_flags().forward_compatible().set();
+    // Start at most 80% of the screen, with the aspect ratio of a frame
+    let (win_w, win_h) = match (args.window, video.desktop_display_mode(0)) {
+        (Some(size), _) => size,
+        (None, Ok(dm)) => {
+            let scale = (dm.w as f64 * 0.8 / 2320.0).min(dm.h as f64 * 0.8 / 1740.0).min(1.0);
+            ((2320.0 * scale) as u32, (1740.0 * scale) as u32)
+        },
+        (None, Err(_)) => (1160, 870),
+    };
+    let mut window = video.window("Preview", win_w, win_h);
+    window.position_centered().resizable().opengl();
+    if args.fullscreen { window.fullscreen_desktop(); }
+    let mut window = window.build().unwrap();
+    let _gl_context = window.gl_create_context().unwrap();
+    // The context was just made current, so its functions can be loaded
+    let gl = unsafe {
+        egui_glow::glow::Context::from_loader_function(|name| {
+            video.gl_get_proc_address(name) as *const _
+        })
+    };
+    let mut gui = gui::Gui::new(gl).unwrap();
     let mut event_pump = sdl.event_pump().unwrap();
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator.create_texture_streaming(
-        PixelFormatEnum::RGB24, 2320, 1740
-    ).unwrap();
-
-
-    // Spawn the camera thread.
-    // Presumably the channel will buffer up pointers to frames for us.
-    let handle = std::thread::spawn(move || -> Result<(), toupcam::Error> {
-        let mut cam = toupcam::Camera::open()?;
-        cam.start_stream()?;
-        let mut fidx = 0;
-        'main: loop {
-            match cam.read_frame() {
-                Ok(frame) => { 
-                    let pkt = DataPacket { frame, ts: Instant::now() };
-                    fidx += 1; 
-                    frame_tx.send(pkt).unwrap();
-                    println!("sent frame {}", fidx);
-                },
-                Err(toupcam::Error::FirstFrame) => { 
-                    println!("skipped first frame");
-                    continue; 
-                },
-                Err(e) => {
-                    println!("{:?}", e);
-                    break 'main;
-                }
-            }
-            match ctrl_rx.try_recv() {
-                Err(TryRecvError::Empty) => {},
-                Ok(msg) => {
-                    println!("shutting down camera thread");
-                    break 'main;
-                },
-                Err(TryRecvError::Disconnected) => {
-                    println!("camera control disconnected?");
-                    break 'main;
-                },
-            }
-        }
-        cam.stop_stream()?;
-        println!("camera thread finished");
-        Ok(())
-    });
 
-    // Allocation for the raster object.
-    // All of these pixels are recomputed each time we demosaic a frame
-    let mut rasbuf = vec![0; 6 * (2320 * 1740)];
+    // Start streaming from each camera, all set up from the profile.
+    // Each one gets a camera thread, and a worker that processes its frames.
+    let cams = preview::open_cameras(args.all, &args.serial);
+    let separate = cams.len() > 1;
+    let mut previews: Vec<preview::Preview> = cams.into_iter().enumerate()
+        .map(|(idx, cam)| preview::Preview::new(cam, idx, &profile, separate, &gui.ctx))
+        .collect();
+    // The camera the keys and the panel act on, picked with 'Tab'
+    let mut selected = 0;
+    // Switched with 'L'
+    let mut layout = preview::Layout::Tabs;
+    set_title(&mut window, &previews, selected);
+    let mut notice = gui::Notice::default();
+    // Toggled with 'P'
+    let mut panel = panel::Panel::new();
+    panel.visible = profile.panel;
+    // From the panel and the keys, applied on the next pass through the loop
+    let mut actions = Vec::new();
 
-    let mut connected = true;
     let mut redraw = true;
     'main: loop {
 
-        // If the camera thread is connected, try to read and process a frame
-        if connected {
-            match frame_rx.try_recv() {
-                Ok(pkt) => {
-                    println!("got {}", pkt.frame.data.len());
-                    let recv_ts = std::time::Instant::now();
-                    let recv_elapsed = pkt.ts.elapsed();
-
-                    // Demosaic the raw frame
-                    let mut ras = RasterMut::new(2320, 1740, 
-                        RasterDepth::Depth16, &mut rasbuf);
-                    bayer::run_demosaic(&mut pkt.frame.data.as_slice(), 
-                        bayer::BayerDepth::Depth16BE, bayer::CFA::RGGB, 
-                        bayer::Demosaic::Linear, &mut ras
-                    );
-
-                    let buf: &[u16] = unsafe { std::slice::from_raw_parts(
-                        rasbuf.as_ptr() as *const u16, rasbuf.len() / 2)
-                    };
+        // Show any processed frames (or reconnect cameras that came back)
+        for p in previews.iter_mut() {
+            if p.receive(&mut notice) { redraw = true; }
+        }
 
-                    // Update the texture
-                    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                        for y in 0..1740 {
-                            let src_offset = (3 * 2320) * y;
-                            let dst_offset = pitch * y;
-                            for i in 0..3 * 2320 {
-                                let v = buf[src_offset + i] >> 8;
-                                buffer[dst_offset + i] = std::cmp::min(v, 255) as u8;
-                            }
-                        }
-                    }).unwrap();
-                    let upd_elapsed = recv_ts.elapsed();
-                    redraw = true;
+        if notice.expire() { redraw = true; }
+        if redraw {
+            // Redraw the window
+            let panes = layout.panes(previews.len(), selected, image_area(&window, &panel));
+            let mut changed = Vec::new();
+            redraw = gui.frame(&window, |ctx| {
+                let p = &previews[selected];
+                let state = panel::State { settings: &p.settings,
+                    connected: p.conn.is_connected(),
+                    camera: (previews.len() > 1).then_some(p.name.as_str()), layout,
+                    stretch: p.stretch, view: p.raw_view, histogram: p.histogram.visible,
+                    zebra: p.zebra.visible, focus: p.focus.mode, fps: p.fps.visible,
+                    calibration: p.wizard.enabled,
+                };
+                changed = panel.show(ctx, &state);
+                for (idx, (p, pane)) in previews.iter().zip(&panes).enumerate() {
+                    let Some(pane) = *pane else { continue; };
+                    // Overlays are drawn inside the pane
+                    let painter = ctx.layer_painter(egui::LayerId::background())
+                        .with_clip_rect(gui::rect(pane));
+                    p.draw(&painter, pane);
+                    if previews.len() > 1 { p.draw_label(&painter, pane, idx == selected); }
+                }
+                notice.draw(ctx);
+            });
+            actions.extend(changed);
+        }
 
-                    println!("frame read={:?} recv={:?} upd={:?}", 
-                            pkt.frame.elapsed, recv_elapsed, upd_elapsed);
+        // Apply whatever was done with the panel or the keys
+        for action in actions.drain(..) {
+            let p = &mut previews[selected];
+            let control = match action {
+                // Sent again for as long as a slider is held
+                panel::Action::Exposure(exposure) => {
+                    let Some(control) = p.settings.set_exposure(exposure) else { continue; };
+                    Some(control)
+                },
+                panel::Action::Gain(gain) => {
+                    let Some(control) = p.settings.set_gain(gain) else { continue; };
+                    Some(control)
+                },
+                panel::Action::NextMode => p.settings.next_mode(),
+                panel::Action::NextDepth => p.settings.next_depth(),
+                panel::Action::Snapshot => { p.snapshot(&mut notice); None },
+                panel::Action::Focus => { p.focus.cycle(); None },
+                panel::Action::Zebra => {
+                    p.zebra.visible = !p.zebra.visible;
+                    notice.show(format!("Zebra {} ({:.0}%)",
+                        if p.zebra.visible { "on" } else { "off" }, p.zebra.threshold() * 100.0),
+                        Color32::WHITE);
+                    None
+                },
+                panel::Action::WhiteBalanceOff => {
+                    p.white_balance_off();
+                    notice.show("White balance off", Color32::WHITE);
+                    None
+                },
+                panel::Action::NextView => {
+                    p.raw_view = p.raw_view.next();
+                    notice.show(p.raw_view.name(), Color32::WHITE);
+                    None
+                },
+                panel::Action::NextStretch => {
+                    p.stretch = p.stretch.next();
+                    notice.show(p.stretch.name(), Color32::WHITE);
+                    None
+                },
+                panel::Action::Calibrate => { p.wizard.start(); None },
+                panel::Action::Calibration => {
+                    p.wizard.enabled = !p.wizard.enabled;
+                    let have = p.wizard.dark().is_some() || p.wizard.flat().is_some();
+                    let msg = match (p.wizard.enabled, have) {
+                        (false, _) => "Calibration off",
+                        (true, true) => "Calibration on",
+                        (true, false) => "No calibration frames yet ('C' to capture)",
+                    };
+                    notice.show(msg, Color32::WHITE);
+                    None
+                },
+                panel::Action::Reconnect => {
+                    if !p.conn.is_connected() { p.reconnect(&mut notice); }
+                    None
+                },
+                panel::Action::Fps => { p.fps.visible = !p.fps.visible; None },
+                panel::Action::Histogram => { p.histogram.visible = !p.histogram.visible; None },
+                panel::Action::NextCamera => {
+                    selected = (selected + 1) % previews.len();
+                    set_title(&mut window, &previews, selected);
+                    None
                 },
-                Err(TryRecvError::Empty) => {},
-                Err(TryRecvError::Disconnected) => {
-                    println!("camera thread disconnected");
-                    connected = false;
-                    redraw = false;
+                panel::Action::Layout => {
+                    layout = layout.next();
+                    notice.show(layout.name(), Color32::WHITE);
+                    None
                 },
+            };
+            if let Some(control) = control {
+                camera_changed(&mut window, &previews, selected, control);
             }
+            redraw = true;
         }
-
-        if redraw {
-            // Redraw the canvas
-            canvas.clear();
-            let _ = canvas.copy(&texture, None, None);
-            canvas.present();
-            redraw = false;
+        for p in previews.iter_mut() {
+            p.conn.set_options(p.options());
         }
 
         // Catch an SDL2 event (i.e. closing the window).
         if let Some(e) = event_pump.wait_event_timeout(1) {
+            gui.handle_event(&e);
+            // Mouse events on the panel (or while dragging one of its
+            // sliders) are only for the panel
+            let on_panel = match e {
+                Event::MouseMotion { x, .. } | Event::MouseButtonDown { x, .. }
+                | Event::MouseButtonUp { x, .. } => {
+                    panel.contains(x, window.size().0) || gui.is_using_pointer()
+                },
+                Event::MouseWheel { .. } => {
+                    panel.contains(event_pump.mouse_state().x(), window.size().0)
+                },
+                _ => false,
+            };
+            if on_panel {
+                redraw = true;
+                continue;
+            }
+            // The selected camera is always shown. Zooming, panning and
+            // the keys act on it, relative to its pane.
+            let panes = layout.panes(previews.len(), selected, image_area(&window, &panel));
+            let pane = panes[selected].unwrap();
+            let size = (pane.width(), pane.height());
+            let mouse = event_pump.mouse_state();
+            let mouse = (mouse.x() - pane.x(), mouse.y() - pane.y());
+            let p = &mut previews[selected];
             match e {
-                sdl2::event::Event::Quit { .. } => {
-                    println!("sent stop message to camera thread");
-                    ctrl_tx.send(CameraCtrl::Stop).unwrap();
+                Event::Quit { .. } => {
                     break 'main;
                 },
+                Event::MouseWheel { y, .. } if y != 0 => {
+                    p.view.wheel(y, mouse, size);
+                    redraw = true;
+                },
+                Event::MouseMotion { mousestate, xrel, yrel, .. } if mousestate.left() => {
+                    p.view.pan(xrel, yrel, size);
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Num1), .. } => {
+                    p.view.one_to_one(mouse, size);
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::F), .. } => {
+                    p.view.toggle_fit(size);
+                    redraw = true;
+                },
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. }
+                | Event::Window { win_event: WindowEvent::Exposed, .. } => {
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Num0), .. } => {
+                    p.view.fit();
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Return), .. } if p.wizard.is_active() => {
+                    p.wizard.confirm();
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::Escape), .. } if p.wizard.is_active() => {
+                    p.wizard.skip();
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+                    panel.visible = !panel.visible;
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(key @ (Keycode::LeftBracket
+                    | Keycode::RightBracket)), .. } =>
+                {
+                    p.zebra.adjust(if key == Keycode::RightBracket { 1 } else { -1 });
+                    notice.show(format!("Zebra threshold {:.0}%", p.zebra.threshold() * 100.0),
+                        Color32::WHITE);
+                    redraw = true;
+                },
+                // Clicking a camera selects it; right-clicking also sets its
+                // white balance
+                Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                    let Some(idx) = pane_at(&panes, (x, y)) else { continue; };
+                    if idx != selected {
+                        selected = idx;
+                        set_title(&mut window, &previews, selected);
+                    }
+                    if mouse_btn == MouseButton::Right {
+                        let pane = panes[idx].unwrap();
+                        previews[idx].white_balance_at((x - pane.x(), y - pane.y()),
+                            (pane.width(), pane.height()), &mut notice);
+                    }
+                    redraw = true;
+                },
+                Event::KeyDown { keycode: Some(key), keymod, .. } => {
+                    // Keys for the same things as the panel
+                    let action = match key {
+                        Keycode::S => Some(panel::Action::Snapshot),
+                        Keycode::A => Some(panel::Action::Focus),
+                        Keycode::Z => Some(panel::Action::Zebra),
+                        Keycode::W => Some(panel::Action::WhiteBalanceOff),
+                        Keycode::V => Some(panel::Action::NextView),
+                        Keycode::T => Some(panel::Action::NextStretch),
+                        Keycode::I => Some(panel::Action::Fps),
+                        Keycode::H => Some(panel::Action::Histogram),
+                        Keycode::C => Some(panel::Action::Calibrate),
+                        Keycode::K => Some(panel::Action::Calibration),
+                        Keycode::R => Some(panel::Action::Reconnect),
+                        Keycode::Tab => Some(panel::Action::NextCamera),
+                        Keycode::L => Some(panel::Action::Layout),
+                        _ => None,
+                    };
+                    match action {
+                        Some(action) => actions.push(action),
+                        None => if let Some(control) = p.settings.handle_key(key, keymod) {
+                            camera_changed(&mut window, &previews, selected, control);
+                            redraw = true;
+                        },
+                    }
+                },
                 _ => (),
             }
         }
 
     }
 
-    // Wait for the camera thread to close
-    handle.join().unwrap();
-    println!("camera thread all done, seeya!");
+    // The first camera's settings are the ones saved
+    let p = &previews[0];
+    let saved = profile::Profile {
+        mode: Some(p.settings.mode),
+        depth: Some(p.settings.depth),
+        exposure: Some(p.settings.exposure),
+        gain: Some(p.settings.gain),
+        white_balance: p.settings.white_balance,
+        stretch: p.stretch,
+        histogram: p.histogram.visible,
+        zebra: p.zebra.visible,
+        zebra_threshold: p.zebra.threshold(),
+        focus: p.focus.mode,
+        fps: p.fps.visible,
+        panel: panel.visible,
+        calibration: p.wizard.enabled,
+        ..profile
+    };
+
+    // Wait for the camera threads to close
+    println!("stopping camera threads");
+    for p in previews {
+        p.conn.stop();
+    }
+    println!("camera threads all done, seeya!");
+
+    if keep_profile {
+        match saved.save(&profile_path) {
+            Ok(()) => println!("saved settings to {}", profile_path.display()),
+            Err(e) => println!("couldn't save settings to {}: {}", profile_path.display(), e),
+        }
+    }
+
+}
+
+/// The part of the window the previews are drawn in (left of the panel).
+fn image_area(window: &Window, panel: &panel::Panel) -> (u32, u32) {
+    let (w, h) = window.size();
+    (w.saturating_sub(panel.width()), h)
+}
+
+/// The camera shown at `at` (in window coordinates).
+fn pane_at(panes: &[Option<Rect>], at: (i32, i32)) -> Option<usize> {
+    panes.iter().position(|pane| pane.is_some_and(|pane| pane.contains_point(at)))
+}
+
+/// Show the settings of the selected camera in the title bar.
+fn set_title(window: &mut Window, previews: &[preview::Preview], selected: usize) {
+    let p = &previews[selected];
+    let desc = p.settings.describe();
+    let title = if previews.len() > 1 {
+        format!("Preview - {} - {}", p.name, desc)
+    } else {
+        format!("Preview - {}", desc)
+    };
+    let _ = window.set_title(&title);
+}
 
+/// Send a change to the selected camera's thread, and show the new
+/// settings.
+fn camera_changed(window: &mut Window, previews: &[preview::Preview], selected: usize,
+    control: Control)
+{
+    previews[selected].conn.control(control);
+    println!("{}", previews[selected].settings.describe());
+    set_title(window, previews, selected);
 }
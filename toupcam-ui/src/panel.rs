@@ -0,0 +1,159 @@
+//! Control panel on the right side of the window (the `P` key).
+//!
+//! The panel is an egui side panel (see [crate::gui]), laid out again every
+//! time the window is drawn, so it always shows the current settings
+//! without keeping a copy of them. Whatever the user did comes back as
+//! [Action]s, the same ones the keyboard shortcuts produce.
+
+use crate::controls::Settings;
+use crate::focus;
+use crate::preview::Layout;
+use crate::raw_view::View;
+use crate::stretch::Stretch;
+use std::time::Duration;
+
+/// Width of the panel, in pixels.
+pub const WIDTH: u32 = 280;
+
+/// Something to change, from the panel or a key.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    Exposure(Duration),
+    Gain(u16),
+    NextMode,
+    NextDepth,
+    WhiteBalanceOff,
+    NextStretch,
+    NextView,
+    Histogram,
+    Zebra,
+    Focus,
+    Fps,
+    Snapshot,
+    Calibrate,
+    Calibration,
+    Reconnect,
+    NextCamera,
+    Layout,
+}
+
+/// What the panel shows, besides the camera settings.
+pub struct State<'a> {
+    pub settings: &'a Settings,
+    /// The camera thread is running
+    pub connected: bool,
+    /// Name of the selected camera, when there's more than one
+    pub camera: Option<&'a str>,
+    pub layout: Layout,
+    pub stretch: Stretch,
+    pub view: View,
+    pub histogram: bool,
+    pub zebra: bool,
+    pub focus: focus::Mode,
+    pub fps: bool,
+    pub calibration: bool,
+}
+
+pub struct Panel {
+    pub visible: bool,
+}
+impl Panel {
+    pub fn new() -> Self {
+        Self { visible: false }
+    }
+
+    /// Width taken from the right side of the window.
+    pub fn width(&self) -> u32 {
+        if self.visible { WIDTH } else { 0 }
+    }
+
+    /// Returns 'true' if `x` (in a window `win_w` wide) is on the panel, so
+    /// a click or scroll there shouldn't also act on the preview.
+    pub fn contains(&self, x: i32, win_w: u32) -> bool {
+        self.visible && x >= win_w as i32 - WIDTH as i32
+    }
+
+    /// Lay out the panel, returning what was changed with it.
+    pub fn show(&self, ctx: &egui::Context, state: &State) -> Vec<Action> {
+        let mut actions = Vec::new();
+        if !self.visible { return actions; }
+        egui::SidePanel::right("panel").exact_width(WIDTH as f32).resizable(false)
+            .show(ctx, |ui| {
+                ui.spacing_mut().slider_width = WIDTH as f32 - 100.0;
+                egui::ScrollArea::vertical().show(ui, |ui| contents(ui, state, &mut actions));
+            });
+        actions
+    }
+}
+
+fn contents(ui: &mut egui::Ui, state: &State, actions: &mut Vec<Action>) {
+    let settings = state.settings;
+    // A button that's highlighted when `on`
+    let button = |ui: &mut egui::Ui, label: &str, on: bool| {
+        ui.add_sized([ui.available_width(), 24.0], egui::SelectableLabel::new(on, label))
+            .clicked()
+    };
+
+    if let Some(camera) = state.camera {
+        ui.heading("Cameras");
+        if button(ui, camera, false) { actions.push(Action::NextCamera); }
+        if button(ui, state.layout.name(), false) { actions.push(Action::Layout); }
+        ui.separator();
+    }
+
+    ui.heading("Camera");
+    if !state.connected && button(ui, "Reconnect", true) { actions.push(Action::Reconnect); }
+    // Exposure on a log scale, since it spans several decades
+    let (min, max) = settings.exposure_range();
+    let (min, max) = (min.as_secs_f64().max(1e-6) * 1e3, max.as_secs_f64() * 1e3);
+    let mut ms = settings.exposure.as_secs_f64() * 1e3;
+    let slider = egui::Slider::new(&mut ms, min..=max).logarithmic(true).suffix(" ms")
+        .text("Exposure");
+    if ui.add(slider).changed() {
+        actions.push(Action::Exposure(Duration::from_secs_f64(ms / 1e3)));
+    }
+    // No slider while the camera only accepts one gain value
+    let (min, max) = settings.gain_range();
+    if min < max {
+        let mut gain = settings.gain;
+        let slider = egui::Slider::new(&mut gain, min..=max).hexadecimal(4, false, false)
+            .text("Gain");
+        if ui.add(slider).changed() { actions.push(Action::Gain(gain)); }
+    } else {
+        ui.label(format!("Gain {:#06x}", settings.gain));
+    }
+    let (w, h) = settings.mode.dimensions();
+    if button(ui, &format!("{:?} {}x{}", settings.mode, w, h), false) {
+        actions.push(Action::NextMode);
+    }
+    if button(ui, &format!("{}-bit", settings.bits()), false) {
+        actions.push(Action::NextDepth);
+    }
+    ui.separator();
+
+    ui.heading("White balance");
+    match settings.white_balance {
+        Some([r, _, b]) => ui.label(format!("R {:.2}, B {:.2}", r, b)),
+        None => ui.label("Right-click something gray"),
+    };
+    if button(ui, "Reset", false) { actions.push(Action::WhiteBalanceOff); }
+    ui.separator();
+
+    ui.heading("Display");
+    if button(ui, state.stretch.name(), false) { actions.push(Action::NextStretch); }
+    if button(ui, state.view.name(), false) { actions.push(Action::NextView); }
+    if button(ui, "Histogram", state.histogram) { actions.push(Action::Histogram); }
+    if button(ui, "Zebra", state.zebra) { actions.push(Action::Zebra); }
+    let focus = match state.focus {
+        focus::Mode::Off | focus::Mode::Patch => "Focus assist",
+        focus::Mode::Peaking => "Focus peaking",
+    };
+    if button(ui, focus, state.focus != focus::Mode::Off) { actions.push(Action::Focus); }
+    if button(ui, "FPS", state.fps) { actions.push(Action::Fps); }
+    ui.separator();
+
+    ui.heading("Capture");
+    if button(ui, "Snapshot", false) { actions.push(Action::Snapshot); }
+    if button(ui, "Calibrate", false) { actions.push(Action::Calibrate); }
+    if button(ui, "Dark/flat", state.calibration) { actions.push(Action::Calibration); }
+}
@@ -0,0 +1,324 @@
+//! One camera in the window.
+//!
+//! Every camera that's opened (see `--all` and `--serial`) gets a
+//! [Preview]: its own camera thread and worker, settings, view, overlays,
+//! snapshots and masters. The keys and the panel act on the selected camera
+//! (`Tab` selects the next one, and clicking a preview selects it).
+//!
+//! With more than one camera, the previews are shown one at a time, like
+//! tabs, or side by side (the `L` key switches between the two), and each
+//! camera keeps its snapshots and masters in a directory named after it.
+
+use crate::calibrate::Wizard;
+use crate::connection::Connection;
+use crate::controls::Settings;
+use crate::focus::{ self, FocusAssist };
+use crate::fps::FpsOverlay;
+use crate::gui::Notice;
+use crate::histogram::HistogramOverlay;
+use crate::profile::Profile;
+use crate::snapshot::Snapshots;
+use crate::stretch::Stretch;
+use crate::zebra::Zebra;
+use crate::{ raw_view, view, white_balance, worker };
+use egui::{ Align2, Color32, ColorImage, Painter, Stroke, TextureHandle, TextureOptions, Vec2 };
+use sdl2::rect::Rect;
+use toupcam::{ Camera, Frame };
+use toupcam::demosaic::Demosaic;
+use toupcam::pipeline::Pipeline;
+use toupcam::white_balance::WhiteBalance;
+
+/// How previews share the window, when there's more than one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Only the selected camera
+    Tabs,
+    /// All of them, in columns
+    SideBySide,
+}
+impl Layout {
+    /// The other layout, for the `L` key.
+    pub fn next(self) -> Self {
+        match self { Self::Tabs => Self::SideBySide, Self::SideBySide => Self::Tabs }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self { Self::Tabs => "Layout: tabs", Self::SideBySide => "Layout: side by side" }
+    }
+
+    /// Where each of `count` previews goes in an `area` at the top-left of
+    /// the window (`None` for those that aren't shown).
+    pub fn panes(self, count: usize, selected: usize, area: (u32, u32)) -> Vec<Option<Rect>> {
+        let full = Rect::new(0, 0, area.0.max(1), area.1.max(1));
+        match self {
+            Self::Tabs => (0..count).map(|idx| (idx == selected).then_some(full)).collect(),
+            Self::SideBySide => {
+                let w = (area.0 / count.max(1) as u32).max(1);
+                (0..count).map(|idx| {
+                    Some(Rect::new(idx as i32 * w as i32, 0, w, area.1.max(1)))
+                }).collect()
+            },
+        }
+    }
+}
+
+pub struct Preview {
+    /// Serial number (or position, if the camera doesn't have one)
+    pub name: String,
+    pub conn: Connection,
+    pub settings: Settings,
+    /// Saved with 'S'
+    pub snapshots: Snapshots,
+    /// Started with 'C', and toggled with 'K'
+    pub wizard: Wizard,
+    /// Only used for snapshots
+    pub pipeline: Pipeline,
+    /// The image on screen, shown 1:1 without smoothing when zoomed in
+    pub texture: TextureHandle,
+    /// Size of the texture, which changes with the mode
+    pub tex_size: (u32, u32),
+    pub view: view::View,
+    /// The frame on screen
+    pub last_frame: Option<Frame>,
+    /// Toggled with 'H'
+    pub histogram: HistogramOverlay,
+    /// Cycled with 'A'
+    pub focus: FocusAssist,
+    /// Toggled with 'Z'
+    pub zebra: Zebra,
+    /// Cycled with 'V'
+    pub raw_view: raw_view::View,
+    /// Toggled with 'I'
+    pub fps: FpsOverlay,
+    /// Cycled with 'T'
+    pub stretch: Stretch,
+}
+impl Preview {
+    /// Set up camera number `idx` as in the `profile`, and start streaming.
+    /// Snapshots and masters go in the profile's output directory, or in a
+    /// directory for the camera inside it when `separate` is set.
+    pub fn new(mut cam: Camera, idx: usize, profile: &Profile, separate: bool,
+        ctx: &egui::Context) -> Self
+    {
+        profile.apply(&mut cam);
+        let serial = cam.serial_number().ok().flatten();
+        let name = serial.clone().unwrap_or_else(|| format!("camera{}", idx + 1));
+        let base = &profile.output_dir;
+        let dir = if separate { base.join(&name) } else { base.clone() };
+        let mut settings = Settings::new(&cam);
+        settings.white_balance = profile.white_balance;
+        let snapshots = Snapshots::new(&dir, cam.metadata());
+        let mut wizard = Wizard::new(&dir);
+        wizard.enabled = profile.calibration;
+        let mut pipeline = Pipeline::new(Demosaic::Bilinear);
+        pipeline.set_white_balance(settings.white_balance.map(|[r, g, b]| {
+            WhiteBalance::Manual { r, g, b }
+        }));
+        let mut histogram = HistogramOverlay::new();
+        histogram.visible = profile.histogram;
+        let mut focus = FocusAssist::new();
+        focus.mode = profile.focus;
+        let mut zebra = Zebra::new();
+        zebra.visible = profile.zebra;
+        zebra.set_threshold(profile.zebra_threshold);
+        let mut fps = FpsOverlay::new();
+        fps.visible = profile.fps;
+        let texture = ctx.load_texture(&name, ColorImage::new([2320, 1740], Color32::BLACK),
+            TextureOptions::NEAREST);
+
+        let conn = Connection::start(cam, serial, worker::Options::default());
+        conn.set_calibration(wizard.dark().cloned(), wizard.flat().cloned());
+        let mut preview = Self { name, conn, settings, snapshots, wizard, pipeline,
+            texture, tex_size: (2320, 1740), view: view::View::new(2320, 1740),
+            last_frame: None, histogram, focus, zebra, raw_view: raw_view::View::Color, fps,
+            stretch: profile.stretch,
+        };
+        preview.conn.set_options(preview.options());
+        preview
+    }
+
+    /// What the worker should do with frames.
+    pub fn options(&self) -> worker::Options {
+        worker::Options {
+            view: self.raw_view,
+            stretch: self.stretch,
+            white_balance: self.settings.white_balance,
+            histogram: self.histogram.visible,
+            focus: self.focus.mode != focus::Mode::Off,
+            peaking: self.focus.mode == focus::Mode::Peaking,
+            zebra: self.zebra.visible.then(|| self.zebra.threshold()),
+            calibration: self.wizard.enabled,
+        }
+    }
+
+    /// Upload the next processed frame, if there is one (or reconnect, if
+    /// the camera was plugged back in). Returns 'true' if the preview needs
+    /// to be redrawn.
+    pub fn receive(&mut self, notice: &mut Notice) -> bool {
+        if !self.conn.is_connected() {
+            if !self.conn.arrived() { return false; }
+            self.reconnect(notice);
+            return true;
+        }
+        let processed = match self.conn.try_recv() {
+            Some(Ok(processed)) => processed,
+            Some(Err(e)) => {
+                println!("{}: camera thread stopped: {:?}", self.name, e);
+                return true;
+            },
+            None => return false,
+        };
+        let recv_ts = std::time::Instant::now();
+        let worker::Processed { frame, image: rgb, histogram: columns,
+            focus: metric, elapsed } = processed;
+        if let Some(columns) = columns { self.histogram.set(columns); }
+        if let Some(metric) = metric { self.focus.push(metric); }
+        match self.wizard.push(&frame, &self.settings) {
+            Some(Ok(msg)) => {
+                println!("{}", msg);
+                notice.show(msg, Color32::from_rgb(128, 255, 128));
+                self.conn.set_calibration(self.wizard.dark().cloned(),
+                    self.wizard.flat().cloned());
+            },
+            Some(Err(msg)) => {
+                println!("{}", msg);
+                notice.show(msg, Color32::from_rgb(255, 96, 96));
+            },
+            None => {},
+        }
+
+        // A new mode (or scaling) starts a new view
+        let size = (rgb.width as u32, rgb.height as u32);
+        if size != self.tex_size {
+            self.view = view::View::new(size.0, size.1);
+            self.tex_size = size;
+        }
+
+        // Update the texture
+        let image = ColorImage::from_rgb([rgb.width, rgb.height], &rgb.data);
+        self.texture.set(image, TextureOptions::NEAREST);
+        self.conn.recycle(rgb);
+        self.fps.shown(&frame, elapsed, self.conn.dropped());
+        let upd_elapsed = recv_ts.elapsed();
+
+        println!("frame read={:?} proc={:?} upd={:?}", frame.elapsed, elapsed, upd_elapsed);
+        self.last_frame = Some(frame);
+        true
+    }
+
+    /// Start streaming again after the camera went away.
+    pub fn reconnect(&mut self, notice: &mut Notice) {
+        match self.conn.reconnect(&self.settings) {
+            Ok(()) => {
+                self.conn.set_calibration(self.wizard.dark().cloned(),
+                    self.wizard.flat().cloned());
+                self.fps.reset();
+                println!("{}: reconnected", self.name);
+                notice.show("Reconnected", Color32::from_rgb(128, 255, 128));
+            },
+            Err(e) => {
+                println!("{}: couldn't reconnect: {}", self.name, e);
+                notice.show("Couldn't reconnect", Color32::from_rgb(255, 96, 96));
+            },
+        }
+    }
+
+    /// Save the frame on screen.
+    pub fn snapshot(&mut self, notice: &mut Notice) {
+        let Some(frame) = self.last_frame.as_ref() else { return; };
+        self.pipeline.set_tonemap(self.stretch.tonemap(frame));
+        let res = self.pipeline.run(frame).and_then(|rgb| {
+            self.snapshots.save(frame, rgb, &self.settings)
+        });
+        match res {
+            Ok(name) => {
+                let path = self.snapshots.dir().join(&name);
+                println!("saved {}", path.display());
+                notice.show(format!("Saved {}", name), Color32::from_rgb(128, 255, 128));
+            },
+            Err(e) => {
+                println!("couldn't save snapshot: {:?}", e);
+                notice.show("Snapshot failed", Color32::from_rgb(255, 96, 96));
+            },
+        }
+    }
+
+    /// Set the white balance from a gray area at `at` (in a pane `area` in
+    /// size).
+    pub fn white_balance_at(&mut self, at: (i32, i32), area: (u32, u32), notice: &mut Notice) {
+        let Some(frame) = self.last_frame.as_ref() else { return; };
+        let Some((ix, iy)) = self.view.to_image(at, area) else { return; };
+        // The preview may be smaller than the frame
+        let fx = (ix * frame.width as f64 / self.tex_size.0 as f64) as usize;
+        let fy = (iy * frame.height as f64 / self.tex_size.1 as f64) as usize;
+        match white_balance::gains_at(frame, fx, fy) {
+            Some(gains) => {
+                let [r, g, b] = gains;
+                self.settings.white_balance = Some(gains);
+                self.pipeline.set_white_balance(Some(WhiteBalance::Manual { r, g, b }));
+                notice.show(format!("White balance R {:.2} B {:.2}", r, b),
+                    Color32::WHITE);
+            },
+            None => notice.show("Too dark for white balance", Color32::from_rgb(255, 96, 96)),
+        }
+    }
+
+    /// Set the white balance back to none.
+    pub fn white_balance_off(&mut self) {
+        self.settings.white_balance = None;
+        self.pipeline.set_white_balance(None);
+    }
+
+    /// Draw the image and overlays in `pane` (with `painter` clipped to
+    /// it).
+    pub fn draw(&self, painter: &Painter, pane: Rect) {
+        let area = crate::gui::rect(pane);
+        let tex_size = Vec2::new(self.tex_size.0 as f32, self.tex_size.1 as f32);
+        if let Some((src, dst)) = self.view.rects((pane.width(), pane.height())) {
+            let (src, dst) = (crate::gui::rect(src), crate::gui::rect(dst));
+            let uv = egui::Rect::from_min_max((src.min.to_vec2() / tex_size).to_pos2(),
+                (src.max.to_vec2() / tex_size).to_pos2());
+            painter.image(self.texture.id(), dst.translate(area.min.to_vec2()), uv,
+                Color32::WHITE);
+        }
+        self.histogram.draw(painter, area);
+        self.fps.draw(painter, area);
+        self.focus.draw(painter, self.texture.id(), self.tex_size, area);
+        self.wizard.draw(painter, area);
+        self.conn.draw(painter, area);
+    }
+
+    /// Draw the name of the camera at the bottom right of `pane`,
+    /// highlighted when it's `selected`.
+    pub fn draw_label(&self, painter: &Painter, pane: Rect, selected: bool) {
+        let area = crate::gui::rect(pane);
+        let color = if selected {
+            Color32::from_rgb(255, 255, 128)
+        } else {
+            Color32::from_rgb(160, 160, 160)
+        };
+        crate::gui::text_box(painter, area.max - Vec2::splat(16.0), Align2::RIGHT_BOTTOM, color,
+            std::slice::from_ref(&self.name));
+        if selected {
+            painter.rect_stroke(area.shrink(0.5), 0.0, Stroke::new(1.0, color));
+        }
+    }
+}
+
+/// Open the cameras asked for on the command line: every camera in
+/// [Camera::list] with `all`, the ones with the given serial numbers, or
+/// else the first one found.
+pub fn open_cameras(all: bool, serials: &[String]) -> Vec<Camera> {
+    if !all && serials.is_empty() { return vec![Camera::open().unwrap()]; }
+    if !all {
+        return serials.iter().map(|serial| Camera::open_serial(serial).unwrap()).collect();
+    }
+    let cams: Vec<Camera> = Camera::list().unwrap().iter().filter_map(|loc| {
+        match Camera::open_at(loc) {
+            Ok(cam) => Some(cam),
+            Err(e) => { println!("couldn't open the camera at {:?}: {:?}", loc, e); None },
+        }
+    }).collect();
+    assert!(!cams.is_empty(), "no cameras found");
+    cams
+}
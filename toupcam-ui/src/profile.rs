@@ -0,0 +1,279 @@
+//! Settings profiles, loaded at startup and saved on exit.
+//!
+//! A profile holds the camera settings and the state of the preview, so
+//! the UI comes back the way it was left. Profiles are named (`--profile`,
+//! `default` otherwise) and kept as `<name>.toml` in
+//! `$XDG_CONFIG_HOME/toupcam-ui` (or `~/.config/toupcam-ui`):
+//!
+//! ```toml
+//! mode = 1
+//! depth = 12
+//! exposure_ms = 20.0
+//! white_balance = [1.9, 1.0, 1.6]
+//! stretch = "percentile"
+//! output_dir = "snapshots"
+//! histogram = true
+//! zebra = false
+//! zebra_threshold = 0.98
+//! focus = "off"
+//! fps = false
+//! panel = false
+//! calibration = false
+//! ```
+//!
+//! A `gain` is saved too, but only values within the camera's range (see
+//! [toupcam::Camera::capabilities]) are applied.
+//!
+//! With more than one camera, they all start out with the profile, and the
+//! first one's settings are saved.
+//!
+//! Missing keys keep their defaults, and unknown keys are ignored.
+
+use crate::focus;
+use crate::stretch::Stretch;
+use serde::{ Deserialize, Serialize };
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+use toupcam::{ BitDepth, Camera, CameraMode };
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Camera settings (left as they are when `None`)
+    #[serde(with = "mode", skip_serializing_if = "Option::is_none")]
+    pub mode: Option<CameraMode>,
+    #[serde(with = "depth", skip_serializing_if = "Option::is_none")]
+    pub depth: Option<BitDepth>,
+    #[serde(rename = "exposure_ms", with = "exposure_ms",
+        skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain: Option<u16>,
+    #[serde(with = "white_balance", skip_serializing_if = "Option::is_none")]
+    pub white_balance: Option<[f32; 3]>,
+    pub stretch: Stretch,
+    /// Where snapshots are saved
+    pub output_dir: PathBuf,
+    pub histogram: bool,
+    pub zebra: bool,
+    pub zebra_threshold: f64,
+    pub focus: focus::Mode,
+    pub fps: bool,
+    pub panel: bool,
+    /// Apply the master dark and flat (see [crate::calibrate])
+    pub calibration: bool,
+}
+impl Default for Profile {
+    fn default() -> Self {
+        Self { mode: None, depth: None, exposure: None, gain: None, white_balance: None,
+            stretch: Stretch::Percentile, output_dir: "snapshots".into(), histogram: true,
+            zebra: false, zebra_threshold: 0.98, focus: focus::Mode::Off, fps: false,
+            panel: false, calibration: false,
+        }
+    }
+}
+
+impl Profile {
+    /// Where the profile called `name` is kept.
+    pub fn path(name: &str) -> PathBuf {
+        let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => match std::env::var_os("HOME") {
+                Some(home) => Path::new(&home).join(".config"),
+                None => PathBuf::from("."),
+            },
+        };
+        dir.join("toupcam-ui").join(format!("{}.toml", name))
+    }
+
+    /// Read a profile. Returns `Ok(None)` if the file doesn't exist yet.
+    pub fn load(path: impl AsRef<Path>) -> Result<Option<Self>, String> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text)
+                .map(Some)
+                .map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Write the profile, creating its directory if needed.
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() { std::fs::create_dir_all(dir)?; }
+        std::fs::write(path, self.to_toml())
+    }
+
+    pub fn to_toml(&self) -> String {
+        let text = toml::to_string(self).expect("profile can always be serialized");
+        format!("# toupcam-ui settings, saved on exit\n{}", text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Set up the camera (before it starts streaming).
+    pub fn apply(&self, cam: &mut Camera) {
+        if let Some(mode) = self.mode {
+            if let Err(e) = cam.set_mode(mode) { println!("couldn't set mode: {:?}", e); }
+        }
+        if let Some(depth) = self.depth {
+            if let Err(e) = cam.set_depth(depth) { println!("couldn't set depth: {:?}", e); }
+        }
+        if let Some(exposure) = self.exposure {
+            if let Err(e) = cam.set_exposure_time(exposure) {
+                println!("couldn't set exposure: {:?}", e);
+            }
+        }
+        if let Some(gain) = self.gain {
+            if let Err(e) = cam.set_gain(gain) { println!("couldn't set gain: {:?}", e); }
+        }
+    }
+}
+
+/// `mode` is saved as the mode number.
+mod mode {
+    use serde::{ Deserialize, Deserializer, Serializer, de::Error };
+    use toupcam::CameraMode;
+
+    pub fn serialize<S: Serializer>(mode: &Option<CameraMode>, s: S) -> Result<S::Ok, S::Error> {
+        match mode {
+            Some(CameraMode::Mode0) => s.serialize_u8(0),
+            Some(CameraMode::Mode1) => s.serialize_u8(1),
+            Some(CameraMode::Mode2) => s.serialize_u8(2),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<CameraMode>, D::Error> {
+        match u8::deserialize(d)? {
+            0 => Ok(Some(CameraMode::Mode0)),
+            1 => Ok(Some(CameraMode::Mode1)),
+            2 => Ok(Some(CameraMode::Mode2)),
+            n => Err(D::Error::custom(format!("no mode {}", n))),
+        }
+    }
+}
+
+/// `depth` is saved as the number of bits.
+mod depth {
+    use serde::{ Deserialize, Deserializer, Serializer, de::Error };
+    use toupcam::BitDepth;
+
+    pub fn serialize<S: Serializer>(depth: &Option<BitDepth>, s: S) -> Result<S::Ok, S::Error> {
+        match depth {
+            Some(BitDepth::BitDepth8) => s.serialize_u8(8),
+            Some(BitDepth::BitDepth12) => s.serialize_u8(12),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<BitDepth>, D::Error> {
+        match u8::deserialize(d)? {
+            8 => Ok(Some(BitDepth::BitDepth8)),
+            12 => Ok(Some(BitDepth::BitDepth12)),
+            n => Err(D::Error::custom(format!("no {}-bit depth", n))),
+        }
+    }
+}
+
+/// The exposure is saved in milliseconds.
+mod exposure_ms {
+    use serde::{ Deserialize, Deserializer, Serializer, de::Error };
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(exposure: &Option<Duration>, s: S)
+        -> Result<S::Ok, S::Error>
+    {
+        match exposure {
+            Some(exposure) => s.serialize_f64(exposure.as_secs_f64() * 1e3),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        let ms = f64::deserialize(d)?;
+        match Duration::try_from_secs_f64(ms / 1e3) {
+            Ok(exposure) if ms > 0.0 => Ok(Some(exposure)),
+            _ => Err(D::Error::custom(format!("bad exposure {} ms", ms))),
+        }
+    }
+}
+
+/// White balance gains are saved with as many digits as they have as `f32`
+/// (`1.9`, rather than `1.899999976158142`).
+mod white_balance {
+    use serde::{ Deserialize, Deserializer, Serialize, Serializer };
+
+    pub fn serialize<S: Serializer>(gains: &Option<[f32; 3]>, s: S) -> Result<S::Ok, S::Error> {
+        let short = |v: f32| v.to_string().parse::<f64>().unwrap_or(v as f64);
+        gains.map(|g| g.map(short)).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<[f32; 3]>, D::Error> {
+        Ok(Some(<[f64; 3]>::deserialize(d)?.map(|v| v as f32)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let profile = Profile {
+            mode: Some(CameraMode::Mode2),
+            depth: Some(BitDepth::BitDepth8),
+            exposure: Some(Duration::from_micros(20_500)),
+            gain: Some(0x610c),
+            white_balance: Some([1.9, 1.0, 1.6]),
+            stretch: Stretch::Full,
+            output_dir: "some \"dir\"".into(),
+            histogram: false,
+            zebra: true,
+            zebra_threshold: 0.9,
+            focus: focus::Mode::Peaking,
+            fps: true,
+            panel: true,
+            calibration: true,
+        };
+        let text = profile.to_toml();
+        assert!(text.contains("white_balance = [1.9, 1.0, 1.6]"), "{}", text);
+        assert!(text.contains("stretch = \"none\""), "{}", text);
+        assert_eq!(Profile::parse(&text).unwrap(), profile);
+        assert_eq!(Profile::parse(&Profile::default().to_toml()).unwrap(), Profile::default());
+    }
+
+    #[test]
+    fn parses_doc_example() {
+        let text = "mode = 1\ndepth = 12\nexposure_ms = 20.0\n\
+            white_balance = [1.9, 1.0, 1.6]\nstretch = \"percentile\"\n\
+            output_dir = \"snapshots\"\nhistogram = true\nzebra = false\n\
+            zebra_threshold = 0.98\nfocus = \"off\"\nfps = false\npanel = false\n\
+            calibration = false\n";
+        let profile = Profile::parse(text).unwrap();
+        assert_eq!(profile.mode, Some(CameraMode::Mode1));
+        assert_eq!(profile.depth, Some(BitDepth::BitDepth12));
+        assert_eq!(profile.exposure, Some(Duration::from_millis(20)));
+        assert_eq!(profile.gain, None);
+        assert_eq!(profile.white_balance, Some([1.9, 1.0, 1.6]));
+    }
+
+    #[test]
+    fn missing_and_unknown_keys() {
+        let profile = Profile::parse("# nothing but\nzebra = true\nshiny = 3\n").unwrap();
+        assert_eq!(profile, Profile { zebra: true, ..Profile::default() });
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for text in ["mode = 3", "depth = 10", "exposure_ms = 0.0", "exposure_ms = 1e300",
+            "gain = 70000", "gain = -1", "white_balance = [1.0, 2.0]", "stretch = \"loud\"",
+            "focus = 1", "histogram = \"yes\"", "mode = ", "this isn't toml"]
+        {
+            assert!(Profile::parse(text).is_err(), "accepted '{}'", text);
+        }
+    }
+}
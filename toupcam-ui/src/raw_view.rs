@@ -0,0 +1,94 @@
+//! Showing the raw Bayer mosaic instead of the processed image (the `V`
+//! key cycles through the views).
+//!
+//! Demosaicing smooths over single-pixel problems, so these views help with
+//! checking the CFA phase, finding defective pixels and spotting readout
+//! artifacts. Samples are scaled linearly from full scale to 8 bits, with
+//! no other processing.
+
+use toupcam::Frame;
+use toupcam::demosaic::RgbImage;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum View {
+    /// The processed image
+    Color,
+    /// Every sample as gray
+    Mosaic,
+    /// Every sample tinted with the color of its filter
+    Tinted,
+    /// Each position in the 2x2 cell as a separate quarter-size image:
+    /// red top-left, greens top-right and bottom-left, blue bottom-right
+    Channels,
+}
+impl View {
+    /// The next view, for the `V` key.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Color => Self::Mosaic,
+            Self::Mosaic => Self::Tinted,
+            Self::Tinted => Self::Channels,
+            Self::Channels => Self::Color,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Color => "Color", Self::Mosaic => "Raw mosaic",
+            Self::Tinted => "Raw mosaic (tinted)", Self::Channels => "Raw channels",
+        }
+    }
+}
+
+pub struct RawView {
+    pub view: View,
+    img: RgbImage,
+}
+impl RawView {
+    pub fn new() -> Self {
+        Self { view: View::Color, img: RgbImage { width: 0, height: 0, data: Vec::new() } }
+    }
+
+    /// Render a frame in the current view ([View::Color] isn't handled
+    /// here, and gives an empty image).
+    pub fn render(&mut self, frame: &Frame) -> &RgbImage {
+        let (w, h) = (frame.width, frame.height);
+        let img = &mut self.img;
+        img.width = w;
+        img.height = h;
+        img.data.clear();
+        img.data.resize(w * h * 3, 0);
+        let received = frame.data.len() / frame.bpp.max(1);
+        if self.view == View::Color || received < w * h { return img; }
+
+        let scale = 255.0 / frame.max_value().max(1) as f32;
+        let value = |idx: usize| {
+            (frame.sample(idx) as f32 * scale).min(255.0) as u8
+        };
+        let (rx, ry) = frame.cfa.red_offset();
+        // Position in the 2x2 cell relative to red: 0 red, 1 and 2 green, 3 blue
+        let cell = |x: usize, y: usize| (((y & 1) ^ ry) << 1) | ((x & 1) ^ rx);
+
+        for y in 0..h {
+            for x in 0..w {
+                let v = value(y * w + x);
+                let c = cell(x, y);
+                let (ox, oy) = match self.view {
+                    // Each channel goes to its own quadrant
+                    View::Channels => ((c & 1) * (w / 2) + x / 2, (c >> 1) * (h / 2) + y / 2),
+                    _ => (x, y),
+                };
+                if ox >= w || oy >= h { continue; }
+                let px = match self.view {
+                    View::Tinted => match c {
+                        0 => [v, 0, 0], 3 => [0, 0, v], _ => [0, v, 0],
+                    },
+                    _ => [v, v, v],
+                };
+                let p = (oy * w + ox) * 3;
+                img.data[p..p + 3].copy_from_slice(&px);
+            }
+        }
+        img
+    }
+}
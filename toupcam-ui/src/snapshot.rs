@@ -0,0 +1,47 @@
+//! Saving the frame on screen (the `S` key).
+//!
+//! Each snapshot is written twice: the raw mosaic as a 16-bit TIFF, and the
+//! processed preview as a PNG, both carrying the acquisition parameters.
+//! Files are numbered `snap_NNNN`, skipping numbers that are already taken
+//! in the directory.
+
+use std::path::{ Path, PathBuf };
+use crate::controls::Settings;
+use std::time::SystemTime;
+use toupcam::demosaic::RgbImage;
+use toupcam::metadata::CaptureMetadata;
+use toupcam::Frame;
+
+pub struct Snapshots {
+    dir: PathBuf,
+    /// Settings that don't change while streaming (model, serial)
+    meta: CaptureMetadata,
+    next: usize,
+}
+impl Snapshots {
+    pub fn new(dir: impl Into<PathBuf>, meta: CaptureMetadata) -> Self {
+        Self { dir: dir.into(), meta, next: 0 }
+    }
+
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    /// Save a frame and the image it was processed into (taken with
+    /// `settings`), returning the name the files were saved under.
+    pub fn save(&mut self, frame: &Frame, rgb: &RgbImage, settings: &Settings)
+        -> Result<String, toupcam::Error>
+    {
+        std::fs::create_dir_all(&self.dir)?;
+        let name = loop {
+            let name = format!("snap_{:04}", self.next);
+            self.next += 1;
+            if !self.dir.join(format!("{}.png", name)).exists() { break name; }
+        };
+        let meta = CaptureMetadata {
+            mode: settings.mode, bits: settings.bits(), exposure: settings.exposure,
+            gain: settings.gain, timestamp: SystemTime::now(), ..self.meta.clone()
+        };
+        frame.save_tiff(self.dir.join(format!("{}_raw.tiff", name)), Some(&meta))?;
+        rgb.save_png(self.dir.join(format!("{}.png", name)), Some(&meta))?;
+        Ok(name)
+    }
+}
@@ -0,0 +1,81 @@
+//! Stretching the preview for display (the `T` key cycles through them).
+//!
+//! Dim 12-bit images are nearly black when mapped straight to 8 bits, so
+//! the preview is stretched by default between percentiles of the image.
+//! The gamma and asinh stretches bring up the shadows even more, between
+//! the same levels taken from the raw frame.
+
+use toupcam::Frame;
+use toupcam::histogram::Channel;
+use toupcam::tonemap::ToneMap;
+
+/// Fraction of samples clipped to black and to white by the stretches.
+const LOW: f64 = 0.001;
+const HIGH: f64 = 0.999;
+/// Only every Nth 2x2 cell in each direction is used to find the levels.
+const SAMPLE_STEP: usize = 4;
+const GAMMA: f32 = 2.2;
+const ASINH: f32 = 20.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stretch {
+    /// Linear between the low and high percentiles
+    Percentile,
+    /// Linear between the darkest and brightest pixels
+    MinMax,
+    /// Gamma curve between the low and high percentiles
+    Gamma,
+    /// Inverse hyperbolic sine between the low and high percentiles
+    Asinh,
+    /// Linear over the full range of the sensor, with no stretch
+    #[serde(rename = "none")]
+    Full,
+}
+impl Stretch {
+    /// The next stretch, for the `T` key.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Percentile => Self::MinMax,
+            Self::MinMax => Self::Gamma,
+            Self::Gamma => Self::Asinh,
+            Self::Asinh => Self::Full,
+            Self::Full => Self::Percentile,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Percentile => "Stretch: percentile", Self::MinMax => "Stretch: min/max",
+            Self::Gamma => "Stretch: gamma", Self::Asinh => "Stretch: asinh",
+            Self::Full => "Stretch: none",
+        }
+    }
+
+    /// The tone map to process `frame` with.
+    pub fn tonemap(self, frame: &Frame) -> ToneMap {
+        match self {
+            Self::Percentile => ToneMap::AutoStretch { low: LOW as f32, high: HIGH as f32 },
+            Self::MinMax => ToneMap::AutoStretch { low: 0.0, high: 1.0 },
+            Self::Gamma => {
+                let (black, white) = levels(frame);
+                ToneMap::Gamma { black, white, gamma: GAMMA }
+            },
+            Self::Asinh => {
+                let (black, white) = levels(frame);
+                ToneMap::Asinh { black, white, stretch: ASINH }
+            },
+            Self::Full => ToneMap::FULL_RANGE,
+        }
+    }
+}
+
+/// Black and white points for a frame, in the 16-bit units of the tone map.
+fn levels(frame: &Frame) -> (u16, u16) {
+    let hist = frame.histogram_sampled(SAMPLE_STEP);
+    let chans = [Channel::Red, Channel::Green1, Channel::Green2, Channel::Blue];
+    let black = chans.iter().map(|ch| hist.percentile(*ch, LOW)).min().unwrap_or(0);
+    let white = chans.iter().map(|ch| hist.percentile(*ch, HIGH)).max().unwrap_or(0);
+    let scale = |v: usize| (v * 0x1_0000 / (frame.max_value() as usize + 1)) as u16;
+    (scale(black), scale(white))
+}
@@ -0,0 +1,134 @@
+//! Zooming and panning the preview.
+//!
+//! By default the whole image is fit into the window, keeping its aspect
+//! ratio (whatever the size of the window or the frames). The mouse wheel
+//! zooms in and out around the cursor, dragging with the left button pans,
+//! `1` shows the image at 1:1 (one sensor pixel per screen pixel), `0` fits
+//! the whole image back into the window, and `F` switches between the two.
+
+use sdl2::rect::Rect;
+
+/// Most screen pixels per image pixel.
+const MAX_SCALE: f64 = 32.0;
+/// Zoom factor for each step of the mouse wheel.
+const WHEEL_STEP: f64 = 1.25;
+
+pub struct View {
+    /// Size of the image
+    width: f64,
+    height: f64,
+    /// Screen pixels per image pixel (`None` fits the image to the window)
+    scale: Option<f64>,
+    /// Point in the image shown at the center of the window
+    center: (f64, f64),
+}
+impl View {
+    pub fn new(width: u32, height: u32) -> Self {
+        let (width, height) = (width as f64, height as f64);
+        Self { width, height, scale: None, center: (width / 2.0, height / 2.0) }
+    }
+
+    /// Scale that fits the whole image into the window.
+    fn fit_scale(&self, win: (u32, u32)) -> f64 {
+        (win.0 as f64 / self.width).min(win.1 as f64 / self.height)
+    }
+
+    fn scale(&self, win: (u32, u32)) -> f64 {
+        self.scale.unwrap_or_else(|| self.fit_scale(win))
+    }
+
+    /// Show the whole image.
+    pub fn fit(&mut self) {
+        self.scale = None;
+        self.center = (self.width / 2.0, self.height / 2.0);
+    }
+
+    /// Switch between fitting the image to the window and 1:1 (around the
+    /// center of the window).
+    pub fn toggle_fit(&mut self, win: (u32, u32)) {
+        if self.scale.is_none() {
+            self.one_to_one(((win.0 / 2) as i32, (win.1 / 2) as i32), win);
+        } else {
+            self.fit();
+        }
+    }
+
+    /// Zoom by `factor`, keeping the image point under `at` (in window
+    /// coordinates) where it is.
+    pub fn zoom_at(&mut self, factor: f64, at: (i32, i32), win: (u32, u32)) {
+        let scale = self.scale(win);
+        self.set_scale_at(scale * factor, at, win);
+    }
+
+    /// Zoom in or out by steps of the mouse wheel.
+    pub fn wheel(&mut self, steps: i32, at: (i32, i32), win: (u32, u32)) {
+        self.zoom_at(WHEEL_STEP.powi(steps), at, win);
+    }
+
+    /// Show the image at 1:1 around `at`.
+    pub fn one_to_one(&mut self, at: (i32, i32), win: (u32, u32)) {
+        self.set_scale_at(1.0, at, win);
+    }
+
+    fn set_scale_at(&mut self, new: f64, at: (i32, i32), win: (u32, u32)) {
+        let old = self.scale(win);
+        // Zooming out further than fitting the window isn't useful
+        let fit = self.fit_scale(win);
+        let new = new.clamp(fit.min(1.0), MAX_SCALE);
+        let (dx, dy) = (at.0 as f64 - win.0 as f64 / 2.0, at.1 as f64 - win.1 as f64 / 2.0);
+        let point = (self.center.0 + dx / old, self.center.1 + dy / old);
+        self.scale = if new <= fit { None } else { Some(new) };
+        if self.scale.is_none() {
+            self.fit();
+            return;
+        }
+        self.center = (point.0 - dx / new, point.1 - dy / new);
+        self.clamp_center();
+    }
+
+    /// Move the image by `(dx, dy)` window pixels.
+    pub fn pan(&mut self, dx: i32, dy: i32, win: (u32, u32)) {
+        if self.scale.is_none() { return; }
+        let scale = self.scale(win);
+        self.center.0 -= dx as f64 / scale;
+        self.center.1 -= dy as f64 / scale;
+        self.clamp_center();
+    }
+
+    fn clamp_center(&mut self) {
+        self.center.0 = self.center.0.clamp(0.0, self.width);
+        self.center.1 = self.center.1.clamp(0.0, self.height);
+    }
+
+    /// The point in the image under `at` (in window coordinates), if there
+    /// is one.
+    pub fn to_image(&self, at: (i32, i32), win: (u32, u32)) -> Option<(f64, f64)> {
+        let scale = self.scale(win);
+        let x = self.center.0 + (at.0 as f64 - win.0 as f64 / 2.0) / scale;
+        let y = self.center.1 + (at.1 as f64 - win.1 as f64 / 2.0) / scale;
+        let inside = (0.0..self.width).contains(&x) && (0.0..self.height).contains(&y);
+        inside.then_some((x, y))
+    }
+
+    /// The part of the image to draw, and where to draw it in the window.
+    pub fn rects(&self, win: (u32, u32)) -> Option<(Rect, Rect)> {
+        let scale = self.scale(win);
+        let (half_w, half_h) = (win.0 as f64 / 2.0 / scale, win.1 as f64 / 2.0 / scale);
+        let x0 = (self.center.0 - half_w).max(0.0).floor();
+        let y0 = (self.center.1 - half_h).max(0.0).floor();
+        let x1 = (self.center.0 + half_w).min(self.width).ceil();
+        let y1 = (self.center.1 + half_h).min(self.height).ceil();
+        if x1 <= x0 || y1 <= y0 { return None; }
+        let src = Rect::new(x0 as i32, y0 as i32, (x1 - x0) as u32, (y1 - y0) as u32);
+
+        let to_win = |x: f64, y: f64| {
+            ((x - self.center.0) * scale + win.0 as f64 / 2.0,
+             (y - self.center.1) * scale + win.1 as f64 / 2.0)
+        };
+        let (wx0, wy0) = to_win(x0, y0);
+        let (wx1, wy1) = to_win(x1, y1);
+        let dst = Rect::new(wx0.round() as i32, wy0.round() as i32,
+            (wx1 - wx0).round().max(1.0) as u32, (wy1 - wy0).round().max(1.0) as u32);
+        Some((src, dst))
+    }
+}
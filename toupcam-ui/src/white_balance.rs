@@ -0,0 +1,22 @@
+//! Click-to-set white balance (right-click a neutral area).
+//!
+//! The gains are worked out from the raw samples around the click, so that
+//! the red and blue means match green there. `W` goes back to no white
+//! balance.
+
+use toupcam::Frame;
+use toupcam::region::Rect;
+
+/// Side of the area averaged around the click, in sensor pixels.
+const AREA: usize = 32;
+
+/// White balance gains `[r, g, b]` that make the area around `(x, y)` (in
+/// sensor pixels) neutral, or `None` if it's too dark to tell.
+pub fn gains_at(frame: &Frame, x: usize, y: usize) -> Option<[f32; 3]> {
+    let half = AREA / 2;
+    let rect = Rect::new(x.saturating_sub(half), y.saturating_sub(half), AREA, AREA);
+    let stats = frame.stats(rect);
+    let green = (stats.green1.mean + stats.green2.mean) / 2.0;
+    if stats.red.mean < 1.0 || stats.blue.mean < 1.0 || green < 1.0 { return None; }
+    Some([(green / stats.red.mean) as f32, 1.0, (green / stats.blue.mean) as f32])
+}
@@ -0,0 +1,197 @@
+//! Processing frames off the event loop.
+//!
+//! Demosaicing and tone mapping a full frame takes long enough to make the
+//! window stutter, so a [Worker] thread takes raw frames from the camera
+//! thread and does everything that touches every pixel: processing (or a
+//! raw view, with calibration), the histogram, the focus metric, peaking and
+//! zebra stripes.
+//! The event loop only uploads the finished images to a texture.
+//!
+//! Only one finished image waits for the event loop at a time. If it falls
+//! behind, the worker waits too, and the camera thread drops old frames.
+
+use crate::raw_view::{ RawView, View };
+use crate::stretch::Stretch;
+use crate::{ focus, histogram, zebra };
+use std::sync::mpsc::{ self, Receiver, Sender, SyncSender };
+use std::sync::{ Arc, Mutex };
+use std::thread::JoinHandle;
+use std::time::{ Duration, Instant };
+use toupcam::{ Error, Frame };
+use toupcam::calibration::{ CalibrationInfo, MasterDark, MasterFlat };
+use toupcam::demosaic::{ Demosaic, RgbImage };
+use toupcam::pipeline::Pipeline;
+use toupcam::stream::FrameReceiver;
+use toupcam::white_balance::WhiteBalance;
+
+/// What the worker does with each frame, set from the event loop.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Options {
+    pub view: View,
+    pub stretch: Stretch,
+    /// Gains (red, green, blue) for the processed image
+    pub white_balance: Option<[f32; 3]>,
+    pub histogram: bool,
+    pub focus: bool,
+    pub peaking: bool,
+    /// Zebra threshold (a fraction of full scale), when zebra is on
+    pub zebra: Option<f64>,
+    /// Apply the masters (see [Worker::set_calibration]) to frames they fit
+    pub calibration: bool,
+}
+impl Default for Options {
+    fn default() -> Self {
+        Self { view: View::Color, stretch: Stretch::Percentile, white_balance: None,
+            histogram: true, focus: false, peaking: false, zebra: None, calibration: false,
+        }
+    }
+}
+
+/// A master dark and flat.
+type Masters = (Option<MasterDark>, Option<MasterFlat>);
+
+/// A frame, ready to be shown.
+pub struct Processed {
+    /// The raw frame
+    pub frame: Frame,
+    /// The image to upload, with overlays painted in
+    pub image: RgbImage,
+    pub histogram: Option<histogram::Columns>,
+    pub focus: Option<f64>,
+    /// Time spent processing
+    pub elapsed: Duration,
+}
+
+pub struct Worker {
+    results: Receiver<Result<Processed, Error>>,
+    options: Arc<Mutex<Options>>,
+    /// Images the event loop is done with, to be reused
+    spare: Sender<RgbImage>,
+    calibration: Sender<Masters>,
+    thread: JoinHandle<()>,
+}
+impl Worker {
+    /// Start processing frames from `frames`.
+    pub fn spawn(frames: FrameReceiver, options: Options) -> Self {
+        let options = Arc::new(Mutex::new(options));
+        let (result_tx, results) = mpsc::sync_channel(1);
+        let (spare, spare_rx) = mpsc::channel();
+        let (calibration, calibration_rx) = mpsc::channel();
+        let opts = options.clone();
+        let thread = std::thread::spawn(move || {
+            run(frames, opts, result_tx, spare_rx, calibration_rx)
+        });
+        Self { results, options, spare, calibration, thread }
+    }
+
+    /// The next finished frame, if there is one. An error means the camera
+    /// thread has stopped.
+    pub fn try_recv(&self) -> Option<Result<Processed, Error>> {
+        self.results.try_recv().ok()
+    }
+
+    /// Change what's done with the following frames.
+    pub fn set_options(&self, options: Options) {
+        *self.options.lock().unwrap() = options;
+    }
+
+    /// Replace the masters applied when [Options::calibration] is set.
+    pub fn set_calibration(&self, dark: Option<MasterDark>, flat: Option<MasterFlat>) {
+        let _ = self.calibration.send((dark, flat));
+    }
+
+    /// Hand back an image that has been uploaded, so its buffer is reused.
+    pub fn recycle(&self, image: RgbImage) {
+        let _ = self.spare.send(image);
+    }
+
+    /// Wait for the worker to finish (after the camera thread has been
+    /// stopped).
+    pub fn join(self) {
+        drop(self.results);
+        let _ = self.thread.join();
+    }
+}
+
+fn run(frames: FrameReceiver, options: Arc<Mutex<Options>>,
+    results: SyncSender<Result<Processed, Error>>, spare: Receiver<RgbImage>,
+    calibration: Receiver<Masters>)
+{
+    // Demosaic, then stretch the data for display (see [Stretch])
+    let mut pipeline = Pipeline::new(Demosaic::Bilinear);
+    let mut raw_view = RawView::new();
+    let mut white_balance = None;
+    let mut tonemap = None;
+    let mut masters: Masters = (None, None);
+    // Whether the dark and flat are set on the pipeline
+    let mut applied = None;
+    // Moves the zebra stripes with every frame
+    let mut phase = 0;
+
+    while let Some(res) = frames.recv() {
+        let frame = match res {
+            Ok(frame) => frame,
+            Err(e) => { let _ = results.send(Err(e)); return; },
+        };
+        let start = Instant::now();
+        let opts = *options.lock().unwrap();
+        if opts.white_balance != white_balance {
+            white_balance = opts.white_balance;
+            pipeline.set_white_balance(white_balance.map(|[r, g, b]| {
+                WhiteBalance::Manual { r, g, b }
+            }));
+        }
+
+        // Masters only apply to frames of the same size and depth
+        while let Ok(new) = calibration.try_recv() {
+            masters = new;
+            applied = None;
+        }
+        let fits = |info: &CalibrationInfo| {
+            opts.calibration && info.width == frame.width && info.height == frame.height
+                && info.bpp == frame.bpp
+        };
+        let want = (masters.0.as_ref().is_some_and(|d| fits(d.info())),
+            masters.1.as_ref().is_some_and(|f| fits(f.info())));
+        if applied != Some(want) {
+            applied = Some(want);
+            pipeline.set_dark(want.0.then(|| masters.0.clone()).flatten());
+            pipeline.set_flat(want.1.then(|| masters.1.clone()).flatten());
+        }
+
+        let stretch = opts.stretch.tonemap(&frame);
+        if tonemap != Some(stretch) {
+            tonemap = Some(stretch);
+            pipeline.set_tonemap(stretch);
+        }
+
+        // Process the raw frame
+        let rgb = if opts.view == View::Color {
+            match pipeline.run(&frame) {
+                Ok(rgb) => rgb,
+                Err(e) => { println!("couldn't process frame: {:?}", e); continue; },
+            }
+        } else {
+            raw_view.view = opts.view;
+            raw_view.render(&frame)
+        };
+        let mut image = spare.try_recv().unwrap_or(RgbImage {
+            width: 0, height: 0, data: Vec::new()
+        });
+        image.width = rgb.width;
+        image.height = rgb.height;
+        image.data.clear();
+        image.data.extend_from_slice(&rgb.data);
+
+        if opts.peaking { focus::peak(&mut image); }
+        if let Some(threshold) = opts.zebra {
+            zebra::paint(&frame, threshold, &mut image, phase);
+            phase += 1;
+        }
+        let histogram = opts.histogram.then(|| histogram::columns(&frame));
+        let focus = if opts.focus { focus::metric(&frame) } else { None };
+
+        let processed = Processed { frame, image, histogram, focus, elapsed: start.elapsed() };
+        if results.send(Ok(processed)).is_err() { return; }
+    }
+}
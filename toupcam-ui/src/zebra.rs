@@ -0,0 +1,61 @@
+//! Zebra stripes over overexposed areas (the `Z` key).
+//!
+//! Pixels at or above a threshold in the raw frame (see
+//! [Frame::threshold_mask]) get moving diagonal stripes in the preview, so
+//! clipping stands out even in a stretched image. `[` and `]` lower and
+//! raise the threshold.
+
+use toupcam::Frame;
+use toupcam::demosaic::RgbImage;
+
+/// Width of a stripe, in image pixels.
+const STRIPE: usize = 4;
+/// Step for `[` and `]` (a fraction of full scale).
+const THRESHOLD_STEP: f64 = 0.01;
+
+pub struct Zebra {
+    pub visible: bool,
+    /// Threshold as a fraction of full scale
+    threshold: f64,
+}
+impl Zebra {
+    pub fn new() -> Self {
+        Self { visible: false, threshold: 0.98 }
+    }
+
+    pub fn threshold(&self) -> f64 { self.threshold }
+
+    /// Set the threshold (a fraction of full scale, at least 0.5).
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold.clamp(0.5, 1.0);
+    }
+
+    /// Raise (or lower, for negative `steps`) the threshold.
+    pub fn adjust(&mut self, steps: i32) {
+        self.threshold = (self.threshold + steps as f64 * THRESHOLD_STEP).clamp(0.5, 1.0);
+    }
+}
+
+/// Paint stripes over the pixels of `frame` at or above `threshold` (a
+/// fraction of full scale) in an image, which may be a different size than
+/// the frame (i.e. downscaled). `phase` moves the stripes.
+pub fn paint(frame: &Frame, threshold: f64, img: &mut RgbImage, phase: usize) {
+    let (fw, fh) = (frame.width, frame.height);
+    let (width, height) = (img.width, img.height);
+    if fw == 0 || fh == 0 { return; }
+    let threshold = (frame.max_value() as f64 * threshold).round() as u16;
+    let mask = frame.threshold_mask(threshold);
+    if mask.len() < fw * fh { return; }
+    let phase = phase % (STRIPE * 2);
+    for y in 0..height {
+        let my = y * fh / height;
+        for x in 0..width {
+            let mx = x * fw / width;
+            if mask[my * fw + mx] == 0 { continue; }
+            let on = ((x + y + phase) / STRIPE).is_multiple_of(2);
+            let p = (y * width + x) * 3;
+            let color = if on { [255, 0, 255] } else { [0, 0, 0] };
+            img.data[p..p + 3].copy_from_slice(&color);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Moving completed capture files off of the local disk.
+//!
+//! An [Archiver] hands finished files (i.e. completed sequence segments) to
+//! an [ArchiveBackend] on a background thread, retrying failed transfers and
+//! checking the SHA1 digest of the copy before (optionally) deleting the
+//! local file.
+//!
+//! Two backends are provided:
+//!
+//! - [CopyBackend] copies into a directory (i.e. an NFS/SMB mount)
+//! - [CommandBackend] runs an external tool (i.e. `aws s3 cp`, `rclone`),
+//!   which is how S3-compatible object storage is supported without pulling
+//!   an HTTP client into the crate
+//!
+
+use crate::Error;
+use std::fs::File;
+use std::io::{ self, Read };
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+use std::sync::mpsc::{ channel, Sender, Receiver };
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crypto::sha1::Sha1;
+use crypto::digest::Digest;
+
+/// Compute the SHA1 digest of a file (as a hex string).
+pub fn sha1_file(path: &Path) -> io::Result<String> {
+    let mut f = File::open(path)?;
+    let mut d = Sha1::new();
+    let mut buf = vec![0u8; 0x0010_0000];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 { break; }
+        d.input(&buf[..n]);
+    }
+    Ok(d.result_str())
+}
+
+/// A place where completed files can be stored.
+pub trait ArchiveBackend: Send {
+    /// Store the local file at `src` under the name `name`.
+    fn store(&mut self, src: &Path, name: &str) -> io::Result<()>;
+
+    /// Return the SHA1 digest of the stored copy of `name`, if the backend
+    /// is able to compute it.
+    fn remote_sha1(&mut self, name: &str) -> io::Result<Option<String>>;
+}
+
+/// Copy files into a directory (typically a network mount).
+pub struct CopyBackend { dest: PathBuf }
+impl CopyBackend {
+    pub fn new(dest: impl Into<PathBuf>) -> Self {
+        Self { dest: dest.into() }
+    }
+}
+impl ArchiveBackend for CopyBackend {
+    fn store(&mut self, src: &Path, name: &str) -> io::Result<()> {
+        // Copy to a temporary name first so that readers on the other end
+        // never see a partially-written file.
+        let tmp = self.dest.join(format!(".{}.part", name));
+        std::fs::copy(src, &tmp)?;
+        File::open(&tmp)?.sync_all()?;
+        std::fs::rename(&tmp, self.dest.join(name))
+    }
+    fn remote_sha1(&mut self, name: &str) -> io::Result<Option<String>> {
+        sha1_file(&self.dest.join(name)).map(Some)
+    }
+}
+
+/// Run an external command to store each file.
+///
+/// Arguments may contain `{src}` (the local path) and `{name}` (the file
+/// name), which are substituted before running the command, i.e.
+///
+/// ```text
+/// CommandBackend::new("aws", &["s3", "cp", "{src}", "s3://bucket/{name}"])
+/// ```
+///
+/// If a verification command is given, it's expected to print the SHA1
+/// digest of the remote object somewhere in its output
+/// (i.e. `rclone sha1sum remote:bucket/{name}`).
+pub struct CommandBackend {
+    store: Vec<String>,
+    verify: Option<Vec<String>>,
+}
+impl CommandBackend {
+    pub fn new(program: &str, args: &[&str]) -> Self {
+        let store = std::iter::once(program).chain(args.iter().copied())
+            .map(String::from).collect();
+        Self { store, verify: None }
+    }
+    pub fn with_verify(mut self, program: &str, args: &[&str]) -> Self {
+        self.verify = Some(std::iter::once(program).chain(args.iter().copied())
+            .map(String::from).collect());
+        self
+    }
+
+    fn run(cmd: &[String], src: &Path, name: &str) -> io::Result<String> {
+        let args: Vec<String> = cmd.iter().map(|a| {
+            a.replace("{src}", &src.to_string_lossy()).replace("{name}", name)
+        }).collect();
+        let out = Command::new(&args[0]).args(&args[1..]).output()?;
+        if !out.status.success() {
+            return Err(io::Error::other(format!("'{}' exited with {}",
+                args[0], out.status)));
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+}
+impl ArchiveBackend for CommandBackend {
+    fn store(&mut self, src: &Path, name: &str) -> io::Result<()> {
+        Self::run(&self.store, src, name).map(|_| ())
+    }
+    fn remote_sha1(&mut self, name: &str) -> io::Result<Option<String>> {
+        match &self.verify {
+            Some(cmd) => {
+                let out = Self::run(cmd, Path::new(""), name)?;
+                Ok(out.split_whitespace()
+                    .find(|w| w.len() == 40 && w.chars().all(|c| c.is_ascii_hexdigit()))
+                    .map(|w| w.to_ascii_lowercase()))
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+/// Policy for retrying failed transfers.
+#[derive(Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    /// Number of attempts before giving up on a file
+    pub attempts: usize,
+    /// Delay before the first retry (doubled after each failure)
+    pub backoff: Duration,
+}
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self { attempts: 5, backoff: Duration::from_secs(1) }
+    }
+}
+
+/// Result of archiving a single file.
+#[derive(Debug)]
+pub struct ArchiveResult {
+    pub path: PathBuf,
+    pub result: Result<(), Error>,
+}
+
+/// Archive a single file, retrying and verifying according to `policy`.
+pub fn archive_file(backend: &mut dyn ArchiveBackend, policy: RetryPolicy,
+    path: &Path) -> Result<(), Error>
+{
+    let name = path.file_name().ok_or(Error::InvalidArgument)?
+        .to_string_lossy().into_owned();
+    let local = sha1_file(path)?;
+
+    let mut delay = policy.backoff;
+    let mut last = None;
+    for attempt in 0..policy.attempts.max(1) {
+        if attempt != 0 {
+            std::thread::sleep(delay);
+            delay *= 2;
+        }
+        let res = backend.store(path, &name).and_then(|_| {
+            match backend.remote_sha1(&name)? {
+                Some(remote) if remote != local => {
+                    Err(io::Error::other(format!(
+                        "checksum mismatch for {} ({} != {})", name, remote, local)))
+                },
+                _ => Ok(()),
+            }
+        });
+        match res {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                eprintln!("archive attempt {} for {} failed: {}", attempt, name, e);
+                last = Some(e);
+            },
+        }
+    }
+    Err(Error::Io(last.unwrap()))
+}
+
+/// Archives completed files on a background thread.
+pub struct Archiver {
+    tx: Option<Sender<PathBuf>>,
+    results: Receiver<ArchiveResult>,
+    handle: Option<JoinHandle<()>>,
+}
+impl Archiver {
+    /// Start the archival thread.
+    ///
+    /// When `remove_local` is set, local files are deleted after they've been
+    /// stored and verified.
+    pub fn spawn(mut backend: Box<dyn ArchiveBackend>, policy: RetryPolicy,
+        remove_local: bool) -> Self
+    {
+        let (tx, rx) = channel::<PathBuf>();
+        let (res_tx, results) = channel();
+        let handle = std::thread::spawn(move || {
+            for path in rx.iter() {
+                let mut result = archive_file(backend.as_mut(), policy, &path);
+                if result.is_ok() && remove_local {
+                    result = std::fs::remove_file(&path).map_err(Error::from);
+                }
+                let _ = res_tx.send(ArchiveResult { path, result });
+            }
+        });
+        Self { tx: Some(tx), results, handle: Some(handle) }
+    }
+
+    /// Queue a completed file for archival.
+    pub fn submit(&self, path: impl Into<PathBuf>) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(path.into());
+        }
+    }
+
+    /// Collect the results for files that have finished (or failed).
+    pub fn poll(&self) -> Vec<ArchiveResult> {
+        self.results.try_iter().collect()
+    }
+
+    /// Wait for all queued files to be archived.
+    pub fn finish(mut self) -> Vec<ArchiveResult> {
+        self.tx.take();
+        if let Some(h) = self.handle.take() { let _ = h.join(); }
+        self.results.try_iter().collect()
+    }
+}
+impl Drop for Archiver {
+    fn drop(&mut self) {
+        self.tx.take();
+        if let Some(h) = self.handle.take() { let _ = h.join(); }
+    }
+}
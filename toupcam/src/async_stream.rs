@@ -0,0 +1,91 @@
+//! Frames as an asynchronous [Stream] (with the `async` feature).
+//!
+//! The USB reads are still blocking, so they run on a tokio blocking task
+//! and frames are passed back through a channel.
+
+use crate::{ Error, Camera, Frame };
+use crate::stream::CancelToken;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::task::{ Context, Poll };
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Number of frames buffered between the blocking task and the stream.
+const QUEUE: usize = 4;
+
+/// An asynchronous stream of frames (see [Camera::into_stream]).
+///
+/// Truncated frames ([Error::FirstFrame]) are skipped. An error ends the
+/// stream. Dropping this stops the blocking task, which stops streaming and
+/// drops the camera.
+pub struct FrameStream {
+    rx: Option<mpsc::Receiver<Result<Frame, Error>>>,
+    stop: Arc<AtomicBool>,
+    cancel: CancelToken,
+    task: Option<JoinHandle<Camera>>,
+}
+impl FrameStream {
+    /// Stop streaming and return the camera.
+    pub async fn stop(mut self) -> Camera {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        // Closing the channel unblocks the task if it's waiting to send
+        self.rx = None;
+        match self.task.take().unwrap().await {
+            Ok(cam) => cam,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+impl Stream for FrameStream {
+    type Item = Result<Frame, Error>;
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut().rx.as_mut() {
+            Some(rx) => rx.poll_recv(cx),
+            None => Poll::Ready(None),
+        }
+    }
+}
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+    }
+}
+
+impl Camera {
+    /// Start streaming, yielding frames as a [Stream].
+    ///
+    /// This must be called from within a tokio runtime.
+    pub fn into_stream(self) -> FrameStream {
+        let (tx, rx) = mpsc::channel(QUEUE);
+        let stop = Arc::new(AtomicBool::new(false));
+        let task_stop = stop.clone();
+        let cancel = self.cancel_token();
+        let mut cam = self;
+        let task = tokio::task::spawn_blocking(move || {
+            let mut res = cam.start_stream();
+            while res.is_ok() && !task_stop.load(Ordering::Relaxed) {
+                let frame = match cam.read_frame() {
+                    Err(Error::FirstFrame) => continue,
+                    Err(Error::Cancelled) if task_stop.load(Ordering::Relaxed) => break,
+                    Err(e) => { res = Err(e); break; },
+                    Ok(frame) => frame,
+                };
+                if tx.blocking_send(Ok(frame)).is_err() { break; }
+            }
+            if let Err(e) = res {
+                let _ = tx.blocking_send(Err(e));
+            }
+            cam.cancel.take();
+            if let Err(e) = cam.stop_stream() {
+                eprintln!("Couldn't stop streaming? {:?}", e);
+            }
+            cam
+        });
+        FrameStream { rx: Some(rx), stop, cancel, task: Some(task) }
+    }
+}
@@ -0,0 +1,55 @@
+//! Capturing a sequence of frames at different exposures.
+
+use crate::{ Error, Camera, Frame };
+use std::time::Duration;
+
+impl Camera {
+    /// Capture one frame at each of the given exposure times (in 
+    /// microseconds), starting the stream if necessary.
+    ///
+    /// After each exposure change, the number of frames given by the protocol
+    /// descriptor's `exposure_latency` are read and discarded, so that each
+    /// returned frame was actually taken with the requested exposure. The 
+    /// previous exposure time is restored afterwards, and a stream started
+    /// here is stopped again.
+    pub fn capture_bracket(&mut self, exposures_us: &[u64]) -> Result<Vec<Frame>, Error> {
+        let saved = self.exposure;
+        let was_streaming = self.streaming;
+        if !was_streaming {
+            self.start_stream()?;
+        }
+        let res = self.capture_bracket_inner(exposures_us);
+        // A failed capture is the more useful error to return, then a failed
+        // restore
+        let restored = self.set_exposure_time(saved);
+        let stopped = if was_streaming { Ok(()) } else { self.stop_stream() };
+        let frames = res?;
+        restored?;
+        stopped?;
+        Ok(frames)
+    }
+
+    fn capture_bracket_inner(&mut self, exposures_us: &[u64]) -> Result<Vec<Frame>, Error> {
+        let latency = self.protocol.exposure_latency;
+        let mut frames = Vec::with_capacity(exposures_us.len());
+        for us in exposures_us.iter() {
+            self.set_exposure_time(Duration::from_micros(*us))?;
+            for _ in 0..latency {
+                self.read_complete_frame()?;
+            }
+            frames.push(self.read_complete_frame()?);
+        }
+        Ok(frames)
+    }
+
+    /// Read a frame, skipping truncated ones.
+    pub (crate) fn read_complete_frame(&mut self) -> Result<Frame, Error> {
+        loop {
+            match self.read_frame() {
+                Err(Error::FirstFrame) => continue,
+                Ok(frame) if !frame.complete => continue,
+                res => return res,
+            }
+        }
+    }
+}
@@ -0,0 +1,921 @@
+//! Calibration frames (master darks and flats).
+//!
+//! A master dark is the average of several frames taken with the lens
+//! capped, at the same exposure time and gain as the frames it's meant to
+//! correct. Subtracting it removes thermal signal and hot pixels, which
+//! dominate long exposures on this sensor. Once set with [Camera::set_dark],
+//! it's subtracted from every frame the camera returns.
+//!
+//! A master flat is the average of several frames of an evenly lit field.
+//! It records how much each pixel's response falls short of its channel's
+//! mean (from vignetting, dust, etc.), and is applied as a per-pixel gain
+//! after dark subtraction (see [Camera::set_flat]).
+//!
+//! Fixed-pattern noise (FPN) shows up at high gain as faint vertical and
+//! horizontal banding. A [FpnCorrection] holds per-column and per-row
+//! offsets measured from bias frames (the shortest possible exposure),
+//! which are subtracted after the dark (see [Camera::set_fpn]). A master
+//! dark taken at the same settings already includes the banding, so the two
+//! don't usually need to be combined.
+//!
+//! A defect map lists hot (or otherwise defective) pixels, usually found in
+//! a master dark. Each of them is replaced by the average of its nearest
+//! neighbours of the same color (see [Camera::set_defects]). The factory
+//! defect list, if the EEPROM has one, isn't understood yet;
+//! [Camera::read_eeprom] returns the raw contents for anyone looking.
+//!
+//! Calibration files are an 8-byte magic number followed by a header and
+//! the samples:
+//!
+//! | Field     | Type                                     |
+//! |-----------|------------------------------------------|
+//! | kind      | `u8` (0 dark, 1 flat, 2 defects, 3 FPN)  |
+//! | width     | `u32`                                    |
+//! | height    | `u32`                                    |
+//! | bpp       | `u8`                                     |
+//! | exposure  | `u64` (microseconds)                     |
+//! | gain      | `u16`                                    |
+//! | frames    | `u32` (number of frames averaged)        |
+//! | data      | `u16` (dark) or `f32` (flat) per pixel   |
+//!
+//! A defect map has a `u32` count followed by the `u32` index (`y * width +
+//! x`) of each defective pixel instead of per-pixel data, and an FPN
+//! correction has an `f32` offset for each column followed by one for each
+//! row.
+//!
+//! All values are little-endian.
+
+use crate::{ Error, Camera, Frame, FrameInfo };
+use crate::cfa::Cfa;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Read, Write };
+use std::path::Path;
+use std::time::Duration;
+
+/// Magic number at the start of every calibration file.
+pub const MAGIC: [u8; 8] = *b"TPCAL\0\0\x01";
+
+const KIND_DARK: u8 = 0;
+const KIND_FLAT: u8 = 1;
+const KIND_DEFECTS: u8 = 2;
+const KIND_FPN: u8 = 3;
+
+/// Conditions a calibration frame was captured under.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CalibrationInfo {
+    pub width: usize,
+    pub height: usize,
+    /// Number of bytes per pixel
+    pub bpp: usize,
+    pub exposure: Duration,
+    pub gain: u16,
+    /// Number of frames averaged
+    pub frames: u32,
+}
+impl CalibrationInfo {
+    /// Size of the header written by [CalibrationInfo::write] (in bytes).
+    const LEN: u64 = 8 + 1 + 4 + 4 + 1 + 8 + 2 + 4;
+
+    /// Returns 'true' if this matches the current settings of `cam`.
+    pub fn matches(&self, cam: &Camera) -> bool {
+        let (width, height) = cam.mode.dimensions();
+        self.width == width && self.height == height
+            && self.bpp == cam.depth_bpp()
+            && self.exposure == cam.exposure && self.gain == cam.gain
+    }
+
+    /// Returns [Error::InvalidArgument] unless this matches the current
+    /// resolution and bit depth of `cam`.
+    fn check_fits(&self, cam: &Camera) -> Result<(), Error> {
+        let (width, height) = cam.mode.dimensions();
+        if self.width != width || self.height != height || self.bpp != cam.depth_bpp() {
+            return Err(Error::InvalidArgument);
+        }
+        Ok(())
+    }
+
+    fn write(&self, kind: u8, w: &mut impl Write) -> Result<(), Error> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&[kind])?;
+        w.write_all(&(self.width as u32).to_le_bytes())?;
+        w.write_all(&(self.height as u32).to_le_bytes())?;
+        w.write_all(&[self.bpp as u8])?;
+        w.write_all(&(self.exposure.as_micros() as u64).to_le_bytes())?;
+        w.write_all(&self.gain.to_le_bytes())?;
+        w.write_all(&self.frames.to_le_bytes())?;
+        Ok(())
+    }
+
+    /// Number of pixels in a frame, unless that overflows.
+    fn pixels(&self) -> Option<usize> {
+        self.width.checked_mul(self.height)
+    }
+
+    /// Read a value of `size` bytes for every pixel, following the header
+    /// in a file that's `len` bytes long.
+    fn read_pixels(&self, r: &mut impl Read, size: usize, len: u64) -> Result<Vec<u8>, Error> {
+        read_values(r, self.pixels(), size, len.saturating_sub(Self::LEN))
+    }
+
+    fn read(kind: u8, r: &mut impl Read) -> Result<Self, Error> {
+        let mut hdr = [0u8; Self::LEN as usize];
+        r.read_exact(&mut hdr)?;
+        if hdr[..8] != MAGIC {
+            return Err(Error::Format("not a calibration file".to_string()));
+        }
+        if hdr[8] != kind {
+            return Err(Error::Format(format!("unexpected calibration kind {}", hdr[8])));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(hdr[i..i + 4].try_into().unwrap());
+        let info = Self {
+            width: u32_at(9) as usize,
+            height: u32_at(13) as usize,
+            bpp: hdr[17] as usize,
+            exposure: Duration::from_micros(u64::from_le_bytes(hdr[18..26].try_into().unwrap())),
+            gain: u16::from_le_bytes([hdr[26], hdr[27]]),
+            frames: u32_at(28),
+        };
+        if info.bpp == 0 || info.bpp > 2 {
+            return Err(Error::Format(format!("unsupported bytes per pixel {}", info.bpp)));
+        }
+        if info.pixels().is_none_or(|n| n == 0) {
+            return Err(Error::Format(format!("bad dimensions {}x{}", info.width, info.height)));
+        }
+        Ok(info)
+    }
+}
+
+/// Read `count` values of `size` bytes each, after checking that they fit in
+/// the `left` bytes left in the file (so a corrupt header can't ask for a
+/// huge buffer).
+fn read_values(r: &mut impl Read, count: Option<usize>, size: usize, left: u64)
+    -> Result<Vec<u8>, Error>
+{
+    let len = count.and_then(|n| n.checked_mul(size)).filter(|n| *n as u64 <= left)
+        .ok_or_else(|| Error::Format("calibration file is truncated".to_string()))?;
+    let mut raw = vec![0u8; len];
+    r.read_exact(&mut raw)?;
+    Ok(raw)
+}
+
+/// Read `count` complete frames, starting the stream if necessary, and
+/// return the per-pixel sums. The flat, FPN correction and defect map
+/// currently set on the camera aren't applied to these frames, and neither
+/// is the dark unless `with_dark` is set. A stream started here is stopped
+/// again afterwards.
+fn sum_frames(cam: &mut Camera, count: u32, with_dark: bool)
+    -> Result<(CalibrationInfo, Vec<u32>), Error>
+{
+    if count == 0 { return Err(Error::InvalidArgument); }
+    let was_streaming = cam.streaming;
+    if !was_streaming {
+        cam.start_stream()?;
+    }
+    let flat = cam.flat.take();
+    let fpn = cam.fpn.take();
+    let defects = cam.defects.take();
+    let dark = if with_dark { cam.dark.clone() } else { cam.dark.take() };
+    let res: Result<_, Error> = (|| {
+        let mut sum: Vec<u32> = Vec::new();
+        let mut info = None;
+        for _ in 0..count {
+            let frame = cam.read_complete_frame()?;
+            if sum.is_empty() {
+                sum = vec![0; frame.width * frame.height];
+            }
+            for (idx, s) in sum.iter_mut().enumerate() {
+                *s += frame.sample(idx) as u32;
+            }
+            info = Some(CalibrationInfo { width: frame.width, height: frame.height,
+                bpp: frame.bpp, exposure: cam.exposure, gain: cam.gain, frames: count,
+            });
+        }
+        Ok((info.unwrap(), sum))
+    })();
+    cam.dark = dark;
+    cam.flat = flat;
+    cam.fpn = fpn;
+    cam.defects = defects;
+    let stopped = if was_streaming { Ok(()) } else { cam.stop_stream() };
+    let res = res?;
+    stopped?;
+    Ok(res)
+}
+
+/// Add up the samples in `frames` (which must all be complete and have the
+/// same dimensions).
+fn sum_captured(frames: &[Frame], exposure: Duration, gain: u16)
+    -> Result<(CalibrationInfo, Vec<u32>), Error>
+{
+    let first = frames.first().ok_or(Error::InvalidArgument)?;
+    if frames.iter().any(|f| !f.complete || f.width != first.width
+        || f.height != first.height || f.bpp != first.bpp)
+    {
+        return Err(Error::InvalidArgument);
+    }
+    let mut sum = vec![0u32; first.width * first.height];
+    for frame in frames {
+        for (idx, s) in sum.iter_mut().enumerate() {
+            *s += frame.sample(idx) as u32;
+        }
+    }
+    let info = CalibrationInfo { width: first.width, height: first.height,
+        bpp: first.bpp, exposure, gain, frames: frames.len() as u32,
+    };
+    Ok((info, sum))
+}
+
+/// A master dark frame.
+#[derive(Clone, Debug)]
+pub struct MasterDark {
+    info: CalibrationInfo,
+    data: Vec<u16>,
+}
+impl MasterDark {
+    /// Capture a master dark by averaging `count` frames with the current
+    /// exposure time and gain. The lens should be capped.
+    pub fn capture(cam: &mut Camera, count: u32) -> Result<Self, Error> {
+        let (info, sum) = sum_frames(cam, count, false)?;
+        Ok(Self::from_sum(info, &sum))
+    }
+
+    /// Build a master dark from frames that have already been captured.
+    ///
+    /// All frames must be complete and have the same dimensions.
+    pub fn from_frames(frames: &[Frame], exposure: Duration, gain: u16)
+        -> Result<Self, Error>
+    {
+        let (info, sum) = sum_captured(frames, exposure, gain)?;
+        Ok(Self::from_sum(info, &sum))
+    }
+
+    fn from_sum(info: CalibrationInfo, sum: &[u32]) -> Self {
+        let count = info.frames;
+        let data = sum.iter().map(|s| ((s + count / 2) / count) as u16).collect();
+        Self { info, data }
+    }
+
+    pub fn info(&self) -> &CalibrationInfo { &self.info }
+
+    /// The averaged samples (row-major).
+    pub fn samples(&self) -> &[u16] { &self.data }
+
+    /// Save to a calibration file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<(), Error> {
+        self.info.write(KIND_DARK, w)?;
+        for v in self.data.iter() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load a calibration file written by [MasterDark::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read(&mut BufReader::new(file), len)
+    }
+
+    /// Read a calibration file that's `len` bytes long.
+    fn read(r: &mut impl Read, len: u64) -> Result<Self, Error> {
+        let info = CalibrationInfo::read(KIND_DARK, r)?;
+        let raw = info.read_pixels(r, 2, len)?;
+        let data = raw.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect();
+        Ok(Self { info, data })
+    }
+
+    /// Subtract the dark from raw frame data (clamping at zero).
+    ///
+    /// `data` may be shorter than a full frame (i.e. a truncated frame), in
+    /// which case only the samples present are corrected.
+    pub fn subtract_raw(&self, data: &mut [u8], bpp: usize) -> Result<(), Error> {
+        if bpp != self.info.bpp || data.len() > self.data.len() * bpp {
+            return Err(Error::InvalidArgument);
+        }
+        match bpp {
+            2 => for (b, d) in data.chunks_exact_mut(2).zip(&self.data) {
+                let v = u16::from_le_bytes([b[0], b[1]]).saturating_sub(*d);
+                b.copy_from_slice(&v.to_le_bytes());
+            },
+            _ => for (b, d) in data.iter_mut().zip(&self.data) {
+                *b = b.saturating_sub(*d as u8);
+            },
+        }
+        Ok(())
+    }
+
+    /// Subtract the dark from a frame.
+    pub fn subtract(&self, frame: &mut Frame) -> Result<(), Error> {
+        if frame.width != self.info.width || frame.height != self.info.height {
+            return Err(Error::InvalidArgument);
+        }
+        self.subtract_raw(&mut frame.data, frame.bpp)
+    }
+}
+
+/// A master flat: a per-pixel gain correcting uneven illumination.
+#[derive(Clone, Debug)]
+pub struct MasterFlat {
+    info: CalibrationInfo,
+    gain: Vec<f32>,
+}
+impl MasterFlat {
+    /// Capture a master flat by averaging `count` frames of an evenly lit
+    /// field. The dark set on the camera (if any) is subtracted first.
+    ///
+    /// The exposure should put the field at roughly half of full scale, with
+    /// no clipped pixels.
+    pub fn capture(cam: &mut Camera, count: u32) -> Result<Self, Error> {
+        let cfa = cam.cfa();
+        let (info, sum) = sum_frames(cam, count, true)?;
+        Ok(Self::from_sum(info, &sum, cfa))
+    }
+
+    /// Build a master flat from frames that have already been captured (and
+    /// dark-subtracted, if necessary).
+    ///
+    /// All frames must be complete and have the same dimensions.
+    pub fn from_frames(frames: &[Frame], exposure: Duration, gain: u16)
+        -> Result<Self, Error>
+    {
+        let (info, sum) = sum_captured(frames, exposure, gain)?;
+        Ok(Self::from_sum(info, &sum, frames[0].cfa))
+    }
+
+    /// Each pixel's gain is the mean of its color channel divided by its own
+    /// value, so the correction doesn't change the color balance.
+    fn from_sum(info: CalibrationInfo, sum: &[u32], cfa: Cfa) -> Self {
+        let w = info.width.max(1);
+        let mut total = [0f64; 3];
+        let mut count = [0usize; 3];
+        for (idx, s) in sum.iter().enumerate() {
+            let c = cfa.color_at(idx % w, idx / w) as usize;
+            total[c] += *s as f64;
+            count[c] += 1;
+        }
+        let mean: Vec<f64> = (0..3).map(|c| total[c] / count[c].max(1) as f64).collect();
+        let gain = sum.iter().enumerate().map(|(idx, s)| {
+            let c = cfa.color_at(idx % w, idx / w) as usize;
+            if *s == 0 { 1.0 } else { (mean[c] / *s as f64) as f32 }
+        }).collect();
+        Self { info, gain }
+    }
+
+    pub fn info(&self) -> &CalibrationInfo { &self.info }
+
+    /// The per-pixel gains (row-major).
+    pub fn gains(&self) -> &[f32] { &self.gain }
+
+    /// Save to a calibration file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<(), Error> {
+        self.info.write(KIND_FLAT, w)?;
+        for v in self.gain.iter() {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load a calibration file written by [MasterFlat::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read(&mut BufReader::new(file), len)
+    }
+
+    /// Read a calibration file that's `len` bytes long.
+    fn read(r: &mut impl Read, len: u64) -> Result<Self, Error> {
+        let info = CalibrationInfo::read(KIND_FLAT, r)?;
+        let raw = info.read_pixels(r, 4, len)?;
+        let gain = raw.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        Ok(Self { info, gain })
+    }
+
+    /// Apply the flat to raw frame data (clamping at full scale).
+    ///
+    /// `data` may be shorter than a full frame (i.e. a truncated frame), in
+    /// which case only the samples present are corrected.
+    pub fn apply_raw(&self, data: &mut [u8], bpp: usize) -> Result<(), Error> {
+        if bpp != self.info.bpp || data.len() > self.gain.len() * bpp {
+            return Err(Error::InvalidArgument);
+        }
+        match bpp {
+            2 => for (b, g) in data.chunks_exact_mut(2).zip(&self.gain) {
+                let v = u16::from_le_bytes([b[0], b[1]]) as f32 * g;
+                let v = (v.round() as u16).min(0x0fff);
+                b.copy_from_slice(&v.to_le_bytes());
+            },
+            _ => for (b, g) in data.iter_mut().zip(&self.gain) {
+                *b = (*b as f32 * g).round() as u8;
+            },
+        }
+        Ok(())
+    }
+
+    /// Apply the flat to a frame.
+    pub fn apply(&self, frame: &mut Frame) -> Result<(), Error> {
+        if frame.width != self.info.width || frame.height != self.info.height {
+            return Err(Error::InvalidArgument);
+        }
+        self.apply_raw(&mut frame.data, frame.bpp)
+    }
+}
+
+/// A list of defective pixels.
+#[derive(Clone, Debug)]
+pub struct DefectMap {
+    info: CalibrationInfo,
+    /// Pixel indices (sorted)
+    pixels: Vec<usize>,
+}
+impl DefectMap {
+    /// An empty map for frames with the given properties.
+    pub fn new(info: CalibrationInfo) -> Self {
+        Self { info, pixels: Vec::new() }
+    }
+
+    /// Find hot pixels in a master dark: those more than `sigma` standard
+    /// deviations above the mean.
+    ///
+    /// Averaging more frames into the dark makes this more reliable, since
+    /// random noise doesn't survive averaging but hot pixels do.
+    pub fn from_dark(dark: &MasterDark, sigma: f64) -> Self {
+        let n = dark.data.len().max(1) as f64;
+        let mean = dark.data.iter().map(|v| *v as f64).sum::<f64>() / n;
+        let var = dark.data.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / n;
+        let limit = mean + sigma * var.sqrt();
+        let pixels = dark.data.iter().enumerate()
+            .filter(|(_, v)| **v as f64 > limit)
+            .map(|(idx, _)| idx).collect();
+        Self { info: dark.info, pixels }
+    }
+
+    pub fn info(&self) -> &CalibrationInfo { &self.info }
+
+    /// Indices (`y * width + x`) of the defective pixels, in order.
+    pub fn pixels(&self) -> &[usize] { &self.pixels }
+
+    pub fn len(&self) -> usize { self.pixels.len() }
+    pub fn is_empty(&self) -> bool { self.pixels.is_empty() }
+
+    /// Returns 'true' if the pixel at `(x, y)` is in the map.
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        self.pixels.binary_search(&(y * self.info.width + x)).is_ok()
+    }
+
+    /// Add the pixel at `(x, y)` to the map.
+    pub fn insert(&mut self, x: usize, y: usize) {
+        if x >= self.info.width || y >= self.info.height { return; }
+        let idx = y * self.info.width + x;
+        if let Err(pos) = self.pixels.binary_search(&idx) {
+            self.pixels.insert(pos, idx);
+        }
+    }
+
+    /// Add every pixel in `other` to this map (i.e. combining the maps found
+    /// at different exposure times).
+    pub fn merge(&mut self, other: &DefectMap) -> Result<(), Error> {
+        if (other.info.width, other.info.height) != (self.info.width, self.info.height) {
+            return Err(Error::InvalidArgument);
+        }
+        self.pixels.extend_from_slice(&other.pixels);
+        self.pixels.sort_unstable();
+        self.pixels.dedup();
+        Ok(())
+    }
+
+    /// Save to a calibration file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<(), Error> {
+        self.info.write(KIND_DEFECTS, w)?;
+        w.write_all(&(self.pixels.len() as u32).to_le_bytes())?;
+        for idx in self.pixels.iter() {
+            w.write_all(&(*idx as u32).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load a calibration file written by [DefectMap::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read(&mut BufReader::new(file), len)
+    }
+
+    /// Read a calibration file that's `len` bytes long.
+    fn read(r: &mut impl Read, len: u64) -> Result<Self, Error> {
+        let info = CalibrationInfo::read(KIND_DEFECTS, r)?;
+        let total = info.pixels().unwrap_or(0);
+        let mut count = [0u8; 4];
+        r.read_exact(&mut count)?;
+        // There can't be more defects than pixels
+        let count = Some(u32::from_le_bytes(count) as usize).filter(|n| *n <= total);
+        let raw = read_values(r, count, 4, len.saturating_sub(CalibrationInfo::LEN + 4))?;
+        let mut pixels: Vec<usize> = raw.chunks_exact(4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+            .filter(|idx| *idx < total)
+            .collect();
+        pixels.sort_unstable();
+        pixels.dedup();
+        Ok(Self { info, pixels })
+    }
+
+    /// Replace each defective pixel in raw frame data with the average of
+    /// its nearest same-color neighbours (two pixels away, horizontally and
+    /// vertically) which aren't defective themselves.
+    ///
+    /// `data` may be shorter than a full frame (i.e. a truncated frame), in
+    /// which case only the samples present are corrected.
+    pub fn correct_raw(&self, data: &mut [u8], bpp: usize) -> Result<(), Error> {
+        if bpp != self.info.bpp || data.len() > self.info.width * self.info.height * bpp {
+            return Err(Error::InvalidArgument);
+        }
+        let (w, h) = (self.info.width, self.info.height);
+        let len = data.len() / bpp;
+        let get = |data: &[u8], idx: usize| Frame::sample_at(data, bpp, idx) as u32;
+        for &idx in self.pixels.iter().take_while(|idx| **idx < len) {
+            let (x, y) = (idx % w, idx / w);
+            let neighbours = [
+                (x >= 2).then(|| idx - 2),
+                (x + 2 < w).then(|| idx + 2),
+                (y >= 2).then(|| idx - 2 * w),
+                (y + 2 < h).then(|| idx + 2 * w),
+            ];
+            let (mut sum, mut count) = (0, 0);
+            for n in neighbours.into_iter().flatten() {
+                if n < len && self.pixels.binary_search(&n).is_err() {
+                    sum += get(data, n);
+                    count += 1;
+                }
+            }
+            if count == 0 { continue; }
+            let v = (sum + count / 2) / count;
+            match bpp {
+                2 => data[idx * 2..idx * 2 + 2].copy_from_slice(&(v as u16).to_le_bytes()),
+                _ => data[idx] = v as u8,
+            }
+        }
+        Ok(())
+    }
+
+    /// Correct the defective pixels in a frame.
+    pub fn correct(&self, frame: &mut Frame) -> Result<(), Error> {
+        if frame.width != self.info.width || frame.height != self.info.height {
+            return Err(Error::InvalidArgument);
+        }
+        self.correct_raw(&mut frame.data, frame.bpp)
+    }
+}
+
+/// Per-column and per-row offsets (fixed-pattern noise).
+#[derive(Clone, Debug)]
+pub struct FpnCorrection {
+    info: CalibrationInfo,
+    columns: Vec<f32>,
+    rows: Vec<f32>,
+}
+impl FpnCorrection {
+    /// Capture `count` bias frames at the shortest exposure time (with the
+    /// current gain) and measure the offsets. The lens should be capped.
+    ///
+    /// The previous exposure time is restored afterwards, and a stream
+    /// started here is stopped again.
+    pub fn capture(cam: &mut Camera, count: u32) -> Result<Self, Error> {
+        let saved = cam.exposure;
+        let was_streaming = cam.streaming;
+        let (min, _) = cam.protocol.exposure_range();
+        let res = (|| {
+            cam.set_exposure_time(min)?;
+            if !cam.streaming {
+                cam.start_stream()?;
+            }
+            for _ in 0..cam.protocol.exposure_latency {
+                cam.read_complete_frame()?;
+            }
+            sum_frames(cam, count, false)
+        })();
+        // Put the exposure back either way, but report a failed capture first
+        let restored = cam.set_exposure_time(saved);
+        let stopped = if was_streaming { Ok(()) } else { cam.stop_stream() };
+        let (info, sum) = res?;
+        restored?;
+        stopped?;
+        Ok(Self::from_sum(info, &sum))
+    }
+
+    /// Measure the offsets from bias frames that have already been captured.
+    ///
+    /// All frames must be complete and have the same dimensions.
+    pub fn from_frames(frames: &[Frame], exposure: Duration, gain: u16)
+        -> Result<Self, Error>
+    {
+        let (info, sum) = sum_captured(frames, exposure, gain)?;
+        Ok(Self::from_sum(info, &sum))
+    }
+
+    /// Column offsets are each column's mean minus the overall mean; row
+    /// offsets are then measured the same way, after removing the column
+    /// offsets.
+    fn from_sum(info: CalibrationInfo, sum: &[u32]) -> Self {
+        let (w, h) = (info.width, info.height);
+        let n = info.frames as f64;
+        let mean = |s: u32| s as f64 / n;
+        let total = sum.iter().map(|s| mean(*s)).sum::<f64>() / (w * h).max(1) as f64;
+
+        let mut columns = vec![0f32; w];
+        for (x, c) in columns.iter_mut().enumerate() {
+            let col = (0..h).map(|y| mean(sum[y * w + x])).sum::<f64>() / h.max(1) as f64;
+            *c = (col - total) as f32;
+        }
+        let rows = (0..h).map(|y| {
+            let row = (0..w).map(|x| mean(sum[y * w + x]) - columns[x] as f64)
+                .sum::<f64>() / w.max(1) as f64;
+            (row - total) as f32
+        }).collect();
+        Self { info, columns, rows }
+    }
+
+    pub fn info(&self) -> &CalibrationInfo { &self.info }
+
+    /// Offset of each column (in raw sample units).
+    pub fn columns(&self) -> &[f32] { &self.columns }
+
+    /// Offset of each row (in raw sample units).
+    pub fn rows(&self) -> &[f32] { &self.rows }
+
+    /// Save to a calibration file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn write(&self, w: &mut impl Write) -> Result<(), Error> {
+        self.info.write(KIND_FPN, w)?;
+        for v in self.columns.iter().chain(self.rows.iter()) {
+            w.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load a calibration file written by [FpnCorrection::save].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Self::read(&mut BufReader::new(file), len)
+    }
+
+    /// Read a calibration file that's `len` bytes long.
+    fn read(r: &mut impl Read, len: u64) -> Result<Self, Error> {
+        let info = CalibrationInfo::read(KIND_FPN, r)?;
+        let count = info.width.checked_add(info.height);
+        let raw = read_values(r, count, 4, len.saturating_sub(CalibrationInfo::LEN))?;
+        let mut values: Vec<f32> = raw.chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect();
+        // All width + height values were read, so this splits them exactly
+        debug_assert_eq!(values.len(), info.width + info.height);
+        let rows = values.split_off(info.width);
+        Ok(Self { info, columns: values, rows })
+    }
+
+    /// Subtract the offsets from raw frame data (clamping to the valid
+    /// range).
+    ///
+    /// `data` may be shorter than a full frame (i.e. a truncated frame), in
+    /// which case only the samples present are corrected.
+    pub fn apply_raw(&self, data: &mut [u8], bpp: usize) -> Result<(), Error> {
+        let w = self.info.width;
+        if bpp != self.info.bpp || data.len() > w * self.info.height * bpp {
+            return Err(Error::InvalidArgument);
+        }
+        if w == 0 { return Ok(()); }
+        let offset = |idx: usize| self.columns[idx % w] + self.rows[idx / w];
+        match bpp {
+            2 => for (idx, b) in data.chunks_exact_mut(2).enumerate() {
+                let v = u16::from_le_bytes([b[0], b[1]]) as f32 - offset(idx);
+                let v = v.round().clamp(0.0, 0x0fff as f32) as u16;
+                b.copy_from_slice(&v.to_le_bytes());
+            },
+            _ => for (idx, b) in data.iter_mut().enumerate() {
+                *b = (*b as f32 - offset(idx)).round().clamp(0.0, 255.0) as u8;
+            },
+        }
+        Ok(())
+    }
+
+    /// Subtract the offsets from a frame.
+    pub fn apply(&self, frame: &mut Frame) -> Result<(), Error> {
+        if frame.width != self.info.width || frame.height != self.info.height {
+            return Err(Error::InvalidArgument);
+        }
+        self.apply_raw(&mut frame.data, frame.bpp)
+    }
+}
+
+impl Camera {
+    /// Subtract `dark` from every frame read from now on (or stop, if
+    /// `None`).
+    ///
+    /// Returns [Error::InvalidArgument] if the dark doesn't match the current
+    /// resolution and bit depth. A dark taken at a different exposure time or
+    /// gain is accepted, but returns 'false', since it will under- or
+    /// over-correct (see [CalibrationInfo::matches]).
+    pub fn set_dark(&mut self, dark: Option<MasterDark>) -> Result<bool, Error> {
+        let matches = match dark.as_ref() {
+            Some(d) => { d.info.check_fits(self)?; d.info.matches(self) },
+            None => true,
+        };
+        self.dark = dark;
+        Ok(matches)
+    }
+    pub fn get_dark(&self) -> Option<&MasterDark> { self.dark.as_ref() }
+
+    /// Apply `flat` to every frame read from now on, after the dark (or
+    /// stop, if `None`).
+    ///
+    /// Returns [Error::InvalidArgument] if the flat doesn't match the current
+    /// resolution and bit depth.
+    pub fn set_flat(&mut self, flat: Option<MasterFlat>) -> Result<(), Error> {
+        if let Some(f) = flat.as_ref() {
+            f.info.check_fits(self)?;
+        }
+        self.flat = flat;
+        Ok(())
+    }
+    pub fn get_flat(&self) -> Option<&MasterFlat> { self.flat.as_ref() }
+
+    /// Subtract the column and row offsets in `fpn` from every frame read
+    /// from now on, after the dark (or stop, if `None`).
+    ///
+    /// Returns [Error::InvalidArgument] if the correction doesn't match the
+    /// current resolution and bit depth.
+    pub fn set_fpn(&mut self, fpn: Option<FpnCorrection>) -> Result<(), Error> {
+        if let Some(f) = fpn.as_ref() {
+            f.info.check_fits(self)?;
+        }
+        self.fpn = fpn;
+        Ok(())
+    }
+    pub fn get_fpn(&self) -> Option<&FpnCorrection> { self.fpn.as_ref() }
+
+    /// Correct the pixels in `defects` in every frame read from now on,
+    /// after the dark and flat (or stop, if `None`).
+    ///
+    /// Returns [Error::InvalidArgument] if the map doesn't match the current
+    /// resolution and bit depth.
+    pub fn set_defects(&mut self, defects: Option<DefectMap>) -> Result<(), Error> {
+        if let Some(d) = defects.as_ref() {
+            d.info.check_fits(self)?;
+        }
+        self.defects = defects;
+        Ok(())
+    }
+    pub fn get_defects(&self) -> Option<&DefectMap> { self.defects.as_ref() }
+
+    /// Drop all calibration data (when it no longer lines up with the
+    /// frames).
+    pub (crate) fn clear_calibration(&mut self) {
+        self.dark = None;
+        self.fpn = None;
+        self.flat = None;
+        self.defects = None;
+    }
+
+    /// Apply the calibration set on the camera to a frame that was just read.
+    pub (crate) fn calibrate(&self, data: &mut [u8], info: &FrameInfo) -> Result<(), Error> {
+        if let Some(dark) = self.dark.as_ref() {
+            dark.subtract_raw(&mut data[..info.received], info.bpp)?;
+        }
+        if let Some(fpn) = self.fpn.as_ref() {
+            fpn.apply_raw(&mut data[..info.received], info.bpp)?;
+        }
+        if let Some(flat) = self.flat.as_ref() {
+            flat.apply_raw(&mut data[..info.received], info.bpp)?;
+        }
+        if let Some(defects) = self.defects.as_ref() {
+            defects.correct_raw(&mut data[..info.received], info.bpp)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(width: usize, height: usize, bpp: usize) -> CalibrationInfo {
+        CalibrationInfo { width, height, bpp, exposure: Duration::from_millis(20),
+            gain: 0x610c, frames: 8,
+        }
+    }
+
+    /// A header for a `width` by `height` file of `kind`, without any data.
+    fn header(kind: u8, width: u32, height: u32, bpp: u8) -> Vec<u8> {
+        let mut file = Vec::new();
+        info(width as usize, height as usize, bpp as usize).write(kind, &mut file).unwrap();
+        file
+    }
+
+    #[test]
+    fn dark_round_trip() {
+        let dark = MasterDark { info: info(3, 2, 2), data: vec![0, 1, 2, 0x800, 0xffe, 0xfff] };
+        let mut file = Vec::new();
+        dark.write(&mut file).unwrap();
+        let back = MasterDark::read(&mut &file[..], file.len() as u64).unwrap();
+        assert_eq!((back.info, back.data), (dark.info, dark.data));
+    }
+
+    #[test]
+    fn dark_rejects_bad_headers() {
+        let huge = u32::MAX;
+        let mut short = header(KIND_DARK, 3, 2, 2);
+        short.extend_from_slice(&[0; 11]);
+        for file in [header(KIND_DARK, huge, huge, 2), header(KIND_DARK, 0, 2, 2),
+            header(KIND_DARK, 3, 2, 3), header(KIND_FLAT, 3, 2, 2), short]
+        {
+            assert!(MasterDark::read(&mut &file[..], file.len() as u64).is_err(), "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn flat_round_trip() {
+        let flat = MasterFlat { info: info(2, 2, 1), gain: vec![0.5, 1.0, 1.25, 2.0] };
+        let mut file = Vec::new();
+        flat.write(&mut file).unwrap();
+        let back = MasterFlat::read(&mut &file[..], file.len() as u64).unwrap();
+        assert_eq!((back.info, back.gain), (flat.info, flat.gain));
+    }
+
+    #[test]
+    fn flat_rejects_bad_headers() {
+        let mut short = header(KIND_FLAT, 2, 2, 1);
+        short.extend_from_slice(&[0; 15]);
+        for file in [header(KIND_FLAT, u32::MAX, u32::MAX, 1), header(KIND_FLAT, 2, 0, 1), short] {
+            assert!(MasterFlat::read(&mut &file[..], file.len() as u64).is_err(), "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn defects_round_trip() {
+        let mut map = DefectMap::new(info(4, 3, 2));
+        for (x, y) in [(3, 2), (0, 0), (1, 1)] { map.insert(x, y); }
+        let mut file = Vec::new();
+        map.write(&mut file).unwrap();
+        let back = DefectMap::read(&mut &file[..], file.len() as u64).unwrap();
+        assert_eq!((back.info, back.pixels), (map.info, vec![0, 5, 11]));
+    }
+
+    #[test]
+    fn defects_reject_bad_counts() {
+        let file = |count: u32| {
+            let mut file = header(KIND_DEFECTS, 4, 3, 2);
+            file.extend_from_slice(&count.to_le_bytes());
+            file.extend_from_slice(&[0; 8]);
+            file
+        };
+        for file in [file(13), file(3), file(u32::MAX)] {
+            assert!(DefectMap::read(&mut &file[..], file.len() as u64).is_err(), "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn fpn_round_trip() {
+        let fpn = FpnCorrection { info: info(3, 2, 2), columns: vec![-1.5, 0.0, 2.25],
+            rows: vec![0.5, -0.5],
+        };
+        let mut file = Vec::new();
+        fpn.write(&mut file).unwrap();
+        let back = FpnCorrection::read(&mut &file[..], file.len() as u64).unwrap();
+        assert_eq!((back.info, back.columns, back.rows), (fpn.info, fpn.columns, fpn.rows));
+    }
+
+    #[test]
+    fn fpn_rejects_bad_headers() {
+        let mut short = header(KIND_FPN, 3, 2, 2);
+        short.extend_from_slice(&[0; 19]);
+        for file in [header(KIND_FPN, u32::MAX, u32::MAX, 2), header(KIND_FPN, 3, 0, 2), short] {
+            assert!(FpnCorrection::read(&mut &file[..], file.len() as u64).is_err(), "{:?}", file);
+        }
+    }
+}
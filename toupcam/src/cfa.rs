@@ -0,0 +1,107 @@
+//! Color filter array (Bayer pattern) phase.
+
+#[cfg(feature = "processing")]
+use crate::Frame;
+
+/// A color channel in the mosaic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Color { Red, Green, Blue }
+
+/// Phase of the 2x2 Bayer pattern, named after the top-left cell
+/// (read left-to-right, top-to-bottom).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cfa { Rggb, Grbg, Gbrg, Bggr }
+impl Cfa {
+    /// The pattern read out by the MU1603 in the default orientation.
+    pub const DEFAULT: Cfa = Cfa::Rggb;
+
+    /// Returns the color of the pixel at `(x, y)`.
+    pub fn color_at(self, x: usize, y: usize) -> Color {
+        use Color::*;
+        let cells = match self {
+            Self::Rggb => [Red, Green, Green, Blue],
+            Self::Grbg => [Green, Red, Blue, Green],
+            Self::Gbrg => [Green, Blue, Red, Green],
+            Self::Bggr => [Blue, Green, Green, Red],
+        };
+        cells[(y & 1) * 2 + (x & 1)]
+    }
+
+    /// Returns the pattern after mirroring and/or flipping the readout
+    /// (assuming an even width and height).
+    pub fn flipped(self, horizontal: bool, vertical: bool) -> Cfa {
+        let mut res = self;
+        if horizontal {
+            res = match res {
+                Self::Rggb => Self::Grbg, Self::Grbg => Self::Rggb,
+                Self::Gbrg => Self::Bggr, Self::Bggr => Self::Gbrg,
+            };
+        }
+        if vertical {
+            res = match res {
+                Self::Rggb => Self::Gbrg, Self::Gbrg => Self::Rggb,
+                Self::Grbg => Self::Bggr, Self::Bggr => Self::Grbg,
+            };
+        }
+        res
+    }
+
+    /// Offset of the red pixel within the 2x2 cell, as `(x, y)`.
+    pub fn red_offset(self) -> (usize, usize) {
+        match self {
+            Self::Rggb => (0, 0),
+            Self::Grbg => (1, 0),
+            Self::Gbrg => (0, 1),
+            Self::Bggr => (1, 1),
+        }
+    }
+
+    /// Name of the pattern (i.e. `"RGGB"`).
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rggb => "RGGB",
+            Self::Grbg => "GRBG",
+            Self::Gbrg => "GBRG",
+            Self::Bggr => "BGGR",
+        }
+    }
+}
+
+/// Guess the pattern from a frame of a target dominated by a single color.
+///
+/// The frame should be filled (or mostly filled) by a red or blue target.
+/// Green cells are found by looking for the diagonal pair of cells with the
+/// most similar response, and the brighter of the remaining two cells is
+/// assumed to be `dominant`. Returns `None` when `dominant` is green, or if
+/// the frame is empty or truncated.
+#[cfg(feature = "processing")]
+pub fn detect(frame: &Frame, dominant: Color) -> Option<Cfa> {
+    if dominant == Color::Green || frame.width < 2 || frame.height < 2 || !frame.complete {
+        return None;
+    }
+
+    // Mean response of each cell in the 2x2 pattern
+    let mut sums = [0u64; 4];
+    for y in 0..frame.height & !1 {
+        for x in 0..frame.width & !1 {
+            sums[(y & 1) * 2 + (x & 1)] += frame.sample(y * frame.width + x) as u64;
+        }
+    }
+    let m: Vec<f64> = sums.iter().map(|s| *s as f64).collect();
+    let rel = |a: f64, b: f64| (a - b).abs() / (a + b).max(1.0);
+
+    // Either cells 0/3 or cells 1/2 are green
+    let (green_main_diag, a, b) = if rel(m[0], m[3]) <= rel(m[1], m[2]) {
+        (true, m[1], m[2])
+    } else {
+        (false, m[0], m[3])
+    };
+    // Is the first of the remaining cells red?
+    let first_red = (a > b) == (dominant == Color::Red);
+    Some(match (green_main_diag, first_red) {
+        (true, true)   => Cfa::Grbg,
+        (true, false)  => Cfa::Gbrg,
+        (false, true)  => Cfa::Rggb,
+        (false, false) => Cfa::Bggr,
+    })
+}
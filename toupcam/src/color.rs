@@ -0,0 +1,107 @@
+//! Color correction and sRGB encoding of demosaiced images.
+//!
+//! Demosaicing produces linear values in the sensor's own RGB space. A 3x3
+//! color correction matrix (CCM) maps these to linear sRGB, and the sRGB
+//! transfer function ("gamma") then makes them suitable for display.
+
+use crate::Error;
+use crate::demosaic::{ Rgb16Image, RgbImage };
+use crate::par;
+use std::path::Path;
+
+/// A 3x3 color correction matrix, applied as `out = m * [r, g, b]`.
+///
+/// Each row should sum to 1.0 so that white balanced grays stay gray.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorMatrix(pub [[f32; 3]; 3]);
+impl ColorMatrix {
+    pub const IDENTITY: ColorMatrix = ColorMatrix([
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+    ]);
+
+    /// Parse a matrix from text: nine numbers, row by row, separated by
+    /// whitespace or commas. Anything after a `#` is a comment.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut values = Vec::with_capacity(9);
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            for tok in line.split(|c: char| c.is_whitespace() || c == ',') {
+                if tok.is_empty() { continue; }
+                let v = tok.parse::<f32>().map_err(|_| {
+                    Error::Format(format!("invalid matrix entry '{}'", tok))
+                })?;
+                values.push(v);
+            }
+        }
+        if values.len() != 9 {
+            return Err(Error::Format(format!("expected 9 matrix entries, found {}",
+                values.len())));
+        }
+        let mut m = [[0.0; 3]; 3];
+        for (idx, v) in values.into_iter().enumerate() {
+            m[idx / 3][idx % 3] = v;
+        }
+        Ok(Self(m))
+    }
+
+    /// Load a matrix from a text file (see [ColorMatrix::parse]).
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Apply the matrix to every pixel of a (linear) image.
+    pub fn apply(&self, img: &mut Rgb16Image) {
+        let m = self.0;
+        par::for_each_row(&mut img.data, img.width * 3, || (), |_, _, row| {
+            for px in row.chunks_exact_mut(3) {
+                let [r, g, b] = [px[0] as f32, px[1] as f32, px[2] as f32];
+                for (out, mr) in px.iter_mut().zip(m.iter()) {
+                    let v = mr[0] * r + mr[1] * g + mr[2] * b;
+                    *out = v.round().clamp(0.0, u16::MAX as f32) as u16;
+                }
+            }
+        });
+    }
+}
+/// No MU1603 matrix has been measured against a color chart yet, so the
+/// default leaves the sensor's colors as they are.
+impl Default for ColorMatrix {
+    fn default() -> Self { Self::IDENTITY }
+}
+
+/// The sRGB transfer function, for a linear value in `0.0..=1.0`.
+pub fn srgb_encode(v: f32) -> f32 {
+    if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Lookup table for [srgb_encode] over 16-bit values, to `bits`-bit output.
+fn srgb_table(bits: u32) -> Vec<u16> {
+    let max = ((1u32 << bits) - 1) as f32;
+    (0..=u16::MAX).map(|v| {
+        (srgb_encode(v as f32 / u16::MAX as f32) * max).round() as u16
+    }).collect()
+}
+
+/// Apply the sRGB transfer function to a linear image, in place.
+pub fn encode_srgb(img: &mut Rgb16Image) {
+    let table = srgb_table(16);
+    par::for_each_row(&mut img.data, img.width * 3, || (), |_, _, row| {
+        for v in row.iter_mut() { *v = table[*v as usize]; }
+    });
+}
+
+/// Color correct a linear image with `matrix` and encode it as 8-bit sRGB
+/// (i.e. for display or saving as PNG/JPEG).
+pub fn to_srgb8(img: &Rgb16Image, matrix: &ColorMatrix) -> RgbImage {
+    let mut linear = img.clone();
+    matrix.apply(&mut linear);
+    let table = srgb_table(8);
+    let mut data = vec![0u8; linear.data.len()];
+    par::for_each_row(&mut data, img.width * 3, || (), |_, y, row| {
+        let src = &linear.data[y * img.width * 3..];
+        for (d, s) in row.iter_mut().zip(src) { *d = table[*s as usize] as u8; }
+    });
+    RgbImage { width: img.width, height: img.height, data }
+}
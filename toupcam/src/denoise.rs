@@ -0,0 +1,73 @@
+//! Temporal denoising for live previews.
+//!
+//! A [TemporalFilter] keeps a running average of the raw frames passed
+//! through it (an exponential moving average, so there's no buffer of past
+//! frames and no added latency). Pixels that change by more than a
+//! threshold are treated as motion and reset to the new value, which keeps
+//! moving subjects from smearing.
+
+use crate::Frame;
+
+/// Exponential moving average over successive frames, with motion
+/// clamping.
+#[derive(Clone, Debug)]
+pub struct TemporalFilter {
+    alpha: f32,
+    threshold: f32,
+    /// Running average (or empty before the first frame)
+    acc: Vec<f32>,
+    dims: (usize, usize, usize),
+}
+impl TemporalFilter {
+    /// Create a filter which blends in each new frame with weight `alpha`
+    /// (0.0 to 1.0; smaller is smoother but slower to follow changes).
+    ///
+    /// A pixel that differs from the average by more than `threshold` (in
+    /// raw sample units) takes the new value outright.
+    pub fn new(alpha: f32, threshold: u16) -> Self {
+        Self { alpha: alpha.clamp(0.0, 1.0), threshold: threshold as f32,
+            acc: Vec::new(), dims: (0, 0, 0),
+        }
+    }
+
+    pub fn get_alpha(&self) -> f32 { self.alpha }
+    pub fn set_alpha(&mut self, alpha: f32) { self.alpha = alpha.clamp(0.0, 1.0); }
+    pub fn get_threshold(&self) -> u16 { self.threshold as u16 }
+    pub fn set_threshold(&mut self, threshold: u16) { self.threshold = threshold as f32; }
+
+    /// Forget the running average (i.e. after changing exposure).
+    pub fn reset(&mut self) { self.acc.clear(); }
+
+    /// Blend `frame` into the running average, replacing its contents with
+    /// the filtered result.
+    ///
+    /// The average is reset whenever the frame dimensions or bit depth
+    /// change. For a truncated frame, only the samples received are blended.
+    pub fn apply(&mut self, frame: &mut Frame) {
+        let dims = (frame.width, frame.height, frame.bpp);
+        let bpp = frame.bpp.max(1);
+        let len = (frame.data.len() / bpp).min(frame.width * frame.height);
+        if dims != self.dims || self.acc.is_empty() {
+            self.dims = dims;
+            self.acc = (0..frame.width * frame.height)
+                .map(|idx| if idx < len { frame.sample(idx) as f32 } else { 0.0 })
+                .collect();
+            return;
+        }
+
+        let (alpha, threshold) = (self.alpha, self.threshold);
+        let blend = |acc: &mut f32, v: f32| -> f32 {
+            if (v - *acc).abs() > threshold { *acc = v; } else { *acc += alpha * (v - *acc); }
+            acc.round()
+        };
+        match frame.bpp {
+            2 => for (b, acc) in frame.data[..len * 2].chunks_exact_mut(2).zip(&mut self.acc) {
+                let v = blend(acc, u16::from_le_bytes([b[0], b[1]]) as f32) as u16;
+                b.copy_from_slice(&v.to_le_bytes());
+            },
+            _ => for (b, acc) in frame.data[..len].iter_mut().zip(&mut self.acc) {
+                *b = blend(acc, *b as f32) as u8;
+            },
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! Saving raw frames as DNG files.
+//!
+//! A DNG is a TIFF holding the raw mosaic, plus the tags a raw converter
+//! (RawTherapee, darktable, Lightroom) needs to develop it: the CFA pattern,
+//! the black and white levels, and a color matrix. The color matrix is
+//! derived from a [ColorMatrix] (camera RGB to linear sRGB), so files develop
+//! with the same colors as [crate::color::to_srgb8] with that matrix. Until
+//! the MU1603 has been measured, the default is the identity matrix.
+
+use crate::{ Error, Frame };
+use crate::cfa::Color;
+use crate::color::ColorMatrix;
+use crate::metadata::CaptureMetadata;
+use crate::tiff_writer::{ self, Entry };
+use crate::white_balance::WhiteBalance;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+
+/// Linear sRGB to CIE XYZ (D65).
+const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4124, 0.3576, 0.1805],
+    [0.2126, 0.7152, 0.0722],
+    [0.0193, 0.1192, 0.9505],
+];
+
+/// `CalibrationIlluminant1` value for D65.
+const D65: u16 = 21;
+
+/// Options for [Frame::save_dng].
+#[derive(Copy, Clone, Debug)]
+pub struct DngOptions {
+    /// Camera RGB to linear sRGB (used to build the DNG `ColorMatrix1`)
+    pub matrix: ColorMatrix,
+    /// Raw value of black (i.e. the mean of a master dark, if the frame
+    /// hasn't been dark subtracted)
+    pub black_level: u16,
+    /// White balance to record as shot (`None` leaves it to the raw
+    /// converter)
+    pub white_balance: Option<WhiteBalance>,
+}
+impl Default for DngOptions {
+    fn default() -> Self {
+        Self { matrix: ColorMatrix::IDENTITY, black_level: 0,
+            white_balance: Some(WhiteBalance::Auto),
+        }
+    }
+}
+
+/// Invert a 3x3 matrix (or `None` if it's singular).
+fn invert(m: [[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cof = |r0: usize, r1: usize, c0: usize, c1: usize| {
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det = m[0][0] * cof(1, 2, 1, 2) - m[0][1] * cof(1, 2, 0, 2) + m[0][2] * cof(1, 2, 0, 1);
+    if det.abs() < 1e-12 { return None; }
+    Some([
+        [ cof(1, 2, 1, 2) / det, -cof(0, 2, 1, 2) / det,  cof(0, 1, 1, 2) / det],
+        [-cof(1, 2, 0, 2) / det,  cof(0, 2, 0, 2) / det, -cof(0, 1, 0, 2) / det],
+        [ cof(1, 2, 0, 1) / det, -cof(0, 2, 0, 1) / det,  cof(0, 1, 0, 1) / det],
+    ])
+}
+
+/// Build `ColorMatrix1` (XYZ to camera RGB) from a camera to sRGB matrix.
+///
+/// Since the rows of `matrix` sum to 1.0, the result maps the D65 white
+/// point to camera `[1, 1, 1]`, which is the normalization DNG expects.
+fn xyz_to_camera(matrix: &ColorMatrix) -> Option<[[f64; 3]; 3]> {
+    let mut cam_to_xyz = [[0.0; 3]; 3];
+    for (r, row) in cam_to_xyz.iter_mut().enumerate() {
+        for (c, v) in row.iter_mut().enumerate() {
+            *v = (0..3).map(|k| SRGB_TO_XYZ[r][k] * matrix.0[k][c] as f64).sum();
+        }
+    }
+    invert(cam_to_xyz)
+}
+
+impl Frame {
+    /// Write the raw frame to a DNG file (see [crate::dng_writer]).
+    ///
+    /// Returns [Error::InvalidArgument] for truncated frames, or if the
+    /// color matrix can't be inverted.
+    pub fn save_dng(&self, path: impl AsRef<Path>, options: &DngOptions,
+        meta: Option<&CaptureMetadata>) -> Result<(), Error>
+    {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_dng(&mut w, options, meta)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Write the raw frame as a DNG to any writer.
+    pub fn write_dng<W: Write>(&self, w: W, options: &DngOptions,
+        meta: Option<&CaptureMetadata>) -> Result<(), Error>
+    {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        let xyz = xyz_to_camera(&options.matrix).ok_or(Error::InvalidArgument)?;
+        let strip = tiff_writer::strip(self);
+        let pattern: Vec<u8> = [(0, 0), (1, 0), (0, 1), (1, 1)].iter()
+            .map(|(x, y)| match self.cfa.color_at(*x, *y) {
+                Color::Red => 0, Color::Green => 1, Color::Blue => 2,
+            }).collect();
+        let model = meta.map(|m| m.model).unwrap_or("AmScope MU1603");
+
+        let mut entries = vec![
+            // NewSubFileType: the main image
+            Entry::long(254, 0),
+            Entry::long(256, self.width as u32),
+            Entry::long(257, self.height as u32),
+            Entry::short(258, 16),
+            Entry::short(259, 1),
+            // PhotometricInterpretation: CFA
+            Entry::short(262, 32803),
+            Entry::short(274, 1),
+            Entry::short(277, 1),
+            Entry::long(278, self.height as u32),
+            Entry::long(279, strip.len() as u32),
+            Entry::short(284, 1),
+            Entry::ascii(305, concat!("toupcam-rs ", env!("CARGO_PKG_VERSION"))),
+            Entry::shorts(33421, &[2, 2]),
+            Entry::bytes(33422, &pattern),
+            Entry::bytes(50706, &[1, 4, 0, 0]),
+            Entry::bytes(50707, &[1, 1, 0, 0]),
+            Entry::ascii(50708, model),
+            Entry::bytes(50710, &[0, 1, 2]),
+            Entry::short(50711, 1),
+            Entry::short(50714, options.black_level),
+            Entry::short(50717, self.max_value()),
+            Entry::srationals(50721, &xyz.iter().flatten()
+                .map(|v| ((v * 10000.0).round() as i32, 10000)).collect::<Vec<_>>()),
+            Entry::short(50778, D65),
+        ];
+        if let Some(wb) = options.white_balance {
+            // AsShotNeutral is the camera's response to white, the inverse
+            // of the gains
+            let gains = wb.gains(self);
+            entries.push(Entry::rationals(50728, &gains.map(|g| {
+                ((10000.0 / g.max(f32::EPSILON)).round() as u32, 10000)
+            })));
+        }
+        if let Some(meta) = meta {
+            entries.extend(tiff_writer::camera_entries(meta));
+            // ExposureTime, in microseconds
+            entries.push(Entry::rational(33434, meta.exposure.as_micros() as u32, 1_000_000));
+        }
+        tiff_writer::write_tiff_file(w, entries, &strip)
+    }
+}
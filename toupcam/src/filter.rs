@@ -0,0 +1,105 @@
+//! Filters for deciding which frames a subscriber receives.
+//!
+//! A [Dispatcher] fans frames out to several subscribers, each with its own
+//! [FrameFilter]. Filters are evaluated on the capturing thread before a
+//! frame is handed off, so a consumer that only wants (for example) every
+//! tenth frame never has to touch, convert, or copy the other nine.
+
+use crate::Frame;
+use crate::focus::focus_metric;
+use std::sync::Arc;
+use std::sync::mpsc::{ channel, Sender, Receiver };
+
+/// Row step used when computing the focus metric for [FrameFilter::MinFocus].
+const FOCUS_STEP: usize = 4;
+
+/// Condition for delivering a frame to a subscriber.
+#[derive(Clone, Debug)]
+pub enum FrameFilter {
+    /// Deliver every frame
+    All,
+    /// Deliver every Nth frame (starting with the first)
+    EveryNth(u64),
+    /// Deliver frames whose [focus_metric] is at least this value
+    MinFocus(f64),
+    /// Deliver frames marked with [crate::Camera::mark_next_frame]
+    Marked,
+    /// Deliver frames matching all of these filters
+    And(Vec<FrameFilter>),
+}
+
+/// Per-frame values shared between all filters, computed on demand.
+struct FrameCtx<'a> {
+    frame: &'a Frame,
+    focus: Option<f64>,
+}
+impl FrameCtx<'_> {
+    fn focus(&mut self) -> f64 {
+        let frame = self.frame;
+        *self.focus.get_or_insert_with(|| focus_metric(frame, FOCUS_STEP))
+    }
+}
+
+/// A filter along with any state it needs.
+struct FilterState {
+    filter: FrameFilter,
+    /// Number of frames evaluated so far
+    count: u64,
+}
+impl FilterState {
+    fn new(filter: FrameFilter) -> Self { Self { filter, count: 0 } }
+
+    fn eval(filter: &FrameFilter, count: u64, ctx: &mut FrameCtx) -> bool {
+        match filter {
+            FrameFilter::All => true,
+            FrameFilter::EveryNth(n) => count.is_multiple_of((*n).max(1)),
+            FrameFilter::MinFocus(min) => ctx.focus() >= *min,
+            FrameFilter::Marked => ctx.frame.marked,
+            FrameFilter::And(fs) => fs.iter().all(|f| Self::eval(f, count, ctx)),
+        }
+    }
+
+    fn accept(&mut self, ctx: &mut FrameCtx) -> bool {
+        let res = Self::eval(&self.filter, self.count, ctx);
+        self.count += 1;
+        res
+    }
+}
+
+/// Fans frames out to subscribers with per-subscriber filters.
+#[derive(Default)]
+pub struct Dispatcher {
+    subs: Vec<(FilterState, Sender<Arc<Frame>>)>,
+}
+impl Dispatcher {
+    pub fn new() -> Self { Self::default() }
+
+    /// Register a new subscriber.
+    pub fn subscribe(&mut self, filter: FrameFilter) -> Receiver<Arc<Frame>> {
+        let (tx, rx) = channel();
+        self.subs.push((FilterState::new(filter), tx));
+        rx
+    }
+
+    /// Returns the number of (connected) subscribers.
+    pub fn len(&self) -> usize { self.subs.len() }
+    pub fn is_empty(&self) -> bool { self.subs.is_empty() }
+
+    /// Deliver a frame to all subscribers whose filter accepts it.
+    ///
+    /// Subscribers that have hung up are removed. Returns the number of
+    /// subscribers that received the frame.
+    pub fn dispatch(&mut self, frame: Frame) -> usize {
+        let frame = Arc::new(frame);
+        let mut ctx = FrameCtx { frame: &frame, focus: None };
+        let mut sent = 0;
+        self.subs.retain_mut(|(state, tx)| {
+            if !state.accept(&mut ctx) { return true; }
+            match tx.send(frame.clone()) {
+                Ok(_) => { sent += 1; true },
+                Err(_) => false,
+            }
+        });
+        sent
+    }
+}
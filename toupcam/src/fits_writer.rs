@@ -0,0 +1,110 @@
+//! Saving raw frames as FITS files (with the `fits` feature).
+//!
+//! Frames are written as a single 16-bit image HDU holding the raw mosaic.
+//! FITS has no unsigned 16-bit type, so samples are stored offset by
+//! `BZERO = 32768` as usual. Rows are written top-down (`ROWORDER`), and the
+//! Bayer pattern is given by `BAYERPAT` with zero `XBAYROFF`/`YBAYROFF`, which
+//! is what most astronomy software expects for debayering.
+
+use crate::{ Error, Frame };
+use crate::metadata::CaptureMetadata;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+
+/// Size of a FITS block (headers and data are padded to a multiple of this).
+const BLOCK: usize = 2880;
+/// Size of a header card.
+const CARD: usize = 80;
+
+/// Builds a FITS header, one 80-character card at a time.
+struct Header(Vec<u8>);
+impl Header {
+    fn card(&mut self, key: &str, value: &str, comment: &str) {
+        let mut card = format!("{:<8}= {:>20}", key, value);
+        if !comment.is_empty() {
+            card.push_str(" / ");
+            card.push_str(comment);
+        }
+        card.truncate(CARD);
+        self.0.extend_from_slice(format!("{:<80}", card).as_bytes());
+    }
+    fn int(&mut self, key: &str, v: i64, comment: &str) {
+        self.card(key, &v.to_string(), comment);
+    }
+    fn float(&mut self, key: &str, v: f64, comment: &str) {
+        self.card(key, &format!("{:E}", v), comment);
+    }
+    fn string(&mut self, key: &str, v: &str, comment: &str) {
+        // Strings are quoted (with quotes doubled), and padded to at least
+        // 8 characters
+        let quoted = format!("'{:<8}'", v.replace('\'', "''"));
+        self.card(key, &format!("{:<20}", quoted), comment);
+    }
+    fn finish(mut self) -> Vec<u8> {
+        self.0.extend_from_slice(format!("{:<80}", "END").as_bytes());
+        let len = self.0.len().next_multiple_of(BLOCK);
+        self.0.resize(len, b' ');
+        self.0
+    }
+}
+
+impl Frame {
+    /// Write the raw frame to a FITS file (see [crate::fits_writer]).
+    ///
+    /// Without `meta`, only the structural keywords and the Bayer pattern
+    /// are written. Returns [Error::InvalidArgument] for truncated frames.
+    pub fn save_fits(&self, path: impl AsRef<Path>, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_fits(&mut w, meta)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Write the raw frame as FITS to any writer.
+    pub fn write_fits<W: Write>(&self, mut w: W, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        let mut hdr = Header(Vec::with_capacity(BLOCK));
+        hdr.card("SIMPLE", "T", "conforms to FITS standard");
+        hdr.int("BITPIX", 16, "");
+        hdr.int("NAXIS", 2, "");
+        hdr.int("NAXIS1", self.width as i64, "");
+        hdr.int("NAXIS2", self.height as i64, "");
+        hdr.int("BZERO", 32768, "offset for unsigned data");
+        hdr.int("BSCALE", 1, "");
+        hdr.int("DATAMAX", self.max_value() as i64, "full scale");
+        hdr.string("ROWORDER", "TOP-DOWN", "");
+        hdr.string("BAYERPAT", self.cfa.name(), "Bayer color pattern");
+        hdr.int("XBAYROFF", 0, "X offset of Bayer pattern");
+        hdr.int("YBAYROFF", 0, "Y offset of Bayer pattern");
+        if let Some(meta) = meta {
+            let (y, mo, d, h, mi, s) = meta.utc();
+            let ms = meta.timestamp.duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.subsec_millis()).unwrap_or(0);
+            hdr.float("EXPTIME", meta.exposure.as_secs_f64(), "[s] exposure time");
+            hdr.int("GAIN", meta.gain as i64, "raw analog gain register");
+            hdr.string("DATE-OBS", &format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+                y, mo, d, h, mi, s, ms), "UTC time of capture");
+            hdr.string("INSTRUME", meta.model, "");
+            hdr.string("READOUTM", &format!("{:?}", meta.mode), "readout mode");
+            hdr.int("BITDEPTH", meta.bits as i64, "significant bits per sample");
+            if let Some(serial) = &meta.serial {
+                hdr.string("CAMSERNO", serial, "USB serial number");
+            }
+        }
+        hdr.string("SWCREATE", concat!("toupcam-rs ", env!("CARGO_PKG_VERSION")), "");
+        w.write_all(&hdr.finish())?;
+
+        // Big-endian, signed (minus BZERO)
+        let mut data: Vec<u8> = (0..self.width * self.height)
+            .flat_map(|idx| ((self.sample(idx) as i32 - 32768) as i16).to_be_bytes())
+            .collect();
+        data.resize(data.len().next_multiple_of(BLOCK), 0);
+        w.write_all(&data)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Sharpness (focus) metrics computed on raw frames.
+
+use crate::Frame;
+
+/// Estimate the sharpness of a raw frame.
+///
+/// This is the mean squared gradient between neighbouring green samples
+/// in the same row or column (which are two pixels apart, whatever the
+/// [Frame::cfa]), so it doesn't depend on demosaicing. Only every
+/// `step`-th pair of rows is considered, which makes this cheap enough to
+/// run on every frame. Larger values are sharper; the absolute value
+/// depends on the scene and exposure, so this is only useful for comparing
+/// frames of the same scene. Truncated frames score zero.
+pub fn focus_metric(frame: &Frame, step: usize) -> f64 {
+    let (w, h) = (frame.width, frame.height);
+    if w < 4 || h < 4 || !frame.complete { return 0.0; }
+    let step = step.max(1) * 2;
+
+    let mut sum = 0.0f64;
+    let mut count = 0usize;
+    // Column of the green sample in the first row of each 2x2 cell
+    let (rx, ry) = frame.cfa.red_offset();
+    let gx = rx ^ ry ^ 1;
+    for y in (0..h - 2).step_by(step) {
+        let row = y * w;
+        for x in (gx..w - 2).step_by(2) {
+            let c  = frame.sample(row + x) as f64;
+            let dx = frame.sample(row + x + 2) as f64 - c;
+            let dy = frame.sample(row + 2 * w + x) as f64 - c;
+            sum += dx * dx + dy * dy;
+            count += 1;
+        }
+    }
+    if count == 0 { 0.0 } else { sum / count as f64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfa::{ Cfa, Color };
+    use std::time::{ Duration, Instant };
+
+    /// An 8x8 frame where samples of `color` alternate between 0 and 200
+    /// every other cell, and the rest are 100.
+    fn stripes(cfa: Cfa, color: Color) -> Frame {
+        let data = (0..64).map(|idx| {
+            let (x, y) = (idx % 8, idx / 8);
+            if cfa.color_at(x, y) != color { 100 } else if (x / 2) % 2 == 0 { 0 } else { 200 }
+        }).collect::<Vec<u8>>();
+        Frame { data: data.into(), width: 8, height: 8, bpp: 1, elapsed: Duration::ZERO,
+            marked: false, cfa, seq: 0, timestamp: Instant::now(), complete: true,
+        }
+    }
+
+    #[test]
+    fn only_measures_green() {
+        for cfa in [Cfa::Rggb, Cfa::Grbg, Cfa::Gbrg, Cfa::Bggr] {
+            assert!(focus_metric(&stripes(cfa, Color::Green), 1) > 0.0, "{:?}", cfa);
+            assert_eq!(focus_metric(&stripes(cfa, Color::Red), 1), 0.0, "{:?}", cfa);
+            assert_eq!(focus_metric(&stripes(cfa, Color::Blue), 1), 0.0, "{:?}", cfa);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+//! Demosaicing, white balance and tone mapping on the GPU (with the `gpu`
+//! feature).
+//!
+//! At full resolution, the CPU pipeline ([crate::demosaic] followed by
+//! [crate::tonemap]) can't keep up with a live preview. A [GpuProcessor]
+//! uploads the raw frame and does all three steps in a single compute shader
+//! pass, reading back an RGBA image. Demosaicing is always bilinear.
+
+use crate::{ Error, Frame };
+use crate::tonemap::ToneMap;
+use crate::white_balance::WhiteBalance;
+use std::borrow::Cow;
+
+/// Size of a workgroup in each direction (see `main` in the shader).
+const WORKGROUP: u32 = 16;
+
+/// Size of the uniform block passed to the shader.
+const PARAMS_LEN: u64 = 64;
+
+const SHADER: &str = r#"
+struct Params {
+    width: u32, height: u32, red_x: u32, red_y: u32,
+    shift: u32, mode: u32, _pad0: u32, _pad1: u32,
+    gains: vec4<f32>,
+    black: f32, white: f32, param: f32, _pad2: f32,
+};
+
+@group(0) @binding(0) var<uniform> p: Params;
+@group(0) @binding(1) var<storage, read> raw: array<u32>;
+@group(0) @binding(2) var<storage, read_write> rgba: array<u32>;
+
+// Reflect at the edges (keeping the Bayer phase)
+fn reflect(v: i32, len: i32) -> u32 {
+    if v < 0 { return u32(-v); }
+    if v >= len { return u32(2 * (len - 1) - v); }
+    return u32(v);
+}
+
+// Sample at (x, y), scaled to 16 bits
+fn sample(x: i32, y: i32) -> f32 {
+    let idx = reflect(y, i32(p.height)) * p.width + reflect(x, i32(p.width));
+    let v = (raw[idx / 2u] >> ((idx % 2u) * 16u)) & 0xffffu;
+    return f32(v << p.shift);
+}
+
+// 0 = red, 1 = green, 2 = blue
+fn color_at(x: u32, y: u32) -> u32 {
+    let dx = (x & 1u) ^ p.red_x;
+    let dy = (y & 1u) ^ p.red_y;
+    if dx == 0u && dy == 0u { return 0u; }
+    if dx == 1u && dy == 1u { return 2u; }
+    return 1u;
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if id.x >= p.width || id.y >= p.height { return; }
+    let x = i32(id.x);
+    let y = i32(id.y);
+    let c = sample(x, y);
+    let h = (sample(x - 1, y) + sample(x + 1, y)) * 0.5;
+    let v = (sample(x, y - 1) + sample(x, y + 1)) * 0.5;
+    let d = (sample(x - 1, y - 1) + sample(x + 1, y - 1)
+        + sample(x - 1, y + 1) + sample(x + 1, y + 1)) * 0.25;
+
+    var rgb: vec3<f32>;
+    let own = color_at(id.x, id.y);
+    if own == 0u {
+        rgb = vec3<f32>(c, (h + v) * 0.5, d);
+    } else if own == 2u {
+        rgb = vec3<f32>(d, (h + v) * 0.5, c);
+    } else if color_at(id.x ^ 1u, id.y) == 0u {
+        rgb = vec3<f32>(h, c, v);
+    } else {
+        rgb = vec3<f32>(v, c, h);
+    }
+    rgb = rgb * p.gains.xyz;
+
+    let t = clamp((rgb - vec3<f32>(p.black)) / max(p.white - p.black, 1.0),
+        vec3<f32>(0.0), vec3<f32>(1.0));
+    var m: vec3<f32>;
+    if p.mode == 1u {
+        m = pow(t, vec3<f32>(1.0 / p.param));
+    } else if p.mode == 2u {
+        m = asinh(p.param * t) / asinh(p.param);
+    } else {
+        m = t;
+    }
+    let o = vec3<u32>(round(clamp(m, vec3<f32>(0.0), vec3<f32>(1.0)) * 255.0));
+    rgba[id.y * p.width + id.x] = o.x | (o.y << 8u) | (o.z << 16u) | (255u << 24u);
+}
+"#;
+
+/// An 8-bit RGBA image (row-major, no padding, alpha always 255).
+#[derive(Clone, Debug)]
+pub struct RgbaImage {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+/// Buffers sized for one frame resolution.
+struct Buffers {
+    dims: (usize, usize),
+    params: wgpu::Buffer,
+    raw: wgpu::Buffer,
+    rgba: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Processes raw frames into RGBA images on the GPU.
+pub struct GpuProcessor {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    buffers: Option<Buffers>,
+    adapter: String,
+}
+impl GpuProcessor {
+    /// Set up on the default adapter.
+    pub fn new() -> Result<Self, Error> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&Default::default()))
+            .map_err(|e| Error::Gpu(e.to_string()))?;
+        let adapter_name = adapter.get_info().name;
+        let (device, queue) = pollster::block_on(adapter.request_device(&Default::default()))
+            .map_err(|e| Error::Gpu(e.to_string()))?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("toupcam"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+        });
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding, ty, count: None, visibility: wgpu::ShaderStages::COMPUTE,
+        };
+        let storage = |read_only| wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false, min_binding_size: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("toupcam"),
+            entries: &[
+                entry(0, wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false, min_binding_size: None }),
+                entry(1, storage(true)),
+                entry(2, storage(false)),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("toupcam"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("toupcam"),
+            layout: Some(&pipeline_layout),
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        Ok(Self { device, queue, pipeline, layout, buffers: None, adapter: adapter_name })
+    }
+
+    /// Name of the adapter in use.
+    pub fn adapter(&self) -> &str { &self.adapter }
+
+    /// (Re)allocate buffers for a `width` x `height` frame.
+    fn buffers(&mut self, width: usize, height: usize) -> &Buffers {
+        if self.buffers.as_ref().is_none_or(|b| b.dims != (width, height)) {
+            let buffer = |size: u64, usage| self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("toupcam"), size, usage, mapped_at_creation: false,
+            });
+            use wgpu::BufferUsages as U;
+            let pixels = (width * height) as u64;
+            let params = buffer(PARAMS_LEN, U::UNIFORM | U::COPY_DST);
+            // Two 16-bit samples per word
+            let raw = buffer(pixels.div_ceil(2).max(1) * 4, U::STORAGE | U::COPY_DST);
+            let rgba = buffer(pixels.max(1) * 4, U::STORAGE | U::COPY_SRC);
+            let readback = buffer(pixels.max(1) * 4, U::MAP_READ | U::COPY_DST);
+            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("toupcam"),
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry { binding: 0, resource: params.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 1, resource: raw.as_entire_binding() },
+                    wgpu::BindGroupEntry { binding: 2, resource: rgba.as_entire_binding() },
+                ],
+            });
+            self.buffers = Some(Buffers { dims: (width, height), params, raw, rgba,
+                readback, bind_group });
+        }
+        self.buffers.as_ref().unwrap()
+    }
+
+    /// Demosaic, white balance and tone map a frame.
+    ///
+    /// Black and white points for the tone map are in the same units as in
+    /// [crate::tonemap]; [ToneMap::AutoStretch] is approximated from the raw
+    /// frame's luminance histogram. Returns [Error::InvalidArgument] for
+    /// truncated frames.
+    pub fn process(&mut self, frame: &Frame, wb: WhiteBalance, tonemap: &ToneMap)
+        -> Result<RgbaImage, Error>
+    {
+        let (w, h) = (frame.width, frame.height);
+        if !frame.complete || w == 0 || h == 0 { return Err(Error::InvalidArgument); }
+        let shift = 16 - frame.bits();
+        let gains = wb.gains(frame);
+        let (mode, black, white, param) = match *tonemap {
+            ToneMap::Linear { black, white } => (0, black, white, 1.0),
+            ToneMap::Gamma { black, white, gamma } => (1, black, white, gamma.max(f32::EPSILON)),
+            ToneMap::Asinh { black, white, stretch } => (2, black, white, stretch.max(f32::EPSILON)),
+            ToneMap::AutoStretch { low, high } => {
+                let hist = frame.histogram_sampled(4);
+                let luma = crate::histogram::Channel::Luma;
+                let at = |f: f32| ((hist.percentile(luma, f as f64) as u32) << shift)
+                    .min(u16::MAX as u32) as u16;
+                (0, at(low), at(high), 1.0)
+            },
+        };
+
+        let mut params = Vec::with_capacity(PARAMS_LEN as usize);
+        let (rx, ry) = frame.cfa.red_offset();
+        for v in [w as u32, h as u32, rx as u32, ry as u32, shift, mode, 0, 0] {
+            params.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [gains[0], gains[1], gains[2], 0.0, black as f32, white as f32, param, 0.0] {
+            params.extend_from_slice(&v.to_le_bytes());
+        }
+        let mut raw: Vec<u8> = match frame.bpp {
+            2 => frame.data.to_vec(),
+            _ => frame.data.iter().flat_map(|v| (*v as u16).to_le_bytes()).collect(),
+        };
+        raw.resize((w * h).div_ceil(2) * 4, 0);
+
+        let queue = self.queue.clone();
+        let device = self.device.clone();
+        let pipeline = self.pipeline.clone();
+        let bufs = self.buffers(w, h);
+        queue.write_buffer(&bufs.params, 0, &params);
+        queue.write_buffer(&bufs.raw, 0, &raw);
+
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bufs.bind_group, &[]);
+            pass.dispatch_workgroups((w as u32).div_ceil(WORKGROUP),
+                (h as u32).div_ceil(WORKGROUP), 1);
+        }
+        let out_len = (w * h * 4) as u64;
+        encoder.copy_buffer_to_buffer(&bufs.rgba, 0, &bufs.readback, 0, out_len);
+        queue.submit([encoder.finish()]);
+
+        let slice = bufs.readback.slice(..out_len);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |res| { let _ = tx.send(res); });
+        device.poll(wgpu::PollType::Wait).map_err(|e| Error::Gpu(e.to_string()))?;
+        rx.recv().map_err(|e| Error::Gpu(e.to_string()))?
+            .map_err(|e| Error::Gpu(e.to_string()))?;
+        let data = slice.get_mapped_range().to_vec();
+        bufs.readback.unmap();
+        Ok(RgbaImage { width: w, height: h, data })
+    }
+}
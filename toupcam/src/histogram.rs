@@ -0,0 +1,112 @@
+//! Per-channel histograms of raw frames.
+
+use crate::Frame;
+
+/// A channel of a [Histogram].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    /// Green pixels in the same rows as the red ones
+    Green1,
+    /// Green pixels in the same rows as the blue ones
+    Green2,
+    Blue,
+    /// Luminance (Rec. 601 weights) of each 2x2 cell
+    Luma,
+}
+
+/// Histograms of each channel of a raw frame, with one bin per sample value.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    pub red: Vec<u32>,
+    pub green1: Vec<u32>,
+    pub green2: Vec<u32>,
+    pub blue: Vec<u32>,
+    pub luma: Vec<u32>,
+}
+impl Histogram {
+    fn new(bins: usize) -> Self {
+        Self { red: vec![0; bins], green1: vec![0; bins], green2: vec![0; bins],
+            blue: vec![0; bins], luma: vec![0; bins],
+        }
+    }
+
+    /// Number of bins (the number of possible sample values).
+    pub fn bins(&self) -> usize { self.red.len() }
+
+    pub fn get(&self, ch: Channel) -> &[u32] {
+        match ch {
+            Channel::Red => &self.red,
+            Channel::Green1 => &self.green1,
+            Channel::Green2 => &self.green2,
+            Channel::Blue => &self.blue,
+            Channel::Luma => &self.luma,
+        }
+    }
+
+    /// Number of samples counted in a channel.
+    pub fn count(&self, ch: Channel) -> u64 {
+        self.get(ch).iter().map(|n| *n as u64).sum()
+    }
+
+    /// Mean sample value in a channel.
+    pub fn mean(&self, ch: Channel) -> f64 {
+        let count = self.count(ch);
+        if count == 0 { return 0.0; }
+        let sum: f64 = self.get(ch).iter().enumerate()
+            .map(|(v, n)| v as f64 * *n as f64).sum();
+        sum / count as f64
+    }
+
+    /// The smallest value with at least `fraction` (0.0 to 1.0) of the
+    /// samples in a channel at or below it.
+    pub fn percentile(&self, ch: Channel, fraction: f64) -> usize {
+        let target = (self.count(ch) as f64 * fraction.clamp(0.0, 1.0)).ceil() as u64;
+        let mut seen = 0u64;
+        for (v, n) in self.get(ch).iter().enumerate() {
+            seen += *n as u64;
+            if seen >= target && seen > 0 { return v; }
+        }
+        self.bins().saturating_sub(1)
+    }
+}
+
+impl Frame {
+    /// Compute per-channel histograms over the whole frame.
+    pub fn histogram(&self) -> Histogram {
+        self.histogram_sampled(1)
+    }
+
+    /// Compute per-channel histograms over every `step`-th 2x2 cell in each
+    /// direction (so `step = 4` looks at 1/16th of the frame).
+    ///
+    /// For truncated frames, only the rows that were received are counted.
+    pub fn histogram_sampled(&self, step: usize) -> Histogram {
+        let max = self.max_value() as usize;
+        let mut hist = Histogram::new(max + 1);
+        let w = self.width;
+        if w < 2 || self.bpp == 0 { return hist; }
+        let rows = self.height.min(self.data.len() / (w * self.bpp));
+
+        let (rx, ry) = self.cfa.red_offset();
+        let step = step.max(1) * 2;
+        for y in (0..rows.saturating_sub(1)).step_by(step) {
+            for x in (0..w - 1).step_by(step) {
+                let at = |dx: usize, dy: usize| {
+                    (self.sample((y + dy) * w + x + dx) as usize).min(max)
+                };
+                let r = at(rx, ry);
+                let g1 = at(rx ^ 1, ry);
+                let g2 = at(rx, ry ^ 1);
+                let b = at(rx ^ 1, ry ^ 1);
+                hist.red[r] += 1;
+                hist.green1[g1] += 1;
+                hist.green2[g2] += 1;
+                hist.blue[b] += 1;
+                let luma = (77 * r + 75 * (g1 + g2) + 29 * b + 128) >> 8;
+                hist.luma[luma.min(max)] += 1;
+            }
+        }
+        hist
+    }
+}
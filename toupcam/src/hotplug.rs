@@ -0,0 +1,109 @@
+//! Notifications for the camera being plugged in or unplugged.
+//!
+//! This wraps the libusb hotplug API, which isn't available on every
+//! platform (notably Windows); [HotplugMonitor::new] returns
+//! [Error::Unimplemented] when it isn't.
+
+use crate::{ Error, VID, PID };
+use rusb::{ Context, Device, UsbContext, Hotplug, HotplugBuilder, Registration };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ channel, Sender, Receiver, RecvTimeoutError };
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A camera was plugged in or unplugged.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HotplugEvent {
+    Arrived { bus: u8, address: u8 },
+    Left { bus: u8, address: u8 },
+}
+
+/// Forwards libusb callbacks into a channel.
+struct Callback { tx: Sender<HotplugEvent> }
+impl Hotplug<Context> for Callback {
+    fn device_arrived(&mut self, dev: Device<Context>) {
+        let _ = self.tx.send(HotplugEvent::Arrived {
+            bus: dev.bus_number(), address: dev.address()
+        });
+    }
+    fn device_left(&mut self, dev: Device<Context>) {
+        let _ = self.tx.send(HotplugEvent::Left {
+            bus: dev.bus_number(), address: dev.address()
+        });
+    }
+}
+
+/// Watches for the camera being plugged in or unplugged.
+///
+/// libusb only delivers hotplug events while something is handling events
+/// on the context, so this runs a small background thread to do that.
+pub struct HotplugMonitor {
+    rx: Receiver<HotplugEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    registration: Option<Registration<Context>>,
+}
+impl HotplugMonitor {
+    /// Start watching for the camera.
+    ///
+    /// When `enumerate` is set, an [HotplugEvent::Arrived] event is also
+    /// delivered for each camera that's already connected.
+    pub fn new(enumerate: bool) -> Result<Self, Error> {
+        if !rusb::has_hotplug() { return Err(Error::Unimplemented); }
+
+        let ctx = Context::new()?;
+        let (tx, rx) = channel();
+        let registration = HotplugBuilder::new()
+            .vendor_id(VID)
+            .product_id(PID)
+            .enumerate(enumerate)
+            .register(ctx.clone(), Box::new(Callback { tx }))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                if let Err(e) = ctx.handle_events(Some(Duration::from_millis(100))) {
+                    eprintln!("hotplug event handling failed: {}", e);
+                    break;
+                }
+            }
+        });
+
+        Ok(Self { rx, stop, thread: Some(thread), registration: Some(registration) })
+    }
+
+    /// Returns the next event, if one is pending.
+    pub fn try_next(&self) -> Option<HotplugEvent> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next event.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<HotplugEvent> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(ev) => Some(ev),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// Wait up to `timeout` for a camera to be plugged in.
+    pub fn wait_for_arrival(&self, timeout: Duration) -> bool {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let now = std::time::Instant::now();
+            if now >= deadline { return false; }
+            if let Some(HotplugEvent::Arrived { .. }) = self.next_timeout(deadline - now) {
+                return true;
+            }
+        }
+    }
+}
+impl Drop for HotplugMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+        self.registration.take();
+    }
+}
@@ -0,0 +1,46 @@
+//! Conversions to [image] crate buffers (with the `image` feature).
+
+use crate::{ Error, Frame };
+use crate::demosaic::{ self, Demosaic, Rgb16Image, RgbImage };
+use crate::tonemap::ToneMap;
+use image::{ ImageBuffer, Luma, Rgb };
+
+impl Frame {
+    /// The raw (mosaiced) data as a grayscale image, scaled to the full
+    /// 16-bit range.
+    ///
+    /// Returns [Error::InvalidArgument] for truncated frames.
+    pub fn to_luma16(&self) -> Result<ImageBuffer<Luma<u16>, Vec<u16>>, Error> {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        let shift = 16 - self.bits();
+        let data = self.to_u16().into_iter().map(|v| v << shift).collect();
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, data)
+            .ok_or(Error::InvalidArgument)
+    }
+
+    /// Demosaic into a 16-bit (linear) RGB image.
+    pub fn to_rgb16(&self, method: Demosaic) -> Result<ImageBuffer<Rgb<u16>, Vec<u16>>, Error> {
+        Ok(demosaic::demosaic(self, method)?.into())
+    }
+
+    /// Demosaic and tone map into an 8-bit RGB image.
+    pub fn to_rgb8(&self, method: Demosaic, tonemap: &ToneMap)
+        -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, Error>
+    {
+        Ok(tonemap.apply(&demosaic::demosaic(self, method)?).into())
+    }
+}
+
+impl From<RgbImage> for ImageBuffer<Rgb<u8>, Vec<u8>> {
+    fn from(img: RgbImage) -> Self {
+        ImageBuffer::from_raw(img.width as u32, img.height as u32, img.data)
+            .expect("RgbImage has a valid length")
+    }
+}
+
+impl From<Rgb16Image> for ImageBuffer<Rgb<u16>, Vec<u16>> {
+    fn from(img: Rgb16Image) -> Self {
+        ImageBuffer::from_raw(img.width as u32, img.height as u32, img.data)
+            .expect("Rgb16Image has a valid length")
+    }
+}
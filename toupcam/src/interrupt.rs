@@ -0,0 +1,111 @@
+//! Listening for messages on an interrupt IN endpoint (if there is one).
+//!
+//! The MU1603 captures so far only show control and bulk traffic, so it's
+//! not known whether the device ever sends anything here. If it does, this
+//! is the place to decode messages (i.e. frame-ready notifications, which
+//! might replace the sleeps around starting the stream). For now, anything
+//! received is surfaced as [DeviceEvent::Unknown].
+
+use crate::{ Error, Camera };
+use rusb::{ Context, DeviceHandle, Direction, TransferType };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ channel, Receiver };
+use std::thread::JoinHandle;
+use std::time::{ Duration, Instant };
+
+/// A message received from the device.
+#[derive(Clone, Debug)]
+pub enum DeviceEvent {
+    /// A message we don't know how to decode yet
+    Unknown { endpoint: u8, data: Vec<u8>, timestamp: Instant },
+    /// The listener stopped because of an error
+    Stopped(String),
+}
+
+/// Decode a message from the interrupt endpoint.
+fn decode(endpoint: u8, data: &[u8]) -> DeviceEvent {
+    DeviceEvent::Unknown { endpoint, data: data.to_vec(), timestamp: Instant::now() }
+}
+
+/// Background thread reading from an interrupt endpoint.
+pub struct InterruptListener {
+    rx: Receiver<DeviceEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+impl InterruptListener {
+    fn spawn(handle: Arc<DeviceHandle<Context>>, endpoint: u8, max_packet: usize)
+        -> Self
+    {
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread = std::thread::spawn(move || {
+            let mut buf = vec![0u8; max_packet.max(64)];
+            let timeout = Duration::from_millis(100);
+            while !thread_stop.load(Ordering::Relaxed) {
+                match handle.read_interrupt(endpoint, &mut buf, timeout) {
+                    Ok(len) => {
+                        if tx.send(decode(endpoint, &buf[..len])).is_err() { break; }
+                    },
+                    Err(rusb::Error::Timeout) => continue,
+                    Err(e) => {
+                        let _ = tx.send(DeviceEvent::Stopped(e.to_string()));
+                        break;
+                    },
+                }
+            }
+        });
+        Self { rx, stop, thread: Some(thread) }
+    }
+
+    /// Returns the next event, if one is pending.
+    pub fn try_next(&self) -> Option<DeviceEvent> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next event.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<DeviceEvent> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+}
+impl Drop for InterruptListener {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+    }
+}
+
+impl Camera {
+    /// Find an interrupt IN endpoint on interface 0.
+    ///
+    /// Returns the endpoint address and maximum packet size.
+    pub fn interrupt_endpoint(&self) -> Result<Option<(u8, usize)>, Error> {
+        let config = self._dev.active_config_descriptor()?;
+        for iface in config.interfaces().filter(|i| i.number() == 0) {
+            for desc in iface.descriptors() {
+                for ep in desc.endpoint_descriptors() {
+                    if ep.transfer_type() == TransferType::Interrupt
+                        && ep.direction() == Direction::In
+                    {
+                        return Ok(Some((ep.address(), ep.max_packet_size() as usize)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Start listening for messages on the interrupt IN endpoint.
+    ///
+    /// Fails with [Error::Unimplemented] if the device doesn't have one.
+    pub fn listen_interrupts(&self) -> Result<InterruptListener, Error> {
+        match self.interrupt_endpoint()? {
+            Some((ep, max_packet)) => {
+                Ok(InterruptListener::spawn(self.handle.clone(), ep, max_packet))
+            },
+            None => Err(Error::Unimplemented),
+        }
+    }
+}
@@ -1,9 +1,95 @@
 
 mod usb;
 mod sensor;
-
+mod transfer;
+pub mod model;
+pub mod protocol;
+pub mod cfa;
+pub mod pool;
+pub mod stream;
+pub mod stats;
+pub mod sink;
+pub mod source;
+pub mod pipe;
+pub mod multi;
+mod bracket;
+pub mod schedule;
+pub mod calibration;
+pub mod region;
+pub mod planes;
+pub mod metadata;
+pub mod pnm;
+pub mod parse;
+#[cfg(feature = "async")]
+pub mod async_stream;
+#[cfg(feature = "unsafe-registers")]
+pub mod raw;
+#[cfg(feature = "writers")]
+pub mod tpraw;
+#[cfg(feature = "writers")]
+pub mod png_writer;
+#[cfg(feature = "writers")]
+pub mod tiff_writer;
+#[cfg(feature = "writers")]
+pub mod ser;
+#[cfg(all(feature = "writers", feature = "processing"))]
+pub mod dng_writer;
+#[cfg(all(feature = "writers", feature = "processing"))]
+pub mod snapshot;
+#[cfg(feature = "fits")]
+pub mod fits_writer;
+#[cfg(feature = "sidecar")]
+pub mod sidecar;
+#[cfg(feature = "archive")]
+pub mod archive;
+#[cfg(feature = "processing")]
+pub mod focus;
+#[cfg(feature = "processing")]
+pub mod filter;
+#[cfg(feature = "processing")]
+pub mod demosaic;
+#[cfg(feature = "processing")]
+pub mod histogram;
+#[cfg(feature = "processing")]
+pub mod white_balance;
+#[cfg(feature = "processing")]
+pub mod color;
+#[cfg(feature = "processing")]
+pub mod tonemap;
+#[cfg(feature = "processing")]
+mod scale;
+#[cfg(feature = "processing")]
+pub mod stack;
+#[cfg(feature = "processing")]
+pub mod denoise;
+#[cfg(feature = "processing")]
+pub mod lucky;
+#[cfg(feature = "processing")]
+pub mod motion;
+#[cfg(feature = "processing")]
+pub mod pipeline;
+#[cfg(feature = "processing")]
+pub mod recorder;
+#[cfg(feature = "processing")]
+pub mod timelapse;
+#[cfg(feature = "image")]
+mod image_conv;
+#[cfg(feature = "ndarray")]
+mod ndarray_conv;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+#[cfg(feature = "processing")]
+mod simd;
+#[cfg(feature = "processing")]
+mod par;
+pub mod hotplug;
+pub mod interrupt;
+
+use std::sync::Arc;
 use std::time::Duration;
 use rusb::{ Context, UsbContext, Device, DeviceHandle, DeviceDescriptor };
+use cfa::Cfa;
+use pool::FrameBuffer;
 
 /// Bit depth of raw sensor data
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -22,23 +108,81 @@ impl CameraMode {
     }
 }
 
-/// Wrapper for [rusb::Error]
+/// Errors returned by this crate (mostly wrapping [rusb::Error]).
 #[derive(Debug)]
 pub enum Error { 
     Rusb(rusb::Error),
+    /// A frame ended early. The first frame after starting the stream (or
+    /// recovering from an error) is always truncated, and is discarded
+    /// without returning this.
     FirstFrame,
     Unimplemented,
+    InvalidArgument,
+    /// The device was unplugged (see [Camera::reconnect]).
+    Disconnected,
+    /// The camera is suspended (see [Camera::suspend]).
+    Suspended,
+    /// The protocol descriptor is invalid or incomplete.
+    Protocol(String),
+    /// A file (i.e. calibration data or a color matrix) is malformed.
+    Format(String),
+    /// No usable GPU, or the GPU failed (with the `gpu` feature).
+    #[cfg(feature = "gpu")]
+    Gpu(String),
+    /// More data than a whole frame arrived before the end of a frame, so
+    /// the frame boundaries were lost. [Camera::read_frame] recovers from 
+    /// this automatically (see [RecoveryPolicy]).
+    Desynchronized,
+    /// The read was cancelled (see [stream::CancelToken]).
+    Cancelled,
+    Io(std::io::Error),
 }
 impl From<rusb::Error> for Error {
-    fn from(e: rusb::Error) -> Self { Self::Rusb(e) }
+    fn from(e: rusb::Error) -> Self { 
+        match e {
+            rusb::Error::NoDevice => Self::Disconnected,
+            e => Self::Rusb(e),
+        }
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Self::Io(e) }
+}
+
+/// USB vendor ID for the camera.
+pub const VID: u16 = 0x0547;
+/// USB product ID for the camera.
+pub const PID: u16 = 0x3016;
+
+/// Size of a single bulk transfer.
+///
+/// This seems like the maximum transfer size on my machine.
+const CHUNK_LEN: usize = 0x0004_0000;
+
+/// Where a device is plugged in: the bus number and the path of hub ports
+/// leading to it (which, unlike the device address, doesn't change when the
+/// device is unplugged and plugged back into the same port).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeviceLocation {
+    pub bus: u8,
+    pub ports: Vec<u8>,
+}
+impl DeviceLocation {
+    fn of<T: UsbContext>(device: &Device<T>) -> rusb::Result<Self> {
+        Ok(Self { bus: device.bus_number(), ports: device.port_numbers()? })
+    }
 }
 
-/// Open a particular device by VID/PID.
-fn open_device<T: UsbContext>(ctx: &mut T, vid: u16, pid: u16) 
+/// Open a particular device by VID/PID (and optionally, location).
+fn open_device<T: UsbContext>(ctx: &mut T, vid: u16, pid: u16, 
+    loc: Option<&DeviceLocation>)
     -> rusb::Result<(Device<T>, DeviceDescriptor, DeviceHandle<T>)> {
     let devices = ctx.devices()?;
     for device in devices.iter() {
         let desc = device.device_descriptor()?;
+        if let Some(loc) = loc {
+            if DeviceLocation::of(&device)? != *loc { continue; }
+        }
         if desc.vendor_id() == vid && desc.product_id() == pid {
             match device.open() {
                 Ok(handle) => return Ok((device, desc, handle)),
@@ -49,6 +193,16 @@ fn open_device<T: UsbContext>(ctx: &mut T, vid: u16, pid: u16)
     Err(rusb::Error::NoDevice)
 }
 
+/// Claim the interface on a freshly-opened handle.
+fn claim(handle: &DeviceHandle<Context>) -> Result<(), Error> {
+    if let Ok(true) = handle.kernel_driver_active(0) {
+        handle.detach_kernel_driver(0)?;
+    }
+    handle.set_active_configuration(1)?;
+    handle.claim_interface(0)?;
+    Ok(())
+}
+
 /// Representing a camera device.
 pub struct Camera {
     /// libusb context associated with this device
@@ -61,92 +215,329 @@ pub struct Camera {
     _desc: DeviceDescriptor,
 
     /// libusb handle for this USB device
-    handle: DeviceHandle<Context>,
+    handle: Arc<DeviceHandle<Context>>,
+
+    /// Where the device is plugged in (used to find it again on reconnect)
+    location: DeviceLocation,
 
     /// Default timeout for commands
     timeout: Duration,
 
     /// Set to 'true' when the camera is streaming data.
     streaming: bool,
+    /// Set while suspended, remembering whether we were streaming.
+    suspended: Option<bool>,
     /// The current sensor/readout mode.
     mode: CameraMode,
     /// The current bit-depth.
     depth: BitDepth,
+    /// Horizontal/vertical readout direction (mirror, flip).
+    flip: (bool, bool),
+    /// The current exposure time.
+    exposure: Duration,
+    /// The current analog gain.
+    gain: u16,
+    /// Protocol descriptor (init scripts, registers, timing).
+    protocol: Arc<protocol::ProtocolDescriptor>,
+    /// Set when the next complete frame should be marked.
+    mark_next: bool,
+    /// How to handle errors on the bulk endpoint.
+    recovery: RecoveryPolicy,
+    /// Upper bound on the time spent stopping the stream in [Drop].
+    teardown_budget: Duration,
+    /// Bayer phase to report instead of the one implied by the readout.
+    cfa_override: Option<Cfa>,
+    /// Where to log raw register accesses (stderr if unset).
+    #[cfg(feature = "unsafe-registers")]
+    reg_log: Option<raw::RegisterLog>,
+    /// Time origin for the register access log.
+    #[cfg(feature = "unsafe-registers")]
+    reg_log_epoch: std::time::Instant,
+    /// Last value written to each register (see [raw::RawAccess::shadow]).
+    #[cfg(feature = "unsafe-registers")]
+    reg_shadow: std::collections::BTreeMap<(protocol::RegisterKind, u16), u16>,
+    /// Minimum time between frames returned by [Camera::read_frame].
+    frame_interval: Option<Duration>,
+    /// When the last frame was returned by [Camera::read_frame].
+    last_frame: Option<std::time::Instant>,
+    /// Number of bulk transfers kept in flight while streaming.
+    queue_depth: usize,
+    /// Queued bulk transfers (created on the first read after starting).
+    bulk: Option<transfer::BulkQueue>,
+    /// Where frame buffers come from (freshly allocated if unset).
+    pool: Option<pool::FramePool>,
+    /// Sequence number for the next complete frame.
+    frame_seq: u64,
+    /// Statistics for the current stream.
+    stats: stats::StreamStats,
+    /// Set when a read failed partway through a frame.
+    desync: bool,
+    /// Return truncated frames instead of [Error::FirstFrame].
+    keep_partial: bool,
+    /// Set until the first frame after (re)starting readout has been read.
+    fresh: bool,
+    /// Checked between bulk transfers while reading a frame.
+    cancel: stream::CancelToken,
+    /// Subtracted from every frame (see [Camera::set_dark]).
+    dark: Option<calibration::MasterDark>,
+    /// Subtracted from every frame after the dark (see [Camera::set_fpn]).
+    fpn: Option<calibration::FpnCorrection>,
+    /// Applied to every frame after the dark (see [Camera::set_flat]).
+    flat: Option<calibration::MasterFlat>,
+    /// Pixels corrected in every frame (see [Camera::set_defects]).
+    defects: Option<calibration::DefectMap>,
 
 }
 impl Camera {
     /// Open an instance of the camera.
     ///
-    /// This assumes the VID/PID for the device is `0x0547:0x3016`.
+    /// This assumes the VID/PID for the device is `0x0547:0x3016`. If more 
+    /// than one camera is connected, this opens the first one found (see 
+    /// [Camera::list] and [Camera::open_at]).
     pub fn open() -> Result<Self, Error> {
+        Self::open_inner(None)
+    }
+
+    /// Open the camera at a particular location.
+    pub fn open_at(loc: &DeviceLocation) -> Result<Self, Error> {
+        Self::open_inner(Some(loc))
+    }
+
+    /// Returns the locations of all connected cameras.
+    pub fn list() -> Result<Vec<DeviceLocation>, Error> {
+        let ctx = Context::new()?;
+        let mut res = Vec::new();
+        for device in ctx.devices()?.iter() {
+            let desc = device.device_descriptor()?;
+            if desc.vendor_id() == VID && desc.product_id() == PID {
+                res.push(DeviceLocation::of(&device)?);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Where this camera is plugged in.
+    pub fn location(&self) -> &DeviceLocation { &self.location }
+
+    fn open_inner(loc: Option<&DeviceLocation>) -> Result<Self, Error> {
         const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
         const DEFAULT_MODE: CameraMode  = CameraMode::Mode1;
         const DEFAULT_DEPTH: BitDepth   = BitDepth::BitDepth12;
-        const VID: u16 = 0x0547;
-        const PID: u16 = 0x3016;
+        const DEFAULT_EXPOSURE: Duration = Duration::from_micros(94_000);
+        const DEFAULT_GAIN: u16 = 0x610c;
+        const DEFAULT_TEARDOWN_BUDGET: Duration = Duration::from_millis(500);
+        const DEFAULT_QUEUE_DEPTH: usize = 4;
 
         let mut _ctx = Context::new().unwrap();
-        let mut res = match open_device(&mut _ctx, VID, PID) {
-            Ok((_dev, _desc, handle)) => { 
-                Self { _ctx, _dev, _desc, handle, 
-                    timeout: DEFAULT_TIMEOUT, 
-                    mode: DEFAULT_MODE,
-                    depth: DEFAULT_DEPTH,
-                    streaming: false,
-                }
-            },
+        let (_dev, _desc, handle) = open_device(&mut _ctx, VID, PID, loc)
+            .map_err(Error::Rusb)?;
+        let protocol = protocol::ProtocolDescriptor::for_device(_desc.vendor_id(),
+            _desc.product_id())?;
+        let protocol = Arc::new(protocol);
+        // Claim the device before there's a Camera, since dropping one
+        // resets the device (i.e. under whoever else has it claimed)
+        claim(&handle)?;
+        let location = DeviceLocation::of(&_dev)?;
+        Ok(Self { _ctx, _dev, _desc, handle: Arc::new(handle), location,
+            timeout: DEFAULT_TIMEOUT, 
+            mode: DEFAULT_MODE,
+            depth: DEFAULT_DEPTH,
+            flip: (false, false),
+            exposure: DEFAULT_EXPOSURE,
+            gain: DEFAULT_GAIN,
+            protocol,
+            mark_next: false,
+            recovery: RecoveryPolicy::default(),
+            teardown_budget: DEFAULT_TEARDOWN_BUDGET,
+            cfa_override: None,
+            #[cfg(feature = "unsafe-registers")]
+            reg_log: None,
+            #[cfg(feature = "unsafe-registers")]
+            reg_log_epoch: std::time::Instant::now(),
+            #[cfg(feature = "unsafe-registers")]
+            reg_shadow: Default::default(),
+            frame_interval: None,
+            last_frame: None,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            bulk: None,
+            pool: None,
+            frame_seq: 0,
+            stats: Default::default(),
+            desync: false,
+            keep_partial: false,
+            fresh: false,
+            cancel: Default::default(),
+            dark: None,
+            fpn: None,
+            flat: None,
+            defects: None,
+            streaming: false,
+            suspended: None,
+        })
+    }
+
+    /// Reopen the device after it was unplugged and plugged back in (to the
+    /// same port).
+    ///
+    /// The current settings (mode, depth, exposure, etc) are kept. If the
+    /// camera was streaming when it disappeared, the stream is restarted.
+    /// This fails with [Error::Disconnected] if the device isn't back yet
+    /// (see [hotplug::HotplugMonitor] for waiting on it).
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        let was_streaming = self.streaming;
+        let loc = Some(&self.location);
+        let (dev, desc, handle) = match open_device(&mut self._ctx, VID, PID, loc) {
+            Ok(res) => res,
+            Err(rusb::Error::NoDevice) => return Err(Error::Disconnected),
             Err(e) => return Err(Error::Rusb(e)),
         };
+        claim(&handle)?;
+        self.bulk = None;
+        self._dev = dev;
+        self._desc = desc;
+        self.handle = Arc::new(handle);
 
-        if let Ok(true) = res.handle.kernel_driver_active(0) {
-            res.handle.detach_kernel_driver(0)?;
+        self.streaming = false;
+        if was_streaming {
+            self.start_stream()?;
         }
-        res.handle.set_active_configuration(1)?;
-        res.handle.claim_interface(0)?;
-
-        Ok(res)
+        Ok(())
     }
 
     pub fn get_mode(&self) -> CameraMode { self.mode }
     pub fn get_depth(&self) -> BitDepth { self.depth }
+    /// Changing the bit depth (or the mode) clears the calibration
+    /// (see [crate::calibration]).
     pub fn set_depth(&mut self, depth: BitDepth) -> Result<(), Error> {
         if depth == self.depth { return Ok(()) }
         if self.streaming { return Err(Error::Unimplemented) }
         self.depth = depth;
+        self.clear_calibration();
         Ok(())
     }
     pub fn set_mode(&mut self, mode: CameraMode) -> Result<(), Error> {
         if mode == self.mode { return Ok(()); }
         if self.streaming { return Err(Error::Unimplemented); }
         self.mode = mode;
+        self.clear_calibration();
         Ok(())
     }
 
-    /// Configure the device and start streaming data
-    pub fn start_stream(&mut self) -> Result<(), Error> {
-        if self.streaming { return Ok(()) }
+    /// Mirror (horizontal) and/or flip (vertical) the sensor readout.
+    ///
+    /// Microscope optics usually invert the image, so this saves a software
+    /// flip pass on every frame. This takes effect immediately if the camera 
+    /// is streaming, otherwise it's applied by the next [Camera::start_stream].
+    ///
+    /// NOTE: Mirroring changes the phase of the Bayer pattern. This also
+    /// clears the calibration (see [crate::calibration]), which no longer
+    /// lines up with the frame.
+    pub fn set_flip(&mut self, horizontal: bool, vertical: bool) 
+        -> Result<(), Error>
+    {
+        if (horizontal, vertical) == self.flip { return Ok(()); }
+        self.flip = (horizontal, vertical);
+        self.clear_calibration();
+        if self.streaming {
+            self.run_script("flip")?;
+        }
+        Ok(())
+    }
 
-        // Set the magic XOR value to zero
-        let mut hbuf: [u8; 2] = [0; 2];
-        self.ven_in(0x16, 0x0000, 0x0000, &mut hbuf)?;
+    /// Returns the current readout direction as `(horizontal, vertical)`.
+    pub fn get_flip(&self) -> (bool, bool) { self.flip }
 
-        self.ven_out(0x01, 0x0001, 0x000f, &[])?;
-        //self.ven_out(0x01, 0x0000, 0x000f, &[])?;
-        //self.ven_out(0x01, 0x0001, 0x000f, &[])?;
+    /// Mark the next complete frame (see [Frame::marked]).
+    ///
+    /// This is useful for flagging interesting frames to subscribers that
+    /// only want marked frames (i.e. `filter::FrameFilter::Marked`).
+    pub fn mark_next_frame(&mut self) { self.mark_next = true; }
 
-        self.ven_in(0x0a, 0x0000, 0xffff, &mut hbuf)?;
-        self.ven_in(0x0a, 0x0000, 0xffff, &mut hbuf)?;
-        self.ven_in(0x0a, 0x0000, 0xfeff, &mut hbuf)?;
-        self.ven_in(0x0a, 0x0000, 0xfeff, &mut hbuf)?;
+    /// Override the Bayer phase reported in [Frame::cfa].
+    ///
+    /// Some units (or firmware revisions) may read out a different phase 
+    /// than expected; see [cfa::detect] for working it out from a capture.
+    /// Use `None` to go back to the phase implied by the readout direction.
+    pub fn set_cfa_override(&mut self, cfa: Option<Cfa>) {
+        self.cfa_override = cfa;
+    }
+    pub fn get_cfa_override(&self) -> Option<Cfa> { self.cfa_override }
 
+    /// Returns the Bayer phase of frames read with the current settings.
+    pub fn cfa(&self) -> Cfa {
+        let (h, v) = self.flip;
+        self.cfa_override.unwrap_or(Cfa::DEFAULT.flipped(h, v))
+    }
+
+    /// Returns the current exposure time.
+    ///
+    /// This is rounded to the nearest line time when it's programmed, so 
+    /// the actual exposure may be off by a few microseconds.
+    pub fn get_exposure_time(&self) -> Duration { self.exposure }
+
+    /// Set the exposure time. 
+    ///
+    /// This takes effect immediately if the camera is streaming, although
+    /// the next frame or two may still be using the old exposure.
+    ///
+    /// NOTE: The line time has only been measured in mode 1.
+    pub fn set_exposure_time(&mut self, exposure: Duration) 
+        -> Result<(), Error>
+    {
+        let (min, max) = self.protocol.exposure_range();
+        if exposure < min || exposure > max { 
+            return Err(Error::InvalidArgument); 
+        }
+        self.exposure = exposure;
+        if self.streaming {
+            self.run_script("exposure")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the current (raw) analog gain.
+    pub fn get_gain(&self) -> u16 { self.gain }
+
+    /// Set the analog gain (the raw value of the sensor gain register).
+    ///
+    /// The default is `0x610c`, which is the only value that's been seen in
+    /// captures; values outside the range in [Camera::capabilities] are
+    /// rejected with [Error::InvalidArgument]. It's not known whether other
+    /// values are safe for the sensor, so writing them is left to
+    /// `RawAccess::set_gain` (the `unsafe-registers` feature).
+    pub fn set_gain(&mut self, gain: u16) -> Result<(), Error> {
+        let (min, max) = self.model_info().gain;
+        if gain < min || gain > max {
+            return Err(Error::InvalidArgument);
+        }
+        self.set_gain_unchecked(gain)
+    }
+
+    /// Set the analog gain without checking it against the model.
+    pub (crate) fn set_gain_unchecked(&mut self, gain: u16) -> Result<(), Error> {
+        self.gain = gain;
+        if self.streaming {
+            self.run_script("gain")?;
+        }
+        Ok(())
+    }
+
+    /// Configure the device and start streaming data
+    pub fn start_stream(&mut self) -> Result<(), Error> {
+        if self.streaming { return Ok(()) }
+        self.suspended = None;
+
+        self.run_script("start")?;
         self.sensor_init()?;
 
-        // After this command, frames should be available for us to read with
+        // After this, frames should be available for us to read with
         // bulk transfers on endpoint 0x81.
-        self.ven_out(0x01, 0x0003, 0x000f, &[])?;
-        std::thread::sleep(Duration::from_millis(10));
+        self.run_script("arm")?;
 
         self.streaming = true;
+        self.desync = false;
+        self.fresh = true;
+        self.reset_stream_stats();
         Ok(())
     }
 
@@ -156,25 +547,185 @@ impl Camera {
     pub fn stop_stream(&mut self) -> Result<(), Error> {
         if !self.streaming { return Ok(()); }
 
-        self.sys_write(0x0a00, 0x0000)?;
-        self.sensor_write(0x1000, 0x0000)?;
-        self.ven_out(0x01, 0x0000, 0x000f, &[])?;
-
-        let mut wbuf: [u8; 4] = [0; 4];
-        self.ven_in(0x17, 0x0000, 0x0000, &mut wbuf)?;
-        std::thread::sleep(Duration::from_millis(10));
+        // Cancel any outstanding transfers before telling the device to stop
+        self.bulk = None;
+        self.run_script("stop")?;
 
         self.streaming = false;
         Ok(())
     }
+
+    /// Stop streaming data, spending at most (roughly) `budget` on it.
+    ///
+    /// The stop sequence is (usually) five control transfers, so each of 
+    /// them gets a fifth of the budget as its timeout. The sequence is abandoned at the
+    /// first failure. This is what [Drop] uses, so that a vanished or wedged 
+    /// device can't hold up application shutdown.
+    pub fn stop_stream_within(&mut self, budget: Duration) -> Result<(), Error> {
+        let saved = self.timeout;
+        self.timeout = std::cmp::min(saved, budget / 5);
+        let res = self.stop_stream();
+        self.timeout = saved;
+        res
+    }
+
+    /// Put the sensor into a low-power state, keeping the device claimed.
+    ///
+    /// This stops the stream (if it was running) and leaves the readout and
+    /// sensor disabled. Use [Camera::resume] to restart streaming with the
+    /// same settings. [Camera::read_frame] fails with [Error::Suspended] 
+    /// until then.
+    pub fn suspend(&mut self) -> Result<(), Error> {
+        if self.suspended.is_some() { return Ok(()); }
+        let was_streaming = self.streaming;
+        if self.streaming {
+            self.stop_stream()?;
+        } else {
+            self.run_script("suspend")?;
+        }
+        self.suspended = Some(was_streaming);
+        Ok(())
+    }
+
+    /// Leave the low-power state entered with [Camera::suspend].
+    ///
+    /// The stream is restarted if it was running before suspending.
+    pub fn resume(&mut self) -> Result<(), Error> {
+        match self.suspended.take() {
+            Some(true) => self.start_stream(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns 'true' if the camera is suspended.
+    pub fn is_suspended(&self) -> bool { self.suspended.is_some() }
+
+    pub fn get_teardown_budget(&self) -> Duration { self.teardown_budget }
+    pub fn set_teardown_budget(&mut self, budget: Duration) {
+        self.teardown_budget = budget;
+    }
 }
 
 
 
+/// Policy for automatically recovering from bulk transfer errors.
+#[derive(Copy, Clone, Debug)]
+pub struct RecoveryPolicy {
+    /// Number of times [Camera::read_frame] may try to recover (zero 
+    /// disables recovery)
+    pub max_attempts: usize,
+    /// Delay before each attempt
+    pub delay: Duration,
+}
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self { max_attempts: 3, delay: Duration::from_millis(50) }
+    }
+}
+
 /// Container for a frame of raw image data returned by the device.
 pub struct Frame {
-    /// Raw image data (in bytes)
-    pub data: Vec<u8>,
+    /// Raw image data (in bytes), as read from the device. 12-bit samples
+    /// take two bytes each, little-endian, with the value in the low 12
+    /// bits (see [Frame::max_value]).
+    pub data: FrameBuffer,
+    /// Number of rows
+    pub height: usize,
+    /// Number of columns
+    pub width: usize,
+    /// Number of bytes per pixel
+    pub bpp: usize,
+    pub elapsed: std::time::Duration,
+    /// Set when the frame was marked with [Camera::mark_next_frame]
+    pub marked: bool,
+    /// Bayer phase of the raw data
+    pub cfa: Cfa,
+    /// Sequence number (counting every frame read from the device, so gaps
+    /// mean frames were discarded)
+    pub seq: u64,
+    /// When readout of the frame finished
+    pub timestamp: std::time::Instant,
+    /// Cleared for truncated frames (see [Camera::set_keep_partial_frames]),
+    /// in which case `data` only holds the bytes actually received
+    pub complete: bool,
+}
+impl Frame {
+    /// Build a frame from raw data and its metadata (i.e. in a
+    /// [sink::FrameSink], to keep a copy of a frame).
+    pub fn from_info(data: impl Into<FrameBuffer>, info: &FrameInfo) -> Frame {
+        Frame { data: data.into(), height: info.height, width: info.width, bpp: info.bpp,
+            elapsed: info.elapsed, marked: info.marked, cfa: info.cfa,
+            seq: info.seq, timestamp: info.timestamp, complete: info.complete,
+        }
+    }
+
+    /// Returns the metadata for this frame.
+    pub fn info(&self) -> FrameInfo {
+        FrameInfo { height: self.height, width: self.width, bpp: self.bpp,
+            elapsed: self.elapsed, marked: self.marked, cfa: self.cfa,
+            seq: self.seq, timestamp: self.timestamp,
+            complete: self.complete, received: self.data.len(),
+        }
+    }
+
+    /// View 16-bit frame data as samples, without copying.
+    ///
+    /// Returns `None` for 8-bit frames, or when the data can't be viewed in
+    /// place (on a big-endian host, or if the buffer isn't aligned); use
+    /// [Frame::to_u16] in that case.
+    pub fn as_u16(&self) -> Option<&[u16]> {
+        if self.bpp != 2 || cfg!(target_endian = "big") { return None; }
+        // SAFETY: every bit pattern is a valid u16
+        let (head, samples, tail) = unsafe { self.data.align_to::<u16>() };
+        if head.is_empty() && tail.is_empty() { Some(samples) } else { None }
+    }
+
+    /// Copy the frame data into a vector of samples (for either bit depth).
+    pub fn to_u16(&self) -> Vec<u16> {
+        match self.bpp {
+            2 => self.data.chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]])).collect(),
+            _ => self.data.iter().map(|b| *b as u16).collect(),
+        }
+    }
+
+    /// Full-scale sample value (`0x0fff` for 12-bit data, `0xff` for 8-bit).
+    pub fn max_value(&self) -> u16 {
+        Self::max_value_for(self.bpp)
+    }
+
+    /// Full-scale value of samples that are `bpp` bytes wide.
+    pub fn max_value_for(bpp: usize) -> u16 {
+        if bpp == 2 { 0x0fff } else { 0x00ff }
+    }
+
+    /// Number of significant bits in each sample (12 or 8).
+    pub fn bits(&self) -> u32 {
+        16 - self.max_value().leading_zeros()
+    }
+
+    /// Returns the sample at the given pixel index.
+    ///
+    /// 16-bit samples are little-endian (as they come off the wire). This
+    /// is how the original test program (`src/bin/test.rs`) reads them, as
+    /// native `u16`s on a little-endian host.
+    pub fn sample(&self, idx: usize) -> u16 {
+        Self::sample_at(&self.data, self.bpp, idx)
+    }
+
+    /// Returns the sample at the given pixel index in raw frame data with
+    /// `bpp` bytes per sample (see [Frame::sample]).
+    pub fn sample_at(data: &[u8], bpp: usize, idx: usize) -> u16 {
+        match bpp {
+            2 => u16::from_le_bytes([data[idx * 2], data[idx * 2 + 1]]),
+            _ => data[idx] as u16,
+        }
+    }
+}
+
+/// Metadata for a frame read with [Camera::read_frame_into].
+#[derive(Copy, Clone, Debug)]
+pub struct FrameInfo {
     /// Number of rows
     pub height: usize,
     /// Number of columns
@@ -182,73 +733,282 @@ pub struct Frame {
     /// Number of bytes per pixel
     pub bpp: usize,
     pub elapsed: std::time::Duration,
+    /// Set when the frame was marked with [Camera::mark_next_frame]
+    pub marked: bool,
+    /// Bayer phase of the raw data
+    pub cfa: Cfa,
+    /// Sequence number (counting every frame read from the device, so gaps
+    /// mean frames were discarded)
+    pub seq: u64,
+    /// When readout of the frame finished
+    pub timestamp: std::time::Instant,
+    /// Cleared for truncated frames (see [Camera::set_keep_partial_frames])
+    pub complete: bool,
+    /// Number of bytes actually received
+    pub received: usize,
+}
+impl FrameInfo {
+    /// Size of the frame (in bytes)
+    pub fn len(&self) -> usize { self.width * self.height * self.bpp }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
 }
 
 impl Camera {
+    /// Return truncated frames (with [Frame::complete] cleared) instead of
+    /// failing with [Error::FirstFrame]. This is mostly useful for looking
+    /// at what the device actually sent.
+    pub fn set_keep_partial_frames(&mut self, keep: bool) {
+        self.keep_partial = keep;
+    }
+    pub fn get_keep_partial_frames(&self) -> bool { self.keep_partial }
+
+    /// Size (in bytes) of a frame with the current mode and bit depth.
+    pub fn frame_len(&self) -> usize {
+        let (width, height) = self.mode.dimensions();
+        width * height * self.depth_bpp()
+    }
+
+    fn depth_bpp(&self) -> usize {
+        match self.depth {
+            BitDepth::BitDepth12 => 2,
+            BitDepth::BitDepth8  => 1,
+        }
+    }
+
     /// Try to read out an entire frame from the device. 
+    ///
+    /// If the bulk endpoint stalls or returns an I/O error, this will try
+    /// to [Camera::recover] up to [RecoveryPolicy::max_attempts] times before
+    /// giving up. The frame being read when the error occurred is lost.
+    ///
+    /// The first frame after starting the stream is truncated, and is read 
+    /// and discarded automatically.
+    ///
+    /// If a frame interval is set (see [Camera::set_frame_interval]), frames
+    /// arriving before the interval has elapsed are read and discarded.
+    ///
+    /// If a [pool::FramePool] is set, this blocks until a buffer is free.
     pub fn read_frame(&mut self) -> Result<Frame, Error> {
-        let timeout = Duration::from_millis(500);
+        let len = self.frame_len();
+        let mut data = match self.pool.as_ref() {
+            Some(pool) => pool.get(len),
+            None => FrameBuffer::from(vec![0u8; len]),
+        };
+        let info = self.read_frame_into(&mut data)?;
+        data.truncate(info.received);
+        Ok(Frame::from_info(data, &info))
+    }
+
+    /// Like [Camera::read_frame], but reads into a caller-provided buffer.
+    ///
+    /// The buffer must be at least [Camera::frame_len] bytes long (otherwise
+    /// this fails with [Error::InvalidArgument]), and only the first 
+    /// [FrameInfo::len] bytes are written. This avoids allocating a new 
+    /// frame buffer every time.
+    pub fn read_frame_into(&mut self, buf: &mut [u8]) -> Result<FrameInfo, Error> {
+        if self.suspended.is_some() { return Err(Error::Suspended); }
+        if buf.len() < self.frame_len() { return Err(Error::InvalidArgument); }
+        loop {
+            let mut info = self.read_frame_recovering(buf)?;
+            if let (Some(interval), Some(last)) = (self.frame_interval, self.last_frame) {
+                if last.elapsed() < interval {
+                    self.stats.discarded += 1;
+                    continue;
+                }
+            }
+            self.last_frame = Some(std::time::Instant::now());
+            info.marked = std::mem::take(&mut self.mark_next);
+            info.cfa = self.cfa();
+            self.calibrate(buf, &info)?;
+            self.stats.delivered += 1;
+            return Ok(info);
+        }
+    }
+
+    /// Limit the rate at which [Camera::read_frame] returns frames.
+    ///
+    /// The sensor frame-timing registers aren't understood yet, so this is 
+    /// done in software: the device keeps producing frames at full rate, and
+    /// the extra ones are drained from the bulk endpoint and dropped (which
+    /// keeps the device from backing up). Use `None` to disable pacing.
+    pub fn set_frame_interval(&mut self, interval: Option<Duration>) {
+        self.frame_interval = interval;
+    }
+    pub fn get_frame_interval(&self) -> Option<Duration> { self.frame_interval }
 
-        // This seems like the maximum transfer size on my machine.
-        const CHUNK_LEN: usize  = 0x0004_0000;
+    fn read_frame_recovering(&mut self, buf: &mut [u8]) -> Result<FrameInfo, Error> {
+        let mut attempts = 0;
+        loop {
+            match self.read_frame_once(buf) {
+                // Expected, so not worth bothering the caller with
+                Err(Error::FirstFrame) if std::mem::take(&mut self.fresh) => {},
+                Err(Error::Rusb(e @ (rusb::Error::Pipe | rusb::Error::Io)))
+                    if attempts < self.recovery.max_attempts =>
+                {
+                    eprintln!("bulk read failed ({}), recovering", e);
+                    attempts += 1;
+                    std::thread::sleep(self.recovery.delay);
+                    self.recover()?;
+                },
+                Err(Error::Desynchronized) if attempts < self.recovery.max_attempts => {
+                    eprintln!("lost frame boundary, resynchronizing");
+                    attempts += 1;
+                },
+                res => return res,
+            }
+        }
+    }
+
+    /// Try to resume streaming after a pipe/I/O error on the bulk endpoint.
+    ///
+    /// This clears the halt condition on endpoint 0x81 and re-issues the 
+    /// command that starts readout (without re-initializing the sensor).
+    pub fn recover(&mut self) -> Result<(), Error> {
+        if !self.streaming { return Ok(()); }
+        self.bulk = None;
+        self.handle.clear_halt(0x81)?;
+        self.run_script("arm")?;
+        self.fresh = true;
+        Ok(())
+    }
+
+    /// Discard data until the end of the current frame.
+    ///
+    /// Gives up with [Error::Desynchronized] if no frame boundary shows up 
+    /// within two frames' worth of data.
+    fn resync(&mut self, frame_len: usize, timeout: Duration) -> Result<(), Error> {
+        let mut buf = [0u8; CHUNK_LEN];
+        let mut total = 0;
+        while total <= frame_len * 2 {
+            let rlen = self.read_chunk(&mut buf, timeout)?;
+            total += rlen;
+            if rlen < CHUNK_LEN {
+                self.desync = false;
+                return Ok(());
+            }
+        }
+        self.stats.desynchronized += 1;
+        Err(Error::Desynchronized)
+    }
+
+    pub fn get_recovery_policy(&self) -> RecoveryPolicy { self.recovery }
+    pub fn set_recovery_policy(&mut self, policy: RecoveryPolicy) {
+        self.recovery = policy;
+    }
+
+    fn read_frame_once(&mut self, data: &mut [u8]) -> Result<FrameInfo, Error> {
+        let timeout = Duration::from_millis(500);
+        // Only used for the tail end of the frame (see below)
         let mut buf   = [0u8; CHUNK_LEN];
 
-        // Allocate space to hold a completed frame
         let (width, height) = self.mode.dimensions();
-        let bpp = match self.depth {
-            BitDepth::BitDepth12 => 2,
-            BitDepth::BitDepth8  => 1,
-        };
+        let bpp = self.depth_bpp();
         let frame_len = (width * height) * bpp;
-        let mut data = vec![0u8; frame_len];
         let mut cur  = 0;
+        let mut total = 0;
+
+        // If the last read failed partway through a frame, the rest of that
+        // frame is still waiting for us.
+        if self.desync {
+            self.resync(frame_len, timeout)?;
+        }
 
         // Issue bulk reads until we've received an entire frame
         let start = std::time::Instant::now();
         loop {
-            match self.handle.read_bulk(0x81, &mut buf, timeout) {
+            if self.cancel.take() {
+                self.desync = total > 0;
+                return Err(Error::Cancelled);
+            }
+            // While there's room for an entire chunk, read straight into the
+            // frame buffer. Otherwise, the device might send more than what
+            // fits (i.e. if we're out of sync), so read into a separate buffer
+            // and only copy what fits.
+            let rem = frame_len - cur;
+            let direct = rem >= CHUNK_LEN;
+            let chunk_start = std::time::Instant::now();
+            let res = if direct {
+                self.read_chunk(&mut data[cur..cur+CHUNK_LEN], timeout)
+            } else {
+                self.read_chunk(&mut buf, timeout)
+            };
+            if res.is_ok() {
+                self.stats.chunk_latency.record(chunk_start.elapsed());
+            }
+            match res {
                 Ok(rlen) => {
+                    total += rlen;
+
                     // If the incoming data would overflow the buffer,
                     // just truncate it and copy the remaining bytes
-                    let rem = frame_len - cur;
                     let len = if rlen > rem { rem } else { rlen };
-
-                    // Copy into frame buffer
-                    data[cur..cur+len].copy_from_slice(&buf[..len]);
+                    if !direct {
+                        data[cur..cur+len].copy_from_slice(&buf[..len]);
+                    }
                     cur += len;
 
                     // If we get less bytes than we requested, this indicates
                     // that the device has finished reading out a frame.
                     if rlen < CHUNK_LEN { break; }
                 },
-                Err(e) => return Err(Error::from(e)),
+                Err(e) => {
+                    self.desync = total > 0;
+                    return Err(e);
+                },
             }
         }
         let elapsed = start.elapsed();
+        self.stats.bytes += cur as u64;
+        self.stats.transfer_time += elapsed;
+
+        // If we got more than a frame, a frame boundary was missed somewhere.
+        // We're at the end of a frame now, so the next read should be fine.
+        if total > frame_len {
+            self.stats.desynchronized += 1;
+            return Err(Error::Desynchronized);
+        }
 
         // This really only occurs on the first frame after initialization; 
-        // the data is typically truncated, and we can just discard it.
-        if cur < frame_len {
-            Err(Error::FirstFrame)
-        } else {
-            Ok(Frame { width, height, bpp, data, elapsed })
+        // the data is typically truncated, and [Camera::read_frame_recovering]
+        // just discards it.
+        let complete = cur == frame_len;
+        if !complete {
+            self.stats.truncated += 1;
+            if !self.keep_partial { return Err(Error::FirstFrame); }
         }
+        self.fresh = false;
+
+        // No frame counter has been found in the data or registers, so
+        // this is counted on our side.
+        let seq = self.frame_seq;
+        self.frame_seq += 1;
+        Ok(FrameInfo { width, height, bpp, elapsed,
+            marked: false, cfa: Cfa::DEFAULT,
+            seq, timestamp: start + elapsed,
+            complete, received: cur,
+        })
     }
 }
 
 impl Drop for Camera {
     fn drop(&mut self) {
-        match self.stop_stream() {
+        match self.stop_stream_within(self.teardown_budget) {
             Ok(_) => {},
-            Err(e) => println!("Couldn't stop streaming? {:?}", e),
+            // If the device is gone (or not responding), don't bother
+            // trying to talk to it any more.
+            Err(e @ (Error::Disconnected | Error::Rusb(rusb::Error::Timeout))) => {
+                eprintln!("Couldn't stop streaming, skipping teardown: {:?}", e);
+                return;
+            },
+            Err(e) => eprintln!("Couldn't stop streaming? {:?}", e),
         }
         match self.handle.release_interface(0) {
             Ok(_) => {},
-            Err(e) => println!("Couldn't release interface 0? {}", e),
+            Err(e) => eprintln!("Couldn't release interface 0? {}", e),
         }
         match self.handle.reset() {
             Ok(_) => {},
-            Err(e) => println!("Couldn't reset handle? {}", e),
+            Err(e) => eprintln!("Couldn't reset handle? {}", e),
         }
     }
 }
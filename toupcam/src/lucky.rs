@@ -0,0 +1,85 @@
+//! Lucky imaging: keeping only the sharpest frames.
+//!
+//! When imaging through turbulent air (i.e. planets), most frames are
+//! blurred, but a few catch a moment of good seeing. A [LuckySelector] is a
+//! [FrameSink] that scores every frame with [focus_metric] and only passes
+//! the best ones on to another sink.
+//!
+//! Frames are judged in batches: out of every `window` frames, the top
+//! `fraction` are kept (in capture order). A copy of every frame in the
+//! current batch is held in memory, so keep the window small at full
+//! resolution.
+
+use crate::{ Error, Frame, FrameInfo };
+use crate::focus::focus_metric;
+use crate::sink::{ Filter, FrameSink };
+
+/// Row step used when scoring frames (see [focus_metric]).
+const FOCUS_STEP: usize = 2;
+
+/// Passes the sharpest frames of each batch on to `S`.
+pub struct LuckySelector<S> {
+    out: Filter<S>,
+    fraction: f64,
+    window: usize,
+    batch: Vec<(f64, Frame)>,
+}
+impl<S: FrameSink> LuckySelector<S> {
+    /// Keep the best `fraction` (0.0 to 1.0) of every `window` frames,
+    /// writing them to `sink`.
+    pub fn new(sink: S, fraction: f64, window: usize) -> Self {
+        Self { out: Filter::new(sink), fraction: fraction.clamp(0.0, 1.0),
+            window: window.max(1), batch: Vec::new(),
+        }
+    }
+
+    /// Number of frames scored so far.
+    pub fn seen(&self) -> u64 { self.out.seen() }
+
+    /// Number of frames passed on to the sink so far.
+    pub fn kept(&self) -> u64 { self.out.kept() }
+
+    pub fn get_ref(&self) -> &S { self.out.get_ref() }
+
+    /// Return the inner sink (frames in an unfinished batch are dropped; call
+    /// [FrameSink::flush] first to keep them).
+    pub fn into_inner(self) -> S { self.out.into_inner() }
+
+    /// Write the best frames of the current batch to the sink.
+    fn select(&mut self) -> Result<(), Error> {
+        if self.batch.is_empty() { return Ok(()); }
+        let keep = ((self.batch.len() as f64 * self.fraction).ceil() as usize)
+            .min(self.batch.len());
+        // Find the score of the worst frame that's still kept
+        let mut scores: Vec<f64> = self.batch.iter().map(|(s, _)| *s).collect();
+        scores.sort_unstable_by(|a, b| b.total_cmp(a));
+        let mut left = keep;
+        let cutoff = scores.get(keep.wrapping_sub(1)).copied().unwrap_or(f64::INFINITY);
+
+        let mut res = Ok(());
+        for (score, frame) in self.batch.drain(..) {
+            if res.is_ok() && left > 0 && score >= cutoff {
+                left -= 1;
+                res = self.out.keep(&frame.data, &frame.info());
+            }
+            self.out.recycle(frame);
+        }
+        res
+    }
+}
+impl<S: FrameSink> FrameSink for LuckySelector<S> {
+    fn write_frame(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        let frame = self.out.copy(data, info);
+        let score = focus_metric(&frame, FOCUS_STEP);
+        self.batch.push((score, frame));
+        if self.batch.len() >= self.window {
+            self.select()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.select()?;
+        self.out.flush()
+    }
+}
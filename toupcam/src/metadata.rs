@@ -0,0 +1,104 @@
+//! Acquisition parameters to store alongside captured frames.
+
+use crate::{ open_device, Camera, CameraMode, Error, VID, PID };
+use rusb::Context;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+/// The camera settings a frame was captured with.
+#[derive(Clone, Debug)]
+pub struct CaptureMetadata {
+    /// Model name (see [crate::model::ModelInfo])
+    pub model: &'static str,
+    /// USB serial number, if the device reports one
+    pub serial: Option<String>,
+    pub mode: CameraMode,
+    /// Significant bits per sample (8 or 12)
+    pub bits: u32,
+    pub exposure: Duration,
+    /// Raw analog gain (see [Camera::set_gain])
+    pub gain: u16,
+    /// Wall-clock time of the capture
+    pub timestamp: SystemTime,
+}
+impl CaptureMetadata {
+    /// The capture time as UTC `(year, month, day, hour, minute, second)`.
+    pub fn utc(&self) -> (i64, u32, u32, u32, u32, u32) {
+        utc(self.timestamp)
+    }
+}
+
+/// Break a time down into UTC `(year, month, day, hour, minute, second)`.
+pub (crate) fn utc(t: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    };
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400) as u32);
+    // Days to civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, rem / 3600, rem / 60 % 60, rem % 60)
+}
+
+impl Camera {
+    /// Read the USB serial number of the device (`None` if it doesn't have
+    /// one).
+    pub fn serial_number(&self) -> Result<Option<String>, Error> {
+        if self._desc.serial_number_string_index().is_none() { return Ok(None); }
+        Ok(Some(self.handle.read_serial_number_string_ascii(&self._desc)?))
+    }
+
+    /// Open the camera with the USB serial number `serial`.
+    ///
+    /// Each connected camera is opened (but not claimed) to read its serial
+    /// number, so cameras that don't match, or are in use elsewhere, are
+    /// left alone. If none match, this returns the error from the last
+    /// camera whose serial number couldn't be read, or
+    /// [rusb::Error::NotFound].
+    pub fn open_serial(serial: &str) -> Result<Self, Error> {
+        let mut ctx = Context::new()?;
+        let mut err = Error::Rusb(rusb::Error::NotFound);
+        for loc in Self::list()? {
+            let res = open_device(&mut ctx, VID, PID, Some(&loc))
+                .and_then(|(_dev, desc, handle)| {
+                    if desc.serial_number_string_index().is_none() { return Ok(None); }
+                    handle.read_serial_number_string_ascii(&desc).map(Some)
+                });
+            match res {
+                Ok(found) if found.as_deref() == Some(serial) => return Self::open_at(&loc),
+                Ok(_) => {},
+                Err(e) => err = Error::Rusb(e),
+            }
+        }
+        Err(err)
+    }
+
+    /// Describe the current settings, timestamped now (i.e. to store with a
+    /// frame that was just read).
+    ///
+    /// This reads the serial number from the device (a USB request), so for
+    /// a long recording, call it once and update the timestamp per frame.
+    /// The serial number is left out if it can't be read.
+    pub fn metadata(&self) -> CaptureMetadata {
+        let model = self.model_info();
+        CaptureMetadata {
+            model: model.name,
+            serial: self.serial_number().ok().flatten(),
+            mode: self.get_mode(),
+            bits: match self.get_depth() {
+                crate::BitDepth::BitDepth8 => 8,
+                crate::BitDepth::BitDepth12 => 12,
+            },
+            exposure: self.get_exposure_time(),
+            gain: self.get_gain(),
+            timestamp: SystemTime::now(),
+        }
+    }
+}
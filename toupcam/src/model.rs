@@ -0,0 +1,81 @@
+//! Per-model information and run-time capability queries.
+
+use crate::{ Camera, CameraMode, BitDepth, VID, PID };
+use std::time::Duration;
+
+/// Static description of a supported camera model.
+#[derive(Copy, Clone, Debug)]
+pub struct ModelInfo {
+    pub name: &'static str,
+    pub vid: u16,
+    pub pid: u16,
+    pub modes: &'static [CameraMode],
+    pub depths: &'static [BitDepth],
+    /// Range of values for the analog gain register
+    pub gain: (u16, u16),
+}
+
+/// AmScope MU1603 (Touptek U3CMOS16000KPA).
+pub const MU1603: ModelInfo = ModelInfo {
+    name: "AmScope MU1603",
+    vid: VID,
+    pid: PID,
+    modes: &[ CameraMode::Mode0, CameraMode::Mode1, CameraMode::Mode2 ],
+    depths: &[ BitDepth::BitDepth8, BitDepth::BitDepth12 ],
+    // Only `0x610c` has been seen in captures, and the mapping onto an
+    // actual amplification factor isn't known yet. Other values can only be
+    // written with `RawAccess::set_gain` (the `unsafe-registers` feature).
+    gain: (0x610c, 0x610c),
+};
+
+/// Table of supported models.
+pub const MODELS: &[ModelInfo] = &[ MU1603 ];
+
+/// Look up a model by VID/PID.
+pub fn lookup(vid: u16, pid: u16) -> Option<&'static ModelInfo> {
+    MODELS.iter().find(|m| m.vid == vid && m.pid == pid)
+}
+
+/// Supported settings for an open camera.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub model: &'static str,
+    /// Supported modes along with their `(width, height)`
+    pub resolutions: Vec<(CameraMode, (usize, usize))>,
+    pub depths: Vec<BitDepth>,
+    pub exposure_min: Duration,
+    pub exposure_max: Duration,
+    /// Range of values for [Camera::set_gain]
+    pub gain_min: u16,
+    pub gain_max: u16,
+}
+
+impl Camera {
+    /// The model of the open camera (assumed to be an MU1603 if unknown).
+    pub (crate) fn model_info(&self) -> &'static ModelInfo {
+        let desc = &self._desc;
+        lookup(desc.vendor_id(), desc.product_id()).unwrap_or(&MU1603)
+    }
+
+    /// Returns the supported modes, bit depths, and exposure/gain ranges.
+    pub fn capabilities(&self) -> Capabilities {
+        let model = self.model_info();
+        let (exposure_min, exposure_max) = self.protocol.exposure_range();
+        Capabilities {
+            model: model.name,
+            resolutions: model.modes.iter().map(|m| (*m, m.dimensions())).collect(),
+            depths: model.depths.to_vec(),
+            exposure_min,
+            exposure_max,
+            gain_min: model.gain.0,
+            gain_max: model.gain.1,
+        }
+    }
+
+    /// The device release number from the USB descriptor (`bcdDevice`) as
+    /// `(major, minor, sub-minor)`, which presumably tracks the firmware.
+    pub fn firmware_version(&self) -> (u8, u8, u8) {
+        let v = self._desc.device_version();
+        (v.major(), v.minor(), v.sub_minor())
+    }
+}
@@ -0,0 +1,208 @@
+//! Detecting changes between frames (i.e. for triggered recording).
+//!
+//! A [MotionDetector] compares each frame against a reference: either the
+//! previous frame, or a fixed frame set with [MotionDetector::set_reference].
+//! Pixels that differ by more than a threshold count as changed, and the
+//! changed pixels are grouped into blocks to find bounding boxes for the
+//! areas that changed. A [MotionTrigger] uses this to only pass frames on to
+//! another sink while something in the field of view is moving.
+
+use crate::{ Error, Frame, FrameInfo };
+use crate::region::Rect;
+use crate::sink::{ Filter, FrameSink };
+
+/// Default size (in pixels) of the blocks used to find changed regions.
+pub const DEFAULT_BLOCK_SIZE: usize = 16;
+
+/// Fraction of the pixels in a block which must change for the block to
+/// count as changed (so that isolated noisy pixels don't form regions).
+const BLOCK_FRACTION: f64 = 0.1;
+
+/// The difference between a frame and the reference.
+#[derive(Clone, Debug, Default)]
+pub struct Motion {
+    /// Fraction of the pixels that changed (0.0 to 1.0)
+    pub score: f64,
+    /// Mean absolute difference over every pixel (in raw sample units)
+    pub mean_difference: f64,
+    /// Bounding boxes of the changed areas (aligned to the block size)
+    pub regions: Vec<Rect>,
+}
+
+/// Compares frames against a reference frame.
+#[derive(Clone, Debug)]
+pub struct MotionDetector {
+    threshold: u16,
+    block: usize,
+    /// Set when the reference is fixed (rather than the previous frame)
+    pinned: bool,
+    reference: Vec<u16>,
+    dims: (usize, usize, usize),
+}
+impl MotionDetector {
+    /// Create a detector comparing each frame with the previous one.
+    ///
+    /// A pixel counts as changed when it differs from the reference by more
+    /// than `threshold` (in raw sample units).
+    pub fn new(threshold: u16) -> Self {
+        Self { threshold, block: DEFAULT_BLOCK_SIZE, pinned: false,
+            reference: Vec::new(), dims: (0, 0, 0),
+        }
+    }
+
+    pub fn get_threshold(&self) -> u16 { self.threshold }
+    pub fn set_threshold(&mut self, threshold: u16) { self.threshold = threshold; }
+    pub fn get_block_size(&self) -> usize { self.block }
+    pub fn set_block_size(&mut self, size: usize) { self.block = size.max(1); }
+
+    /// Compare every following frame with `frame`, instead of with the
+    /// previous one.
+    pub fn set_reference(&mut self, frame: &Frame) {
+        self.reference = frame.to_u16();
+        self.dims = (frame.width, frame.height, frame.bpp);
+        self.pinned = true;
+    }
+
+    /// Forget the reference and go back to comparing with the previous
+    /// frame.
+    pub fn reset(&mut self) {
+        self.reference.clear();
+        self.pinned = false;
+    }
+
+    /// Compare `frame` with the reference.
+    ///
+    /// Returns `None` when there's nothing to compare against yet (the first
+    /// frame, or after the dimensions or bit depth change), and for
+    /// truncated frames. Unless the reference is fixed, `frame` becomes the
+    /// reference for the next call.
+    pub fn detect(&mut self, frame: &Frame) -> Option<Motion> {
+        if !frame.complete { return None; }
+        let dims = (frame.width, frame.height, frame.bpp);
+        if dims != self.dims || self.reference.is_empty() {
+            if dims != self.dims { self.pinned = false; }
+            self.reference = frame.to_u16();
+            self.dims = dims;
+            return None;
+        }
+
+        let (w, h) = (frame.width, frame.height);
+        let block = self.block;
+        let (bw, bh) = (w.div_ceil(block), h.div_ceil(block));
+        let mut changed = vec![0usize; bw * bh];
+        let mut total_changed = 0usize;
+        let mut total_diff = 0u64;
+        for y in 0..h {
+            let brow = (y / block) * bw;
+            for x in 0..w {
+                let idx = y * w + x;
+                let v = frame.sample(idx);
+                let diff = v.abs_diff(self.reference[idx]);
+                total_diff += diff as u64;
+                if diff > self.threshold {
+                    changed[brow + x / block] += 1;
+                    total_changed += 1;
+                }
+                if !self.pinned { self.reference[idx] = v; }
+            }
+        }
+
+        // Mark blocks with enough changed pixels
+        let marked: Vec<bool> = changed.iter().enumerate().map(|(b, n)| {
+            let bx = b % bw;
+            let by = b / bw;
+            let area = (block.min(w - bx * block) * block.min(h - by * block)) as f64;
+            *n as f64 > area * BLOCK_FRACTION
+        }).collect();
+
+        let pixels = (w * h).max(1) as f64;
+        Some(Motion {
+            score: total_changed as f64 / pixels,
+            mean_difference: total_diff as f64 / pixels,
+            regions: regions(&marked, bw, bh, block, w, h),
+        })
+    }
+}
+
+/// Bounding boxes (in pixels) of each connected group of marked blocks.
+fn regions(marked: &[bool], bw: usize, bh: usize, block: usize, w: usize, h: usize)
+    -> Vec<Rect>
+{
+    let mut seen = vec![false; marked.len()];
+    let mut stack = Vec::new();
+    let mut out = Vec::new();
+    for start in 0..marked.len() {
+        if !marked[start] || seen[start] { continue; }
+        seen[start] = true;
+        stack.push(start);
+        let (mut x0, mut y0, mut x1, mut y1) = (usize::MAX, usize::MAX, 0, 0);
+        while let Some(b) = stack.pop() {
+            let (bx, by) = (b % bw, b / bw);
+            x0 = x0.min(bx); y0 = y0.min(by);
+            x1 = x1.max(bx); y1 = y1.max(by);
+            let mut visit = |n: usize| if marked[n] && !seen[n] {
+                seen[n] = true;
+                stack.push(n);
+            };
+            if bx > 0 { visit(b - 1); }
+            if bx + 1 < bw { visit(b + 1); }
+            if by > 0 { visit(b - bw); }
+            if by + 1 < bh { visit(b + bw); }
+        }
+        out.push(Rect::new(x0 * block, y0 * block,
+            (x1 + 1 - x0) * block, (y1 + 1 - y0) * block).clip(w, h));
+    }
+    out
+}
+
+/// Passes frames on to `S` only while there's motion.
+///
+/// Frames are written when their [Motion::score] reaches `min_score`, and
+/// for a number of frames afterwards (see [MotionTrigger::set_hold]), so a
+/// recording doesn't stop the moment the subject pauses.
+pub struct MotionTrigger<S> {
+    out: Filter<S>,
+    detector: MotionDetector,
+    min_score: f64,
+    hold: u64,
+    /// Frames left to write after the last motion
+    remaining: u64,
+}
+impl<S: FrameSink> MotionTrigger<S> {
+    /// Write frames to `sink` whenever `detector` reports at least
+    /// `min_score` (0.0 to 1.0) of the frame changed.
+    pub fn new(sink: S, detector: MotionDetector, min_score: f64) -> Self {
+        Self { out: Filter::new(sink), detector, min_score, hold: 0, remaining: 0 }
+    }
+
+    /// Keep writing `frames` frames after motion stops.
+    pub fn set_hold(&mut self, frames: u64) { self.hold = frames; }
+
+    /// Number of frames checked so far.
+    pub fn seen(&self) -> u64 { self.out.seen() }
+
+    /// Number of frames passed on to the sink so far.
+    pub fn kept(&self) -> u64 { self.out.kept() }
+
+    pub fn get_ref(&self) -> &S { self.out.get_ref() }
+    pub fn detector_mut(&mut self) -> &mut MotionDetector { &mut self.detector }
+    pub fn into_inner(self) -> S { self.out.into_inner() }
+}
+impl<S: FrameSink> FrameSink for MotionTrigger<S> {
+    fn write_frame(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        let frame = self.out.copy(data, info);
+        let moving = self.detector.detect(&frame)
+            .is_some_and(|m| m.score >= self.min_score);
+        self.out.recycle(frame);
+        if moving {
+            self.remaining = self.hold + 1;
+        }
+        if self.remaining == 0 { return Ok(()); }
+        self.remaining -= 1;
+        self.out.keep(data, info)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,153 @@
+//! Streaming from several cameras at once.
+//!
+//! Each [Camera] has its own libusb context, so the devices don't share any
+//! event handling and can be streamed from independently. [MultiCamera] runs
+//! a streaming thread for each of them and merges their frames into a
+//! single queue, tagged with the camera they came from.
+//!
+//! The merged queue holds up to [StreamConfig::queue] frames, and applies
+//! [StreamConfig::backpressure] like each camera's own queue. With
+//! [Backpressure::Block] and [Backpressure::DropOldest], frames wait in the
+//! camera's queue while the merged queue is full, so the oldest ones are
+//! dropped there (or the camera's streaming thread blocks).
+
+use crate::{ Error, Camera, Frame, DeviceLocation };
+use crate::stream::{ Backpressure, StreamConfig, StreamHandle };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::sync::mpsc::{ sync_channel, Receiver, SyncSender, TrySendError };
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A frame (or error) from one of the cameras in a [MultiCamera].
+pub struct TaggedFrame {
+    /// Index of the camera in the [MultiCamera]
+    pub source: usize,
+    pub result: Result<Frame, Error>,
+}
+
+/// A set of cameras.
+pub struct MultiCamera {
+    cameras: Vec<Camera>,
+}
+impl MultiCamera {
+    /// Open every connected camera.
+    pub fn open_all() -> Result<Self, Error> {
+        let cameras = Camera::list()?.iter()
+            .map(Camera::open_at)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { cameras })
+    }
+
+    /// Use a set of cameras that are already open.
+    pub fn new(cameras: Vec<Camera>) -> Self { Self { cameras } }
+
+    pub fn len(&self) -> usize { self.cameras.len() }
+    pub fn is_empty(&self) -> bool { self.cameras.is_empty() }
+
+    /// Returns the location of each camera.
+    pub fn locations(&self) -> Vec<DeviceLocation> {
+        self.cameras.iter().map(|c| c.location().clone()).collect()
+    }
+
+    pub fn get(&self, idx: usize) -> Option<&Camera> { self.cameras.get(idx) }
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut Camera> {
+        self.cameras.get_mut(idx)
+    }
+
+    /// Take the cameras back.
+    pub fn into_inner(self) -> Vec<Camera> { self.cameras }
+
+    /// Start a streaming thread for each camera.
+    pub fn start_streaming(self, config: StreamConfig) -> (MultiReceiver, MultiHandle) {
+        let (tx, rx) = sync_channel(config.queue.max(1));
+        let stop = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let mut streams = Vec::new();
+        let mut forwarders = Vec::new();
+        for (source, cam) in self.cameras.into_iter().enumerate() {
+            let (frames, handle) = cam.start_streaming_thread(config);
+            let tx = tx.clone();
+            let stop = stop.clone();
+            let dropped = dropped.clone();
+            forwarders.push(std::thread::spawn(move || {
+                for result in frames {
+                    let msg = TaggedFrame { source, result };
+                    if !forward(&tx, msg, config.backpressure, &stop, &dropped) { break; }
+                }
+            }));
+            streams.push(handle);
+        }
+        (MultiReceiver { rx }, MultiHandle { streams, forwarders, stop, dropped })
+    }
+}
+
+/// Put a frame on the merged queue, applying the backpressure policy to
+/// frames (errors always wait for room). Returns 'false' if the receiver has
+/// gone away, or the streams are being stopped.
+fn forward(tx: &SyncSender<TaggedFrame>, mut msg: TaggedFrame, policy: Backpressure,
+    stop: &AtomicBool, dropped: &AtomicU64) -> bool
+{
+    loop {
+        match tx.try_send(msg) {
+            Ok(()) => return true,
+            Err(TrySendError::Disconnected(_)) => return false,
+            Err(TrySendError::Full(m)) => {
+                if policy == Backpressure::DropNewest && m.result.is_ok() {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                if stop.load(Ordering::Relaxed) { return false; }
+                msg = m;
+                std::thread::sleep(Duration::from_millis(10));
+            },
+        }
+    }
+}
+
+/// Receives frames from all of the cameras in a [MultiCamera].
+///
+/// This ends once every camera's streaming thread has stopped.
+pub struct MultiReceiver {
+    rx: Receiver<TaggedFrame>,
+}
+impl MultiReceiver {
+    pub fn recv(&self) -> Option<TaggedFrame> { self.rx.recv().ok() }
+    pub fn try_recv(&self) -> Option<TaggedFrame> { self.rx.try_recv().ok() }
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<TaggedFrame> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+}
+impl Iterator for MultiReceiver {
+    type Item = TaggedFrame;
+    fn next(&mut self) -> Option<TaggedFrame> { self.recv() }
+}
+
+/// Handle for the streaming threads started by [MultiCamera::start_streaming].
+pub struct MultiHandle {
+    streams: Vec<StreamHandle>,
+    forwarders: Vec<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+    dropped: Arc<AtomicU64>,
+}
+impl MultiHandle {
+    /// Returns the stream handle for a particular camera.
+    pub fn get(&self, idx: usize) -> Option<&StreamHandle> { self.streams.get(idx) }
+
+    /// Number of frames thrown away because a queue was full, either a
+    /// camera's own queue or the merged one.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+            + self.streams.iter().map(|s| s.dropped()).sum::<u64>()
+    }
+
+    /// Stop every stream and return the cameras.
+    pub fn stop(self) -> MultiCamera {
+        self.stop.store(true, Ordering::Relaxed);
+        let cameras = self.streams.into_iter().map(|s| s.stop()).collect();
+        for t in self.forwarders {
+            let _ = t.join();
+        }
+        MultiCamera { cameras }
+    }
+}
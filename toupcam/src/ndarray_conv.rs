@@ -0,0 +1,31 @@
+//! Conversions to [ndarray] arrays (with the `ndarray` feature).
+
+use crate::{ Error, Frame };
+use crate::demosaic::{ self, Demosaic, Rgb16Image };
+use ndarray::{ Array2, Array3 };
+
+impl Frame {
+    /// The raw (mosaiced) samples as an `height x width` array.
+    ///
+    /// Samples keep their original values (0 to [Frame::max_value]).
+    /// Returns [Error::InvalidArgument] for truncated frames.
+    pub fn to_array2(&self) -> Result<Array2<u16>, Error> {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        Array2::from_shape_vec((self.height, self.width), self.to_u16())
+            .map_err(|_| Error::InvalidArgument)
+    }
+
+    /// Demosaic into a `height x width x 3` array (scaled to the full 16-bit
+    /// range, see [Rgb16Image]).
+    pub fn to_array3(&self, method: Demosaic) -> Result<Array3<u16>, Error> {
+        Ok(demosaic::demosaic(self, method)?.into_array3())
+    }
+}
+
+impl Rgb16Image {
+    /// Convert into a `height x width x 3` array.
+    pub fn into_array3(self) -> Array3<u16> {
+        Array3::from_shape_vec((self.height, self.width, 3), self.data)
+            .expect("Rgb16Image has a valid length")
+    }
+}
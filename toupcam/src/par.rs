@@ -0,0 +1,26 @@
+//! Row-parallel loops over images (with the `rayon` feature).
+//!
+//! Without the feature, these run on the calling thread.
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+/// Call `f` for every row of `data` (with the row index), where each row is
+/// `row_len` elements long.
+///
+/// `init` creates per-thread scratch state, which is passed to `f`.
+pub (crate) fn for_each_row<T, S, I, F>(data: &mut [T], row_len: usize, init: I, f: F)
+    where T: Send, I: Fn() -> S + Sync + Send, F: Fn(&mut S, usize, &mut [T]) + Sync + Send
+{
+    if row_len == 0 { return; }
+    #[cfg(feature = "rayon")]
+    data.par_chunks_mut(row_len).enumerate()
+        .for_each_init(init, |state, (y, row)| f(state, y, row));
+    #[cfg(not(feature = "rayon"))]
+    {
+        let mut state = init();
+        for (y, row) in data.chunks_mut(row_len).enumerate() {
+            f(&mut state, y, row);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Parsing settings written out as text (i.e. on a command line).
+//!
+//! Camera modes and bit depths parse with [str::parse] (`0`, `1` or `2`,
+//! and `8` or `12`). Errors are messages, ready to be shown to the user.
+
+use crate::{ BitDepth, CameraMode };
+use std::str::FromStr;
+use std::time::Duration;
+
+impl FromStr for CameraMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "0" => Ok(Self::Mode0),
+            "1" => Ok(Self::Mode1),
+            "2" => Ok(Self::Mode2),
+            _ => Err(format!("unknown mode '{}' (expected 0, 1, or 2)", s)),
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "8"  => Ok(Self::BitDepth8),
+            "12" => Ok(Self::BitDepth12),
+            _ => Err(format!("unknown bit depth '{}' (expected 8 or 12)", s)),
+        }
+    }
+}
+
+/// Parse a duration with a unit suffix (i.e. `500us`, `20ms`, `1.5s`).
+pub fn duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(|| format!("missing unit in '{}'", s))?;
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.parse().map_err(|_| format!("bad number in '{}'", s))?;
+    let scale = match unit {
+        "us" => 1e-6,
+        "ms" => 1e-3,
+        "s"  => 1.0,
+        _ => return Err(format!("unknown unit '{}' (expected us/ms/s)", unit)),
+    };
+    Duration::try_from_secs_f64(num * scale)
+        .map_err(|_| format!("duration '{}' is too long", s))
+}
+
+/// Parse a raw gain value (decimal, or hex with a `0x` prefix).
+pub fn gain(s: &str) -> Result<u16, String> {
+    let res = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.map_err(|_| format!("bad gain '{}' (expected 0 to 65535)", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_settings() {
+        assert_eq!("2".parse(), Ok(CameraMode::Mode2));
+        assert_eq!("12".parse(), Ok(BitDepth::BitDepth12));
+        assert!("3".parse::<CameraMode>().is_err());
+        assert!("16".parse::<BitDepth>().is_err());
+        assert_eq!(gain("0x610c"), Ok(0x610c));
+        assert_eq!(gain("100"), Ok(100));
+        assert!(gain("65536").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(duration("500us"), Ok(Duration::from_micros(500)));
+        assert_eq!(duration(" 20ms"), Ok(Duration::from_millis(20)));
+        assert_eq!(duration("1.5s"), Ok(Duration::from_millis(1500)));
+        for bad in ["20", "ms", "1.2.3s", "5min", "99999999999999999999999s"] {
+            assert!(duration(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Streaming frames to stdout or a named pipe.
+//!
+//! A [PipeWriter] writes each frame as soon as it's read, in a format other
+//! programs can consume as a stream:
+//!
+//! - [PipeFormat::Y4m] (with the `processing` feature) demosaics and tone
+//!   maps frames to 8 bits, and writes them as YUV4MPEG2 (4:4:4, full range),
+//!   which `ffmpeg` and `mpv` read directly (i.e. `... | mpv -`).
+//! - [PipeFormat::Raw] writes the raw mosaic, each frame prefixed with a
+//!   32-byte header so a reader can pick up the stream at any frame:
+//!
+//! | Offset | Size | Contents                                         |
+//! |--------|------|--------------------------------------------------|
+//! | 0      | 4    | [FRAME_MAGIC]                                    |
+//! | 4      | 4    | Width (u32)                                      |
+//! | 8      | 4    | Height (u32)                                     |
+//! | 12     | 1    | Bytes per sample                                 |
+//! | 13     | 1    | Bayer phase (RGGB, GRBG, GBRG, BGGR as 0 to 3)   |
+//! | 14     | 2    | Significant bits per sample (u16)                |
+//! | 16     | 8    | Sequence number (u64)                            |
+//! | 24     | 8    | Time since the first frame, in microseconds (u64)|
+//!
+//! All fields are little-endian, and so are 16-bit samples in the data that
+//! follows (`width * height * bpp` bytes).
+
+use crate::{ Error, Frame, FrameInfo };
+use crate::cfa::Cfa;
+#[cfg(feature = "processing")]
+use crate::demosaic::{ Demosaic, RgbImage };
+#[cfg(feature = "processing")]
+use crate::pipeline::Pipeline;
+use crate::sink::FrameSink;
+use std::fs::{ File, OpenOptions };
+use std::io::{ BufWriter, StdoutLock, Write };
+use std::path::Path;
+use std::time::Instant;
+
+/// Start of every frame in a [PipeFormat::Raw] stream.
+pub const FRAME_MAGIC: &[u8; 4] = b"TPFR";
+/// Size of the header before each frame in a [PipeFormat::Raw] stream.
+pub const FRAME_HEADER_LEN: usize = 32;
+
+/// How frames are written to the pipe.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PipeFormat {
+    /// Raw mosaic with a header per frame (see [crate::pipe])
+    Raw,
+    /// Demosaiced, 8-bit YUV4MPEG2
+    #[cfg(feature = "processing")]
+    Y4m,
+}
+
+/// Writes frames to a pipe (or any other writer) as a stream.
+///
+/// Output is flushed after every frame, so a reader on the other end sees
+/// frames as they arrive. If the reader goes away, writes fail with
+/// [Error::Io] (a broken pipe).
+pub struct PipeWriter<W: Write> {
+    w: W,
+    format: PipeFormat,
+    #[cfg(feature = "processing")]
+    frame_rate: f64,
+    #[cfg(feature = "processing")]
+    pipeline: Pipeline,
+    /// Image size, once the YUV4MPEG2 header has been written
+    #[cfg(feature = "processing")]
+    dims: Option<(usize, usize)>,
+    /// Timestamp of the first frame
+    first: Option<Instant>,
+    frames: u64,
+    /// Reused for planar YUV
+    #[cfg(feature = "processing")]
+    yuv: Vec<u8>,
+    /// Reused for the copy of each frame written through [FrameSink]
+    buf: Vec<u8>,
+}
+
+impl PipeWriter<BufWriter<StdoutLock<'static>>> {
+    /// Write to standard output.
+    pub fn stdout(format: PipeFormat) -> Self {
+        Self::new(BufWriter::new(std::io::stdout().lock()), format)
+    }
+}
+
+impl PipeWriter<BufWriter<File>> {
+    /// Write to the file or named pipe at `path` (a named pipe blocks here
+    /// until a reader opens it).
+    pub fn create(path: impl AsRef<Path>, format: PipeFormat) -> Result<Self, Error> {
+        let f = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        Ok(Self::new(BufWriter::new(f), format))
+    }
+}
+
+impl<W: Write> PipeWriter<W> {
+    /// Write to `w`, demosaicing with the default pipeline for
+    /// [PipeFormat::Y4m].
+    pub fn new(w: W, format: PipeFormat) -> Self {
+        Self { w, format,
+            #[cfg(feature = "processing")]
+            frame_rate: 30.0,
+            #[cfg(feature = "processing")]
+            pipeline: Pipeline::new(Demosaic::Bilinear),
+            #[cfg(feature = "processing")]
+            dims: None,
+            #[cfg(feature = "processing")]
+            yuv: Vec::new(),
+            first: None, frames: 0, buf: Vec::new(),
+        }
+    }
+
+    /// Set the frame rate given in the YUV4MPEG2 header (it's only a hint
+    /// for players; frames are written as they arrive).
+    #[cfg(feature = "processing")]
+    pub fn with_frame_rate(mut self, fps: f64) -> Self {
+        self.frame_rate = fps;
+        self
+    }
+
+    /// Process frames with `pipeline` for [PipeFormat::Y4m].
+    #[cfg(feature = "processing")]
+    pub fn with_pipeline(mut self, pipeline: Pipeline) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> u64 { self.frames }
+
+    /// Flush and return the writer.
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+
+    /// Write a frame (which must be complete).
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        if !frame.complete { return Err(Error::InvalidArgument); }
+        match self.format {
+            PipeFormat::Raw => self.write_raw(frame)?,
+            #[cfg(feature = "processing")]
+            PipeFormat::Y4m => {
+                let img = self.pipeline.run(frame)?;
+                write_y4m(&mut self.w, &mut self.dims, &mut self.yuv, self.frame_rate, img)?;
+            },
+        }
+        self.w.flush()?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn write_raw(&mut self, frame: &Frame) -> Result<(), Error> {
+        let first = *self.first.get_or_insert(frame.timestamp);
+        let time = frame.timestamp.saturating_duration_since(first).as_micros() as u64;
+        let cfa: u8 = match frame.cfa {
+            Cfa::Rggb => 0, Cfa::Grbg => 1, Cfa::Gbrg => 2, Cfa::Bggr => 3,
+        };
+        let bits = frame.bits() as u16;
+        let mut head = [0u8; FRAME_HEADER_LEN];
+        head[0..4].copy_from_slice(FRAME_MAGIC);
+        head[4..8].copy_from_slice(&(frame.width as u32).to_le_bytes());
+        head[8..12].copy_from_slice(&(frame.height as u32).to_le_bytes());
+        head[12] = frame.bpp as u8;
+        head[13] = cfa;
+        head[14..16].copy_from_slice(&bits.to_le_bytes());
+        head[16..24].copy_from_slice(&frame.seq.to_le_bytes());
+        head[24..32].copy_from_slice(&time.to_le_bytes());
+        self.w.write_all(&head)?;
+        self.w.write_all(&frame.data[..frame.info().len()])?;
+        Ok(())
+    }
+}
+
+/// Write an image as a YUV4MPEG2 frame, writing the stream header first if
+/// needed. Every image must be the same size as the first.
+#[cfg(feature = "processing")]
+fn write_y4m<W: Write>(w: &mut W, dims: &mut Option<(usize, usize)>, buf: &mut Vec<u8>,
+    frame_rate: f64, img: &RgbImage) -> Result<(), Error>
+{
+    match *dims {
+        Some(d) if d != (img.width, img.height) => return Err(Error::InvalidArgument),
+        Some(_) => {},
+        None => {
+            let rate = (frame_rate.max(0.001) * 1000.0).round() as u64;
+            writeln!(w, "YUV4MPEG2 W{} H{} F{}:1000 Ip A1:1 C444 XCOLORRANGE=FULL",
+                img.width, img.height, rate)?;
+            *dims = Some((img.width, img.height));
+        },
+    }
+    // Full-range BT.601, one plane after the other
+    let len = img.width * img.height;
+    buf.clear();
+    buf.resize(len * 3, 0);
+    let (y, uv) = buf.split_at_mut(len);
+    let (u, v) = uv.split_at_mut(len);
+    for (idx, px) in img.data.chunks_exact(3).enumerate() {
+        let (r, g, b) = (px[0] as f32, px[1] as f32, px[2] as f32);
+        y[idx] = (0.299 * r + 0.587 * g + 0.114 * b).round() as u8;
+        u[idx] = (128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b).round().clamp(0.0, 255.0) as u8;
+        v[idx] = (128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b).round().clamp(0.0, 255.0) as u8;
+    }
+    w.write_all(b"FRAME\n")?;
+    w.write_all(buf)?;
+    Ok(())
+}
+
+impl<W: Write> FrameSink for PipeWriter<W> {
+    fn write_frame(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        buf.extend_from_slice(data);
+        let frame = Frame::from_info(buf, info);
+        let res = PipeWriter::write_frame(self, &frame);
+        self.buf = frame.data.into_vec();
+        res
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! Composing the processing steps from raw frame to 8-bit RGB image.
+//!
+//! A [Pipeline] is set up once with the stages it should run, then called for
+//! every frame. The intermediate buffers are kept between frames, so a
+//! stream of frames of the same size doesn't allocate after the first.
+//!
+//! Stages run in a fixed order (any of them can be left out):
+//!
+//! 1. Dark subtraction, fixed-pattern noise, flat field and defect
+//!    correction (see [crate::calibration]), on a copy of the raw data
+//! 2. Demosaicing
+//! 3. White balance
+//! 4. Color correction
+//! 5. Downscaling
+//! 6. Tone mapping
+//!
+//! Downscaling happens before tone mapping (rather than after) since it's
+//! cheaper on the linear data, and the tone curve is then computed over the
+//! smaller image.
+
+use crate::{ Error, Frame };
+use crate::calibration::{ DefectMap, FpnCorrection, MasterDark, MasterFlat };
+use crate::color::ColorMatrix;
+use crate::demosaic::{ self, Demosaic, Rgb16Image, RgbImage };
+use crate::tonemap::{ self, ToneMap };
+use crate::white_balance::WhiteBalance;
+
+/// A fixed sequence of processing stages, with buffers reused between
+/// frames.
+pub struct Pipeline {
+    dark: Option<MasterDark>,
+    fpn: Option<FpnCorrection>,
+    flat: Option<MasterFlat>,
+    defects: Option<DefectMap>,
+    method: Demosaic,
+    wb: Option<WhiteBalance>,
+    matrix: Option<ColorMatrix>,
+    downscale: usize,
+    tonemap: ToneMap,
+
+    /// Copy of the raw data (when there's any calibration to apply)
+    raw: Vec<u8>,
+    rgb: Rgb16Image,
+    scaled: Rgb16Image,
+    out: RgbImage,
+    /// Tone mapping table (kept unless the tone map is adaptive)
+    table: Vec<u8>,
+}
+impl Pipeline {
+    /// A pipeline which only demosaics (with `method`) and tone maps (with
+    /// the default [ToneMap]).
+    pub fn new(method: Demosaic) -> Self {
+        let empty = || Rgb16Image { width: 0, height: 0, data: Vec::new() };
+        Self { dark: None, fpn: None, flat: None, defects: None, method, wb: None,
+            matrix: None, downscale: 1, tonemap: ToneMap::default(),
+            raw: Vec::new(), rgb: empty(), scaled: empty(),
+            out: RgbImage { width: 0, height: 0, data: Vec::new() },
+            table: Vec::new(),
+        }
+    }
+
+    pub fn with_dark(mut self, dark: MasterDark) -> Self {
+        self.dark = Some(dark);
+        self
+    }
+    pub fn with_fpn(mut self, fpn: FpnCorrection) -> Self {
+        self.fpn = Some(fpn);
+        self
+    }
+    pub fn with_flat(mut self, flat: MasterFlat) -> Self {
+        self.flat = Some(flat);
+        self
+    }
+    pub fn with_defects(mut self, defects: DefectMap) -> Self {
+        self.defects = Some(defects);
+        self
+    }
+    pub fn with_white_balance(mut self, wb: WhiteBalance) -> Self {
+        self.wb = Some(wb);
+        self
+    }
+    pub fn with_color_matrix(mut self, matrix: ColorMatrix) -> Self {
+        self.matrix = Some(matrix);
+        self
+    }
+    /// Shrink the image by an integer `factor` (1 leaves it as is).
+    pub fn with_downscale(mut self, factor: usize) -> Self {
+        self.downscale = factor.max(1);
+        self
+    }
+    pub fn with_tonemap(mut self, tonemap: ToneMap) -> Self {
+        self.tonemap = tonemap;
+        self.table.clear();
+        self
+    }
+
+    /// Change the white balance (i.e. from a UI control) without rebuilding
+    /// the pipeline.
+    pub fn set_white_balance(&mut self, wb: Option<WhiteBalance>) { self.wb = wb; }
+
+    /// Change the master dark (see [Pipeline::set_white_balance]).
+    pub fn set_dark(&mut self, dark: Option<MasterDark>) { self.dark = dark; }
+
+    /// Change the master flat (see [Pipeline::set_white_balance]).
+    pub fn set_flat(&mut self, flat: Option<MasterFlat>) { self.flat = flat; }
+
+    /// Change the tone map without rebuilding the pipeline.
+    pub fn set_tonemap(&mut self, tonemap: ToneMap) {
+        self.tonemap = tonemap;
+        self.table.clear();
+    }
+
+    /// Run every stage on a frame.
+    ///
+    /// The result is only valid until the next call. Returns
+    /// [Error::InvalidArgument] for truncated frames, or if a calibration
+    /// frame doesn't match the frame.
+    pub fn run(&mut self, frame: &Frame) -> Result<&RgbImage, Error> {
+        if self.dark.is_some() || self.fpn.is_some() || self.flat.is_some()
+            || self.defects.is_some()
+        {
+            let mut buf = std::mem::take(&mut self.raw);
+            buf.clear();
+            buf.extend_from_slice(&frame.data);
+            let mut copy = Frame::from_info(buf, &frame.info());
+            let res = self.process(&mut copy);
+            self.raw = copy.data.into_vec();
+            res?;
+        } else {
+            self.develop(frame)?;
+        }
+        Ok(&self.out)
+    }
+
+    /// Apply calibration to a copy of the raw data, then develop it.
+    fn process(&mut self, frame: &mut Frame) -> Result<(), Error> {
+        if let Some(dark) = &self.dark { dark.subtract(frame)?; }
+        if let Some(fpn) = &self.fpn { fpn.apply(frame)?; }
+        if let Some(flat) = &self.flat { flat.apply(frame)?; }
+        if let Some(defects) = &self.defects { defects.correct(frame)?; }
+        self.develop(frame)
+    }
+
+    /// Run the stages from demosaicing onwards.
+    fn develop(&mut self, frame: &Frame) -> Result<(), Error> {
+        demosaic::demosaic_into(frame, self.method, &mut self.rgb)?;
+        if let Some(wb) = self.wb {
+            self.rgb.apply_gains(wb.gains(frame));
+        }
+        if let Some(matrix) = &self.matrix {
+            matrix.apply(&mut self.rgb);
+        }
+        let img = if self.downscale > 1 {
+            self.rgb.downscale_into(self.downscale, &mut self.scaled);
+            &self.scaled
+        } else {
+            &self.rgb
+        };
+        if self.table.is_empty() || self.tonemap.is_adaptive() {
+            self.table = self.tonemap.table(img);
+        }
+        tonemap::map(&self.table, img, &mut self.out);
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+//! Splitting raw frames into un-interpolated color planes.
+
+use crate::{ Error, Frame };
+
+/// The four positions of the Bayer pattern as separate quarter-resolution
+/// planes (samples are in raw units, as in the frame).
+#[derive(Clone, Debug)]
+pub struct Planes {
+    /// Width of each plane
+    pub width: usize,
+    /// Height of each plane
+    pub height: usize,
+    pub red: Vec<u16>,
+    /// Green pixels in the same rows as the red ones
+    pub green1: Vec<u16>,
+    /// Green pixels in the same rows as the blue ones
+    pub green2: Vec<u16>,
+    pub blue: Vec<u16>,
+}
+impl Planes {
+    /// The planes in order (red, green1, green2, blue).
+    pub fn as_array(&self) -> [&[u16]; 4] {
+        [&self.red, &self.green1, &self.green2, &self.blue]
+    }
+}
+
+impl Frame {
+    /// Split the frame into one plane per position in the Bayer pattern,
+    /// without any interpolation.
+    ///
+    /// An odd last row or column (which doesn't hold a whole 2x2 cell) is
+    /// dropped. Returns [Error::InvalidArgument] for truncated frames.
+    pub fn split_channels(&self) -> Result<Planes, Error> {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        let (w, h) = (self.width / 2, self.height / 2);
+        let (rx, ry) = self.cfa.red_offset();
+        // Indexed by position in the 2x2 cell, relative to red
+        let mut planes: [Vec<u16>; 4] = Default::default();
+        for (cell, plane) in planes.iter_mut().enumerate() {
+            let (dx, dy) = ((cell & 1) ^ rx, (cell >> 1) ^ ry);
+            plane.reserve_exact(w * h);
+            for y in 0..h {
+                let base = (y * 2 + dy) * self.width + dx;
+                plane.extend((0..w).map(|x| self.sample(base + x * 2)));
+            }
+        }
+        let [red, green1, green2, blue] = planes;
+        Ok(Planes { width: w, height: h, red, green1, green2, blue })
+    }
+}
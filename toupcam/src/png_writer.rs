@@ -0,0 +1,96 @@
+//! Saving frames as 16-bit PNG files.
+//!
+//! Raw frames are written as grayscale (the mosaic is kept as is), or
+//! demosaiced into RGB (with the `processing` feature). Either way, samples
+//! are written with 16 bits, so nothing is lost from 12-bit data.
+
+use crate::{ Error, Frame };
+#[cfg(feature = "processing")]
+use crate::demosaic::{ self, Demosaic };
+#[cfg(feature = "processing")]
+use crate::white_balance::WhiteBalance;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+
+/// Options for [Frame::save_png].
+#[derive(Copy, Clone, Debug)]
+pub struct PngOptions {
+    /// Scale samples to the full 16-bit range (so that a viewer shows 12-bit
+    /// data at the right brightness). Otherwise, the raw values are written.
+    pub scale: bool,
+    /// Demosaic into RGB with this method, instead of writing the raw
+    /// mosaic as grayscale
+    #[cfg(feature = "processing")]
+    pub demosaic: Option<Demosaic>,
+    /// White balance to apply when demosaicing
+    #[cfg(feature = "processing")]
+    pub white_balance: Option<WhiteBalance>,
+}
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self { scale: true,
+            #[cfg(feature = "processing")]
+            demosaic: None,
+            #[cfg(feature = "processing")]
+            white_balance: None,
+        }
+    }
+}
+
+pub (crate) fn png_error(e: png::EncodingError) -> Error {
+    match e {
+        png::EncodingError::IoError(e) => Error::Io(e),
+        e => Error::Io(std::io::Error::other(e)),
+    }
+}
+
+/// Encode 16-bit samples (native order) as a PNG.
+fn write_png<W: Write>(w: W, width: usize, height: usize, color: png::ColorType,
+    samples: &[u16]) -> Result<(), Error>
+{
+    let mut enc = png::Encoder::new(w, width as u32, height as u32);
+    enc.set_color(color);
+    enc.set_depth(png::BitDepth::Sixteen);
+    let mut writer = enc.write_header().map_err(png_error)?;
+    // PNG samples are big-endian
+    let data: Vec<u8> = samples.iter().flat_map(|v| v.to_be_bytes()).collect();
+    writer.write_image_data(&data).map_err(png_error)?;
+    writer.finish().map_err(png_error)
+}
+
+impl Frame {
+    /// Write the frame to a 16-bit PNG file.
+    ///
+    /// Returns [Error::InvalidArgument] for truncated frames.
+    pub fn save_png(&self, path: impl AsRef<Path>, options: &PngOptions) -> Result<(), Error> {
+        let w = BufWriter::new(File::create(path)?);
+        self.write_png(w, options)
+    }
+
+    /// Write the frame as a 16-bit PNG to any writer.
+    pub fn write_png<W: Write>(&self, w: W, options: &PngOptions) -> Result<(), Error> {
+        if !self.complete { return Err(Error::InvalidArgument); }
+
+        #[cfg(feature = "processing")]
+        if let Some(method) = options.demosaic {
+            // Demosaiced images are already scaled to 16 bits
+            let mut img = demosaic::demosaic(self, method)?;
+            if let Some(wb) = options.white_balance {
+                img.apply_gains(wb.gains(self));
+            }
+            if !options.scale {
+                let shift = 16 - self.bits();
+                img.data.iter_mut().for_each(|v| *v >>= shift);
+            }
+            return write_png(w, img.width, img.height, png::ColorType::Rgb, &img.data);
+        }
+
+        let mut samples = self.to_u16();
+        if options.scale {
+            let shift = 16 - self.bits();
+            samples.iter_mut().for_each(|v| *v <<= shift);
+        }
+        write_png(w, self.width, self.height, png::ColorType::Grayscale, &samples)
+    }
+}
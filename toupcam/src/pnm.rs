@@ -0,0 +1,175 @@
+//! Saving frames as PGM/PPM files, for a quick look with netpbm tools.
+//!
+//! These are binary netpbm files (`P5` and `P6`) and need no encoder. Raw
+//! frames are written as grayscale with the sensor's full scale as the
+//! maximum value (so 12-bit data is stored as 16-bit big-endian samples up
+//! to 4095), and demosaiced images (with the `processing` feature) as RGB.
+//! PGM files can be read back as raw frames (i.e. to process a directory of
+//! captures with [crate::source::ImageDirectory]).
+
+use crate::{ Error, Frame };
+use crate::cfa::Cfa;
+#[cfg(feature = "processing")]
+use crate::demosaic::{ Rgb16Image, RgbImage };
+use std::fs::File;
+use std::io::{ BufRead, BufReader, BufWriter, ErrorKind, Read, Write };
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+/// Write a binary netpbm file. Samples are 8-bit when `maxval` fits in a
+/// byte, and 16-bit big-endian otherwise.
+fn write_pnm<W: Write>(mut w: W, magic: &str, width: usize, height: usize, maxval: u16,
+    samples: impl Iterator<Item = u16>) -> Result<(), Error>
+{
+    write!(w, "{}\n{} {}\n{}\n", magic, width, height, maxval)?;
+    let data: Vec<u8> = if maxval < 256 {
+        samples.map(|v| v as u8).collect()
+    } else {
+        samples.flat_map(|v| v.to_be_bytes()).collect()
+    };
+    w.write_all(&data)?;
+    Ok(())
+}
+
+/// Read one whitespace-separated header field (skipping comments).
+fn header_field<R: BufRead>(r: &mut R) -> Result<String, Error> {
+    let mut field = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        r.read_exact(&mut byte)?;
+        match byte[0] {
+            b'#' if field.is_empty() => {
+                let mut line = Vec::new();
+                r.read_until(b'\n', &mut line)?;
+            },
+            c if c.is_ascii_whitespace() => if !field.is_empty() { return Ok(field); },
+            c => field.push(c as char),
+        }
+    }
+}
+
+/// Create `path` and write to it with `f`.
+fn save(path: impl AsRef<Path>, f: impl FnOnce(&mut BufWriter<File>) -> Result<(), Error>)
+    -> Result<(), Error>
+{
+    let mut w = BufWriter::new(File::create(path)?);
+    f(&mut w)?;
+    w.flush()?;
+    Ok(())
+}
+
+impl Frame {
+    /// Write the raw mosaic to a PGM file.
+    ///
+    /// Returns [Error::InvalidArgument] for truncated frames.
+    pub fn save_pgm(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        save(path, |w| self.write_pgm(w))
+    }
+
+    /// Write the raw mosaic as a PGM to any writer.
+    pub fn write_pgm<W: Write>(&self, w: W) -> Result<(), Error> {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        write_pnm(w, "P5", self.width, self.height, self.max_value(),
+            (0..self.width * self.height).map(|idx| self.sample(idx)))
+    }
+
+    /// Read a raw frame back from a PGM file, with the Bayer phase `cfa`
+    /// (which isn't stored in the file).
+    pub fn load_pgm(path: impl AsRef<Path>, cfa: Cfa) -> Result<Frame, Error> {
+        Self::read_pgm(BufReader::new(File::open(path)?), cfa)
+    }
+
+    /// Read a raw frame from a PGM.
+    ///
+    /// Files with a maximum value up to 255 become 8-bit frames, and
+    /// anything deeper becomes a 12-bit frame (values above 4095 are scaled
+    /// down). Returns [Error::Format] if this isn't a binary PGM.
+    pub fn read_pgm<R: BufRead>(mut r: R, cfa: Cfa) -> Result<Frame, Error> {
+        let bad = || Error::Format("not a binary PGM file".to_string());
+        if header_field(&mut r)? != "P5" { return Err(bad()); }
+        let mut num = || -> Result<usize, Error> {
+            header_field(&mut r)?.parse().map_err(|_| bad())
+        };
+        let (width, height, maxval) = (num()?, num()?, num()?);
+        if width == 0 || height == 0 || maxval == 0 || maxval > 65535 { return Err(bad()); }
+
+        let bpp = if maxval < 256 { 1 } else { 2 };
+        let len = width.checked_mul(height).and_then(|n| n.checked_mul(bpp))
+            .ok_or_else(|| Error::Format("PGM image is too large".to_string()))?;
+        // Read without trusting the header with the allocation
+        let mut raw = Vec::new();
+        r.take(len as u64).read_to_end(&mut raw)?;
+        if raw.len() != len { return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()); }
+        let data = if bpp == 1 { raw } else {
+            raw.chunks_exact(2).flat_map(|b| {
+                let v = u16::from_be_bytes([b[0], b[1]]) as usize;
+                let v = if maxval > 0x0fff { v * 0x0fff / maxval } else { v };
+                (v as u16).to_le_bytes()
+            }).collect()
+        };
+        Ok(Frame { data: data.into(), height, width, bpp,
+            elapsed: Duration::ZERO, marked: false, cfa, seq: 0, timestamp: Instant::now(),
+            complete: true,
+        })
+    }
+}
+
+#[cfg(feature = "processing")]
+impl Rgb16Image {
+    /// Write the image to a 16-bit PPM file.
+    pub fn save_ppm(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        save(path, |w| self.write_ppm(w))
+    }
+
+    /// Write the image as a 16-bit PPM to any writer.
+    pub fn write_ppm<W: Write>(&self, w: W) -> Result<(), Error> {
+        write_pnm(w, "P6", self.width, self.height, u16::MAX, self.data.iter().copied())
+    }
+}
+
+#[cfg(feature = "processing")]
+impl RgbImage {
+    /// Write the image to an 8-bit PPM file.
+    pub fn save_ppm(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        save(path, |w| self.write_ppm(w))
+    }
+
+    /// Write the image as an 8-bit PPM to any writer.
+    pub fn write_ppm<W: Write>(&self, w: W) -> Result<(), Error> {
+        write_pnm(w, "P6", self.width, self.height, u8::MAX as u16,
+            self.data.iter().map(|v| *v as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(bpp: usize, data: Vec<u8>) -> Frame {
+        Frame { data: data.into(), width: 2, height: 2, bpp, elapsed: Duration::ZERO,
+            marked: false, cfa: Cfa::DEFAULT, seq: 0, timestamp: Instant::now(),
+            complete: true,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let samples: [u16; 4] = [0, 1, 0x800, 0xfff];
+        let deep = frame(2, samples.iter().flat_map(|v| v.to_le_bytes()).collect());
+        for frame in [frame(1, vec![0, 1, 128, 255]), deep] {
+            let mut file = Vec::new();
+            frame.write_pgm(&mut file).unwrap();
+            let back = Frame::read_pgm(&file[..], frame.cfa).unwrap();
+            assert_eq!((back.width, back.height, back.bpp), (2, 2, frame.bpp));
+            assert_eq!(back.data[..], frame.data[..]);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let huge = format!("P5\n{} {}\n65535\n", usize::MAX / 2, 3);
+        for file in ["P6\n2 2\n255\n", "P5\n0 2\n255\n", "P5\n2 2\n255\n\x01\x02", &huge] {
+            assert!(Frame::read_pgm(file.as_bytes(), Cfa::DEFAULT).is_err(), "{:?}", file);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Recycling frame buffers.
+//!
+//! At full resolution a frame is about 32MB, and allocating (and zeroing)
+//! a new one for every frame adds up quickly. A [FramePool] keeps a fixed
+//! number of buffers around: frames read with a pool attached (see
+//! [Camera::set_frame_pool]) borrow one, and it goes back to the pool when
+//! the frame is dropped. When every buffer is in use, reading the next frame
+//! blocks until one is returned, which keeps slow consumers from piling up
+//! an unbounded number of frames.
+
+use crate::Camera;
+use std::ops::{ Deref, DerefMut };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::time::Duration;
+
+struct PoolState {
+    /// Buffers available for reuse
+    free: Vec<Vec<u8>>,
+    /// Number of buffers owned by the pool (free or in use)
+    allocated: usize,
+}
+
+struct Shared {
+    state: Mutex<PoolState>,
+    returned: Condvar,
+    capacity: usize,
+}
+
+/// A bounded set of reusable frame buffers.
+///
+/// This is a cheap handle; clones refer to the same pool.
+#[derive(Clone)]
+pub struct FramePool {
+    shared: Arc<Shared>,
+}
+impl FramePool {
+    /// Create a pool holding at most `capacity` buffers.
+    ///
+    /// Buffers are allocated lazily, the first time they're needed.
+    pub fn new(capacity: usize) -> Self {
+        Self { shared: Arc::new(Shared {
+            state: Mutex::new(PoolState { free: Vec::new(), allocated: 0 }),
+            returned: Condvar::new(),
+            capacity: capacity.max(1),
+        })}
+    }
+
+    /// Maximum number of buffers.
+    pub fn capacity(&self) -> usize { self.shared.capacity }
+
+    /// Number of buffers that can be taken without blocking.
+    pub fn available(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.free.len() + (self.shared.capacity - state.allocated)
+    }
+
+    fn take(&self, state: &mut PoolState, len: usize) -> Option<FrameBuffer> {
+        let mut data = match state.free.pop() {
+            Some(data) => data,
+            None if state.allocated < self.shared.capacity => {
+                state.allocated += 1;
+                Vec::with_capacity(len)
+            },
+            None => return None,
+        };
+        data.resize(len, 0);
+        Some(FrameBuffer { data, pool: Some(self.shared.clone()) })
+    }
+
+    /// Take a buffer of `len` bytes, waiting for one to be returned if the
+    /// pool is exhausted.
+    pub fn get(&self, len: usize) -> FrameBuffer {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(buf) = self.take(&mut state, len) { return buf; }
+            state = self.shared.returned.wait(state).unwrap();
+        }
+    }
+
+    /// Take a buffer of `len` bytes, waiting at most `timeout` for one.
+    pub fn get_timeout(&self, len: usize, timeout: Duration) -> Option<FrameBuffer> {
+        let state = self.shared.state.lock().unwrap();
+        let (mut state, _) = self.shared.returned.wait_timeout_while(state, timeout,
+            |s| s.free.is_empty() && s.allocated >= self.shared.capacity).unwrap();
+        self.take(&mut state, len)
+    }
+
+    /// Take a buffer of `len` bytes if one is available.
+    pub fn try_get(&self, len: usize) -> Option<FrameBuffer> {
+        let mut state = self.shared.state.lock().unwrap();
+        self.take(&mut state, len)
+    }
+}
+
+/// Storage for the data in a [crate::Frame].
+///
+/// This dereferences to `[u8]`. If it came from a [FramePool], it's returned
+/// to the pool when dropped.
+pub struct FrameBuffer {
+    data: Vec<u8>,
+    pool: Option<Arc<Shared>>,
+}
+impl FrameBuffer {
+    pub fn as_slice(&self) -> &[u8] { &self.data }
+    pub fn as_mut_slice(&mut self) -> &mut [u8] { &mut self.data }
+
+    /// Shorten the buffer to `len` bytes (this has no effect if it's already
+    /// shorter).
+    pub fn truncate(&mut self, len: usize) { self.data.truncate(len); }
+
+    /// Returns 'true' if this buffer will be returned to a pool.
+    pub fn is_pooled(&self) -> bool { self.pool.is_some() }
+
+    /// Take ownership of the underlying storage.
+    ///
+    /// If the buffer came from a pool, the pool allocates a replacement the
+    /// next time it needs one.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        let data = std::mem::take(&mut self.data);
+        if let Some(shared) = self.pool.take() {
+            shared.state.lock().unwrap().allocated -= 1;
+            shared.returned.notify_one();
+        }
+        data
+    }
+}
+impl From<Vec<u8>> for FrameBuffer {
+    fn from(data: Vec<u8>) -> Self { Self { data, pool: None } }
+}
+impl Deref for FrameBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] { &self.data }
+}
+impl DerefMut for FrameBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] { &mut self.data }
+}
+impl Drop for FrameBuffer {
+    fn drop(&mut self) {
+        if let Some(shared) = self.pool.take() {
+            let data = std::mem::take(&mut self.data);
+            shared.state.lock().unwrap().free.push(data);
+            shared.returned.notify_one();
+        }
+    }
+}
+
+impl Camera {
+    /// Use buffers from `pool` for frames returned by [Camera::read_frame]
+    /// (or allocate a new buffer for each frame, if `None`).
+    pub fn set_frame_pool(&mut self, pool: Option<FramePool>) {
+        self.pool = pool;
+    }
+    pub fn get_frame_pool(&self) -> Option<&FramePool> { self.pool.as_ref() }
+}
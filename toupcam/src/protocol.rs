@@ -0,0 +1,417 @@
+//! Versioned descriptions of the wire protocol.
+//!
+//! Everything we know about talking to the device (initialization scripts,
+//! register addresses, timing) was recovered from packet captures, and will
+//! probably keep changing as more is learned. Instead of hardcoding it, the
+//! sequences live in a small text format (see `protocol/mu1603.txt` for the
+//! built-in descriptor and a description of the syntax). Users can load a
+//! different descriptor with [Camera::set_protocol], or by pointing the
+//! `TOUPCAM_PROTOCOL` environment variable at a file before opening the
+//! camera, which means fixes can be shipped as data without a new release.
+
+use crate::{ Error, Camera };
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Latest version of the descriptor format understood by this crate.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Environment variable naming a descriptor to use instead of the default.
+pub const PROTOCOL_ENV: &str = "TOUPCAM_PROTOCOL";
+
+/// Exposure latency (in frames) assumed when a descriptor doesn't say.
+pub const DEFAULT_EXPOSURE_LATENCY: usize = 2;
+
+/// The descriptor shipped with the crate.
+pub const BUILTIN: &str = include_str!("../protocol/mu1603.txt");
+
+/// An argument to a command.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Const(u16),
+    /// Exposure time (in lines)
+    Exposure,
+    /// Analog gain
+    Gain,
+    /// Readout direction
+    ReadoutDir,
+}
+
+/// A single command in a script.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Op {
+    Sensor { addr: u16, val: Value },
+    Sys { addr: u16, val: Value },
+    VenIn { req: u8, val: u16, idx: u16, len: usize },
+    VenOut { req: u8, val: u16, idx: u16 },
+    Sleep(u64),
+}
+
+/// Kind of register (which also determines how it's written).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RegisterKind { Sensor, Sys }
+
+/// A named register.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Register {
+    pub name: String,
+    pub kind: RegisterKind,
+    pub addr: u16,
+}
+
+/// A complete description of the protocol for a device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProtocolDescriptor {
+    /// Version of the descriptor format
+    pub format: u32,
+    /// Revision of the data (bumped whenever the contents change)
+    pub revision: u32,
+    /// VID/PID this descriptor applies to
+    pub model: (u16, u16),
+    /// Duration of a single line (in nanoseconds)
+    pub line_time_ns: u64,
+    /// Number of frames read out before a new exposure time takes effect
+    pub exposure_latency: usize,
+    /// Known registers
+    pub registers: Vec<Register>,
+    /// Named command scripts
+    pub scripts: BTreeMap<String, Vec<Op>>,
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16).map_err(|_| format!("bad hex value '{}'", s))
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+    match s {
+        "$exposure"    => Ok(Value::Exposure),
+        "$gain"        => Ok(Value::Gain),
+        "$readout_dir" => Ok(Value::ReadoutDir),
+        _ if s.starts_with('$') => Err(format!("unknown variable '{}'", s)),
+        _ => parse_hex(s).map(Value::Const),
+    }
+}
+
+fn parse_op(words: &[&str]) -> Result<Op, String> {
+    let arg = |i: usize| words.get(i).copied()
+        .ok_or_else(|| format!("'{}' is missing arguments", words[0]));
+    let op = match words[0] {
+        "sensor" => Op::Sensor { addr: parse_hex(arg(1)?)?, val: parse_value(arg(2)?)? },
+        "sys" => Op::Sys { addr: parse_hex(arg(1)?)?, val: parse_value(arg(2)?)? },
+        "ven_in" => Op::VenIn {
+            req: parse_hex(arg(1)?)? as u8,
+            val: parse_hex(arg(2)?)?,
+            idx: parse_hex(arg(3)?)?,
+            len: parse_hex(arg(4)?)? as usize,
+        },
+        "ven_out" => Op::VenOut {
+            req: parse_hex(arg(1)?)? as u8,
+            val: parse_hex(arg(2)?)?,
+            idx: parse_hex(arg(3)?)?,
+        },
+        "sleep" => Op::Sleep(arg(1)?.parse().map_err(|_| "bad sleep duration")?),
+        w => return Err(format!("unknown command '{}'", w)),
+    };
+    Ok(op)
+}
+
+impl ProtocolDescriptor {
+    /// Parse a descriptor from text.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        Self::parse_inner(text).map_err(Error::Protocol)
+    }
+
+    fn parse_inner(text: &str) -> Result<Self, String> {
+        let mut res = Self {
+            format: 0, revision: 0, model: (0, 0), line_time_ns: 0,
+            exposure_latency: DEFAULT_EXPOSURE_LATENCY,
+            registers: Vec::new(), scripts: BTreeMap::new(),
+        };
+        let mut section: Option<String> = None;
+        for (num, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() { continue; }
+            let err = |e: String| format!("line {}: {}", num + 1, e);
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = Some(name.to_string());
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let val = |i: usize| words.get(i).copied()
+                .ok_or_else(|| err(format!("'{}' is missing arguments", words[0])));
+
+            match section.as_deref() {
+                None => match words[0] {
+                    "format" => res.format = val(1)?.parse().map_err(|_| err("bad format".into()))?,
+                    "revision" => res.revision = val(1)?.parse().map_err(|_| err("bad revision".into()))?,
+                    "model" => {
+                        let (v, p) = val(1)?.split_once(':').ok_or_else(|| err("bad model".into()))?;
+                        res.model = (parse_hex(v).map_err(err)?, parse_hex(p).map_err(err)?);
+                    },
+                    w => return Err(err(format!("unknown key '{}'", w))),
+                },
+                Some("timing") => match words[0] {
+                    "line_time_ns" => res.line_time_ns = val(1)?.parse()
+                        .map_err(|_| err("bad line time".into()))?,
+                    "exposure_latency" => res.exposure_latency = val(1)?.parse()
+                        .map_err(|_| err("bad exposure latency".into()))?,
+                    w => return Err(err(format!("unknown timing '{}'", w))),
+                },
+                Some("registers") => {
+                    let kind = match val(1)? {
+                        "sensor" => RegisterKind::Sensor,
+                        "sys" => RegisterKind::Sys,
+                        k => return Err(err(format!("unknown register kind '{}'", k))),
+                    };
+                    res.registers.push(Register {
+                        name: words[0].to_string(), kind, addr: parse_hex(val(2)?).map_err(err)?,
+                    });
+                },
+                Some(name) => {
+                    let op = parse_op(&words).map_err(err)?;
+                    res.scripts.entry(name.to_string()).or_default().push(op);
+                },
+            }
+        }
+
+        if res.format == 0 || res.format > FORMAT_VERSION {
+            return Err(format!("unsupported descriptor format {} (expected <= {})",
+                res.format, FORMAT_VERSION));
+        }
+        if res.line_time_ns == 0 {
+            return Err("missing line_time_ns".into());
+        }
+        if res.model == (0, 0) {
+            return Err("missing model".into());
+        }
+        Ok(res)
+    }
+
+    /// Load a descriptor from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The descriptor shipped with the crate.
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).expect("built-in protocol descriptor is invalid")
+    }
+
+    /// The descriptor named by `TOUPCAM_PROTOCOL`, or the built-in one.
+    ///
+    /// If the file can't be read or parsed, this is an [Error::Format]
+    /// naming the file.
+    pub fn from_env() -> Result<Self, Error> {
+        let Some(path) = std::env::var_os(PROTOCOL_ENV) else { return Ok(Self::builtin()); };
+        Self::load(&path).map_err(|e| {
+            let msg = match e { Error::Protocol(msg) => msg, e => format!("{:?}", e) };
+            Error::Format(format!("{} ({}): {}", PROTOCOL_ENV, Path::new(&path).display(), msg))
+        })
+    }
+
+    /// The descriptor to use for the device `vid:pid` (see
+    /// [ProtocolDescriptor::from_env]). A descriptor for another model is an
+    /// [Error::Format].
+    pub (crate) fn for_device(vid: u16, pid: u16) -> Result<Self, Error> {
+        let res = Self::from_env()?;
+        if res.model != (vid, pid) {
+            let source = match std::env::var_os(PROTOCOL_ENV) {
+                Some(path) => format!("{} ({})", PROTOCOL_ENV, Path::new(&path).display()),
+                None => "built-in protocol descriptor".to_string(),
+            };
+            return Err(Error::Format(format!("{}: descriptor is for {:04x}:{:04x}, \
+                but the camera is {:04x}:{:04x}", source, res.model.0, res.model.1, vid, pid)));
+        }
+        Ok(res)
+    }
+
+    /// Look up a register by name.
+    pub fn register(&self, name: &str) -> Option<&Register> {
+        self.registers.iter().find(|r| r.name == name)
+    }
+
+    /// Range of exposure times that can be programmed.
+    pub fn exposure_range(&self) -> (Duration, Duration) {
+        (Duration::from_nanos(self.line_time_ns),
+         Duration::from_nanos(self.line_time_ns * u16::MAX as u64))
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Const(v) => write!(f, "{:04x}", v),
+            Self::Exposure => write!(f, "$exposure"),
+            Self::Gain => write!(f, "$gain"),
+            Self::ReadoutDir => write!(f, "$readout_dir"),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sensor { addr, val } => write!(f, "sensor  {:04x} {}", addr, val),
+            Self::Sys { addr, val } => write!(f, "sys     {:04x} {}", addr, val),
+            Self::VenIn { req, val, idx, len } => {
+                write!(f, "ven_in  {:02x} {:04x} {:04x} {:x}", req, val, idx, len)
+            },
+            Self::VenOut { req, val, idx } => {
+                write!(f, "ven_out {:02x} {:04x} {:04x}", req, val, idx)
+            },
+            Self::Sleep(ms) => write!(f, "sleep   {}", ms),
+        }
+    }
+}
+
+/// Serializes back into the text format (without comments).
+impl fmt::Display for ProtocolDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "format {}", self.format)?;
+        writeln!(f, "revision {}", self.revision)?;
+        writeln!(f, "model {:04x}:{:04x}", self.model.0, self.model.1)?;
+        writeln!(f, "\n[timing]\nline_time_ns {}", self.line_time_ns)?;
+        writeln!(f, "exposure_latency {}", self.exposure_latency)?;
+        writeln!(f, "\n[registers]")?;
+        for r in self.registers.iter() {
+            let kind = match r.kind {
+                RegisterKind::Sensor => "sensor",
+                RegisterKind::Sys => "sys",
+            };
+            writeln!(f, "{:15} {:7} {:04x}", r.name, kind, r.addr)?;
+        }
+        for (name, ops) in self.scripts.iter() {
+            writeln!(f, "\n[{}]", name)?;
+            for op in ops.iter() {
+                writeln!(f, "{}", op)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Camera {
+    /// Returns the protocol descriptor in use.
+    pub fn protocol(&self) -> &ProtocolDescriptor { &self.protocol }
+
+    /// Use a different protocol descriptor.
+    ///
+    /// This can't be changed while streaming, and the descriptor has to be
+    /// for this model ([Error::Format] otherwise).
+    pub fn set_protocol(&mut self, desc: ProtocolDescriptor) -> Result<(), Error> {
+        if self.streaming { return Err(Error::Unimplemented); }
+        let model = (self._desc.vendor_id(), self._desc.product_id());
+        if desc.model != model {
+            return Err(Error::Format(format!("descriptor is for {:04x}:{:04x}, \
+                but the camera is {:04x}:{:04x}", desc.model.0, desc.model.1, model.0, model.1)));
+        }
+        self.protocol = Arc::new(desc);
+        Ok(())
+    }
+
+    fn resolve(&self, val: Value) -> u16 {
+        match val {
+            Value::Const(v) => v,
+            Value::Exposure => self.exposure_lines(),
+            Value::Gain => self.gain,
+            Value::ReadoutDir => self.readout_dir(),
+        }
+    }
+
+    /// Run one of the scripts in the protocol descriptor.
+    pub (crate) fn run_script(&mut self, name: &str) -> Result<(), Error> {
+        let protocol = self.protocol.clone();
+        let ops = protocol.scripts.get(name).ok_or_else(|| {
+            Error::Protocol(format!("descriptor has no [{}] script", name))
+        })?;
+        for op in ops.iter() {
+            match *op {
+                Op::Sensor { addr, val } => self.sensor_write(addr, self.resolve(val))?,
+                Op::Sys { addr, val } => self.sys_write(addr, self.resolve(val))?,
+                Op::VenIn { req, val, idx, len } => {
+                    let mut buf = vec![0u8; len];
+                    self.ven_in(req, val, idx, &mut buf)?;
+                },
+                Op::VenOut { req, val, idx } => self.ven_out(req, val, idx, &[])?,
+                Op::Sleep(ms) => std::thread::sleep(Duration::from_millis(ms)),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = "format 1\nmodel 0547:3016\n[timing]\nline_time_ns 1000\n";
+
+    fn parse_err(text: &str) -> String {
+        match ProtocolDescriptor::parse(text) {
+            Err(Error::Protocol(msg)) => msg,
+            res => panic!("expected a protocol error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn builtin_round_trips() {
+        let desc = ProtocolDescriptor::builtin();
+        assert_eq!(desc.model, (crate::VID, crate::PID));
+        for script in ["start", "exposure", "gain"] {
+            assert!(desc.scripts.contains_key(script), "no [{}] script", script);
+        }
+        assert_eq!(ProtocolDescriptor::parse(&desc.to_string()).unwrap(), desc);
+    }
+
+    #[test]
+    fn parses_sections() {
+        let text = format!("{}exposure_latency 3  # comment\n\
+            [registers]\nexposure sensor 3012\nmode sys 0200\n\
+            [start]\nsensor 301a 10dc\nsys 0200 $readout_dir\n\
+            ven_in 16 0001 000f 2\nven_out 0a 0000 0003\nsleep 5\n\
+            [gain]\nsensor 305e $gain\n", MINIMAL);
+        let desc = ProtocolDescriptor::parse(&text).unwrap();
+        assert_eq!((desc.format, desc.revision, desc.model), (1, 0, (0x0547, 0x3016)));
+        assert_eq!((desc.line_time_ns, desc.exposure_latency), (1000, 3));
+        assert_eq!(desc.register("mode"),
+            Some(&Register { name: "mode".into(), kind: RegisterKind::Sys, addr: 0x200 }));
+        assert_eq!(desc.scripts["start"], [
+            Op::Sensor { addr: 0x301a, val: Value::Const(0x10dc) },
+            Op::Sys { addr: 0x200, val: Value::ReadoutDir },
+            Op::VenIn { req: 0x16, val: 1, idx: 0xf, len: 2 },
+            Op::VenOut { req: 0xa, val: 0, idx: 3 },
+            Op::Sleep(5),
+        ]);
+        assert_eq!(desc.scripts["gain"], [Op::Sensor { addr: 0x305e, val: Value::Gain }]);
+        assert_eq!(desc.exposure_range().0, Duration::from_micros(1));
+    }
+
+    #[test]
+    fn rejects_bad_descriptors() {
+        assert!(parse_err("model 0547:3016\n[timing]\nline_time_ns 1\n")
+            .contains("format 0"));
+        assert!(parse_err("format 2\nmodel 0547:3016\n[timing]\nline_time_ns 1\n")
+            .contains("format 2"));
+        assert_eq!(parse_err("format 1\nmodel 0547:3016\n"), "missing line_time_ns");
+        assert_eq!(parse_err("format 1\n[timing]\nline_time_ns 1\n"), "missing model");
+        assert_eq!(parse_err(&format!("colour blue\n{}", MINIMAL)),
+            "line 1: unknown key 'colour'");
+        assert_eq!(parse_err("format 1\nmodel 0547\n"), "line 2: bad model");
+        let bad = [
+            ("line_time_ns_max 5\n", "line 5: unknown timing 'line_time_ns_max'"),
+            ("[timing]\nline_time_ns fast\n", "line 6: bad line time"),
+            ("[registers]\nfoo eeprom 0001\n", "line 6: unknown register kind 'eeprom'"),
+            ("[start]\nsensor 30zz 0001\n", "line 6: bad hex value '30zz'"),
+            ("[start]\nsensor 3012 $iso\n", "line 6: unknown variable '$iso'"),
+            ("[start]\nsys 0200\n", "line 6: 'sys' is missing arguments"),
+            ("[start]\npoke 0200 1\n", "line 6: unknown command 'poke'"),
+        ];
+        for (extra, msg) in bad {
+            assert_eq!(parse_err(&format!("{}{}", MINIMAL, extra)), msg);
+        }
+    }
+}
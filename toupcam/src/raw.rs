@@ -0,0 +1,111 @@
+//! Direct access to device registers and vendor commands.
+//!
+//! # Safety
+//! This is an escape hatch for reverse-engineering. Nothing here is checked,
+//! and (as with the sensor initialization sequence) it's not clear whether
+//! writing the wrong values can damage the device. Every access is logged.
+//!
+//! No command for reading registers back has been found in the captures.
+//! Instead, the camera keeps a shadow copy of every register write it makes
+//! (including the ones in protocol scripts), which [RawAccess::shadow]
+//! returns. This says what the device was told, not what it's doing.
+
+use crate::{ Error, Camera };
+use crate::protocol::RegisterKind;
+use std::io::Write;
+
+/// Destination for register access logs.
+pub type RegisterLog = Box<dyn Write + Send>;
+
+/// Handle for issuing raw commands to the camera (see [Camera::raw]).
+pub struct RawAccess<'a> {
+    cam: &'a mut Camera,
+}
+
+impl Camera {
+    /// Get a handle for issuing raw register writes and vendor commands.
+    pub fn raw(&mut self) -> RawAccess<'_> {
+        RawAccess { cam: self }
+    }
+
+    /// Send the log of raw accesses somewhere other than stderr.
+    pub fn set_register_log(&mut self, log: Option<RegisterLog>) {
+        self.reg_log = log;
+    }
+}
+
+impl RawAccess<'_> {
+    fn log<T>(&mut self, msg: std::fmt::Arguments, res: &Result<T, Error>) {
+        let status = match res {
+            Ok(_) => "ok".to_string(),
+            Err(e) => format!("{:?}", e),
+        };
+        let ts = self.cam.reg_log_epoch.elapsed().as_secs_f64();
+        match self.cam.reg_log.as_mut() {
+            Some(w) => { let _ = writeln!(w, "[{:12.6}] {} -> {}", ts, msg, status); },
+            None => eprintln!("[raw {:12.6}] {} -> {}", ts, msg, status),
+        }
+    }
+
+    /// Value last written to a register since the camera was opened, or
+    /// `None` if it hasn't been written.
+    pub fn shadow(&mut self, kind: RegisterKind, addr: u16) -> Option<u16> {
+        let val = self.cam.reg_shadow.get(&(kind, addr)).copied();
+        let res: Result<_, Error> = Ok(());
+        let name = match kind { RegisterKind::Sensor => "sensor", RegisterKind::Sys => "sys" };
+        match val {
+            Some(val) => self.log(format_args!("{}_shadow {:04x} = {:04x}", name, addr, val), &res),
+            None => self.log(format_args!("{}_shadow {:04x} = (unwritten)", name, addr), &res),
+        }
+        val
+    }
+
+    /// Every register written since the camera was opened, with its last
+    /// value, in order of kind and address.
+    pub fn shadow_all(&self) -> Vec<(RegisterKind, u16, u16)> {
+        self.cam.reg_shadow.iter().map(|((kind, addr), val)| (*kind, *addr, *val)).collect()
+    }
+
+    /// Set the analog gain to any raw value, outside the range that
+    /// [Camera::set_gain] accepts. Like [Camera::set_gain], the gain is
+    /// written now if streaming, and again whenever the stream starts.
+    pub fn set_gain(&mut self, gain: u16) -> Result<(), Error> {
+        let res = self.cam.set_gain_unchecked(gain);
+        self.log(format_args!("set_gain {:04x}", gain), &res);
+        res
+    }
+
+    /// Write to a sensor register.
+    pub fn sensor_write(&mut self, addr: u16, val: u16) -> Result<(), Error> {
+        let res = self.cam.sensor_write(addr, val);
+        self.log(format_args!("sensor_write {:04x} = {:04x}", addr, val), &res);
+        res
+    }
+
+    /// Write to a system register.
+    pub fn sys_write(&mut self, addr: u16, val: u16) -> Result<(), Error> {
+        let res = self.cam.sys_write(addr, val);
+        self.log(format_args!("sys_write {:04x} = {:04x}", addr, val), &res);
+        res
+    }
+
+    /// Send a vendor command (input).
+    pub fn ven_in(&mut self, req: u8, val: u16, idx: u16, buf: &mut [u8])
+        -> Result<(), Error>
+    {
+        let res = self.cam.ven_in(req, val, idx, buf);
+        self.log(format_args!("ven_in req={:02x} val={:04x} idx={:04x} -> {:02x?}",
+            req, val, idx, buf), &res);
+        res
+    }
+
+    /// Send a vendor command (output).
+    pub fn ven_out(&mut self, req: u8, val: u16, idx: u16, buf: &[u8])
+        -> Result<(), Error>
+    {
+        let res = self.cam.ven_out(req, val, idx, buf);
+        self.log(format_args!("ven_out req={:02x} val={:04x} idx={:04x} <- {:02x?}",
+            req, val, idx, buf), &res);
+        res
+    }
+}
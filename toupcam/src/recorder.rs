@@ -0,0 +1,299 @@
+//! Recording video by piping frames to ffmpeg.
+//!
+//! A [Recorder] runs each frame through a [Pipeline] (demosaicing and tone
+//! mapping by default) and writes the 8-bit RGB result to the standard input
+//! of an `ffmpeg` process, which encodes it with whatever codec and
+//! container the output path asks for (i.e. `.mp4` or `.mkv`).
+//!
+//! Writing to the pipe happens on a separate thread, with a small queue in
+//! between, so a slow encoder doesn't stall the caller (which is usually
+//! reading frames from the camera). What happens when the queue fills up is
+//! set with [Backpressure], as for [crate::stream].
+
+use crate::{ Error, Frame, FrameInfo };
+use crate::demosaic::{ Demosaic, RgbImage };
+use crate::pipeline::Pipeline;
+use crate::sink::FrameSink;
+use crate::stream::Backpressure;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::process::{ Child, ChildStdin, Command, Stdio };
+use std::sync::{ Arc, Condvar, Mutex };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::thread::JoinHandle;
+
+/// Settings for a [Recorder].
+#[derive(Clone, Debug)]
+pub struct RecorderConfig {
+    /// Encoder to run
+    pub program: PathBuf,
+    /// Video codec (passed to `-c:v`)
+    pub codec: String,
+    /// Target bitrate in kbit/s (`None` leaves it to the codec)
+    pub bitrate: Option<u32>,
+    /// Frame rate of the output
+    pub frame_rate: f64,
+    /// Pixel format of the output (passed to `-pix_fmt`)
+    pub pixel_format: String,
+    /// Extra arguments, inserted before the output path
+    pub extra_args: Vec<String>,
+    /// Number of frames that can be waiting for the encoder
+    pub queue: usize,
+    /// What to do when the queue is full
+    pub backpressure: Backpressure,
+}
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self { program: "ffmpeg".into(), codec: "libx264".to_string(), bitrate: None,
+            frame_rate: 30.0, pixel_format: "yuv420p".to_string(), extra_args: Vec::new(),
+            queue: 4, backpressure: Backpressure::Block,
+        }
+    }
+}
+
+struct QueueState {
+    items: VecDeque<Vec<u8>>,
+    /// Set while the writer thread is writing a frame it took from `items`
+    writing: bool,
+    /// Set once no more frames will be queued
+    closed: bool,
+    /// Set when writing to the encoder failed
+    error: Option<std::io::Error>,
+}
+
+/// Frames waiting to be written to the encoder.
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+
+/// Body of the writer thread.
+fn run(queue: &Queue, mut stdin: ChildStdin) {
+    loop {
+        let buf = {
+            let mut state = queue.state.lock().unwrap();
+            loop {
+                if let Some(buf) = state.items.pop_front() {
+                    state.writing = true;
+                    break buf;
+                }
+                if state.closed { return; }
+                state = queue.changed.wait(state).unwrap();
+            }
+        };
+        queue.changed.notify_all();
+        let res = stdin.write_all(&buf);
+        let mut state = queue.state.lock().unwrap();
+        state.writing = false;
+        queue.changed.notify_all();
+        if let Err(e) = res {
+            state.error = Some(e);
+            state.items.clear();
+            return;
+        }
+    }
+    // Dropping stdin closes the pipe, which ends the encoder's input
+}
+
+/// The encoder process and the thread feeding it.
+struct Encoder {
+    child: Child,
+    queue: Arc<Queue>,
+    thread: JoinHandle<()>,
+    dims: (usize, usize),
+}
+
+/// Records frames to a video file through ffmpeg.
+pub struct Recorder {
+    path: PathBuf,
+    config: RecorderConfig,
+    pipeline: Pipeline,
+    /// Started on the first frame (when the size is known)
+    encoder: Option<Encoder>,
+    frames: u64,
+    /// Reused for the copy of each frame written through [FrameSink]
+    buf: Vec<u8>,
+}
+impl Recorder {
+    /// Record to `path`, demosaicing with `method` and the default tone map.
+    ///
+    /// The encoder is started with the first frame.
+    pub fn new(path: impl AsRef<Path>, method: Demosaic, config: RecorderConfig) -> Self {
+        Self::with_pipeline(path, Pipeline::new(method), config)
+    }
+
+    /// Record to `path`, processing frames with `pipeline`.
+    pub fn with_pipeline(path: impl AsRef<Path>, pipeline: Pipeline, config: RecorderConfig)
+        -> Self
+    {
+        Self { path: path.as_ref().into(), config, pipeline, encoder: None, frames: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// The pipeline used to process frames (i.e. to change the white balance
+    /// during a recording).
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline { &mut self.pipeline }
+
+    /// Number of frames passed on to the encoder so far.
+    pub fn frames(&self) -> u64 { self.frames }
+
+    /// Number of frames thrown away because the encoder fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.encoder.as_ref().map_or(0, |e| e.queue.dropped.load(Ordering::Relaxed))
+    }
+
+    /// Start the encoder for `width` x `height` images.
+    fn start(path: &Path, c: &RecorderConfig, width: usize, height: usize)
+        -> Result<Encoder, Error>
+    {
+        let mut cmd = Command::new(&c.program);
+        cmd.args(["-hide_banner", "-loglevel", "error", "-y",
+            "-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .arg("-s").arg(format!("{}x{}", width, height))
+            .arg("-r").arg(c.frame_rate.to_string())
+            .args(["-i", "-", "-c:v"]).arg(&c.codec);
+        if let Some(kbps) = c.bitrate {
+            cmd.arg("-b:v").arg(format!("{}k", kbps));
+        }
+        cmd.arg("-pix_fmt").arg(&c.pixel_format)
+            .args(&c.extra_args)
+            .arg(path)
+            .stdin(Stdio::piped()).stdout(Stdio::null());
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take()
+            .ok_or_else(|| std::io::Error::other("no pipe to the encoder's input"))?;
+
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState { items: VecDeque::new(), writing: false, closed: false,
+                error: None,
+            }),
+            changed: Condvar::new(),
+            capacity: c.queue.max(1),
+            dropped: AtomicU64::new(0),
+        });
+        let q = queue.clone();
+        let thread = std::thread::spawn(move || run(&q, stdin));
+        Ok(Encoder { child, queue, thread, dims: (width, height) })
+    }
+
+    /// Queue an already processed image for encoding.
+    ///
+    /// Every image must be the same size as the first. Returns
+    /// [Error::InvalidArgument] if it isn't, or [Error::Io] if the encoder
+    /// has failed.
+    pub fn write_rgb(&mut self, img: &RgbImage) -> Result<(), Error> {
+        let queued = Self::push(&mut self.encoder, &self.path, &self.config, img)?;
+        if queued { self.frames += 1; }
+        Ok(())
+    }
+
+    /// Queue an image, starting the encoder if needed. Returns 'false' if
+    /// the image was dropped.
+    fn push(encoder: &mut Option<Encoder>, path: &Path, config: &RecorderConfig,
+        img: &RgbImage) -> Result<bool, Error>
+    {
+        if encoder.is_none() {
+            *encoder = Some(Self::start(path, config, img.width, img.height)?);
+        }
+        let enc = encoder.as_ref().unwrap();
+        if enc.dims != (img.width, img.height) { return Err(Error::InvalidArgument); }
+
+        let queue = &enc.queue;
+        let mut state = queue.state.lock().unwrap();
+        loop {
+            if let Some(e) = state.error.take() { return Err(Error::Io(e)); }
+            if state.items.len() < queue.capacity { break; }
+            match config.backpressure {
+                Backpressure::DropNewest => {
+                    queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(false);
+                },
+                Backpressure::DropOldest => {
+                    state.items.pop_front();
+                    queue.dropped.fetch_add(1, Ordering::Relaxed);
+                },
+                Backpressure::Block => state = queue.changed.wait(state).unwrap(),
+            }
+        }
+        state.items.push_back(img.data.clone());
+        queue.changed.notify_all();
+        Ok(true)
+    }
+
+    /// Process a raw frame and queue it for encoding.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        let img = self.pipeline.run(frame)?;
+        let queued = Self::push(&mut self.encoder, &self.path, &self.config, img)?;
+        if queued { self.frames += 1; }
+        Ok(())
+    }
+
+    /// Wait for the queued frames to be written to the encoder (which keeps
+    /// running).
+    ///
+    /// Returns [Error::Io] if the encoder failed.
+    pub fn drain(&mut self) -> Result<(), Error> {
+        let Some(enc) = self.encoder.as_ref() else { return Ok(()); };
+        let mut state = enc.queue.state.lock().unwrap();
+        loop {
+            if let Some(e) = state.error.take() { return Err(Error::Io(e)); }
+            if state.items.is_empty() && !state.writing { return Ok(()); }
+            state = enc.queue.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Wait for the queued frames to be written, close the encoder's input,
+    /// and wait for it to finish the file.
+    ///
+    /// Returns [Error::Io] if the encoder failed or exited with an error.
+    pub fn finish(mut self) -> Result<(), Error> {
+        let Some(mut enc) = self.encoder.take() else { return Ok(()); };
+        {
+            let mut state = enc.queue.state.lock().unwrap();
+            state.closed = true;
+            enc.queue.changed.notify_all();
+        }
+        let _ = enc.thread.join();
+        let status = enc.child.wait()?;
+        if let Some(e) = enc.queue.state.lock().unwrap().error.take() {
+            return Err(Error::Io(e));
+        }
+        if !status.success() {
+            return Err(Error::Io(std::io::Error::other(format!("'{}' exited with {}",
+                self.config.program.display(), status))));
+        }
+        Ok(())
+    }
+}
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // Let the encoder finish the file even if finish() wasn't called
+        if let Some(mut enc) = self.encoder.take() {
+            enc.queue.state.lock().unwrap().closed = true;
+            enc.queue.changed.notify_all();
+            let _ = enc.thread.join();
+            let _ = enc.child.wait();
+        }
+    }
+}
+impl FrameSink for Recorder {
+    fn write_frame(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        buf.extend_from_slice(data);
+        let frame = Frame::from_info(buf, info);
+        let res = Recorder::write_frame(self, &frame);
+        self.buf = frame.data.into_vec();
+        res
+    }
+
+    /// Waits for the queued frames to be written (see [Recorder::drain]).
+    /// The file is only complete after [Recorder::finish].
+    fn flush(&mut self) -> Result<(), Error> {
+        self.drain()
+    }
+}
@@ -0,0 +1,161 @@
+//! Statistics over a region of a raw frame, and clipping detection.
+
+use crate::Frame;
+
+/// A rectangle in pixel coordinates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl Rect {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// The rectangle of `size` centered in a `width` x `height` frame.
+    pub fn centered(width: usize, height: usize, size: usize) -> Self {
+        let (w, h) = (size.min(width), size.min(height));
+        Self { x: (width - w) / 2, y: (height - h) / 2, width: w, height: h }
+    }
+
+    /// Clip to a `width` x `height` frame.
+    pub fn clip(&self, width: usize, height: usize) -> Rect {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Rect { x, y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+}
+
+/// Statistics for one channel of a region.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ChannelStats {
+    /// Number of pixels counted
+    pub count: usize,
+    pub min: u16,
+    pub max: u16,
+    pub mean: f64,
+    pub stddev: f64,
+    /// Number of pixels at full scale (see [Frame::max_value])
+    pub saturated_count: usize,
+}
+
+/// Running sums for [ChannelStats].
+#[derive(Copy, Clone)]
+struct Accum { count: usize, min: u16, max: u16, sum: f64, sum_sq: f64, saturated: usize }
+impl Accum {
+    const EMPTY: Accum = Accum { count: 0, min: u16::MAX, max: 0, sum: 0.0, sum_sq: 0.0,
+        saturated: 0 };
+
+    fn add(&mut self, v: u16, full_scale: u16) {
+        self.count += 1;
+        self.min = self.min.min(v);
+        self.max = self.max.max(v);
+        self.sum += v as f64;
+        self.sum_sq += (v as f64) * (v as f64);
+        if v >= full_scale { self.saturated += 1; }
+    }
+
+    fn finish(&self) -> ChannelStats {
+        if self.count == 0 { return ChannelStats::default(); }
+        let n = self.count as f64;
+        let mean = self.sum / n;
+        ChannelStats { count: self.count, min: self.min, max: self.max, mean,
+            stddev: (self.sum_sq / n - mean * mean).max(0.0).sqrt(),
+            saturated_count: self.saturated,
+        }
+    }
+}
+
+/// Statistics for a region of a raw frame, overall and for each position in
+/// the Bayer pattern.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RegionStats {
+    /// Every pixel in the region
+    pub all: ChannelStats,
+    pub red: ChannelStats,
+    /// Green pixels in the same rows as the red ones
+    pub green1: ChannelStats,
+    /// Green pixels in the same rows as the blue ones
+    pub green2: ChannelStats,
+    pub blue: ChannelStats,
+}
+
+impl Frame {
+    /// The rectangle covering the whole frame.
+    pub fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Compute statistics over `rect` (clipped to the frame, and to the rows
+    /// actually received for a truncated frame).
+    pub fn stats(&self, rect: Rect) -> RegionStats {
+        let w = self.width;
+        let rows = if w == 0 || self.bpp == 0 { 0 }
+            else { self.height.min(self.data.len() / (w * self.bpp)) };
+        let rect = rect.clip(w, rows);
+        let full_scale = self.max_value();
+        let (rx, ry) = self.cfa.red_offset();
+
+        let mut all = Accum::EMPTY;
+        // Indexed by position in the 2x2 cell, relative to red
+        let mut cells = [Accum::EMPTY; 4];
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let v = self.sample(y * w + x);
+                all.add(v, full_scale);
+                let cell = (((y & 1) ^ ry) << 1) | ((x & 1) ^ rx);
+                cells[cell].add(v, full_scale);
+            }
+        }
+        RegionStats {
+            all: all.finish(),
+            red: cells[0].finish(),
+            green1: cells[1].finish(),
+            green2: cells[2].finish(),
+            blue: cells[3].finish(),
+        }
+    }
+}
+
+impl Frame {
+    /// Number of clipped pixels (at or above [Frame::max_value]) in the
+    /// samples received.
+    pub fn clipped_count(&self) -> usize {
+        let full_scale = self.max_value();
+        match self.as_u16() {
+            Some(samples) => samples.iter().filter(|v| **v >= full_scale).count(),
+            None => (0..self.data.len() / self.bpp.max(1))
+                .filter(|idx| self.sample(*idx) >= full_scale).count(),
+        }
+    }
+
+    /// Fraction of the frame that's clipped (0.0 to 1.0).
+    pub fn clipped_fraction(&self) -> f64 {
+        let total = self.width * self.height;
+        if total == 0 { 0.0 } else { self.clipped_count() as f64 / total as f64 }
+    }
+
+    /// A mask with one byte per pixel: 255 where the pixel is clipped, 0
+    /// elsewhere (i.e. for a zebra overlay). Pixels that weren't received
+    /// are 0.
+    pub fn clip_mask(&self) -> Vec<u8> {
+        self.threshold_mask(self.max_value())
+    }
+
+    /// Like [Frame::clip_mask], but marking pixels at or above `threshold`
+    /// (i.e. to warn before pixels actually clip).
+    pub fn threshold_mask(&self, threshold: u16) -> Vec<u8> {
+        let mut mask = vec![0u8; self.width * self.height];
+        let received = (self.data.len() / self.bpp.max(1)).min(mask.len());
+        for (idx, m) in mask[..received].iter_mut().enumerate() {
+            if self.sample(idx) >= threshold { *m = 255; }
+        }
+        mask
+    }
+}
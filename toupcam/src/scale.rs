@@ -0,0 +1,80 @@
+//! Binning raw frames and downscaling RGB images.
+
+use crate::{ Error, Frame };
+use crate::demosaic::Rgb16Image;
+use crate::par;
+
+impl Frame {
+    /// Bin `factor` x `factor` blocks of same-color pixels, producing a
+    /// smaller raw frame with the same Bayer pattern.
+    ///
+    /// Each output pixel is the average of the pixels it covers, which cuts
+    /// the noise (and the cost of everything downstream) without disturbing
+    /// the mosaic. Returns [Error::InvalidArgument] for a zero factor or a
+    /// truncated frame.
+    pub fn bin(&self, factor: usize) -> Result<Frame, Error> {
+        if factor == 0 || !self.complete { return Err(Error::InvalidArgument); }
+        // Keep whole 2x2 cells
+        let (ow, oh) = ((self.width / factor) & !1, (self.height / factor) & !1);
+        let count = (factor * factor) as u32;
+        let mut data = vec![0u8; ow * oh * self.bpp];
+        par::for_each_row(&mut data, ow * self.bpp, || (), |_, y, row| {
+            // Top-left input pixel of the same color as output row `y`
+            let y0 = (y & !1) * factor + (y & 1);
+            for x in 0..ow {
+                let x0 = (x & !1) * factor + (x & 1);
+                let mut sum = 0u32;
+                for j in 0..factor {
+                    let base = (y0 + 2 * j) * self.width + x0;
+                    for i in 0..factor {
+                        sum += self.sample(base + 2 * i) as u32;
+                    }
+                }
+                let v = (sum + count / 2) / count;
+                match self.bpp {
+                    2 => row[x * 2..x * 2 + 2].copy_from_slice(&(v as u16).to_le_bytes()),
+                    _ => row[x] = v as u8,
+                }
+            }
+        });
+        Ok(Frame { data: data.into(), height: oh, width: ow, bpp: self.bpp,
+            elapsed: self.elapsed, marked: self.marked, cfa: self.cfa, seq: self.seq,
+            timestamp: self.timestamp, complete: true,
+        })
+    }
+}
+
+impl Rgb16Image {
+    /// Shrink the image by an integer `factor`, averaging each block of
+    /// pixels (any leftover rows or columns are dropped).
+    pub fn downscale(&self, factor: usize) -> Rgb16Image {
+        let mut out = Rgb16Image { width: 0, height: 0, data: Vec::new() };
+        self.downscale_into(factor, &mut out);
+        out
+    }
+
+    /// Like [Rgb16Image::downscale], but into `out` (reusing its
+    /// allocation).
+    pub fn downscale_into(&self, factor: usize, out: &mut Rgb16Image) {
+        let factor = factor.max(1);
+        let (ow, oh) = (self.width / factor, self.height / factor);
+        let count = (factor * factor) as u32;
+        out.width = ow;
+        out.height = oh;
+        out.data.resize(ow * oh * 3, 0);
+        par::for_each_row(&mut out.data, ow * 3, || (), |_, y, row| {
+            for x in 0..ow {
+                let mut sum = [0u32; 3];
+                for j in 0..factor {
+                    let start = ((y * factor + j) * self.width + x * factor) * 3;
+                    for px in self.data[start..start + factor * 3].chunks_exact(3) {
+                        for c in 0..3 { sum[c] += px[c] as u32; }
+                    }
+                }
+                for c in 0..3 {
+                    row[x * 3 + c] = ((sum[c] + count / 2) / count) as u16;
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,148 @@
+//! Capturing frames at fixed intervals (i.e. for time-lapse).
+
+use crate::{ Error, Camera, Frame };
+use crate::stream::CancelToken;
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::mpsc::{ channel, Receiver, Sender };
+use std::thread::JoinHandle;
+use std::time::{ Duration, Instant, SystemTime };
+
+/// What the camera does between shots.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleMode {
+    /// Keep streaming, reading and discarding frames until the next shot.
+    /// Shots are taken on time, but the sensor never rests.
+    Drain,
+    /// Suspend the camera between shots (see [Camera::suspend]). Better for
+    /// long intervals, although each shot is delayed by restarting the
+    /// stream.
+    Suspend,
+}
+
+/// When to capture frames.
+#[derive(Copy, Clone, Debug)]
+pub struct Schedule {
+    /// Time between shots
+    pub interval: Duration,
+    /// Number of shots (or `None` to keep going until stopped)
+    pub count: Option<u64>,
+    pub idle: IdleMode,
+}
+
+/// A frame captured by a [Scheduler].
+pub struct ScheduledFrame {
+    /// Index of the shot (starting from zero)
+    pub index: u64,
+    /// Wall-clock time when the frame was received
+    pub wall_clock: SystemTime,
+    pub frame: Frame,
+}
+
+/// Captures frames on a background thread according to a [Schedule].
+///
+/// An error ends the schedule: after receiving one, the thread has already
+/// stopped. Dropping this stops the thread (and drops the camera).
+pub struct Scheduler {
+    rx: Receiver<Result<ScheduledFrame, Error>>,
+    stop: Arc<AtomicBool>,
+    cancel: CancelToken,
+    thread: Option<JoinHandle<Camera>>,
+}
+impl Scheduler {
+    /// Start capturing with `cam`.
+    pub fn start(cam: Camera, schedule: Schedule) -> Self {
+        let (tx, rx) = channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let cancel = cam.cancel_token();
+        let mut cam = cam;
+        let thread = std::thread::spawn(move || {
+            if let Err(e) = run(&mut cam, schedule, &tx, &thread_stop) {
+                if !matches!(e, Error::Cancelled) || !thread_stop.load(Ordering::Relaxed) {
+                    let _ = tx.send(Err(e));
+                }
+            }
+            cam.cancel.take();
+            if let Err(e) = cam.stop_stream() {
+                eprintln!("Couldn't stop streaming? {:?}", e);
+            }
+            cam
+        });
+        Self { rx, stop, cancel, thread: Some(thread) }
+    }
+
+    /// Wait for the next frame. Returns `None` once the schedule is done.
+    pub fn recv(&self) -> Option<Result<ScheduledFrame, Error>> {
+        self.rx.recv().ok()
+    }
+
+    /// Returns the next frame, if one is pending.
+    pub fn try_recv(&self) -> Option<Result<ScheduledFrame, Error>> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Wait up to `timeout` for the next frame.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Result<ScheduledFrame, Error>> {
+        self.rx.recv_timeout(timeout).ok()
+    }
+
+    /// Returns 'true' until every shot has been taken (or the thread failed).
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Stop capturing and return the camera.
+    ///
+    /// With [IdleMode::Suspend], the camera may be returned suspended.
+    pub fn stop(mut self) -> Camera {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        match self.thread.take().unwrap().join() {
+            Ok(cam) => cam,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+    }
+}
+
+/// Body of the scheduler thread.
+fn run(cam: &mut Camera, schedule: Schedule,
+    tx: &Sender<Result<ScheduledFrame, Error>>, stop: &AtomicBool) -> Result<(), Error>
+{
+    cam.start_stream()?;
+    let start = Instant::now();
+    let mut index = 0;
+    while schedule.count.is_none_or(|n| index < n) {
+        // Wait for the next shot
+        let due = start + schedule.interval * index as u32;
+        while Instant::now() < due {
+            if stop.load(Ordering::Relaxed) { return Ok(()); }
+            match schedule.idle {
+                IdleMode::Drain => { cam.read_complete_frame()?; },
+                IdleMode::Suspend => {
+                    let left = due.saturating_duration_since(Instant::now());
+                    std::thread::sleep(left.min(Duration::from_millis(50)));
+                },
+            }
+        }
+        if stop.load(Ordering::Relaxed) { return Ok(()); }
+
+        cam.resume()?;
+        let frame = cam.read_complete_frame()?;
+        let shot = ScheduledFrame { index, wall_clock: SystemTime::now(), frame };
+        if tx.send(Ok(shot)).is_err() { return Ok(()); }
+        index += 1;
+
+        if schedule.idle == IdleMode::Suspend && schedule.count.is_none_or(|n| index < n) {
+            cam.suspend()?;
+        }
+    }
+    Ok(())
+}
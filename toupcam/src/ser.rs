@@ -0,0 +1,456 @@
+//! Writer for SER video files.
+//!
+//! SER is the usual format for planetary imaging (read by AutoStakkert,
+//! RegiStax, PIPP, SER Player, ...). A file is a 178-byte header, the raw
+//! frames back-to-back, and a trailer with a UTC timestamp for every frame.
+//! 12-bit frames are stored as 16-bit little-endian samples with a pixel
+//! depth of 12. [SerReader] reads Bayer files back (from this crate or
+//! other capture software).
+//!
+//! NOTE: The header's `LittleEndian` field is written as 0. The format
+//! description says 1 means little-endian, but most capture software writes
+//! 0 for little-endian data, so that's what readers expect.
+
+use crate::{ Error, Frame, FrameInfo };
+use crate::source::FrameSource;
+use crate::cfa::Cfa;
+use crate::sink::FrameSink;
+use crate::tpraw::MAX_FRAME_LEN;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Read, Seek, SeekFrom, Write };
+use std::path::Path;
+use std::time::{ Duration, Instant, SystemTime, UNIX_EPOCH };
+
+/// File ID at the start of every SER file.
+pub const FILE_ID: &[u8; 14] = b"LUCAM-RECORDER";
+/// Size of the header.
+pub const HEADER_LEN: u64 = 178;
+
+/// The Unix epoch in SER timestamps (100ns ticks since 0001-01-01).
+const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+
+/// Offset of the `FrameCount` field in the header.
+const FRAME_COUNT_OFFSET: u64 = 38;
+/// Offset of the `DateTime` field in the header.
+const DATE_TIME_OFFSET: u64 = 162;
+
+/// `ColorID` for a Bayer pattern.
+fn color_id(cfa: Cfa) -> i32 {
+    match cfa { Cfa::Rggb => 8, Cfa::Grbg => 9, Cfa::Gbrg => 10, Cfa::Bggr => 11 }
+}
+
+/// Bayer pattern for a `ColorID`.
+fn cfa_from_color_id(id: i32) -> Option<Cfa> {
+    match id { 8 => Some(Cfa::Rggb), 9 => Some(Cfa::Grbg), 10 => Some(Cfa::Gbrg),
+        11 => Some(Cfa::Bggr), _ => None }
+}
+
+/// Convert a time to SER ticks.
+fn ticks(t: SystemTime) -> i64 {
+    let since = |d: std::time::Duration| (d.as_nanos() / 100) as i64;
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => UNIX_EPOCH_TICKS + since(d),
+        Err(e) => UNIX_EPOCH_TICKS - since(e.duration()),
+    }
+}
+
+/// Convert SER ticks to a time, unless it can't be represented.
+fn from_ticks(ticks: i64) -> Option<SystemTime> {
+    let d = |t: i64| Duration::from_nanos(t.unsigned_abs().saturating_mul(100));
+    let since = ticks.checked_sub(UNIX_EPOCH_TICKS)?;
+    if since >= 0 { UNIX_EPOCH.checked_add(d(since)) } else { UNIX_EPOCH.checked_sub(d(since)) }
+}
+
+/// Fixed-size, space-padded string field.
+fn field(s: &str) -> [u8; 40] {
+    let mut out = [b' '; 40];
+    let len = s.len().min(40);
+    out[..len].copy_from_slice(&s.as_bytes()[..len]);
+    out
+}
+
+/// Writes raw frames to a SER file.
+///
+/// Every frame must have the dimensions and bit depth given when creating
+/// the writer. The frame count in the header and the timestamp trailer are
+/// only written by [SerWriter::finish].
+pub struct SerWriter<W: Write + Seek> {
+    w: W,
+    width: usize,
+    height: usize,
+    bpp: usize,
+    /// UTC timestamp of each frame written so far
+    timestamps: Vec<i64>,
+    /// Maps [Frame::timestamp] onto wall-clock time
+    epoch: (Instant, SystemTime),
+}
+
+impl SerWriter<BufWriter<File>> {
+    /// Create a new file at `path`.
+    pub fn create(path: impl AsRef<Path>, width: usize, height: usize, bpp: usize, cfa: Cfa)
+        -> Result<Self, Error>
+    {
+        let f = BufWriter::new(File::create(path)?);
+        Self::new(f, width, height, bpp, cfa)
+    }
+}
+
+impl<W: Write + Seek> SerWriter<W> {
+    /// Start a new file, writing the header.
+    pub fn new(mut w: W, width: usize, height: usize, bpp: usize, cfa: Cfa)
+        -> Result<Self, Error>
+    {
+        if bpp == 0 || bpp > 2 { return Err(Error::InvalidArgument); }
+        let now = SystemTime::now();
+        let mut head = Vec::with_capacity(HEADER_LEN as usize);
+        head.extend_from_slice(FILE_ID);
+        // LuID, ColorID, LittleEndian
+        head.extend_from_slice(&0i32.to_le_bytes());
+        head.extend_from_slice(&color_id(cfa).to_le_bytes());
+        head.extend_from_slice(&0i32.to_le_bytes());
+        head.extend_from_slice(&(width as i32).to_le_bytes());
+        head.extend_from_slice(&(height as i32).to_le_bytes());
+        let depth = (16 - Frame::max_value_for(bpp).leading_zeros()) as i32;
+        head.extend_from_slice(&depth.to_le_bytes());
+        // FrameCount (filled in by finish())
+        head.extend_from_slice(&0i32.to_le_bytes());
+        head.extend_from_slice(&field(""));
+        head.extend_from_slice(&field("AmScope MU1603"));
+        head.extend_from_slice(&field(""));
+        // DateTime (local) and DateTime_UTC; the local time zone isn't
+        // known, so both are UTC until finish()
+        head.extend_from_slice(&ticks(now).to_le_bytes());
+        head.extend_from_slice(&ticks(now).to_le_bytes());
+        debug_assert_eq!(head.len() as u64, HEADER_LEN);
+        w.write_all(&head)?;
+        Ok(Self { w, width, height, bpp, timestamps: Vec::new(),
+            epoch: (Instant::now(), now),
+        })
+    }
+
+    /// Set the `Observer`, `Instrument` and `Telescope` fields (at most 40
+    /// characters each).
+    pub fn set_description(&mut self, observer: &str, instrument: &str, telescope: &str)
+        -> Result<(), Error>
+    {
+        let pos = self.w.stream_position()?;
+        self.w.seek(SeekFrom::Start(42))?;
+        self.w.write_all(&field(observer))?;
+        self.w.write_all(&field(instrument))?;
+        self.w.write_all(&field(telescope))?;
+        self.w.seek(SeekFrom::Start(pos))?;
+        Ok(())
+    }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> u64 { self.timestamps.len() as u64 }
+
+    /// Wall-clock time of a frame read at `t`.
+    fn wall_clock(&self, t: Instant) -> SystemTime {
+        let (instant, system) = self.epoch;
+        match t.checked_duration_since(instant) {
+            Some(d) => system + d,
+            None => system - instant.duration_since(t),
+        }
+    }
+
+    /// Append raw frame data with its metadata.
+    fn append(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        if (info.width, info.height, info.bpp) != (self.width, self.height, self.bpp)
+            || !info.complete || data.len() < info.len()
+        {
+            return Err(Error::InvalidArgument);
+        }
+        self.w.write_all(&data[..info.len()])?;
+        let ts = ticks(self.wall_clock(info.timestamp));
+        self.timestamps.push(ts);
+        Ok(())
+    }
+
+    /// Append a frame (which must be complete).
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        self.append(&frame.data, &frame.info())
+    }
+
+    /// Write the timestamp trailer, fill in the frame count and start time,
+    /// and flush the file.
+    pub fn finish(mut self) -> Result<W, Error> {
+        for ts in self.timestamps.iter() {
+            self.w.write_all(&ts.to_le_bytes())?;
+        }
+        self.w.seek(SeekFrom::Start(FRAME_COUNT_OFFSET))?;
+        self.w.write_all(&(self.timestamps.len() as i32).to_le_bytes())?;
+        if let Some(first) = self.timestamps.first() {
+            self.w.seek(SeekFrom::Start(DATE_TIME_OFFSET))?;
+            self.w.write_all(&first.to_le_bytes())?;
+            self.w.write_all(&first.to_le_bytes())?;
+        }
+        self.w.seek(SeekFrom::End(0))?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl<W: Write + Seek> FrameSink for SerWriter<W> {
+    fn write_frame(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        self.append(data, info)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.w.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the frames back from a SER file.
+///
+/// Only Bayer files with up to 16 bits per sample are supported. Samples
+/// are read as little-endian whatever the `LittleEndian` field says (see
+/// the note in [crate::ser]), and data deeper than 12 bits is shifted down
+/// to 12. As for [crate::tpraw::RawSequenceReader], [Frame::timestamp] is
+/// offset from when the reader was created (using the timestamp trailer, if
+/// there is one), and [Frame::seq] is the frame index.
+pub struct SerReader<R: Read + Seek> {
+    r: R,
+    width: usize,
+    height: usize,
+    bpp: usize,
+    depth: u32,
+    cfa: Cfa,
+    /// Number of frames in the file
+    count: u64,
+    /// Index of the next frame
+    next: u64,
+    /// UTC timestamp of each frame (empty if the file has no trailer)
+    timestamps: Vec<i64>,
+    epoch: Instant,
+}
+
+impl SerReader<BufReader<File>> {
+    /// Open the file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> SerReader<R> {
+    /// Read the header and the timestamp trailer.
+    ///
+    /// Returns [Error::Format] if this isn't a SER file, or it isn't a Bayer
+    /// format this crate can handle.
+    pub fn new(mut r: R) -> Result<Self, Error> {
+        let mut head = [0u8; HEADER_LEN as usize];
+        r.read_exact(&mut head)?;
+        if &head[..FILE_ID.len()] != FILE_ID {
+            return Err(Error::Format("not a SER file".to_string()));
+        }
+        let i32_at = |i: usize| i32::from_le_bytes(head[i..i + 4].try_into().unwrap());
+        let cfa = cfa_from_color_id(i32_at(18))
+            .ok_or_else(|| Error::Format(format!("unsupported SER color ID {}", i32_at(18))))?;
+        let (width, height, depth) = (i32_at(26), i32_at(30), i32_at(34));
+        if width <= 0 || height <= 0 || !(1..=16).contains(&depth) {
+            return Err(Error::Format("invalid SER header".to_string()));
+        }
+        let (width, height, depth) = (width as usize, height as usize, depth as u32);
+        let bpp = if depth > 8 { 2 } else { 1 };
+        let count = i32_at(FRAME_COUNT_OFFSET as usize).max(0) as u64;
+        let frame_len = (width as u64).checked_mul(height as u64)
+            .and_then(|n| n.checked_mul(bpp as u64))
+            .filter(|n| *n <= MAX_FRAME_LEN)
+            .ok_or_else(|| Error::Format("SER frame size is too large".to_string()))?;
+
+        // The trailer is optional. The frame count and size are at most
+        // 2^31 and 2^30, so this can't overflow.
+        r.seek(SeekFrom::Start(HEADER_LEN + count * frame_len))?;
+        let mut trailer = Vec::new();
+        r.by_ref().take(count * 8).read_to_end(&mut trailer)?;
+        let mut timestamps: Vec<i64> = if trailer.len() as u64 == count * 8 {
+            trailer.chunks_exact(8).map(|b| i64::from_le_bytes(b.try_into().unwrap())).collect()
+        } else {
+            Vec::new()
+        };
+        // A frame count that's too low makes frame data look like the
+        // trailer; drop timestamps that go backwards or before year 1
+        if timestamps.first().is_some_and(|t| *t < 0)
+            || timestamps.windows(2).any(|t| t[1] < t[0])
+        {
+            timestamps.clear();
+        }
+        r.seek(SeekFrom::Start(HEADER_LEN))?;
+        Ok(Self { r, width, height, bpp, depth, cfa, count, next: 0, timestamps,
+            epoch: Instant::now(),
+        })
+    }
+
+    pub fn width(&self) -> usize { self.width }
+    pub fn height(&self) -> usize { self.height }
+    /// Bytes per sample.
+    pub fn bpp(&self) -> usize { self.bpp }
+    /// Bits per sample, as given in the header.
+    pub fn depth(&self) -> u32 { self.depth }
+    pub fn cfa(&self) -> Cfa { self.cfa }
+
+    /// Number of frames in the file.
+    pub fn frame_count(&self) -> u64 { self.count }
+
+    /// Wall-clock time of frame `idx`, if the file has a timestamp trailer.
+    pub fn timestamp(&self, idx: u64) -> Option<SystemTime> {
+        self.timestamps.get(idx as usize).and_then(|t| from_ticks(*t))
+    }
+
+    /// Read the next frame (or `None` after the last one).
+    pub fn read_frame(&mut self) -> Result<Option<Frame>, Error> {
+        if self.next >= self.count { return Ok(None); }
+        let mut data = vec![0u8; self.width * self.height * self.bpp];
+        self.r.read_exact(&mut data)?;
+        if self.depth > 12 {
+            let shift = self.depth - 12;
+            for b in data.chunks_exact_mut(2) {
+                let v = u16::from_le_bytes([b[0], b[1]]) >> shift;
+                b.copy_from_slice(&v.to_le_bytes());
+            }
+        }
+        let idx = self.next;
+        self.next += 1;
+        let time = match (self.timestamps.first(), self.timestamps.get(idx as usize)) {
+            (Some(first), Some(t)) => {
+                Duration::from_nanos((t.saturating_sub(*first).max(0) as u64).saturating_mul(100))
+            },
+            _ => Duration::ZERO,
+        };
+        Ok(Some(Frame { data: data.into(), height: self.height, width: self.width,
+            bpp: self.bpp, elapsed: Duration::ZERO, marked: false, cfa: self.cfa, seq: idx,
+            timestamp: self.epoch.checked_add(time).unwrap_or(self.epoch), complete: true,
+        }))
+    }
+
+    /// Go back to the first frame.
+    pub fn rewind(&mut self) -> Result<(), Error> {
+        self.r.seek(SeekFrom::Start(HEADER_LEN))?;
+        self.next = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Iterator for SerReader<R> {
+    type Item = Result<Frame, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+impl<R: Read + Seek> FrameSource for SerReader<R> {
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> { self.read_frame() }
+    fn rewind(&mut self) -> Result<(), Error> { SerReader::rewind(self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(width: usize, height: usize, bpp: usize, seed: u8, timestamp: Instant) -> Frame {
+        let data: Vec<u8> = (0..width * height * bpp)
+            .map(|i| (i as u8).wrapping_mul(29).wrapping_add(seed)).collect();
+        Frame { data: data.into(), height, width, bpp, elapsed: Duration::ZERO,
+            marked: false, cfa: Cfa::Rggb, seq: 0, timestamp, complete: true,
+        }
+    }
+
+    fn round_trip(bpp: usize) {
+        let start = Instant::now();
+        let frames: Vec<Frame> = (0..3).map(|i| {
+            frame(6, 4, bpp, i, start + Duration::from_millis(i as u64 * 40))
+        }).collect();
+        let mut w = SerWriter::new(Cursor::new(Vec::new()), 6, 4, bpp, Cfa::Rggb).unwrap();
+        for f in frames.iter() { w.write_frame(f).unwrap(); }
+        let mut file = w.finish().unwrap();
+        file.set_position(0);
+
+        let mut r = SerReader::new(file).unwrap();
+        assert_eq!((r.width(), r.height(), r.bpp(), r.cfa()), (6, 4, bpp, Cfa::Rggb));
+        assert_eq!(r.depth(), if bpp == 2 { 12 } else { 8 });
+        assert_eq!(r.frame_count(), 3);
+        let gap = r.timestamp(2).unwrap().duration_since(r.timestamp(0).unwrap()).unwrap();
+        assert_eq!(gap, Duration::from_millis(80));
+        for _ in 0..2 {
+            let read: Vec<Frame> = r.by_ref().collect::<Result<_, _>>().unwrap();
+            assert_eq!(read.len(), 3);
+            for (idx, (a, b)) in read.iter().zip(frames.iter()).enumerate() {
+                assert_eq!(a.seq, idx as u64);
+                assert_eq!(a.data[..], b.data[..]);
+            }
+            assert_eq!(read[1].timestamp - read[0].timestamp, Duration::from_millis(40));
+            r.rewind().unwrap();
+        }
+    }
+
+    #[test]
+    fn round_trip_8bit() { round_trip(1); }
+
+    #[test]
+    fn round_trip_16bit() { round_trip(2); }
+
+    /// A header with everything but the dimensions, depth and frame count
+    /// left at what [SerWriter] writes.
+    fn header(width: i32, height: i32, depth: i32, count: i32) -> Vec<u8> {
+        let w = SerWriter::new(Cursor::new(Vec::new()), 1, 1, 1, Cfa::Bggr).unwrap();
+        let mut head = w.finish().unwrap().into_inner();
+        head[26..30].copy_from_slice(&width.to_le_bytes());
+        head[30..34].copy_from_slice(&height.to_le_bytes());
+        head[34..38].copy_from_slice(&depth.to_le_bytes());
+        head[38..42].copy_from_slice(&count.to_le_bytes());
+        head
+    }
+
+    #[test]
+    fn shifts_deep_samples() {
+        // 16-bit samples, without a trailer
+        let mut file = header(2, 1, 16, 1);
+        file.extend_from_slice(&0xfff0u16.to_le_bytes());
+        file.extend_from_slice(&0x0010u16.to_le_bytes());
+        let mut r = SerReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(r.timestamp(0), None);
+        let f = r.read_frame().unwrap().unwrap();
+        assert_eq!((f.sample(0), f.sample(1)), (0x0fff, 0x0001));
+        assert!(r.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn drops_bad_trailers() {
+        // The frame count is too low, so the reader takes the second frame
+        // for the trailer
+        let mut file = header(4, 2, 8, 1);
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&i64::MIN.to_le_bytes());
+        let mut r = SerReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(r.timestamp(0), None);
+        assert!(r.read_frame().unwrap().is_some());
+
+        // Timestamps that overflow
+        let mut file = header(4, 2, 8, 2);
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&0i64.to_le_bytes());
+        file.extend_from_slice(&i64::MAX.to_le_bytes());
+        let mut r = SerReader::new(Cursor::new(file)).unwrap();
+        // These may not be representable, but mustn't panic
+        let _ = (r.timestamp(0), r.timestamp(1));
+        let read: Vec<Frame> = r.by_ref().collect::<Result<_, _>>().unwrap();
+        assert_eq!(read.len(), 2);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let open = |file: Vec<u8>| SerReader::new(Cursor::new(file));
+        let mut file = header(4, 4, 8, 0);
+        file[..4].copy_from_slice(b"NOPE");
+        assert!(matches!(open(file), Err(Error::Format(_))));
+        assert!(matches!(open(header(0, 4, 8, 0)), Err(Error::Format(_))));
+        assert!(matches!(open(header(4, 4, 17, 0)), Err(Error::Format(_))));
+        assert!(matches!(open(header(i32::MAX, i32::MAX, 16, i32::MAX)),
+            Err(Error::Format(_))));
+        // Frames missing from the end of the file
+        let mut r = open(header(4, 4, 8, 2)).unwrap();
+        assert!(r.read_frame().is_err());
+    }
+}
@@ -0,0 +1,156 @@
+//! JSON sidecar files describing captures (with the `sidecar` feature).
+//!
+//! A sidecar sits next to a file written by the crate (`img_001.tiff` gets
+//! `img_001.tiff.json`) and records the camera settings, when the frames
+//! were taken, and what was done to the data before it was written, so a
+//! capture session can be reproduced or processed by scripts later.
+//!
+//! Writing a sidecar is up to the caller: after saving a frame, use
+//! [Camera::sidecar] to describe it, fill in [Sidecar::processing] if the
+//! writer changed the data (i.e. demosaicing for PNG), and call
+//! [Sidecar::save_next_to] with the path of the file. For a sequence (i.e. a
+//! `.tpraw` or SER file), describe the first frame and set [Sidecar::frames]
+//! before saving.
+
+use crate::{ Camera, Error, FrameInfo };
+use crate::metadata::{ utc, CaptureMetadata };
+use serde::{ Deserialize, Serialize };
+use std::path::{ Path, PathBuf };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+/// What was done to the data before it was written.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Processing {
+    /// A master dark was subtracted (see [Camera::set_dark])
+    pub dark: bool,
+    /// Fixed-pattern noise was removed (see [Camera::set_fpn])
+    pub fpn: bool,
+    /// A master flat was applied (see [Camera::set_flat])
+    pub flat: bool,
+    /// Defective pixels were replaced (see [Camera::set_defects])
+    pub defects: bool,
+    /// Demosaicing method, if the file holds RGB data
+    pub demosaic: Option<String>,
+    /// White balance gains (red, green, blue), if any were applied
+    pub white_balance: Option<[f32; 3]>,
+    /// Samples were scaled up to the full 16-bit range
+    pub scaled: bool,
+}
+
+/// Contents of a sidecar file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sidecar {
+    /// Software that wrote the file (`toupcam-rs <version>`)
+    pub software: String,
+    /// Model name (see [crate::model::ModelInfo])
+    pub model: String,
+    /// USB serial number, if the device reports one
+    pub serial: Option<String>,
+    /// Readout mode (i.e. `Mode1`)
+    pub mode: String,
+    /// Significant bits per sample (8 or 12)
+    pub bits: u32,
+    pub exposure_us: u64,
+    /// Raw analog gain (see [Camera::set_gain])
+    pub gain: u16,
+    /// Capture time of the first frame, as ISO 8601 (UTC)
+    pub timestamp: String,
+    /// Capture time of the first frame, in seconds since the Unix epoch
+    pub unix_time: f64,
+    pub width: usize,
+    pub height: usize,
+    /// Bytes per sample in the raw data
+    pub bytes_per_pixel: usize,
+    /// Bayer phase of the raw data (i.e. `RGGB`)
+    pub cfa: String,
+    /// Sequence number of the first frame (see [FrameInfo::seq])
+    pub seq: u64,
+    /// Number of frames in the file (1 for a still image)
+    pub frames: u64,
+    pub processing: Processing,
+}
+
+/// Format a time as ISO 8601 (UTC, with milliseconds).
+fn iso8601(t: SystemTime) -> String {
+    let (y, mo, d, h, mi, s) = utc(t);
+    let ms = t.duration_since(UNIX_EPOCH).map(|d| d.subsec_millis()).unwrap_or(0);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z", y, mo, d, h, mi, s, ms)
+}
+
+impl Sidecar {
+    /// Describe a single frame captured with `meta`, with no processing.
+    pub fn new(meta: &CaptureMetadata, info: &FrameInfo) -> Self {
+        let unix_time = match meta.timestamp.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+        Self {
+            software: concat!("toupcam-rs ", env!("CARGO_PKG_VERSION")).to_string(),
+            model: meta.model.to_string(),
+            serial: meta.serial.clone(),
+            mode: format!("{:?}", meta.mode),
+            bits: meta.bits,
+            exposure_us: meta.exposure.as_micros() as u64,
+            gain: meta.gain,
+            timestamp: iso8601(meta.timestamp),
+            unix_time,
+            width: info.width,
+            height: info.height,
+            bytes_per_pixel: info.bpp,
+            cfa: info.cfa.name().to_string(),
+            seq: info.seq,
+            frames: 1,
+            processing: Processing::default(),
+        }
+    }
+
+    /// Where the sidecar for `output` goes (`.json` appended to the name).
+    pub fn path_for(output: impl AsRef<Path>) -> PathBuf {
+        let mut name = output.as_ref().as_os_str().to_owned();
+        name.push(".json");
+        name.into()
+    }
+
+    /// Serialize as pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        // Serializing plain fields can't fail
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Write the sidecar to `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let mut json = self.to_json();
+        json.push('\n');
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Write the sidecar next to `output` (see [Sidecar::path_for]).
+    pub fn save_next_to(&self, output: impl AsRef<Path>) -> Result<(), Error> {
+        self.save(Self::path_for(output))
+    }
+
+    /// Read a sidecar back.
+    ///
+    /// Returns [Error::Format] if the file isn't a valid sidecar.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let json = std::fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| Error::Format(e.to_string()))
+    }
+}
+
+impl Camera {
+    /// Describe a frame just read with the current settings, including the
+    /// calibration applied to it (see [Camera::metadata]).
+    pub fn sidecar(&self, info: &FrameInfo) -> Sidecar {
+        let mut sidecar = Sidecar::new(&self.metadata(), info);
+        sidecar.processing = Processing {
+            dark: self.dark.is_some(),
+            fpn: self.fpn.is_some(),
+            flat: self.flat.is_some(),
+            defects: self.defects.is_some(),
+            ..Processing::default()
+        };
+        sidecar
+    }
+}
@@ -0,0 +1,176 @@
+//! Vectorized inner loops for demosaicing and bit depth conversion.
+//!
+//! On x86_64, SSE2 is always available and AVX2 is detected at runtime.
+//! Other targets use the scalar versions (which the compiler may still
+//! vectorize on its own).
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+/// Write `src[i] >> shift` (saturated to 255) into `dst[i]`.
+pub (crate) fn narrow(src: &[u16], shift: u32, dst: &mut [u8]) {
+    let len = src.len().min(dst.len());
+    let (src, dst) = (&src[..len], &mut dst[..len]);
+    #[cfg(target_arch = "x86_64")]
+    {
+        let done = if is_x86_feature_detected!("avx2") {
+            unsafe { narrow_avx2(src, shift, dst) }
+        } else {
+            unsafe { narrow_sse2(src, shift, dst) }
+        };
+        narrow_scalar(&src[done..], shift, &mut dst[done..]);
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    narrow_scalar(src, shift, dst);
+}
+
+fn narrow_scalar(src: &[u16], shift: u32, dst: &mut [u8]) {
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = (s >> shift).min(255) as u8;
+    }
+}
+
+/// Returns the number of samples converted.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn narrow_avx2(src: &[u16], shift: u32, dst: &mut [u8]) -> usize {
+    let count = _mm_cvtsi32_si128(shift as i32);
+    let max = _mm256_set1_epi16(255);
+    // Packing is signed, so clamp to 255 first: min(x, 255) = x - (x -sat 255)
+    let clamp = |x: __m256i| _mm256_sub_epi16(x, _mm256_subs_epu16(x, max));
+    let mut i = 0;
+    while i + 32 <= src.len() {
+        let a = _mm256_loadu_si256(src.as_ptr().add(i) as *const __m256i);
+        let b = _mm256_loadu_si256(src.as_ptr().add(i + 16) as *const __m256i);
+        let a = clamp(_mm256_srl_epi16(a, count));
+        let b = clamp(_mm256_srl_epi16(b, count));
+        // Packing works within 128-bit lanes, so put the lanes back in order
+        let packed = _mm256_permute4x64_epi64(_mm256_packus_epi16(a, b), 0b11_01_10_00);
+        _mm256_storeu_si256(dst.as_mut_ptr().add(i) as *mut __m256i, packed);
+        i += 32;
+    }
+    i
+}
+
+/// Returns the number of samples converted.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn narrow_sse2(src: &[u16], shift: u32, dst: &mut [u8]) -> usize {
+    let count = _mm_cvtsi32_si128(shift as i32);
+    let max = _mm_set1_epi16(255);
+    // Packing is signed, so clamp to 255 first: min(x, 255) = x - (x -sat 255)
+    let clamp = |x: __m128i| _mm_sub_epi16(x, _mm_subs_epu16(x, max));
+    let mut i = 0;
+    while i + 16 <= src.len() {
+        let a = _mm_loadu_si128(src.as_ptr().add(i) as *const __m128i);
+        let b = _mm_loadu_si128(src.as_ptr().add(i + 8) as *const __m128i);
+        let packed = _mm_packus_epi16(clamp(_mm_srl_epi16(a, count)),
+            clamp(_mm_srl_epi16(b, count)));
+        _mm_storeu_si128(dst.as_mut_ptr().add(i) as *mut __m128i, packed);
+        i += 16;
+    }
+    i
+}
+
+/// Sums of the neighbours of each sample in `mid`, for `1 <= x < len - 1`:
+/// horizontal (`h`), vertical (`v`) and diagonal (`d`).
+///
+/// Sums saturate at `u16::MAX`, which only happens when samples have more
+/// than 14 significant bits (i.e. a corrupted frame).
+pub (crate) fn neighbour_sums(up: &[u16], mid: &[u16], down: &[u16],
+    h: &mut [u16], v: &mut [u16], d: &mut [u16])
+{
+    let len = mid.len();
+    if len < 3 { return; }
+    assert!(up.len() >= len && down.len() >= len);
+    assert!(h.len() >= len && v.len() >= len && d.len() >= len);
+    #[cfg(target_arch = "x86_64")]
+    let start = unsafe { neighbour_sums_sse2(up, mid, down, h, v, d) };
+    #[cfg(not(target_arch = "x86_64"))]
+    let start = 1;
+    neighbour_sums_scalar(up, mid, down, h, v, d, start);
+}
+
+fn neighbour_sums_scalar(up: &[u16], mid: &[u16], down: &[u16],
+    h: &mut [u16], v: &mut [u16], d: &mut [u16], start: usize)
+{
+    for x in start..mid.len().saturating_sub(1) {
+        h[x] = mid[x - 1].saturating_add(mid[x + 1]);
+        v[x] = up[x].saturating_add(down[x]);
+        d[x] = up[x - 1].saturating_add(up[x + 1])
+            .saturating_add(down[x - 1].saturating_add(down[x + 1]));
+    }
+}
+
+/// Returns the first column that wasn't handled.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn neighbour_sums_sse2(up: &[u16], mid: &[u16], down: &[u16],
+    h: &mut [u16], v: &mut [u16], d: &mut [u16]) -> usize
+{
+    let load = |s: &[u16], i: usize| _mm_loadu_si128(s.as_ptr().add(i) as *const __m128i);
+    let mut x = 1;
+    while x + 9 <= mid.len() {
+        let hs = _mm_adds_epu16(load(mid, x - 1), load(mid, x + 1));
+        let vs = _mm_adds_epu16(load(up, x), load(down, x));
+        let ds = _mm_adds_epu16(
+            _mm_adds_epu16(load(up, x - 1), load(up, x + 1)),
+            _mm_adds_epu16(load(down, x - 1), load(down, x + 1)));
+        _mm_storeu_si128(h.as_mut_ptr().add(x) as *mut __m128i, hs);
+        _mm_storeu_si128(v.as_mut_ptr().add(x) as *mut __m128i, vs);
+        _mm_storeu_si128(d.as_mut_ptr().add(x) as *mut __m128i, ds);
+        x += 8;
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random samples with `bits` significant bits.
+    fn samples(len: usize, bits: u32, seed: u32) -> Vec<u16> {
+        let mut x = seed;
+        (0..len).map(|_| {
+            x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+            ((x >> 16) as u16) >> (16 - bits)
+        }).collect()
+    }
+
+    #[test]
+    fn narrow_matches_scalar() {
+        for len in (0..40).chain([67, 100]) {
+            let src = samples(len, 16, len as u32);
+            for shift in [0, 4, 8] {
+                let (mut fast, mut slow) = (vec![0u8; len], vec![0u8; len]);
+                narrow(&src, shift, &mut fast);
+                narrow_scalar(&src, shift, &mut slow);
+                assert_eq!(fast, slow, "len {} shift {}", len, shift);
+                // narrow() takes the AVX2 path where it's available
+                #[cfg(target_arch = "x86_64")]
+                {
+                    fast.fill(0);
+                    let done = unsafe { narrow_sse2(&src, shift, &mut fast) };
+                    assert_eq!(fast[..done], slow[..done], "SSE2 len {} shift {}", len, shift);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbour_sums_match_scalar() {
+        for len in (0..40).chain([67, 100]) {
+            // 12-bit samples, and 16-bit ones whose sums saturate
+            for bits in [12, 16] {
+                let rows: Vec<Vec<u16>> = (0..3).map(|r| samples(len, bits, r)).collect();
+                let mut fast = [vec![0u16; len], vec![0u16; len], vec![0u16; len]];
+                let mut slow = fast.clone();
+                let [h, v, d] = &mut fast;
+                neighbour_sums(&rows[0], &rows[1], &rows[2], h, v, d);
+                let [h, v, d] = &mut slow;
+                neighbour_sums_scalar(&rows[0], &rows[1], &rows[2], h, v, d, 1);
+                assert_eq!(fast, slow, "len {} bits {}", len, bits);
+            }
+        }
+    }
+}
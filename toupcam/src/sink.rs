@@ -0,0 +1,249 @@
+//! Writing frames straight to disk from the streaming thread.
+//!
+//! At full resolution, moving every frame through a channel to another
+//! thread which then writes it out costs a lot of memory bandwidth (and a
+//! buffer per frame in flight). With [Camera::start_sink_thread], the thread
+//! reading from the device reuses a single buffer and hands each frame to a
+//! [FrameSink] directly.
+
+use crate::{ Error, Camera, FrameInfo };
+use crate::stream::CancelToken;
+use std::fs::{ File, OpenOptions };
+use std::io::{ Seek, SeekFrom, Write };
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::thread::JoinHandle;
+
+/// Something that consumes raw frames.
+pub trait FrameSink {
+    /// Consume a single frame.
+    fn write_frame(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error>;
+
+    /// Called once after the last frame.
+    fn flush(&mut self) -> Result<(), Error> { Ok(()) }
+}
+
+/// The output side of a sink that only passes some frames on to another
+/// sink (i.e. [crate::lucky::LuckySelector]): copies of the frames to look
+/// at, and counts of the frames seen and kept.
+#[cfg(feature = "processing")]
+pub (crate) struct Filter<S> {
+    sink: S,
+    /// Buffers from copies that are done with, reused for the next ones
+    spare: Vec<Vec<u8>>,
+    seen: u64,
+    kept: u64,
+}
+#[cfg(feature = "processing")]
+impl<S: FrameSink> Filter<S> {
+    pub (crate) fn new(sink: S) -> Self {
+        Self { sink, spare: Vec::new(), seen: 0, kept: 0 }
+    }
+
+    /// Copy a frame (the sink thread reuses its buffer), counting it as
+    /// seen.
+    pub (crate) fn copy(&mut self, data: &[u8], info: &FrameInfo) -> crate::Frame {
+        let mut buf = self.spare.pop().unwrap_or_default();
+        buf.clear();
+        buf.extend_from_slice(data);
+        self.seen += 1;
+        crate::Frame::from_info(buf, info)
+    }
+
+    /// Keep the buffer of a copy for the next one.
+    pub (crate) fn recycle(&mut self, frame: crate::Frame) {
+        self.spare.push(frame.data.into_vec());
+    }
+
+    /// Pass a frame on to the sink.
+    pub (crate) fn keep(&mut self, data: &[u8], info: &FrameInfo) -> Result<(), Error> {
+        self.sink.write_frame(data, info)?;
+        self.kept += 1;
+        Ok(())
+    }
+
+    /// Flush the sink, and free the spare buffers.
+    pub (crate) fn flush(&mut self) -> Result<(), Error> {
+        self.spare.clear();
+        self.sink.flush()
+    }
+
+    pub (crate) fn seen(&self) -> u64 { self.seen }
+    pub (crate) fn kept(&self) -> u64 { self.kept }
+    pub (crate) fn get_ref(&self) -> &S { &self.sink }
+    pub (crate) fn into_inner(self) -> S { self.sink }
+}
+
+/// How a [DiskSink] lays out frames.
+#[derive(Debug)]
+enum Layout {
+    /// Every frame appended to a single file
+    Sequential(File),
+    /// A fixed set of preallocated files, overwritten in turn
+    Ring(Vec<File>),
+}
+
+/// Writes raw frames to disk.
+pub struct DiskSink {
+    layout: Layout,
+    path: PathBuf,
+    written: u64,
+}
+impl DiskSink {
+    /// Append every frame to the file at `path`.
+    ///
+    /// The file is just the raw frames back-to-back, with no header.
+    pub fn sequential(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = File::create(path.as_ref())?;
+        Ok(Self { layout: Layout::Sequential(file), path: path.as_ref().into(), written: 0 })
+    }
+
+    /// Keep the last `count` frames in files named `frame_NNNN.raw` in the
+    /// directory `dir`, each preallocated to `frame_len` bytes.
+    ///
+    /// Since the files are reused, this never needs more than a fixed
+    /// amount of disk space (i.e. for capturing the moments before some
+    /// event).
+    pub fn ring(dir: impl AsRef<Path>, count: usize, frame_len: usize)
+        -> Result<Self, Error>
+    {
+        std::fs::create_dir_all(dir.as_ref())?;
+        let mut files = Vec::with_capacity(count);
+        for idx in 0..count.max(1) {
+            let file = OpenOptions::new().read(true).write(true).create(true)
+                .truncate(false).open(Self::ring_file(dir.as_ref(), idx))?;
+            file.set_len(frame_len as u64)?;
+            files.push(file);
+        }
+        Ok(Self { layout: Layout::Ring(files), path: dir.as_ref().into(), written: 0 })
+    }
+
+    fn ring_file(dir: &Path, idx: usize) -> PathBuf {
+        dir.join(format!("frame_{:04}.raw", idx))
+    }
+
+    /// Path of the file (or directory) being written to.
+    pub fn path(&self) -> &Path { &self.path }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> u64 { self.written }
+
+    /// For a ring, the file holding the most recent frame.
+    pub fn latest(&self) -> Option<PathBuf> {
+        match &self.layout {
+            Layout::Ring(files) if self.written > 0 => {
+                let idx = (self.written - 1) as usize % files.len();
+                Some(Self::ring_file(&self.path, idx))
+            },
+            _ => None,
+        }
+    }
+}
+impl FrameSink for DiskSink {
+    fn write_frame(&mut self, data: &[u8], _info: &FrameInfo) -> Result<(), Error> {
+        match &mut self.layout {
+            Layout::Sequential(f) => f.write_all(data)?,
+            Layout::Ring(files) => {
+                let idx = self.written as usize % files.len();
+                let f = &mut files[idx];
+                f.seek(SeekFrom::Start(0))?;
+                f.write_all(data)?;
+            },
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.layout {
+            Layout::Sequential(f) => f.sync_data()?,
+            Layout::Ring(files) => for f in files.iter() { f.sync_data()?; },
+        }
+        Ok(())
+    }
+}
+
+/// What a sink thread hands back when it's stopped.
+type SinkResult<S> = (Camera, S, Result<(), Error>);
+
+/// Handle for a thread started with [Camera::start_sink_thread].
+///
+/// Dropping this stops the thread (and drops the camera and the sink).
+pub struct SinkHandle<S> {
+    stop: Arc<AtomicBool>,
+    cancel: CancelToken,
+    written: Arc<AtomicU64>,
+    thread: Option<JoinHandle<SinkResult<S>>>,
+}
+impl<S> SinkHandle<S> {
+    /// Returns 'true' if the thread is still running.
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Number of frames passed to the sink so far.
+    pub fn frames(&self) -> u64 { self.written.load(Ordering::Relaxed) }
+
+    /// Stop the thread, returning the camera, the sink, and the error that
+    /// stopped the thread early (if any).
+    pub fn stop(mut self) -> SinkResult<S> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        match self.thread.take().unwrap().join() {
+            Ok(res) => res,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+impl<S> Drop for SinkHandle<S> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+    }
+}
+
+/// Body of the sink thread.
+fn run<S: FrameSink>(cam: &mut Camera, sink: &mut S, stop: &AtomicBool,
+    written: &AtomicU64) -> Result<(), Error>
+{
+    cam.start_stream()?;
+    let mut buf = vec![0u8; cam.frame_len()];
+    while !stop.load(Ordering::Relaxed) {
+        let info = match cam.read_frame_into(&mut buf) {
+            Ok(info) => info,
+            Err(Error::FirstFrame) => continue,
+            Err(Error::Cancelled) if stop.load(Ordering::Relaxed) => break,
+            Err(e) => return Err(e),
+        };
+        sink.write_frame(&buf[..info.received], &info)?;
+        written.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+impl Camera {
+    /// Start streaming on a background thread which passes every frame to
+    /// `sink`. Truncated frames ([Error::FirstFrame]) are skipped.
+    pub fn start_sink_thread<S>(self, sink: S) -> SinkHandle<S>
+        where S: FrameSink + Send + 'static
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let written = Arc::new(AtomicU64::new(0));
+        let cancel = self.cancel_token();
+        let (thread_stop, thread_written) = (stop.clone(), written.clone());
+        let mut cam = self;
+        let mut sink = sink;
+        let thread = std::thread::spawn(move || {
+            let mut res = run(&mut cam, &mut sink, &thread_stop, &thread_written);
+            cam.cancel.take();
+            res = res.and(sink.flush());
+            if let Err(e) = cam.stop_stream() {
+                eprintln!("Couldn't stop streaming? {:?}", e);
+            }
+            (cam, sink, res)
+        });
+        SinkHandle { stop, cancel, written, thread: Some(thread) }
+    }
+}
@@ -0,0 +1,102 @@
+//! Saving 8-bit RGB snapshots as JPEG or PNG, with the acquisition
+//! parameters.
+//!
+//! These are for sharing what the preview shows (i.e. a processed
+//! [RgbImage] from a [crate::pipeline::Pipeline]), rather than for keeping
+//! the data: use [Frame::save_tiff] or [Frame::save_dng] for that. Given a
+//! [CaptureMetadata], the camera model, capture time, exposure time and gain
+//! are embedded as EXIF (an `APP1` segment in JPEG files, an `eXIf` chunk in
+//! PNG files). PNG files also get the same information as `tEXt` chunks,
+//! since not every viewer reads `eXIf`.
+//!
+//! [Frame::save_tiff]: crate::Frame::save_tiff
+//! [Frame::save_dng]: crate::Frame::save_dng
+
+use crate::Error;
+use crate::demosaic::RgbImage;
+use crate::metadata::CaptureMetadata;
+use crate::png_writer::png_error;
+use crate::tiff_writer;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+
+/// Marks an EXIF block in a JPEG `APP1` segment.
+const EXIF_HEADER: &[u8; 6] = b"Exif\0\0";
+
+fn jpeg_error(e: jpeg_encoder::EncodingError) -> Error {
+    match e {
+        jpeg_encoder::EncodingError::IoError(e) => Error::Io(e),
+        e => Error::Io(std::io::Error::other(e)),
+    }
+}
+
+impl RgbImage {
+    /// Write the image to a JPEG file, with `quality` from 1 to 100.
+    pub fn save_jpeg(&self, path: impl AsRef<Path>, quality: u8,
+        meta: Option<&CaptureMetadata>) -> Result<(), Error>
+    {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_jpeg(&mut w, quality, meta)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Write the image as a JPEG to any writer.
+    ///
+    /// Returns [Error::InvalidArgument] if the image is larger than JPEG
+    /// allows (65535 pixels on a side).
+    pub fn write_jpeg<W: Write>(&self, w: W, quality: u8, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        let (Ok(width), Ok(height)) = (u16::try_from(self.width), u16::try_from(self.height))
+            else { return Err(Error::InvalidArgument); };
+        let mut enc = jpeg_encoder::Encoder::new(w, quality.clamp(1, 100));
+        if let Some(meta) = meta {
+            let mut app1 = EXIF_HEADER.to_vec();
+            app1.extend_from_slice(&tiff_writer::exif(meta));
+            enc.add_app_segment(1, &app1).map_err(jpeg_error)?;
+        }
+        enc.encode(&self.data, width, height, jpeg_encoder::ColorType::Rgb)
+            .map_err(jpeg_error)
+    }
+
+    /// Write the image to an 8-bit PNG file.
+    pub fn save_png(&self, path: impl AsRef<Path>, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_png(&mut w, meta)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Write the image as an 8-bit PNG to any writer.
+    pub fn write_png<W: Write>(&self, w: W, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        let mut enc = png::Encoder::new(w, self.width as u32, self.height as u32);
+        enc.set_color(png::ColorType::Rgb);
+        enc.set_depth(png::BitDepth::Eight);
+        if let Some(meta) = meta {
+            let (y, mo, d, h, mi, s) = meta.utc();
+            let text = [
+                ("Software", concat!("toupcam-rs ", env!("CARGO_PKG_VERSION")).to_string()),
+                ("Source", meta.model.to_string()),
+                ("Creation Time", format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                    y, mo, d, h, mi, s)),
+                ("Description", tiff_writer::description(meta, None)),
+            ];
+            for (key, value) in text {
+                enc.add_text_chunk(key.to_string(), value).map_err(png_error)?;
+            }
+        }
+        let mut writer = enc.write_header().map_err(png_error)?;
+        if let Some(meta) = meta {
+            writer.write_chunk(png::chunk::ChunkType(*b"eXIf"), &tiff_writer::exif(meta))
+                .map_err(png_error)?;
+        }
+        writer.write_image_data(&self.data).map_err(png_error)?;
+        writer.finish().map_err(png_error)
+    }
+}
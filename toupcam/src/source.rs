@@ -0,0 +1,149 @@
+//! Reading frames from a camera or from a recording, interchangeably.
+//!
+//! [FrameSource] is implemented by [Camera], by the receiving end of a
+//! streaming thread ([FrameReceiver]), by [ImageDirectory] (a directory of
+//! PGM captures), and (with the `writers` feature) by the readers for
+//! recorded sequences ([crate::tpraw::RawSequenceReader] and
+//! [crate::ser::SerReader]). Code written against the trait runs the
+//! same on live hardware and on captures, which makes it easy to debug
+//! processing without a camera attached.
+//!
+//! Recordings are read as fast as possible; frame times are only kept in
+//! [crate::Frame::timestamp].
+
+use crate::{ Camera, Error, Frame };
+use crate::cfa::Cfa;
+use crate::sink::FrameSink;
+use crate::stream::FrameReceiver;
+use std::path::{ Path, PathBuf };
+use std::time::{ Instant, SystemTime };
+
+/// Something that produces raw frames.
+pub trait FrameSource {
+    /// Read the next frame, or `None` when there are no more (i.e. at the
+    /// end of a recording).
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error>;
+
+    /// Start again from the first frame.
+    ///
+    /// Returns [Error::Unimplemented] for live sources.
+    fn rewind(&mut self) -> Result<(), Error> { Err(Error::Unimplemented) }
+
+    /// Returns 'true' for a live source, which never runs out of frames.
+    fn is_live(&self) -> bool { false }
+}
+
+impl FrameSource for Camera {
+    /// Read a frame, starting the stream if it isn't running yet.
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        if !self.streaming { self.start_stream()?; }
+        loop {
+            match self.read_frame() {
+                Err(Error::FirstFrame) => continue,
+                res => return res.map(Some),
+            }
+        }
+    }
+
+    fn is_live(&self) -> bool { true }
+}
+
+impl FrameSource for FrameReceiver {
+    /// Wait for the next frame (`None` once the streaming thread has
+    /// stopped).
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        self.recv().transpose()
+    }
+
+    fn is_live(&self) -> bool { true }
+}
+
+/// Raw frames from the PGM files in a directory (see [Frame::save_pgm]), in
+/// order of file name.
+///
+/// [Frame::seq] is the index of the file, and [Frame::timestamp] follows the
+/// modification times of the files (offset from when the directory was
+/// opened), which is usually close to when each frame was captured.
+pub struct ImageDirectory {
+    files: Vec<PathBuf>,
+    cfa: Cfa,
+    next: usize,
+    /// Modification time of the first file
+    first: Option<SystemTime>,
+    epoch: Instant,
+}
+impl ImageDirectory {
+    /// List the `.pgm` files in `dir`. Frames are given the Bayer phase
+    /// `cfa`, since PGM files don't store it.
+    pub fn open(dir: impl AsRef<Path>, cfa: Cfa) -> Result<Self, Error> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_pgm = path.extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("pgm"));
+            if is_pgm && path.is_file() { files.push(path); }
+        }
+        files.sort();
+        let first = match files.first() {
+            Some(path) => std::fs::metadata(path)?.modified().ok(),
+            None => None,
+        };
+        Ok(Self { files, cfa, next: 0, first, epoch: Instant::now() })
+    }
+
+    /// The files, in the order they're read.
+    pub fn files(&self) -> &[PathBuf] { &self.files }
+
+    pub fn len(&self) -> usize { self.files.len() }
+    pub fn is_empty(&self) -> bool { self.files.is_empty() }
+}
+impl FrameSource for ImageDirectory {
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> {
+        let Some(path) = self.files.get(self.next) else { return Ok(None); };
+        let mut frame = Frame::load_pgm(path, self.cfa)?;
+        frame.seq = self.next as u64;
+        let modified = std::fs::metadata(path)?.modified().ok();
+        let offset = match (self.first, modified) {
+            (Some(first), Some(t)) => t.duration_since(first).unwrap_or_default(),
+            _ => Default::default(),
+        };
+        frame.timestamp = self.epoch + offset;
+        self.next += 1;
+        Ok(Some(frame))
+    }
+
+    fn rewind(&mut self) -> Result<(), Error> {
+        self.next = 0;
+        Ok(())
+    }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for &mut S {
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> { (**self).next_frame() }
+    fn rewind(&mut self) -> Result<(), Error> { (**self).rewind() }
+    fn is_live(&self) -> bool { (**self).is_live() }
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> { (**self).next_frame() }
+    fn rewind(&mut self) -> Result<(), Error> { (**self).rewind() }
+    fn is_live(&self) -> bool { (**self).is_live() }
+}
+
+/// Pass frames from `source` to `sink` until the source runs out, or after
+/// `limit` frames. Flushes the sink and returns the number of frames copied.
+pub fn copy_frames<S, K>(mut source: S, sink: &mut K, limit: Option<u64>)
+    -> Result<u64, Error>
+where
+    S: FrameSource,
+    K: FrameSink + ?Sized,
+{
+    let mut copied = 0;
+    while limit.is_none_or(|limit| copied < limit) {
+        let Some(frame) = source.next_frame()? else { break; };
+        sink.write_frame(&frame.data, &frame.info())?;
+        copied += 1;
+    }
+    sink.flush()?;
+    Ok(copied)
+}
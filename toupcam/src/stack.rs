@@ -0,0 +1,153 @@
+//! Stacking frames of a static scene to reduce noise.
+
+use crate::{ Error, Frame };
+use crate::cfa::Cfa;
+use crate::par;
+
+/// How a [Stacker] combines each pixel across frames.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StackMethod {
+    /// Plain average (only keeps a running sum, so any number of frames
+    /// can be stacked)
+    Mean,
+    /// Median (robust against outliers, but noisier than the mean)
+    Median,
+    /// Average after repeatedly discarding values more than `kappa`
+    /// standard deviations from the mean (i.e. satellite trails, cosmic
+    /// rays, or a hand passing in front of the lens)
+    SigmaClip { kappa: f32, iterations: u32 },
+}
+
+/// The result of stacking, as raw (mosaiced) data.
+///
+/// Samples are in the same units as the input frames, but keep the
+/// fractional part, so the result has more bit depth than any single frame.
+#[derive(Clone, Debug)]
+pub struct Stacked {
+    pub width: usize,
+    pub height: usize,
+    pub cfa: Cfa,
+    /// Number of frames stacked
+    pub frames: usize,
+    pub data: Vec<f32>,
+}
+
+/// Accumulates frames and combines them.
+///
+/// Every frame must have the same dimensions and bit depth as the first.
+/// For [StackMethod::Median] and [StackMethod::SigmaClip], a copy of every
+/// frame is kept until [Stacker::finish].
+pub struct Stacker {
+    method: StackMethod,
+    width: usize,
+    height: usize,
+    bpp: usize,
+    cfa: Cfa,
+    count: usize,
+    sum: Vec<u32>,
+    frames: Vec<Vec<u16>>,
+}
+impl Stacker {
+    pub fn new(method: StackMethod) -> Self {
+        Self { method, width: 0, height: 0, bpp: 0, cfa: Cfa::DEFAULT, count: 0,
+            sum: Vec::new(), frames: Vec::new(),
+        }
+    }
+
+    /// Add a frame to the stack.
+    ///
+    /// Returns [Error::InvalidArgument] for truncated frames, or if the frame
+    /// doesn't match the ones already stacked.
+    pub fn add(&mut self, frame: &Frame) -> Result<(), Error> {
+        if !frame.complete { return Err(Error::InvalidArgument); }
+        if self.count == 0 {
+            self.width = frame.width;
+            self.height = frame.height;
+            self.bpp = frame.bpp;
+            self.cfa = frame.cfa;
+            if self.method == StackMethod::Mean {
+                self.sum = vec![0; frame.width * frame.height];
+            }
+        } else if (frame.width, frame.height, frame.bpp) != (self.width, self.height, self.bpp) {
+            return Err(Error::InvalidArgument);
+        }
+        match self.method {
+            StackMethod::Mean => for (idx, s) in self.sum.iter_mut().enumerate() {
+                *s += frame.sample(idx) as u32;
+            },
+            _ => self.frames.push(frame.to_u16()),
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Number of frames stacked so far.
+    pub fn len(&self) -> usize { self.count }
+    pub fn is_empty(&self) -> bool { self.count == 0 }
+
+    /// Discard every frame, keeping the method.
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.sum = Vec::new();
+        self.frames = Vec::new();
+    }
+
+    /// Combine the frames stacked so far (or `None` if there aren't any).
+    pub fn finish(&self) -> Option<Stacked> {
+        if self.count == 0 { return None; }
+        let w = self.width;
+        let mut data = vec![0f32; w * self.height];
+        match self.method {
+            StackMethod::Mean => {
+                let n = self.count as f32;
+                for (d, s) in data.iter_mut().zip(self.sum.iter()) {
+                    *d = *s as f32 / n;
+                }
+            },
+            StackMethod::Median => {
+                par::for_each_row(&mut data, w, || Vec::with_capacity(self.count),
+                    |values, y, row| for (x, d) in row.iter_mut().enumerate() {
+                        self.gather(values, y * w + x);
+                        *d = median(values);
+                    });
+            },
+            StackMethod::SigmaClip { kappa, iterations } => {
+                par::for_each_row(&mut data, w, || Vec::with_capacity(self.count),
+                    |values, y, row| for (x, d) in row.iter_mut().enumerate() {
+                        self.gather(values, y * w + x);
+                        *d = sigma_clip(values, kappa, iterations);
+                    });
+            },
+        }
+        Some(Stacked { width: w, height: self.height, cfa: self.cfa, frames: self.count,
+            data,
+        })
+    }
+
+    /// Collect the values of pixel `idx` from every frame.
+    fn gather(&self, values: &mut Vec<f32>, idx: usize) {
+        values.clear();
+        values.extend(self.frames.iter().map(|f| f[idx] as f32));
+    }
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] }
+}
+
+fn sigma_clip(values: &mut Vec<f32>, kappa: f32, iterations: u32) -> f32 {
+    let mean = |v: &[f32]| v.iter().sum::<f32>() / v.len() as f32;
+    for _ in 0..iterations {
+        let m = mean(values);
+        let sd = (values.iter().map(|v| (v - m) * (v - m)).sum::<f32>()
+            / values.len() as f32).sqrt();
+        let before = values.len();
+        values.retain(|v| (v - m).abs() <= kappa * sd);
+        // Never throw everything away
+        if values.is_empty() { return m; }
+        if values.len() == before { break; }
+    }
+    mean(values)
+}
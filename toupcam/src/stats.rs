@@ -0,0 +1,132 @@
+//! Streaming statistics.
+
+use crate::Camera;
+use std::time::{ Duration, Instant };
+
+/// Counters for the current stream (see [Camera::stream_stats]).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StreamStats {
+    /// When the stream was started
+    pub started: Option<Instant>,
+    /// Frames returned to the caller
+    pub delivered: u64,
+    /// Complete frames read and thrown away (see [Camera::set_frame_interval])
+    pub discarded: u64,
+    /// Frames that ended early ([crate::Error::FirstFrame])
+    pub truncated: u64,
+    /// Times the frame boundaries were lost ([crate::Error::Desynchronized])
+    pub desynchronized: u64,
+    /// Total number of bytes read from the bulk endpoint
+    pub bytes: u64,
+    /// Total time spent reading frames
+    pub transfer_time: Duration,
+    /// How long each bulk read took to complete
+    pub chunk_latency: LatencyHistogram,
+}
+impl StreamStats {
+    /// Time since the stream was started.
+    pub fn uptime(&self) -> Duration {
+        self.started.map(|t| t.elapsed()).unwrap_or_default()
+    }
+
+    /// Average rate of delivered frames since the stream was started.
+    pub fn fps(&self) -> f64 {
+        let secs = self.uptime().as_secs_f64();
+        if secs > 0.0 { self.delivered as f64 / secs } else { 0.0 }
+    }
+
+    /// Average time taken to read out a frame (complete or not).
+    pub fn avg_transfer_time(&self) -> Duration {
+        let frames = self.delivered + self.discarded + self.truncated;
+        if frames == 0 { return Duration::ZERO; }
+        self.transfer_time / frames as u32
+    }
+
+    /// Average throughput on the bulk endpoint (in bytes per second).
+    pub fn throughput(&self) -> f64 {
+        let secs = self.uptime().as_secs_f64();
+        if secs > 0.0 { self.bytes as f64 / secs } else { 0.0 }
+    }
+}
+
+/// Number of buckets in a [LatencyHistogram].
+pub const LATENCY_BUCKETS: usize = 20;
+
+/// Histogram of durations, in power-of-two buckets of microseconds.
+///
+/// Bucket 0 counts durations under 2µs, bucket `n` counts durations from
+/// `2^n` up to `2^(n+1)` µs, and the last bucket also counts anything longer
+/// (about a second and up).
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    counts: [u64; LATENCY_BUCKETS],
+    total: Duration,
+    max: Duration,
+}
+impl LatencyHistogram {
+    /// Count a duration.
+    pub fn record(&mut self, d: Duration) {
+        let us = d.as_micros().max(1);
+        let idx = (us.ilog2() as usize).min(LATENCY_BUCKETS - 1);
+        self.counts[idx] += 1;
+        self.total += d;
+        self.max = self.max.max(d);
+    }
+
+    /// Number of durations in each bucket.
+    pub fn counts(&self) -> &[u64; LATENCY_BUCKETS] { &self.counts }
+
+    /// Lower and upper bound of bucket `idx` (the upper bound of the last
+    /// bucket is [Duration::MAX]).
+    pub fn bucket_range(idx: usize) -> (Duration, Duration) {
+        let lo = if idx == 0 { Duration::ZERO } else { Duration::from_micros(1 << idx) };
+        let hi = if idx + 1 >= LATENCY_BUCKETS {
+            Duration::MAX
+        } else {
+            Duration::from_micros(1 << (idx + 1))
+        };
+        (lo, hi)
+    }
+
+    /// Number of durations recorded.
+    pub fn count(&self) -> u64 { self.counts.iter().sum() }
+
+    /// Longest duration recorded.
+    pub fn max(&self) -> Duration { self.max }
+
+    /// Average of the durations recorded.
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            n => self.total.div_f64(n as f64),
+        }
+    }
+
+    /// Upper bound of the bucket holding the `p`-th percentile (`0.0` to
+    /// `1.0`), which is as precise as the buckets allow.
+    pub fn percentile(&self, p: f64) -> Duration {
+        let count = self.count();
+        if count == 0 { return Duration::ZERO; }
+        let rank = ((count as f64 * p.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (idx, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Self::bucket_range(idx).1.min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+impl Camera {
+    /// Returns statistics for the current stream.
+    ///
+    /// These are reset by [Camera::start_stream].
+    pub fn stream_stats(&self) -> StreamStats { self.stats }
+
+    /// Reset the statistics, as though the stream had just started.
+    pub fn reset_stream_stats(&mut self) {
+        self.stats = StreamStats { started: Some(Instant::now()), ..Default::default() };
+    }
+}
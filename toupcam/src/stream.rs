@@ -0,0 +1,392 @@
+//! Reading frames on a background thread.
+//!
+//! [Camera::start_streaming_thread] moves the camera onto its own thread,
+//! which starts the stream and keeps reading frames into a queue until it's
+//! told to stop (or fails). The camera is handed back by [StreamHandle::stop].
+//! What happens when the receiver can't keep up is set with [Backpressure].
+//!
+//! For simpler cases, [Camera::run_with_callback] runs the read loop on the
+//! calling thread instead.
+//!
+//! A read can block for a while (up to 500ms for each bulk transfer), so
+//! there's also a [CancelToken] for interrupting one from another thread.
+//! Settings can be changed while the thread is running by sending it a
+//! [Control] (see [StreamHandle::control]), which is applied between frames.
+
+use crate::{ BitDepth, Camera, CameraMode, Error, Frame };
+use std::collections::VecDeque;
+use std::sync::{ Arc, Condvar, Mutex };
+use std::sync::atomic::{ AtomicBool, AtomicU64, Ordering };
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{ Duration, Instant };
+
+/// Interrupts a frame read from another thread (see [Camera::cancel_token]).
+///
+/// Cancelling makes the current read (or the next one, if the camera isn't
+/// reading) fail with [Error::Cancelled] at the next bulk transfer. The 
+/// request is cleared when that happens, so later reads work normally.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+impl CancelToken {
+    pub fn cancel(&self) { self.flag.store(true, Ordering::Relaxed); }
+
+    /// Returns 'true' if a cancellation is pending.
+    pub fn is_cancelled(&self) -> bool { self.flag.load(Ordering::Relaxed) }
+
+    /// Clear a pending cancellation, returning 'true' if there was one.
+    pub fn take(&self) -> bool { self.flag.swap(false, Ordering::Relaxed) }
+}
+
+/// What the streaming thread does when the receiver falls behind.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Stop reading frames until there's room in the queue (the device
+    /// keeps producing frames, so this may lead to truncated frames)
+    Block,
+    /// Throw away the oldest queued frame to make room (i.e. for previews,
+    /// where only the latest frame matters)
+    DropOldest,
+    /// Throw away the new frame
+    DropNewest,
+}
+
+/// Settings for [Camera::start_streaming_thread].
+#[derive(Copy, Clone, Debug)]
+pub struct StreamConfig {
+    /// Number of frames that can be waiting in the queue
+    pub queue: usize,
+    /// What to do when the queue is full
+    pub backpressure: Backpressure,
+    /// Silently drop truncated frames ([Error::FirstFrame]) instead of
+    /// passing them to the receiver
+    pub skip_first_frame: bool,
+}
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self { queue: 4, backpressure: Backpressure::Block, skip_first_frame: true }
+    }
+}
+
+/// A change of settings for the streaming thread.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Control {
+    /// See [Camera::set_exposure_time]
+    Exposure(Duration),
+    /// See [Camera::set_gain]
+    Gain(u16),
+    /// Restart the stream in another mode (see [Camera::set_mode])
+    Mode(CameraMode),
+    /// Restart the stream with another bit depth (see [Camera::set_depth])
+    Depth(BitDepth),
+}
+
+type Message = Result<Frame, Error>;
+
+struct QueueState {
+    items: VecDeque<Message>,
+    receiver_alive: bool,
+    sender_alive: bool,
+}
+
+/// Queue shared between the streaming thread and the [FrameReceiver].
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    capacity: usize,
+    dropped: AtomicU64,
+}
+impl Queue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                items: VecDeque::new(), receiver_alive: true, sender_alive: true,
+            }),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Queue a message, applying the backpressure policy to frames (errors
+    /// are always queued). Returns 'false' if the receiver has gone away.
+    fn push(&self, msg: Message, policy: Backpressure, stop: &AtomicBool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if !state.receiver_alive { return false; }
+            if state.items.len() < self.capacity || msg.is_err() { break; }
+            match policy {
+                Backpressure::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                },
+                Backpressure::DropOldest => {
+                    state.items.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                },
+                Backpressure::Block => {
+                    if stop.load(Ordering::Relaxed) { return true; }
+                    state = self.changed.wait_timeout(state, Duration::from_millis(10))
+                        .unwrap().0;
+                },
+            }
+        }
+        state.items.push_back(msg);
+        self.changed.notify_all();
+        true
+    }
+
+    fn pop(&self, timeout: Option<Duration>) -> Option<Message> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(msg) = state.items.pop_front() {
+                self.changed.notify_all();
+                return Some(msg);
+            }
+            if !state.sender_alive { return None; }
+            state = match deadline {
+                None => self.changed.wait(state).unwrap(),
+                Some(d) => {
+                    let now = Instant::now();
+                    if now >= d { return None; }
+                    self.changed.wait_timeout(state, d - now).unwrap().0
+                },
+            };
+        }
+    }
+}
+
+/// Receiving end for frames read by the streaming thread.
+///
+/// An error ends the stream: after receiving one, the thread has already
+/// stopped and the remaining calls return `None`.
+pub struct FrameReceiver {
+    queue: Arc<Queue>,
+}
+impl FrameReceiver {
+    /// Wait for the next frame. Returns `None` once the thread has stopped.
+    pub fn recv(&self) -> Option<Result<Frame, Error>> {
+        self.queue.pop(None)
+    }
+
+    /// Returns the next frame, if one is pending.
+    pub fn try_recv(&self) -> Option<Result<Frame, Error>> {
+        self.queue.pop(Some(Duration::ZERO))
+    }
+
+    /// Wait up to `timeout` for the next frame.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Result<Frame, Error>> {
+        self.queue.pop(Some(timeout))
+    }
+
+    /// Number of frames thrown away because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+impl Iterator for FrameReceiver {
+    type Item = Result<Frame, Error>;
+    fn next(&mut self) -> Option<Self::Item> { self.recv() }
+}
+impl Drop for FrameReceiver {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().receiver_alive = false;
+        self.queue.changed.notify_all();
+    }
+}
+
+/// Marks the queue as finished when the streaming thread exits.
+struct Sender {
+    queue: Arc<Queue>,
+}
+impl Drop for Sender {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().sender_alive = false;
+        self.queue.changed.notify_all();
+    }
+}
+
+/// Handle for controlling the streaming thread.
+///
+/// Dropping this stops the thread (and the camera along with it).
+pub struct StreamHandle {
+    stop: Arc<AtomicBool>,
+    cancel: CancelToken,
+    queue: Arc<Queue>,
+    controls: mpsc::Sender<Control>,
+    thread: Option<JoinHandle<Camera>>,
+}
+impl StreamHandle {
+    /// Returns 'true' if the thread is still reading frames.
+    pub fn is_running(&self) -> bool {
+        self.thread.as_ref().is_some_and(|t| !t.is_finished())
+    }
+
+    /// Number of frames thrown away because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Change a setting. This is applied after the frame currently being
+    /// read, and ignored if the thread has stopped.
+    ///
+    /// Changing the mode or bit depth restarts the stream, so frames of the
+    /// new size follow right after the old ones.
+    pub fn control(&self, control: Control) {
+        let _ = self.controls.send(control);
+    }
+
+    /// Stop the stream, wait for the thread to exit, and return the camera.
+    pub fn stop(mut self) -> Camera {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        let thread = self.thread.take().unwrap();
+        match thread.join() {
+            Ok(cam) => cam,
+            Err(e) => std::panic::resume_unwind(e),
+        }
+    }
+}
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.cancel.cancel();
+        if let Some(t) = self.thread.take() { let _ = t.join(); }
+    }
+}
+
+/// Apply any pending settings changes.
+///
+/// Settings that are rejected are only logged; failing to stop or restart
+/// the stream is returned, since the thread can't go on after that.
+fn apply_controls(cam: &mut Camera, controls: &mpsc::Receiver<Control>)
+    -> Result<(), Error>
+{
+    while let Ok(control) = controls.try_recv() {
+        let res = match control {
+            Control::Exposure(exposure) => cam.set_exposure_time(exposure),
+            Control::Gain(gain) => cam.set_gain(gain),
+            Control::Mode(mode) if mode != cam.get_mode() => {
+                cam.stop_stream()?;
+                let res = cam.set_mode(mode);
+                cam.start_stream()?;
+                res
+            },
+            Control::Depth(depth) if depth != cam.get_depth() => {
+                cam.stop_stream()?;
+                let res = cam.set_depth(depth);
+                cam.start_stream()?;
+                res
+            },
+            Control::Mode(_) | Control::Depth(_) => Ok(()),
+        };
+        if let Err(e) = res {
+            eprintln!("Couldn't apply {:?}: {:?}", control, e);
+        }
+    }
+    Ok(())
+}
+
+/// Body of the streaming thread.
+fn run(cam: &mut Camera, config: StreamConfig, tx: Sender, stop: &AtomicBool,
+    controls: mpsc::Receiver<Control>)
+{
+    let policy = config.backpressure;
+    if let Err(e) = cam.start_stream() {
+        tx.queue.push(Err(e), policy, stop);
+        return;
+    }
+    while !stop.load(Ordering::Relaxed) {
+        if let Err(e) = apply_controls(cam, &controls) {
+            tx.queue.push(Err(e), policy, stop);
+            break;
+        }
+        let msg = match cam.read_frame() {
+            Err(Error::FirstFrame) if config.skip_first_frame => continue,
+            Err(Error::Cancelled) if stop.load(Ordering::Relaxed) => break,
+            res => res,
+        };
+        let failed = msg.is_err();
+        if !tx.queue.push(msg, policy, stop) || failed { break; }
+    }
+}
+
+impl Camera {
+    /// Start streaming on a background thread.
+    ///
+    /// Frames are delivered through the returned [FrameReceiver]. Use
+    /// [StreamHandle::stop] to stop the stream and get the camera back.
+    pub fn start_streaming_thread(self, config: StreamConfig)
+        -> (FrameReceiver, StreamHandle)
+    {
+        let queue = Arc::new(Queue::new(config.queue));
+        let tx = Sender { queue: queue.clone() };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let cancel = self.cancel_token();
+        let (controls, controls_rx) = mpsc::channel();
+        let mut cam = self;
+        let thread = std::thread::spawn(move || {
+            run(&mut cam, config, tx, &thread_stop, controls_rx);
+            cam.cancel.take();
+            if let Err(e) = cam.stop_stream() {
+                eprintln!("Couldn't stop streaming? {:?}", e);
+            }
+            cam
+        });
+        (FrameReceiver { queue: queue.clone() },
+         StreamHandle { stop, cancel, queue, controls, thread: Some(thread) })
+    }
+
+    /// Returns a token for cancelling frame reads from another thread.
+    pub fn cancel_token(&self) -> CancelToken { self.cancel.clone() }
+
+    /// Iterate over frames, starting the stream if necessary.
+    ///
+    /// Truncated frames ([Error::FirstFrame]) are skipped. The iterator never
+    /// ends on its own, and the stream is left running afterwards.
+    pub fn frames(&mut self) -> Frames<'_> {
+        Frames { cam: self }
+    }
+
+    /// Start streaming and call `f` with each frame until it returns 'false'.
+    ///
+    /// Truncated frames ([Error::FirstFrame]) are skipped. The stream is
+    /// stopped before returning, including when reading fails.
+    pub fn run_with_callback<F>(&mut self, mut f: F) -> Result<(), Error>
+        where F: FnMut(Frame) -> bool
+    {
+        self.start_stream()?;
+        let res = loop {
+            match self.read_frame() {
+                Ok(frame) => if !f(frame) { break Ok(()); },
+                Err(Error::FirstFrame) => continue,
+                Err(e) => break Err(e),
+            }
+        };
+        let stopped = self.stop_stream();
+        res.and(stopped)
+    }
+}
+
+/// Blocking iterator over frames (see [Camera::frames]).
+pub struct Frames<'a> {
+    cam: &'a mut Camera,
+}
+impl Iterator for Frames<'_> {
+    type Item = Result<Frame, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.cam.streaming {
+            if let Err(e) = self.cam.start_stream() { return Some(Err(e)); }
+        }
+        loop {
+            match self.cam.read_frame() {
+                Err(Error::FirstFrame) => continue,
+                res => return Some(res),
+            }
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Saving raw frames as 16-bit TIFF files, with the acquisition parameters.
+//!
+//! Files are little-endian baseline TIFF: a single uncompressed grayscale
+//! strip holding the raw mosaic (the sample values aren't scaled). The
+//! acquisition parameters are stored in standard tags where there is one:
+//!
+//! | Tag                          | Contents                                 |
+//! |------------------------------|------------------------------------------|
+//! | `ImageDescription` (270)     | `key=value` lines (see below)            |
+//! | `Make` (271), `Model` (272)  | Camera model                             |
+//! | `MaxSampleValue` (281)       | Full scale for the bit depth (i.e. 4095) |
+//! | `DateTime` (306)             | Capture time (UTC)                       |
+//! | `CameraSerialNumber` (50735) | USB serial number                        |
+//!
+//! The description has one line each for `exposure_us`, `gain`, `mode`,
+//! `bits`, `cfa`, `timestamp` (seconds since the Unix epoch) and `serial`.
+
+use crate::{ Error, Frame };
+use crate::cfa::Cfa;
+use crate::metadata::CaptureMetadata;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{ BufWriter, Write };
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+const UNDEFINED: u16 = 7;
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+const SRATIONAL: u16 = 10;
+
+/// Tag for `StripOffsets` (filled in by [write_tiff_file]).
+const STRIP_OFFSETS: u16 = 273;
+/// Tag for the offset of the EXIF IFD (filled in by [exif]).
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+const EXIF_IFD: u16 = 34665;
+
+/// One IFD entry, with its value (inline or not) already encoded.
+pub (crate) struct Entry { tag: u16, ty: u16, count: u32, value: Vec<u8> }
+// Some of these are only used by the DNG writer
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+impl Entry {
+    pub (crate) fn bytes(tag: u16, v: &[u8]) -> Self {
+        Self { tag, ty: BYTE, count: v.len() as u32, value: v.to_vec() }
+    }
+    pub (crate) fn short(tag: u16, v: u16) -> Self { Self::shorts(tag, &[v]) }
+    pub (crate) fn shorts(tag: u16, v: &[u16]) -> Self {
+        Self { tag, ty: SHORT, count: v.len() as u32,
+            value: v.iter().flat_map(|v| v.to_le_bytes()).collect(),
+        }
+    }
+    pub (crate) fn long(tag: u16, v: u32) -> Self {
+        Self { tag, ty: LONG, count: 1, value: v.to_le_bytes().to_vec() }
+    }
+    pub (crate) fn ascii(tag: u16, s: &str) -> Self {
+        let mut value = s.as_bytes().to_vec();
+        value.push(0);
+        Self { tag, ty: ASCII, count: value.len() as u32, value }
+    }
+    pub (crate) fn rational(tag: u16, num: u32, den: u32) -> Self {
+        Self::rationals(tag, &[(num, den)])
+    }
+    pub (crate) fn rationals(tag: u16, v: &[(u32, u32)]) -> Self {
+        Self { tag, ty: RATIONAL, count: v.len() as u32,
+            value: v.iter().flat_map(|(n, d)| n.to_le_bytes().into_iter()
+                .chain(d.to_le_bytes())).collect(),
+        }
+    }
+    pub (crate) fn srationals(tag: u16, v: &[(i32, i32)]) -> Self {
+        Self { tag, ty: SRATIONAL, count: v.len() as u32,
+            value: v.iter().flat_map(|(n, d)| n.to_le_bytes().into_iter()
+                .chain(d.to_le_bytes())).collect(),
+        }
+    }
+}
+
+/// The frame's samples as little-endian 16-bit values.
+pub (crate) fn strip(frame: &Frame) -> Cow<'_, [u8]> {
+    let len = frame.width * frame.height;
+    match frame.bpp {
+        2 => Cow::Borrowed(&frame.data[..len * 2]),
+        _ => Cow::Owned(frame.data[..len].iter()
+            .flat_map(|v| (*v as u16).to_le_bytes()).collect()),
+    }
+}
+
+/// Size of an IFD with `entries`, including values that don't fit in an
+/// entry.
+fn ifd_len(entries: &[Entry]) -> usize {
+    2 + entries.len() * 12 + 4 + entries.iter()
+        .filter(|e| e.value.len() > 4).map(|e| e.value.len().next_multiple_of(2)).sum::<usize>()
+}
+
+/// Encode an IFD at offset `pos` in the file, followed by the values that
+/// don't fit in an entry. `next` is the offset of the next IFD (or 0).
+fn encode_ifd(entries: &[Entry], pos: u32, next: u32) -> Vec<u8> {
+    let mut extra_pos = pos + (2 + entries.len() * 12 + 4) as u32;
+    let mut out = Vec::with_capacity(ifd_len(entries));
+    let mut extra = Vec::new();
+    out.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    for e in entries.iter() {
+        out.extend_from_slice(&e.tag.to_le_bytes());
+        out.extend_from_slice(&e.ty.to_le_bytes());
+        out.extend_from_slice(&e.count.to_le_bytes());
+        if e.value.len() <= 4 {
+            let mut inline = [0u8; 4];
+            inline[..e.value.len()].copy_from_slice(&e.value);
+            out.extend_from_slice(&inline);
+        } else {
+            out.extend_from_slice(&extra_pos.to_le_bytes());
+            extra.extend_from_slice(&e.value);
+            // Values start on a word boundary
+            if e.value.len() % 2 == 1 { extra.push(0); }
+            extra_pos += e.value.len().next_multiple_of(2) as u32;
+        }
+    }
+    out.extend_from_slice(&next.to_le_bytes());
+    out.extend_from_slice(&extra);
+    out
+}
+
+/// The little-endian TIFF header, with the first IFD at offset 8.
+const HEADER: [u8; 8] = [b'I', b'I', 42, 0, 8, 0, 0, 0];
+
+/// Write a little-endian TIFF with a single IFD and a single strip of image
+/// data (`StripOffsets` is added here).
+pub (crate) fn write_tiff_file<W: Write>(mut w: W, mut entries: Vec<Entry>, strip: &[u8])
+    -> Result<(), Error>
+{
+    entries.retain(|e| e.tag != STRIP_OFFSETS);
+    entries.push(Entry::long(STRIP_OFFSETS, 0));
+    entries.sort_by_key(|e| e.tag);
+
+    // Header, then the IFD and its values, then the strip
+    let strip_pos = (HEADER.len() + ifd_len(&entries)) as u32;
+    for e in entries.iter_mut().filter(|e| e.tag == STRIP_OFFSETS) {
+        e.value = strip_pos.to_le_bytes().to_vec();
+    }
+    w.write_all(&HEADER)?;
+    w.write_all(&encode_ifd(&entries, HEADER.len() as u32, 0))?;
+    w.write_all(strip)?;
+    Ok(())
+}
+
+/// Format a time as a TIFF `DateTime` (UTC).
+pub (crate) fn datetime(meta: &CaptureMetadata) -> String {
+    let (y, mo, d, h, mi, s) = meta.utc();
+    format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}", y, mo, d, h, mi, s)
+}
+
+/// Tags for the acquisition parameters shared by TIFF and DNG files (`Make`,
+/// `Model`, `DateTime` and `CameraSerialNumber`).
+pub (crate) fn camera_entries(meta: &CaptureMetadata) -> Vec<Entry> {
+    let mut entries = vec![
+        Entry::ascii(271, meta.model.split(' ').next().unwrap_or("")),
+        Entry::ascii(272, meta.model),
+        Entry::ascii(306, &datetime(meta)),
+    ];
+    if let Some(serial) = &meta.serial {
+        entries.push(Entry::ascii(50735, serial));
+    }
+    entries
+}
+
+/// The `key=value` description stored in `ImageDescription` (the `cfa` line
+/// is left out for images that aren't raw).
+pub (crate) fn description(meta: &CaptureMetadata, cfa: Option<Cfa>) -> String {
+    let ts = meta.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let cfa = cfa.map(|cfa| format!("cfa={}\n", cfa.name())).unwrap_or_default();
+    format!(concat!("exposure_us={}\ngain=0x{:04x}\nmode={:?}\nbits={}\n{}",
+        "timestamp={}.{:06}\nserial={}\n"),
+        meta.exposure.as_micros(), meta.gain, meta.mode, meta.bits, cfa,
+        ts.as_secs(), ts.subsec_micros(), meta.serial.as_deref().unwrap_or(""))
+}
+
+/// An EXIF block describing the capture, for embedding in JPEG and PNG
+/// files: a TIFF structure with `Make`, `Model`, `Software`, `DateTime` and
+/// the `ImageDescription` in the first IFD, and the exposure time, capture
+/// time and serial number in the EXIF IFD.
+#[cfg_attr(not(feature = "processing"), allow(dead_code))]
+pub (crate) fn exif(meta: &CaptureMetadata) -> Vec<u8> {
+    let ms = meta.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default().subsec_millis();
+    let mut exif = vec![
+        // ExposureTime, in microseconds
+        Entry::rational(33434, meta.exposure.as_micros() as u32, 1_000_000),
+        // ExifVersion 2.32
+        Entry { tag: 36864, ty: UNDEFINED, count: 4, value: b"0232".to_vec() },
+        // DateTimeOriginal, SubSecTimeOriginal and OffsetTimeOriginal
+        Entry::ascii(36867, &datetime(meta)),
+        Entry::ascii(37521, &format!("{:03}", ms)),
+        Entry::ascii(36881, "+00:00"),
+    ];
+    if let Some(serial) = &meta.serial {
+        // BodySerialNumber
+        exif.push(Entry::ascii(42033, serial));
+    }
+    let mut ifd0 = camera_entries(meta);
+    ifd0.retain(|e| e.tag != 50735);
+    ifd0.push(Entry::ascii(270, &description(meta, None)));
+    ifd0.push(Entry::ascii(305, concat!("toupcam-rs ", env!("CARGO_PKG_VERSION"))));
+    ifd0.push(Entry::long(EXIF_IFD, 0));
+    ifd0.sort_by_key(|e| e.tag);
+    exif.sort_by_key(|e| e.tag);
+
+    let exif_pos = (HEADER.len() + ifd_len(&ifd0)) as u32;
+    for e in ifd0.iter_mut().filter(|e| e.tag == EXIF_IFD) {
+        e.value = exif_pos.to_le_bytes().to_vec();
+    }
+    let mut out = HEADER.to_vec();
+    out.extend_from_slice(&encode_ifd(&ifd0, HEADER.len() as u32, 0));
+    out.extend_from_slice(&encode_ifd(&exif, exif_pos, 0));
+    out
+}
+
+impl Frame {
+    /// Write the raw frame to a 16-bit TIFF file (see [crate::tiff_writer]).
+    ///
+    /// Returns [Error::InvalidArgument] for truncated frames.
+    pub fn save_tiff(&self, path: impl AsRef<Path>, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_tiff(&mut w, meta)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Write the raw frame as a 16-bit TIFF to any writer.
+    pub fn write_tiff<W: Write>(&self, w: W, meta: Option<&CaptureMetadata>)
+        -> Result<(), Error>
+    {
+        if !self.complete { return Err(Error::InvalidArgument); }
+        let strip = strip(self);
+        let mut entries = vec![
+            Entry::long(256, self.width as u32),
+            Entry::long(257, self.height as u32),
+            Entry::short(258, 16),
+            Entry::short(259, 1),
+            // BlackIsZero
+            Entry::short(262, 1),
+            Entry::short(277, 1),
+            Entry::long(278, self.height as u32),
+            Entry::long(279, strip.len() as u32),
+            Entry::short(281, self.max_value()),
+            Entry::rational(282, 72, 1),
+            Entry::rational(283, 72, 1),
+            Entry::short(296, 2),
+            Entry::ascii(305, concat!("toupcam-rs ", env!("CARGO_PKG_VERSION"))),
+        ];
+        if let Some(meta) = meta {
+            entries.push(Entry::ascii(270, &description(meta, Some(self.cfa))));
+            entries.extend(camera_entries(meta));
+        }
+        write_tiff_file(w, entries, &strip)
+    }
+}
@@ -0,0 +1,154 @@
+//! Assembling captures into a time-lapse.
+//!
+//! A [Timelapse] reads frames from any [FrameSource] (i.e. a `.tpraw` or
+//! SER recording, or an [crate::source::ImageDirectory] of PGM captures),
+//! optionally keeps only every Nth frame and evens out the brightness, runs
+//! them through a [Pipeline], and writes the result as a video (through a
+//! [Recorder]) or as numbered PPM images.
+//!
+//! By default, frames are tone mapped linearly over the full range, so that
+//! changes in brightness over the sequence are kept. Over a long session
+//! (clouds, a lamp warming up, ...) that usually shows up as flicker, which
+//! [Normalize] takes out by scaling each raw frame to a common mean level
+//! before it's processed. An adaptive tone map (i.e. [ToneMap::AutoStretch])
+//! also evens things out, but reacts to the content of each frame as well.
+
+use crate::{ Error, Frame };
+use crate::demosaic::{ Demosaic, RgbImage };
+use crate::pipeline::Pipeline;
+use crate::recorder::{ Recorder, RecorderConfig };
+use crate::source::FrameSource;
+use crate::tonemap::ToneMap;
+use std::path::Path;
+
+/// How the brightness of each frame is adjusted before processing.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Normalize {
+    /// Leave frames as they are
+    None,
+    /// Scale every frame to the mean level of the first one
+    MatchFirst,
+    /// Scale every frame to this mean level (a fraction of full scale)
+    Mean(f32),
+}
+
+/// Turns a sequence of frames into a time-lapse.
+pub struct Timelapse {
+    pipeline: Pipeline,
+    normalize: Normalize,
+    step: usize,
+    /// Target mean for [Normalize::MatchFirst] (set by the first frame)
+    reference: Option<f32>,
+    /// Reused for the normalized copy of each frame
+    buf: Vec<u8>,
+}
+impl Timelapse {
+    /// Demosaic with `method` and tone map linearly, without normalization.
+    pub fn new(method: Demosaic) -> Self {
+        let pipeline = Pipeline::new(method)
+            .with_tonemap(ToneMap::Linear { black: 0, white: u16::MAX });
+        Self::with_pipeline(pipeline)
+    }
+
+    /// Process frames with `pipeline`.
+    pub fn with_pipeline(pipeline: Pipeline) -> Self {
+        Self { pipeline, normalize: Normalize::None, step: 1, reference: None,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Adjust the brightness of each frame (see [Normalize]).
+    pub fn with_normalize(mut self, normalize: Normalize) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Only use every `step`-th frame (i.e. to speed up a long sequence).
+    pub fn with_step(mut self, step: usize) -> Self {
+        self.step = step.max(1);
+        self
+    }
+
+    /// The pipeline used to process frames.
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline { &mut self.pipeline }
+
+    /// Encode the time-lapse into a video at `path` (see [Recorder]).
+    ///
+    /// Returns the number of frames written. The source must be a recording:
+    /// live sources never run out, and return [Error::InvalidArgument].
+    pub fn to_video(&mut self, source: impl FrameSource, path: impl AsRef<Path>,
+        config: RecorderConfig) -> Result<u64, Error>
+    {
+        let mut recorder = Recorder::new(path, Demosaic::Bilinear, config);
+        self.run(source, |_, img| recorder.write_rgb(img))?;
+        let frames = recorder.frames();
+        recorder.finish()?;
+        Ok(frames)
+    }
+
+    /// Write the time-lapse as `frame_NNNNNN.ppm` files in `dir` (which is
+    /// created if needed).
+    ///
+    /// Returns the number of frames written (see [Timelapse::to_video]).
+    pub fn to_images(&mut self, source: impl FrameSource, dir: impl AsRef<Path>)
+        -> Result<u64, Error>
+    {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        self.run(source, |idx, img| img.save_ppm(dir.join(format!("frame_{:06}.ppm", idx))))
+    }
+
+    /// Process the frames to keep, handing each image to `f` along with its
+    /// index in the output. Returns the number of frames processed.
+    fn run<S, F>(&mut self, mut source: S, mut f: F) -> Result<u64, Error>
+    where
+        S: FrameSource,
+        F: FnMut(u64, &RgbImage) -> Result<(), Error>,
+    {
+        if source.is_live() { return Err(Error::InvalidArgument); }
+        self.reference = None;
+        let (mut read, mut written) = (0, 0);
+        while let Some(frame) = source.next_frame()? {
+            read += 1;
+            if (read - 1) % self.step != 0 || !frame.complete { continue; }
+            let img = match self.normalized(&frame) {
+                Some(normalized) => {
+                    let img = self.pipeline.run(&normalized);
+                    self.buf = normalized.data.into_vec();
+                    img?
+                },
+                None => self.pipeline.run(&frame)?,
+            };
+            f(written, img)?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    /// Copy `frame` scaled to the target mean level, or `None` if it doesn't
+    /// need to be changed.
+    fn normalized(&mut self, frame: &Frame) -> Option<Frame> {
+        let count = frame.width * frame.height;
+        if self.normalize == Normalize::None || count == 0 { return None; }
+        let max = frame.max_value() as f32;
+        let mean = (0..count).map(|idx| frame.sample(idx) as f64).sum::<f64>()
+            / count as f64;
+        let mean = mean as f32 / max;
+        let target = match self.normalize {
+            Normalize::None => return None,
+            Normalize::MatchFirst => *self.reference.get_or_insert(mean),
+            Normalize::Mean(level) => level,
+        };
+        if mean <= 0.0 { return None; }
+        let gain = target / mean;
+
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        let scale = |v: u16| (v as f32 * gain).round().min(max) as u16;
+        match frame.bpp {
+            2 => buf.extend((0..count).flat_map(|idx| scale(frame.sample(idx)).to_le_bytes())),
+            _ => buf.extend((0..count).map(|idx| scale(frame.sample(idx)) as u8)),
+        }
+        Some(Frame::from_info(buf, &frame.info()))
+    }
+}
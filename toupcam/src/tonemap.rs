@@ -0,0 +1,108 @@
+//! Tone mapping 16-bit images down to 8 bits for display or export.
+//!
+//! With 12-bit data, simply keeping the top 8 bits leaves most scenes dark
+//! and wastes the dynamic range the sensor has. Each operator here maps a
+//! range of input values (between a black point and a white point) onto
+//! `0..=255` through some curve. Black and white points are in the units of
+//! [Rgb16Image], which scales samples to the full 16-bit range (so a 12-bit
+//! value `v` is `v << 4`).
+
+use crate::demosaic::{ Rgb16Image, RgbImage };
+use crate::par;
+
+/// A tone mapping operator.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Straight line between the black and white points
+    Linear { black: u16, white: u16 },
+    /// Power curve (`gamma` > 1.0 brightens the shadows)
+    Gamma { black: u16, white: u16, gamma: f32 },
+    /// Inverse hyperbolic sine stretch, which brings up faint detail while
+    /// compressing highlights (larger `stretch` is stronger)
+    Asinh { black: u16, white: u16, stretch: f32 },
+    /// Linear between the `low` and `high` percentiles (0.0 to 1.0) of the
+    /// image, recomputed for every image
+    AutoStretch { low: f32, high: f32 },
+}
+impl Default for ToneMap {
+    fn default() -> Self { Self::AutoStretch { low: 0.001, high: 0.999 } }
+}
+impl ToneMap {
+    /// Map the full range linearly (roughly the same as [Rgb16Image::to_rgb8]).
+    pub const FULL_RANGE: ToneMap = ToneMap::Linear { black: 0, white: u16::MAX };
+
+    /// Build the 16-bit to 8-bit lookup table for an image.
+    pub fn table(&self, img: &Rgb16Image) -> Vec<u8> {
+        let (black, white, curve): (u16, u16, Box<dyn Fn(f32) -> f32>) = match *self {
+            Self::Linear { black, white } => (black, white, Box::new(|x| x)),
+            Self::Gamma { black, white, gamma } => {
+                let inv = 1.0 / gamma.max(f32::EPSILON);
+                (black, white, Box::new(move |x: f32| x.powf(inv)))
+            },
+            Self::Asinh { black, white, stretch } => {
+                let s = stretch.max(f32::EPSILON);
+                let norm = s.asinh();
+                (black, white, Box::new(move |x: f32| (s * x).asinh() / norm))
+            },
+            Self::AutoStretch { low, high } => {
+                let (black, white) = percentiles(img, low, high);
+                (black, white, Box::new(|x| x))
+            },
+        };
+        let range = (white.max(black.saturating_add(1)) - black) as f32;
+        (0..=u16::MAX).map(|v| {
+            let x = (v.saturating_sub(black) as f32 / range).min(1.0);
+            (curve(x) * 255.0).round().clamp(0.0, 255.0) as u8
+        }).collect()
+    }
+
+    /// Tone map an image down to 8 bits per sample.
+    pub fn apply(&self, img: &Rgb16Image) -> RgbImage {
+        let mut out = RgbImage { width: 0, height: 0, data: Vec::new() };
+        self.apply_into(img, &mut out);
+        out
+    }
+
+    /// Tone map an image into `out` (reusing its allocation).
+    pub fn apply_into(&self, img: &Rgb16Image, out: &mut RgbImage) {
+        map(&self.table(img), img, out);
+    }
+
+    /// Whether the lookup table depends on the image (so it can't be reused
+    /// between images).
+    pub fn is_adaptive(&self) -> bool {
+        matches!(self, Self::AutoStretch { .. })
+    }
+}
+
+/// Map every sample of `img` through a table from [ToneMap::table].
+pub (crate) fn map(table: &[u8], img: &Rgb16Image, out: &mut RgbImage) {
+    out.width = img.width;
+    out.height = img.height;
+    out.data.resize(img.data.len(), 0);
+    par::for_each_row(&mut out.data, img.width * 3, || (), |_, y, row| {
+        let src = &img.data[y * img.width * 3..];
+        for (d, s) in row.iter_mut().zip(src) { *d = table[*s as usize]; }
+    });
+}
+
+/// Find the values at the `low` and `high` percentiles over all samples of
+/// an image (looking at every 7th sample, which is plenty).
+fn percentiles(img: &Rgb16Image, low: f32, high: f32) -> (u16, u16) {
+    let mut hist = vec![0u32; 1 << 16];
+    let mut count = 0u64;
+    for v in img.data.iter().step_by(7) {
+        hist[*v as usize] += 1;
+        count += 1;
+    }
+    let find = |fraction: f32| {
+        let target = (count as f64 * fraction.clamp(0.0, 1.0) as f64) as u64;
+        let mut seen = 0u64;
+        for (v, n) in hist.iter().enumerate() {
+            seen += *n as u64;
+            if seen > target { return v as u16; }
+        }
+        u16::MAX
+    };
+    (find(low), find(high))
+}
@@ -0,0 +1,635 @@
+//! Reading and writing `.tpraw` sequence files.
+//!
+//! A `.tpraw` file is an 8-byte magic number followed by a list of chunks.
+//! Each chunk is a 4-byte tag, a little-endian `u64` payload length, and
+//! the payload. Readers should skip chunks with unknown tags.
+//!
+//! | Tag    | Payload                                                          |
+//! |--------|------------------------------------------------------------------|
+//! | `HEAD` | `u16` version, `u32` width, `u32` height, `u8` bpp, `u8` CFA,    |
+//! |        | `u64` exposure (in microseconds), `u16` gain                     |
+//! | `FRAM` | `u64` frame index, `u64` time since the first frame (in          |
+//! |        | microseconds), raw frame data                                    |
+//! | `FRMZ` | Same as `FRAM`, but the frame data is compressed (see below)     |
+//! | `THMB` | `u64` frame index, `u16` width, `u16` height, JPEG data          |
+//! | `TIDX` | `u64` count, then (`u64` frame index, `u64` offset) pairs        |
+//! | `TEND` | `u64` file offset of the `TIDX` chunk                            |
+//!
+//! Version 1 files don't have the exposure and gain in `HEAD`, or the frame
+//! times in `FRAM`; [RawSequenceReader] reads both versions.
+//!
+//! With the `zstd` feature, frames can be compressed (see
+//! [TprawWriter::set_compression]). Each frame is compressed on its own, so
+//! frames can still be read in any order, and a damaged frame doesn't take
+//! the rest of the file with it. For 16-bit data, the bytes are shuffled
+//! before compressing (the low byte of every sample, then the high byte of
+//! every sample), which compresses 12-bit data much better.
+//!
+//! Thumbnails are optional: when enabled, a small JPEG of every Nth frame is
+//! written right after it. On [TprawWriter::finish], an index of all the
+//! thumbnails is written, followed by a fixed-size `TEND` chunk at the very
+//! end of the file. This means a viewer (or a remote tool doing range
+//! requests) can read the last 20 bytes, jump to the index, and browse the
+//! thumbnails without reading any of the raw frames.
+
+use crate::{ Camera, Error, Frame };
+use crate::cfa::{ Cfa, Color };
+use crate::source::FrameSource;
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Read, Seek, SeekFrom, Write };
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+/// Magic number at the start of every `.tpraw` file.
+pub const MAGIC: [u8; 8] = *b"TPRAW\0\0\x01";
+/// Version of the `HEAD` chunk (and the layout of `FRAM`).
+pub const VERSION: u16 = 2;
+/// Size of the `TEND` chunk (tag, length, offset).
+pub const TEND_LEN: u64 = 4 + 8 + 8;
+/// Longest `HEAD` chunk accepted by [RawSequenceReader].
+const MAX_HEAD_LEN: u64 = 4096;
+/// Largest frame accepted by [RawSequenceReader] and [crate::ser::SerReader]
+/// (far more than any sensor this reads), so a corrupt header can't ask for
+/// an absurd allocation.
+pub (crate) const MAX_FRAME_LEN: u64 = 1 << 30;
+
+/// Encode a CFA phase for the `HEAD` chunk.
+pub (crate) fn cfa_to_u8(cfa: Cfa) -> u8 {
+    match cfa { Cfa::Rggb => 0, Cfa::Grbg => 1, Cfa::Gbrg => 2, Cfa::Bggr => 3 }
+}
+
+/// Decode a CFA phase from the `HEAD` chunk.
+pub (crate) fn cfa_from_u8(v: u8) -> Option<Cfa> {
+    match v { 0 => Some(Cfa::Rggb), 1 => Some(Cfa::Grbg), 2 => Some(Cfa::Gbrg),
+        3 => Some(Cfa::Bggr), _ => None }
+}
+
+/// Shuffle the bytes of 16-bit samples (low bytes, then high bytes) and
+/// compress them.
+#[cfg(feature = "zstd")]
+fn compress(data: &[u8], bpp: usize, level: i32) -> Result<Vec<u8>, Error> {
+    if bpp != 2 { return Ok(zstd::bulk::compress(data, level)?); }
+    let half = data.len() / 2;
+    let mut shuffled = vec![0u8; data.len()];
+    for (i, s) in data.chunks_exact(2).enumerate() {
+        shuffled[i] = s[0];
+        shuffled[half + i] = s[1];
+    }
+    Ok(zstd::bulk::compress(&shuffled, level)?)
+}
+
+/// Undo [compress], into `out` (which must be the size of a frame).
+#[cfg(feature = "zstd")]
+fn decompress(packed: &[u8], bpp: usize, out: &mut [u8]) -> Result<(), Error> {
+    let bad = || Error::Format("corrupt compressed frame".to_string());
+    let raw = zstd::bulk::decompress(packed, out.len()).map_err(|_| bad())?;
+    if raw.len() != out.len() { return Err(bad()); }
+    if bpp != 2 {
+        out.copy_from_slice(&raw);
+        return Ok(());
+    }
+    let half = raw.len() / 2;
+    for (i, s) in out.chunks_exact_mut(2).enumerate() {
+        s[0] = raw[i];
+        s[1] = raw[half + i];
+    }
+    Ok(())
+}
+
+/// The contents of the `HEAD` chunk.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TprawHeader {
+    pub width: usize,
+    pub height: usize,
+    /// Number of bytes per pixel
+    pub bpp: usize,
+    pub cfa: Cfa,
+    /// Exposure time (zero if unknown)
+    pub exposure: Duration,
+    /// Raw analog gain (zero if unknown)
+    pub gain: u16,
+}
+impl TprawHeader {
+    /// The header for frames read with the camera's current settings.
+    pub fn for_camera(cam: &Camera) -> Self {
+        let (width, height) = cam.get_mode().dimensions();
+        let bpp = match cam.get_depth() {
+            crate::BitDepth::BitDepth8 => 1,
+            crate::BitDepth::BitDepth12 => 2,
+        };
+        Self { width, height, bpp, cfa: cam.cfa(), exposure: cam.get_exposure_time(),
+            gain: cam.get_gain(),
+        }
+    }
+
+    /// Size of a frame (in bytes).
+    pub fn frame_len(&self) -> usize { self.width * self.height * self.bpp }
+}
+
+/// Build a small RGB thumbnail from a raw frame.
+///
+/// Each 2x2 cell becomes one RGB pixel (averaging the two greens), and cells
+/// are skipped so that the result is at most `max_width` pixels wide.
+pub (crate) fn thumbnail(frame: &Frame, max_width: usize) -> (usize, usize, Vec<u8>) {
+    let (cw, ch) = (frame.width / 2, frame.height / 2);
+    let step = cw.div_ceil(max_width.max(1)).max(1);
+    let (tw, th) = (cw.div_ceil(step), ch.div_ceil(step));
+    let shift = frame.bits() - 8;
+
+    let mut rgb = Vec::with_capacity(tw * th * 3);
+    for ty in 0..th {
+        for tx in 0..tw {
+            let (x0, y0) = (tx * step * 2, ty * step * 2);
+            let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (x, y) = (x0 + dx, y0 + dy);
+                let idx = y * frame.width + x;
+                let v = frame.sample(idx) as u32;
+                match frame.cfa.color_at(x, y) {
+                    Color::Red   => r += v,
+                    Color::Green => g += v,
+                    Color::Blue  => b += v,
+                }
+            }
+            for c in [r, g / 2, b] {
+                rgb.push((c >> shift).min(255) as u8);
+            }
+        }
+    }
+    (tw, th, rgb)
+}
+
+/// Writes frames (and optional thumbnails) to a `.tpraw` file.
+pub struct TprawWriter<W: Write> {
+    w: W,
+    /// Current offset into the file
+    pos: u64,
+    /// Number of frames written so far
+    frames: u64,
+    /// Write a thumbnail for every Nth frame
+    thumb_every: Option<u64>,
+    /// Maximum thumbnail width
+    thumb_width: usize,
+    /// (frame index, offset) for each thumbnail
+    thumbs: Vec<(u64, u64)>,
+    width: usize,
+    height: usize,
+    bpp: usize,
+    /// When the first frame was read (frame times are relative to this)
+    start: Option<Instant>,
+    /// zstd compression level for frames (uncompressed if unset)
+    #[cfg(feature = "zstd")]
+    compression: Option<i32>,
+}
+
+impl TprawWriter<BufWriter<File>> {
+    /// Create a new file at `path`.
+    pub fn create(path: impl AsRef<Path>, width: usize, height: usize,
+        bpp: usize, cfa: Cfa) -> Result<Self, Error>
+    {
+        let f = BufWriter::new(File::create(path)?);
+        Self::new(f, width, height, bpp, cfa)
+    }
+
+    /// Create a new file at `path`, with a full header.
+    pub fn create_with_header(path: impl AsRef<Path>, header: &TprawHeader)
+        -> Result<Self, Error>
+    {
+        let f = BufWriter::new(File::create(path)?);
+        Self::with_header(f, header)
+    }
+}
+
+impl<W: Write> TprawWriter<W> {
+    /// Start a new sequence, writing the magic number and header (with the
+    /// exposure and gain unknown).
+    pub fn new(w: W, width: usize, height: usize, bpp: usize, cfa: Cfa)
+        -> Result<Self, Error>
+    {
+        Self::with_header(w, &TprawHeader { width, height, bpp, cfa,
+            exposure: Duration::ZERO, gain: 0,
+        })
+    }
+
+    /// Start a new sequence, writing the magic number and `header`.
+    pub fn with_header(mut w: W, header: &TprawHeader) -> Result<Self, Error> {
+        w.write_all(&MAGIC)?;
+        let mut res = Self {
+            w, pos: MAGIC.len() as u64, frames: 0,
+            thumb_every: None, thumb_width: 160, thumbs: Vec::new(),
+            width: header.width, height: header.height, bpp: header.bpp, start: None,
+            #[cfg(feature = "zstd")]
+            compression: None,
+        };
+        let mut head = Vec::new();
+        head.extend_from_slice(&VERSION.to_le_bytes());
+        head.extend_from_slice(&(header.width as u32).to_le_bytes());
+        head.extend_from_slice(&(header.height as u32).to_le_bytes());
+        head.push(header.bpp as u8);
+        head.push(cfa_to_u8(header.cfa));
+        head.extend_from_slice(&(header.exposure.as_micros() as u64).to_le_bytes());
+        head.extend_from_slice(&header.gain.to_le_bytes());
+        res.write_chunk(b"HEAD", &[&head])?;
+        Ok(res)
+    }
+
+    /// Write a JPEG thumbnail (at most `max_width` pixels wide) after every
+    /// `every`-th frame, starting with the first.
+    pub fn set_thumbnails(&mut self, every: Option<u64>, max_width: usize) {
+        self.thumb_every = every.map(|n| n.max(1));
+        self.thumb_width = max_width;
+    }
+
+    /// Compress frames with zstd at `level` (1 is fastest; 3 is a good
+    /// default), or write them uncompressed with `None`.
+    #[cfg(feature = "zstd")]
+    pub fn set_compression(&mut self, level: Option<i32>) {
+        self.compression = level;
+    }
+
+    /// Number of frames written so far.
+    pub fn frames(&self) -> u64 { self.frames }
+
+    /// Number of bytes written so far.
+    pub fn len(&self) -> u64 { self.pos }
+    pub fn is_empty(&self) -> bool { self.pos == 0 }
+
+    fn write_chunk(&mut self, tag: &[u8; 4], parts: &[&[u8]]) -> Result<u64, Error> {
+        let offset = self.pos;
+        let len: u64 = parts.iter().map(|p| p.len() as u64).sum();
+        self.w.write_all(tag)?;
+        self.w.write_all(&len.to_le_bytes())?;
+        for p in parts {
+            self.w.write_all(p)?;
+        }
+        self.pos += 12 + len;
+        Ok(offset)
+    }
+
+    /// Append a frame to the sequence (which must be complete, and match the
+    /// dimensions given when creating the writer).
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), Error> {
+        if (frame.width, frame.height, frame.bpp) != (self.width, self.height, self.bpp)
+            || !frame.complete
+        {
+            return Err(Error::InvalidArgument);
+        }
+        let idx = self.frames;
+        let start = *self.start.get_or_insert(frame.timestamp);
+        let time = frame.timestamp.saturating_duration_since(start).as_micros() as u64;
+        #[cfg(feature = "zstd")]
+        if let Some(level) = self.compression {
+            let packed = compress(&frame.data, self.bpp, level)?;
+            self.write_chunk(b"FRMZ", &[&idx.to_le_bytes(), &time.to_le_bytes(), &packed])?;
+        } else {
+            self.write_chunk(b"FRAM", &[&idx.to_le_bytes(), &time.to_le_bytes(), &frame.data])?;
+        }
+        #[cfg(not(feature = "zstd"))]
+        self.write_chunk(b"FRAM", &[&idx.to_le_bytes(), &time.to_le_bytes(), &frame.data])?;
+
+        if let Some(n) = self.thumb_every {
+            if idx.is_multiple_of(n) {
+                self.write_thumbnail(idx, frame)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn write_thumbnail(&mut self, idx: u64, frame: &Frame) -> Result<(), Error> {
+        let (tw, th, rgb) = thumbnail(frame, self.thumb_width);
+        let mut jpeg = Vec::new();
+        jpeg_encoder::Encoder::new(&mut jpeg, 75)
+            .encode(&rgb, tw as u16, th as u16, jpeg_encoder::ColorType::Rgb)
+            .map_err(|e| Error::Io(std::io::Error::other(e)))?;
+
+        let mut hdr = Vec::with_capacity(12);
+        hdr.extend_from_slice(&idx.to_le_bytes());
+        hdr.extend_from_slice(&(tw as u16).to_le_bytes());
+        hdr.extend_from_slice(&(th as u16).to_le_bytes());
+        let offset = self.write_chunk(b"THMB", &[&hdr, &jpeg])?;
+        self.thumbs.push((idx, offset));
+        Ok(())
+    }
+
+    /// Write the thumbnail index and trailer, and flush the file.
+    pub fn finish(mut self) -> Result<W, Error> {
+        let mut idx = Vec::with_capacity(8 + self.thumbs.len() * 16);
+        idx.extend_from_slice(&(self.thumbs.len() as u64).to_le_bytes());
+        for (frame, offset) in self.thumbs.iter() {
+            idx.extend_from_slice(&frame.to_le_bytes());
+            idx.extend_from_slice(&offset.to_le_bytes());
+        }
+        let tidx = self.write_chunk(b"TIDX", &[&idx])?;
+        self.write_chunk(b"TEND", &[&tidx.to_le_bytes()])?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+/// Reads the frames back from a `.tpraw` file.
+///
+/// Frames come out as they went in, so a recorded session can be run
+/// through the same processing as live frames. Frame times are kept
+/// relative to each other: [Frame::timestamp] is offset from when the reader
+/// was created, and [Frame::seq] is the frame index.
+pub struct RawSequenceReader<R: Read + Seek> {
+    r: R,
+    version: u16,
+    header: TprawHeader,
+    epoch: Instant,
+}
+
+impl RawSequenceReader<BufReader<File>> {
+    /// Open the file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read + Seek> RawSequenceReader<R> {
+    /// Read the magic number and header.
+    ///
+    /// Returns [Error::Format] if this isn't a `.tpraw` file, or it's from a
+    /// newer version.
+    pub fn new(mut r: R) -> Result<Self, Error> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::Format("not a .tpraw file".to_string()));
+        }
+        // The header is the first chunk
+        let (tag, len) = read_chunk_header(&mut r)?
+            .ok_or_else(|| Error::Format("missing HEAD chunk".to_string()))?;
+        if &tag != b"HEAD" || !(12..=MAX_HEAD_LEN).contains(&len) {
+            return Err(Error::Format("missing HEAD chunk".to_string()));
+        }
+        let mut head = vec![0u8; len as usize];
+        r.read_exact(&mut head)?;
+        let u16_at = |i: usize| u16::from_le_bytes([head[i], head[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(head[i..i + 4].try_into().unwrap());
+        let version = u16_at(0);
+        if version == 0 || version > VERSION {
+            return Err(Error::Format(format!("unsupported .tpraw version {}", version)));
+        }
+        let cfa = cfa_from_u8(head[11])
+            .ok_or_else(|| Error::Format("invalid CFA in HEAD chunk".to_string()))?;
+        let (exposure, gain) = if version >= 2 && head.len() >= 22 {
+            (Duration::from_micros(u64::from_le_bytes(head[12..20].try_into().unwrap())),
+                u16_at(20))
+        } else {
+            (Duration::ZERO, 0)
+        };
+        let header = TprawHeader { width: u32_at(2) as usize, height: u32_at(6) as usize,
+            bpp: head[10] as usize, cfa, exposure, gain,
+        };
+        if header.bpp == 0 || header.bpp > 2 {
+            return Err(Error::Format("invalid bpp in HEAD chunk".to_string()));
+        }
+        let frame_len = header.width.checked_mul(header.height)
+            .and_then(|n| n.checked_mul(header.bpp));
+        if frame_len.is_none_or(|n| n as u64 > MAX_FRAME_LEN) {
+            return Err(Error::Format("frame size in HEAD chunk is too large".to_string()));
+        }
+        Ok(Self { r, version, header, epoch: Instant::now() })
+    }
+
+    pub fn header(&self) -> &TprawHeader { &self.header }
+
+    /// Version of the file format.
+    pub fn version(&self) -> u16 { self.version }
+
+    /// Read the next frame (or `None` at the end of the file).
+    pub fn read_frame(&mut self) -> Result<Option<Frame>, Error> {
+        let prefix = if self.version >= 2 { 16 } else { 8 };
+        let frame_len = self.header.frame_len() as u64;
+        while let Some((tag, len)) = read_chunk_header(&mut self.r)? {
+            let compressed = &tag == b"FRMZ";
+            if &tag != b"FRAM" && !compressed {
+                skip(&mut self.r, len)?;
+                continue;
+            }
+            if len < prefix || (!compressed && len != prefix + frame_len) {
+                return Err(Error::Format("FRAM chunk doesn't match the header".to_string()));
+            }
+            let mut buf = [0u8; 16];
+            self.r.read_exact(&mut buf[..prefix as usize])?;
+            let idx = u64::from_le_bytes(buf[..8].try_into().unwrap());
+            let time = if prefix == 16 {
+                Duration::from_micros(u64::from_le_bytes(buf[8..].try_into().unwrap()))
+            } else {
+                Duration::ZERO
+            };
+            let mut data = vec![0u8; frame_len as usize];
+            if compressed {
+                #[cfg(feature = "zstd")]
+                {
+                    // No frame compresses to more than this
+                    let max = zstd::zstd_safe::compress_bound(frame_len as usize) as u64;
+                    if len - prefix > max {
+                        return Err(Error::Format("FRMZ chunk is too long".to_string()));
+                    }
+                    let mut packed = vec![0u8; (len - prefix) as usize];
+                    self.r.read_exact(&mut packed)?;
+                    decompress(&packed, self.header.bpp, &mut data)?;
+                }
+                #[cfg(not(feature = "zstd"))]
+                return Err(Error::Format(
+                    "compressed frames need the zstd feature".to_string()));
+            } else {
+                self.r.read_exact(&mut data)?;
+            }
+            let h = &self.header;
+            return Ok(Some(Frame { data: data.into(), height: h.height, width: h.width,
+                bpp: h.bpp, elapsed: Duration::ZERO, marked: false, cfa: h.cfa, seq: idx,
+                timestamp: self.epoch + time, complete: true,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Go back to the first frame.
+    pub fn rewind(&mut self) -> Result<(), Error> {
+        self.r.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+        // Skip the header
+        if let Some((_, len)) = read_chunk_header(&mut self.r)? {
+            skip(&mut self.r, len)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Iterator for RawSequenceReader<R> {
+    type Item = Result<Frame, Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+impl<R: Read + Seek> FrameSource for RawSequenceReader<R> {
+    fn next_frame(&mut self) -> Result<Option<Frame>, Error> { self.read_frame() }
+    fn rewind(&mut self) -> Result<(), Error> { RawSequenceReader::rewind(self) }
+}
+
+/// Seek past the payload of a chunk.
+fn skip<R: Seek>(r: &mut R, len: u64) -> Result<(), Error> {
+    let len = i64::try_from(len)
+        .map_err(|_| Error::Format("invalid chunk length".to_string()))?;
+    r.seek(SeekFrom::Current(len))?;
+    Ok(())
+}
+
+/// Read a chunk's tag and length (or `None` at the end of the file).
+fn read_chunk_header<R: Read>(r: &mut R) -> Result<Option<([u8; 4], u64)>, Error> {
+    let mut buf = [0u8; 12];
+    let mut got = 0;
+    while got < buf.len() {
+        match r.read(&mut buf[got..])? {
+            0 if got == 0 => return Ok(None),
+            0 => return Err(Error::Format("truncated chunk header".to_string())),
+            n => got += n,
+        }
+    }
+    let tag = buf[..4].try_into().unwrap();
+    Ok(Some((tag, u64::from_le_bytes(buf[4..].try_into().unwrap()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn frame(width: usize, height: usize, bpp: usize, seed: u8) -> Frame {
+        let data: Vec<u8> = (0..width * height * bpp)
+            .map(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)).collect();
+        Frame { data: data.into(), height, width, bpp, elapsed: Duration::ZERO,
+            marked: false, cfa: Cfa::Grbg, seq: 0, timestamp: Instant::now(), complete: true,
+        }
+    }
+
+    fn write(header: &TprawHeader, frames: &[Frame], compression: Option<i32>) -> Vec<u8> {
+        let mut w = TprawWriter::with_header(Vec::new(), header).unwrap();
+        #[cfg(feature = "zstd")]
+        w.set_compression(compression);
+        #[cfg(not(feature = "zstd"))]
+        assert!(compression.is_none());
+        for f in frames { w.write_frame(f).unwrap(); }
+        w.finish().unwrap()
+    }
+
+    fn chunk(tag: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = tag.to_vec();
+        out.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn v1_head(width: u32, height: u32, bpp: u8) -> Vec<u8> {
+        let mut head = 1u16.to_le_bytes().to_vec();
+        head.extend_from_slice(&width.to_le_bytes());
+        head.extend_from_slice(&height.to_le_bytes());
+        head.extend_from_slice(&[bpp, cfa_to_u8(Cfa::Bggr)]);
+        head
+    }
+
+    fn round_trip(bpp: usize, compression: Option<i32>) {
+        let header = TprawHeader { width: 8, height: 6, bpp, cfa: Cfa::Grbg,
+            exposure: Duration::from_millis(20), gain: 0x610c,
+        };
+        let frames = [frame(8, 6, bpp, 1), frame(8, 6, bpp, 2), frame(8, 6, bpp, 3)];
+        let file = write(&header, &frames, compression);
+        let mut r = RawSequenceReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(r.version(), VERSION);
+        assert_eq!(*r.header(), header);
+        for pass in 0..2 {
+            let read: Vec<Frame> = r.by_ref().collect::<Result<_, _>>().unwrap();
+            assert_eq!(read.len(), frames.len(), "pass {}", pass);
+            for (idx, (a, b)) in read.iter().zip(frames.iter()).enumerate() {
+                assert_eq!(a.seq, idx as u64);
+                assert_eq!((a.width, a.height, a.bpp, a.cfa), (8, 6, bpp, Cfa::Grbg));
+                assert_eq!(a.data[..], b.data[..]);
+            }
+            r.rewind().unwrap();
+        }
+    }
+
+    #[test]
+    fn round_trip_8bit() { round_trip(1, None); }
+
+    #[test]
+    fn round_trip_16bit() { round_trip(2, None); }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn round_trip_compressed() {
+        round_trip(1, Some(3));
+        round_trip(2, Some(3));
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let data: Vec<u8> = (0..4 * 2 * 2).collect();
+        let mut file = MAGIC.to_vec();
+        file.extend(chunk(b"HEAD", &v1_head(4, 2, 2)));
+        file.extend(chunk(b"XTRA", b"skipped"));
+        file.extend(chunk(b"FRAM", &[&7u64.to_le_bytes()[..], &data].concat()));
+        let mut r = RawSequenceReader::new(Cursor::new(file)).unwrap();
+        assert_eq!(r.version(), 1);
+        assert_eq!(*r.header(), TprawHeader { width: 4, height: 2, bpp: 2, cfa: Cfa::Bggr,
+            exposure: Duration::ZERO, gain: 0,
+        });
+        let f = r.read_frame().unwrap().unwrap();
+        assert_eq!(f.seq, 7);
+        assert_eq!(f.data[..], data[..]);
+        assert!(r.read_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let open = |file: Vec<u8>| RawSequenceReader::new(Cursor::new(file));
+        assert!(matches!(open(b"not tpraw at all".to_vec()), Err(Error::Format(_))));
+
+        // A HEAD chunk claiming to be huge
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(b"HEAD");
+        file.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(open(file), Err(Error::Format(_))));
+
+        // Frame size overflows
+        let mut file = MAGIC.to_vec();
+        file.extend(chunk(b"HEAD", &v1_head(u32::MAX, u32::MAX, 2)));
+        assert!(matches!(open(file), Err(Error::Format(_))));
+    }
+
+    #[test]
+    fn rejects_bad_chunks() {
+        let mut file = MAGIC.to_vec();
+        file.extend(chunk(b"HEAD", &v1_head(4, 2, 1)));
+        // Unknown chunk with a length that can't be seeked past
+        let mut bad = file.clone();
+        bad.extend_from_slice(b"XTRA");
+        bad.extend_from_slice(&u64::MAX.to_le_bytes());
+        let mut r = RawSequenceReader::new(Cursor::new(bad)).unwrap();
+        assert!(matches!(r.read_frame(), Err(Error::Format(_))));
+
+        // Frame that doesn't match the header
+        let mut bad = file.clone();
+        bad.extend(chunk(b"FRAM", &[0u8; 8 + 3]));
+        let mut r = RawSequenceReader::new(Cursor::new(bad)).unwrap();
+        assert!(matches!(r.read_frame(), Err(Error::Format(_))));
+
+        // Compressed frame claiming to be far longer than any frame could be
+        let mut bad = file.clone();
+        bad.extend_from_slice(b"FRMZ");
+        bad.extend_from_slice(&(1u64 << 40).to_le_bytes());
+        bad.extend_from_slice(&[0u8; 8]);
+        let mut r = RawSequenceReader::new(Cursor::new(bad)).unwrap();
+        assert!(matches!(r.read_frame(), Err(Error::Format(_))));
+
+        // Truncated chunk header
+        let mut bad = file;
+        bad.extend_from_slice(b"FRA");
+        let mut r = RawSequenceReader::new(Cursor::new(bad)).unwrap();
+        assert!(matches!(r.read_frame(), Err(Error::Format(_))));
+    }
+}
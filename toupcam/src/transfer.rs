@@ -0,0 +1,208 @@
+//! [Private] Asynchronous bulk transfers with several URBs in flight.
+//!
+//! With synchronous `read_bulk()` calls, the bus sits idle between one
+//! transfer completing and the next one being submitted. Instead, we keep a
+//! ring of transfers queued on endpoint 0x81 and resubmit each one as soon as
+//! its data has been consumed. Transfers on an endpoint complete in the order
+//! they were submitted, so the data still comes out in order.
+//!
+//! rusb doesn't wrap the libusb asynchronous API, so this uses `libusb1-sys`
+//! directly.
+
+use crate::{ Error, Camera };
+use libusb1_sys as ffi;
+use libusb1_sys::constants::*;
+use rusb::{ Context, DeviceHandle, UsbContext };
+use std::os::raw::{ c_int, c_uint, c_void };
+use std::sync::Arc;
+use std::time::Duration;
+
+extern "system" fn transfer_cb(xfer: *mut ffi::libusb_transfer) {
+    // SAFETY: user_data always points at the slot's completion flag, which
+    // outlives the transfer (see BulkQueue::drop).
+    unsafe { *((*xfer).user_data as *mut c_int) = 1; }
+}
+
+/// A single transfer along with its buffer.
+struct Slot {
+    xfer: *mut ffi::libusb_transfer,
+    buf: Vec<u8>,
+    /// Set to 1 by the completion callback
+    done: Box<c_int>,
+    /// Set while the transfer is submitted
+    in_flight: bool,
+}
+
+/// A ring of bulk IN transfers kept queued on a single endpoint.
+pub (crate) struct BulkQueue {
+    handle: Arc<DeviceHandle<Context>>,
+    slots: Vec<Slot>,
+    /// Index of the oldest transfer (the next one to complete)
+    next: usize,
+}
+
+// SAFETY: the raw transfer pointers are only touched through `&mut self`.
+unsafe impl Send for BulkQueue {}
+
+fn status_to_error(status: c_int) -> rusb::Error {
+    match status {
+        LIBUSB_TRANSFER_TIMED_OUT => rusb::Error::Timeout,
+        LIBUSB_TRANSFER_STALL => rusb::Error::Pipe,
+        LIBUSB_TRANSFER_NO_DEVICE => rusb::Error::NoDevice,
+        LIBUSB_TRANSFER_OVERFLOW => rusb::Error::Overflow,
+        LIBUSB_TRANSFER_CANCELLED => rusb::Error::Interrupted,
+        _ => rusb::Error::Io,
+    }
+}
+
+fn code_to_error(code: c_int) -> rusb::Error {
+    match code {
+        LIBUSB_ERROR_NO_DEVICE => rusb::Error::NoDevice,
+        LIBUSB_ERROR_PIPE => rusb::Error::Pipe,
+        LIBUSB_ERROR_BUSY => rusb::Error::Busy,
+        LIBUSB_ERROR_NO_MEM => rusb::Error::NoMem,
+        _ => rusb::Error::Io,
+    }
+}
+
+impl BulkQueue {
+    /// Allocate and submit `depth` transfers of `chunk_len` bytes each.
+    pub (crate) fn new(handle: Arc<DeviceHandle<Context>>, endpoint: u8,
+        depth: usize, chunk_len: usize, timeout: Duration) -> Result<Self, Error>
+    {
+        let mut res = Self { handle, slots: Vec::with_capacity(depth), next: 0 };
+        for _ in 0..depth {
+            // SAFETY: zero isochronous packets for a bulk transfer
+            let xfer = unsafe { ffi::libusb_alloc_transfer(0) };
+            if xfer.is_null() { return Err(Error::Rusb(rusb::Error::NoMem)); }
+            let mut slot = Slot {
+                xfer, buf: vec![0u8; chunk_len], done: Box::new(0), in_flight: false,
+            };
+            // SAFETY: the buffer and completion flag live as long as the slot,
+            // and the slot is only freed after the transfer is no longer in
+            // flight.
+            unsafe {
+                ffi::libusb_fill_bulk_transfer(xfer, res.handle.as_raw(), endpoint,
+                    slot.buf.as_mut_ptr(), chunk_len as c_int, transfer_cb,
+                    &mut *slot.done as *mut c_int as *mut c_void,
+                    timeout.as_millis() as c_uint);
+            }
+            res.slots.push(slot);
+        }
+        for idx in 0..res.slots.len() {
+            res.submit(idx)?;
+        }
+        Ok(res)
+    }
+
+    fn submit(&mut self, idx: usize) -> Result<(), Error> {
+        let slot = &mut self.slots[idx];
+        *slot.done = 0;
+        // SAFETY: the transfer was filled in by BulkQueue::new
+        let rc = unsafe { ffi::libusb_submit_transfer(slot.xfer) };
+        if rc != 0 { return Err(Error::from(code_to_error(rc))); }
+        slot.in_flight = true;
+        Ok(())
+    }
+
+    /// Handle libusb events until the given slot has completed.
+    fn wait(&mut self, idx: usize) -> Result<(), Error> {
+        let ctx = self.handle.context().as_raw();
+        let tv = libc::timeval { tv_sec: 1, tv_usec: 0 };
+        let done: *mut c_int = &mut *self.slots[idx].done;
+        // SAFETY: `done` points at the slot's completion flag, which is
+        // written by the callback from inside libusb_handle_events
+        while unsafe { std::ptr::read_volatile(done) } == 0 {
+            let rc = unsafe { ffi::libusb_handle_events_timeout_completed(ctx, &tv, done) };
+            if rc != 0 && rc != LIBUSB_ERROR_INTERRUPTED {
+                return Err(Error::from(code_to_error(rc)));
+            }
+        }
+        self.slots[idx].in_flight = false;
+        Ok(())
+    }
+
+    /// Wait for the next transfer, copying its data into `dst`.
+    ///
+    /// Returns the number of bytes received (which may be more than the
+    /// length of `dst`, in which case the data is truncated). The transfer
+    /// is resubmitted immediately.
+    pub (crate) fn read(&mut self, dst: &mut [u8]) -> Result<usize, Error> {
+        let idx = self.next;
+        self.wait(idx)?;
+
+        // SAFETY: the transfer is complete, so libusb is done with it
+        let (status, len) = unsafe {
+            ((*self.slots[idx].xfer).status, (*self.slots[idx].xfer).actual_length as usize)
+        };
+        let res = if status == LIBUSB_TRANSFER_COMPLETED {
+            let n = len.min(dst.len());
+            dst[..n].copy_from_slice(&self.slots[idx].buf[..n]);
+            Ok(len)
+        } else {
+            Err(Error::from(status_to_error(status)))
+        };
+
+        self.next = (idx + 1) % self.slots.len();
+        self.submit(idx)?;
+        res
+    }
+}
+
+impl Drop for BulkQueue {
+    fn drop(&mut self) {
+        for slot in self.slots.iter() {
+            if slot.in_flight {
+                // SAFETY: the transfer is submitted
+                unsafe { ffi::libusb_cancel_transfer(slot.xfer); }
+            }
+        }
+        for idx in 0..self.slots.len() {
+            if self.slots[idx].in_flight && self.wait(idx).is_err() {
+                // If we can't wait for the cancellation, the transfer (and
+                // its buffer) has to be leaked rather than freed under libusb.
+                let slot = &mut self.slots[idx];
+                std::mem::forget(std::mem::take(&mut slot.buf));
+                std::mem::forget(std::mem::replace(&mut slot.done, Box::new(0)));
+                slot.xfer = std::ptr::null_mut();
+            }
+        }
+        for slot in self.slots.iter() {
+            if !slot.xfer.is_null() {
+                // SAFETY: the transfer is no longer in flight
+                unsafe { ffi::libusb_free_transfer(slot.xfer); }
+            }
+        }
+    }
+}
+
+impl Camera {
+    /// Read a single chunk from the bulk endpoint into `buf`, either with
+    /// the queue of asynchronous transfers or a synchronous read.
+    pub (crate) fn read_chunk(&mut self, buf: &mut [u8], timeout: Duration)
+        -> Result<usize, Error>
+    {
+        if self.queue_depth < 2 {
+            return Ok(self.handle.read_bulk(0x81, buf, timeout)?);
+        }
+        if self.bulk.is_none() {
+            self.bulk = Some(BulkQueue::new(self.handle.clone(), 0x81,
+                self.queue_depth, buf.len(), timeout)?);
+        }
+        let res = self.bulk.as_mut().unwrap().read(buf);
+        if res.is_err() {
+            // Start over with a fresh queue on the next read
+            self.bulk = None;
+        }
+        res
+    }
+
+    /// Set the number of bulk transfers kept in flight while streaming.
+    ///
+    /// Values less than 2 use synchronous reads. Takes effect the next time
+    /// the stream is started.
+    pub fn set_queue_depth(&mut self, depth: usize) {
+        self.queue_depth = depth;
+    }
+    pub fn get_queue_depth(&self) -> usize { self.queue_depth }
+}
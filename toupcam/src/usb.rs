@@ -2,6 +2,8 @@
 
 use rusb::{ request_type, Direction, RequestType, Recipient };
 use crate::{ Error, Camera };
+#[cfg(feature = "unsafe-registers")]
+use crate::protocol::RegisterKind;
 
 impl Camera {
 
@@ -17,8 +19,10 @@ impl Camera {
         if buf[0] == 0x08 {
             self.handle.read_control(rt, 0x0b, val, 0x1100, &mut buf, self.timeout)?;
         } else {
-            println!("sensor write to {:04x} returned {:02x}?", addr, buf[0]);
+            eprintln!("sensor write to {:04x} returned {:02x}?", addr, buf[0]);
         }
+        #[cfg(feature = "unsafe-registers")]
+        self.reg_shadow.insert((RegisterKind::Sensor, addr), val);
         Ok(())
     }
 
@@ -29,7 +33,11 @@ impl Camera {
         let mut buf: [u8; 1] = [ 0 ];
         let rt = request_type(Direction::In, RequestType::Vendor, Recipient::Device);
         match self.handle.read_control(rt, 0x0b, val, addr, &mut buf, self.timeout) {
-            Ok(_) => Ok(()),
+            Ok(_) => {
+                #[cfg(feature = "unsafe-registers")]
+                self.reg_shadow.insert((RegisterKind::Sys, addr), val);
+                Ok(())
+            },
             Err(e) => Err(Error::from(e)),
         }
     }
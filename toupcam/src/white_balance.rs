@@ -0,0 +1,52 @@
+//! Software white balance.
+//!
+//! No digital gain registers (per-channel or otherwise) have been found on
+//! the sensor, so white balance is applied to the image after demosaicing
+//! (see [crate::demosaic::demosaic_balanced]).
+
+use crate::Frame;
+use crate::histogram::Channel;
+
+/// How to pick the per-channel gains.
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub enum WhiteBalance {
+    /// Gray world: assume the scene averages out to gray, and scale red and
+    /// blue so that their means match green
+    #[default]
+    Auto,
+    /// Fixed gains
+    Manual { r: f32, g: f32, b: f32 },
+}
+impl WhiteBalance {
+    /// No correction.
+    pub const NONE: WhiteBalance = WhiteBalance::Manual { r: 1.0, g: 1.0, b: 1.0 };
+
+    /// Returns the `[r, g, b]` gains for a frame.
+    pub fn gains(&self, frame: &Frame) -> [f32; 3] {
+        match *self {
+            Self::Auto => gray_world(frame),
+            Self::Manual { r, g, b } => [r, g, b],
+        }
+    }
+}
+
+/// Compute gray-world gains (normalized so that green is 1.0).
+///
+/// Only every 4th cell is looked at, and samples in the top 2% (which are
+/// likely to be clipped in at least one channel) are ignored.
+pub fn gray_world(frame: &Frame) -> [f32; 3] {
+    let hist = frame.histogram_sampled(4);
+    let mean = |ch: Channel| {
+        let limit = hist.percentile(ch, 0.98);
+        let (mut sum, mut count) = (0f64, 0u64);
+        for (v, n) in hist.get(ch).iter().enumerate().take(limit + 1) {
+            sum += v as f64 * *n as f64;
+            count += *n as u64;
+        }
+        if count == 0 { 0.0 } else { sum / count as f64 }
+    };
+    let (r, b) = (mean(Channel::Red), mean(Channel::Blue));
+    let g = (mean(Channel::Green1) + mean(Channel::Green2)) / 2.0;
+    let gain = |c: f64| if c > 0.0 && g > 0.0 { (g / c) as f32 } else { 1.0 };
+    [gain(r), 1.0, gain(b)]
+}